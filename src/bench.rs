@@ -0,0 +1,177 @@
+//! Standard benchmark scenarios and a runner to compare the algorithms (and cutoffs) on your own hardware.
+//! Requires the `bench` feature.
+//!
+//! # Examples
+//! ```
+//! use box_intersect_ze::bench::{run, Algorithm, Scenario};
+//!
+//! for algorithm in [Algorithm::Ze, Algorithm::Scan] {
+//!     let result = run(Scenario::Uniform, algorithm, 1000, 1234);
+//!     println!("{:?}", result);
+//! }
+//! ```
+
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::{Rng as OtherRng, SeedableRng};
+
+use crate::boxes::Box3Df32;
+use crate::set::BBoxSet;
+use crate::{intersect_brute_force, intersect_scan, intersect_ze, intersect_ze_custom};
+
+/// Set of boxes as used by the benchmarks
+pub type BenchSet = BBoxSet<Box3Df32, usize>;
+
+/// Distribution of boxes to benchmark with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scenario {
+    /// Boxes placed uniformly at random, with side lengths up to `n^(2/3)` in a cube of side length `n`
+    Uniform,
+    /// Like [`Scenario::Uniform`] but with boxes concentrated around the given number of cluster centers
+    Clustered(usize),
+    /// Two distinct sets of flat boxes in interleaved layers, intersected with each other
+    BipartiteLayers,
+    /// Worst case for the scans: all boxes share the same low boundary in dimension 0
+    IdenticalLows,
+}
+
+/// Algorithm to benchmark
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    /// [`intersect_ze`] with the default cutoff
+    Ze,
+    /// [`intersect_ze_custom`] with cutoff 10
+    Ze10,
+    /// [`intersect_ze_custom`] with cutoff 100
+    Ze100,
+    /// [`intersect_ze_custom`] with cutoff 2000
+    Ze2000,
+    /// [`intersect_scan`]
+    Scan,
+    /// [`intersect_brute_force`]
+    BruteForce,
+}
+
+/// Timings and statistics of one benchmark run
+#[derive(Clone, Debug)]
+pub struct BenchResult {
+    pub scenario: Scenario,
+    pub algorithm: Algorithm,
+    /// Total number of boxes in the input
+    pub boxes: usize,
+    /// Number of intersecting pairs found
+    pub pairs: usize,
+    /// Time spent sorting the input
+    pub sort: Duration,
+    /// Time spent finding intersections
+    pub intersect: Duration,
+}
+
+impl Scenario {
+    /// Generates `n` boxes for this scenario, deterministically derived from `seed`.
+    /// Returns a second set if the scenario is bipartite.
+    pub fn generate(&self, n: usize, seed: u64) -> (BenchSet, Option<BenchSet>) {
+        let mut r = StdRng::seed_from_u64(seed);
+        let size = n.max(2) as f32;
+        let len_max = size.powf(2.0 / 3.0).max(1.0);
+        let mut set = BBoxSet::with_capacity(n);
+
+        match *self {
+            Scenario::Uniform => {
+                for id in 0..n {
+                    let (min, max) = random_box(&mut r, [0.0; 3], size, len_max);
+                    set.push(id, Box3Df32::new(min, max));
+                }
+            }
+            Scenario::Clustered(clusters) => {
+                let clusters = clusters.max(1);
+                let spread = size / clusters as f32;
+                let centers: Vec<[f32; 3]> = (0..clusters)
+                    .map(|_| [0; 3].map(|_| r.gen_range(0.0..size)))
+                    .collect();
+                for id in 0..n {
+                    let center = centers[id % clusters];
+                    let (min, max) = random_box(&mut r, center, spread, len_max);
+                    set.push(id, Box3Df32::new(min, max));
+                }
+            }
+            Scenario::BipartiteLayers => {
+                let mut other = BBoxSet::with_capacity(n - n / 2);
+                for id in 0..n {
+                    let layer = (id % 16) as f32;
+                    let (mut min, mut max) = random_box(&mut r, [0.0; 3], size, len_max);
+                    min[2] = layer;
+                    max[2] = layer + 1.5;
+                    let bbox = Box3Df32::new(min, max);
+                    if id % 2 == 0 {
+                        set.push(id, bbox);
+                    } else {
+                        other.push(id, bbox);
+                    }
+                }
+                return (set, Some(other));
+            }
+            Scenario::IdenticalLows => {
+                for id in 0..n {
+                    let (mut min, mut max) = random_box(&mut r, [0.0; 3], size, len_max);
+                    max[0] -= min[0];
+                    min[0] = 0.0;
+                    set.push(id, Box3Df32::new(min, max));
+                }
+            }
+        }
+        (set, None)
+    }
+}
+
+fn random_box<R: OtherRng>(
+    r: &mut R,
+    origin: [f32; 3],
+    size: f32,
+    len_max: f32,
+) -> ([f32; 3], [f32; 3]) {
+    let mut min = [0.0; 3];
+    let mut max = [0.0; 3];
+    for d in 0..3 {
+        min[d] = origin[d] + r.gen_range(0.0..size);
+        max[d] = min[d] + r.gen_range(0.0..len_max);
+    }
+    (min, max)
+}
+
+/// Generates `n` boxes for `scenario` from `seed`, then sorts them and finds their intersections
+/// using `algorithm`, measuring the time spent on each.
+pub fn run(scenario: Scenario, algorithm: Algorithm, n: usize, seed: u64) -> BenchResult {
+    let (mut a, mut b) = scenario.generate(n, seed);
+
+    let now = Instant::now();
+    a.sort();
+    if let Some(b) = &mut b {
+        b.sort();
+    }
+    let sort = now.elapsed();
+
+    let b = b.as_ref().unwrap_or(&a);
+    let mut rand = StdRng::seed_from_u64(seed);
+    let mut out = Vec::new();
+    let now = Instant::now();
+    match algorithm {
+        Algorithm::Ze => intersect_ze(&a, b, &mut out, &mut rand),
+        Algorithm::Ze10 => intersect_ze_custom::<_, _, _, 10>(&a, b, &mut out, &mut rand),
+        Algorithm::Ze100 => intersect_ze_custom::<_, _, _, 100>(&a, b, &mut out, &mut rand),
+        Algorithm::Ze2000 => intersect_ze_custom::<_, _, _, 2000>(&a, b, &mut out, &mut rand),
+        Algorithm::Scan => intersect_scan(&a, b, &mut out),
+        Algorithm::BruteForce => intersect_brute_force(&a, b, &mut out),
+    }
+    let intersect = now.elapsed();
+
+    BenchResult {
+        scenario,
+        algorithm,
+        boxes: n,
+        pairs: out.len(),
+        sort,
+        intersect,
+    }
+}
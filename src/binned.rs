@@ -0,0 +1,304 @@
+//! Two-level algorithm for heavily clustered boxes: partitions space into bins of about equally many boxes,
+//! then runs [`intersect_ze`] within each bin, see [`intersect_binned`].
+
+use crate::boxes::{BBox, BoxND};
+use crate::set::BBoxSet;
+use crate::sink::Sink;
+use crate::source::{self, BoxSource};
+use crate::{intersect_ze, HasInfinity, Rng};
+
+/// Default number of boxes per bin, see [`intersect_binned_with_bin_size`]
+pub const BIN_SIZE: usize = 2048;
+
+/// Boxes overlapping more bins than this are intersected with all boxes at once instead of being copied into their bins
+const MAX_BINS: usize = 8;
+
+/// Number of centers of boxes sampled per bin to partition space
+const SAMPLES_PER_BIN: usize = 64;
+
+/// Like [`intersect_ze`], but first partitions space into bins of about [`BIN_SIZE`] boxes each, by recursively
+/// splitting the centers of the boxes at their median in the dimension in which they spread the most,
+/// then runs [`intersect_ze`] within each bin. A box is copied into every bin it overlaps,
+/// and a pair of boxes sharing several bins is only reported by the bin containing the lowest corner of their overlap,
+/// so every pair is reported exactly once.
+/// * Worth it for heavily clustered boxes: the bins adapt to the clusters, while the median splits of
+///   [`intersect_ze`] are chosen for all boxes at once and cut through many clusters.
+/// * Boxes overlapping more than 8 bins are intersected with all boxes directly instead.
+/// * `a` and `b` may be either the same or distinct [`BBoxSet`]s (or other [`BoxSource`]s) and must be sorted before calling.
+pub fn intersect_binned<N, ID, R, const D: usize>(
+    a: &impl BoxSource<BoxND<N, D>, ID>,
+    b: &impl BoxSource<BoxND<N, D>, ID>,
+    out: &mut impl Sink<BoxND<N, D>, ID>,
+    rand: &mut R,
+) where
+    N: Copy + PartialOrd + HasInfinity + Into<f64>,
+    ID: PartialOrd + Copy,
+    R: Rng,
+{
+    intersect_binned_with_bin_size(a, b, out, rand, BIN_SIZE);
+}
+
+/// Like [`intersect_binned`], but with bins of about `bin_size` boxes. Smaller bins adapt more closely to the clusters,
+/// but more boxes overlap several bins and are copied.
+///
+/// # Panics
+/// If `bin_size` is 0.
+pub fn intersect_binned_with_bin_size<N, ID, R, const D: usize>(
+    a: &impl BoxSource<BoxND<N, D>, ID>,
+    b: &impl BoxSource<BoxND<N, D>, ID>,
+    out: &mut impl Sink<BoxND<N, D>, ID>,
+    rand: &mut R,
+    bin_size: usize,
+) where
+    N: Copy + PartialOrd + HasInfinity + Into<f64>,
+    ID: PartialOrd + Copy,
+    R: Rng,
+{
+    assert!(bin_size > 0, "bins must hold at least one box");
+    let same = source::same(a, b);
+    let b_len = if same { 0 } else { b.len() };
+    // the partition is built from a random sample of the centers of the boxes, with up to SAMPLES_PER_BIN in each bin
+    let len = a.len() + b_len;
+    let center_of = |idx: usize| {
+        let bbox = if idx < a.len() {
+            a.get(idx).0
+        } else {
+            b.get(idx - a.len()).0
+        };
+        let mut center = [0.0; D];
+        for (dim, c) in center.iter_mut().enumerate() {
+            *c = bbox.lo(dim).into() / 2.0 + bbox.hi(dim).into() / 2.0;
+        }
+        center
+    };
+    let samples = (len / bin_size + 1) * SAMPLES_PER_BIN;
+    let mut centers: Vec<[f64; D]> = if samples < len {
+        (0..samples)
+            .map(|_| center_of(rand.rand_usize(len)))
+            .collect()
+    } else {
+        (0..len).map(center_of).collect()
+    };
+    let partition = Partition::new(&mut centers, SAMPLES_PER_BIN);
+
+    // copies of the boxes by bin, and the boxes overlapping too many bins; iterating the sources in order keeps them sorted
+    let split = |source: &dyn Fn(usize) -> (BoxND<N, D>, ID), len: usize| {
+        let mut bins = vec![BBoxSet::new(); partition.bins];
+        let mut large = BBoxSet::new();
+        let mut large_idx = Vec::new();
+        let (mut overlapped, mut stack) = (Vec::new(), Vec::new());
+        for idx in 0..len {
+            let (bbox, id) = source(idx);
+            partition.bins_of(&bbox, &mut overlapped, &mut stack);
+            if overlapped.len() > MAX_BINS {
+                large.push(id, bbox);
+                large_idx.push(idx);
+            } else {
+                for &bin in &overlapped {
+                    bins[bin].push(id, bbox);
+                }
+            }
+        }
+        (bins, large, large_idx)
+    };
+    let (a_bins, a_large, a_large_idx) = split(&|idx| a.get(idx), a.len());
+    let mut in_bin = InBin {
+        out,
+        partition: &partition,
+        bin: 0,
+    };
+    if same {
+        for (bin, boxes) in a_bins.iter().enumerate() {
+            in_bin.bin = bin;
+            intersect_ze(boxes, boxes, &mut in_bin, rand);
+        }
+        if !a_large.is_empty() {
+            let a_small = without(a, &a_large_idx);
+            intersect_ze(&a_large, &a_large, out, rand);
+            intersect_ze(&a_large, &a_small, out, rand);
+        }
+    } else {
+        let (b_bins, b_large, _) = split(&|idx| b.get(idx), b.len());
+        for (bin, (a_boxes, b_boxes)) in a_bins.iter().zip(&b_bins).enumerate() {
+            in_bin.bin = bin;
+            intersect_ze(a_boxes, b_boxes, &mut in_bin, rand);
+        }
+        if !a_large.is_empty() {
+            intersect_ze(&a_large, b, out, rand);
+        }
+        if !b_large.is_empty() {
+            let a_small = without(a, &a_large_idx);
+            intersect_ze(&a_small, &b_large, out, rand);
+        }
+    }
+}
+
+/// Returns the boxes of `source` except those at the indices `skip`, in ascending order, keeping them sorted.
+fn without<B: BBox, ID: Copy + PartialEq>(
+    source: &impl BoxSource<B, ID>,
+    skip: &[usize],
+) -> BBoxSet<B, ID> {
+    let mut skip = skip.iter().peekable();
+    let mut rest = BBoxSet::with_capacity(source.len() - skip.len());
+    for idx in 0..source.len() {
+        if skip.next_if(|&&skipped| skipped == idx).is_none() {
+            let (bbox, id) = source.get(idx);
+            rest.push(id, bbox);
+        }
+    }
+    rest
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Node {
+    /// Points with a coordinate in `dim` below `value` lie in the `left` subtree, the others in the `right` one
+    Split {
+        dim: usize,
+        value: f64,
+        left: usize,
+        right: usize,
+    },
+    Bin(usize),
+}
+
+/// A kd partition of space into bins, the root at index 0
+struct Partition {
+    nodes: Vec<Node>,
+    bins: usize,
+}
+
+impl Partition {
+    /// Partitions space so that every bin holds up to `bin_size` of `centers`, reordering them.
+    fn new<const D: usize>(centers: &mut [[f64; D]], bin_size: usize) -> Self {
+        let mut partition = Partition {
+            nodes: vec![Node::Bin(0)],
+            bins: 0,
+        };
+        let mut stack = vec![(0, 0, centers.len())];
+        while let Some((node, start, end)) = stack.pop() {
+            let range = &mut centers[start..end];
+            let mut min = [f64::INFINITY; D];
+            let mut max = [f64::NEG_INFINITY; D];
+            if range.len() > bin_size {
+                for center in range.iter() {
+                    for dim in 0..D {
+                        min[dim] = min[dim].min(center[dim]);
+                        max[dim] = max[dim].max(center[dim]);
+                    }
+                }
+            }
+            let spread = |dim: usize| max[dim] - min[dim];
+            let dim = (0..D).max_by(|&a, &b| spread(a).total_cmp(&spread(b)));
+            // split until the bins are small enough, but centers that all coincide can't be split
+            let dim = match dim {
+                Some(dim) if range.len() > bin_size && spread(dim) > 0.0 => dim,
+                _ => {
+                    partition.nodes[node] = Node::Bin(partition.bins);
+                    partition.bins += 1;
+                    continue;
+                }
+            };
+            let mid = range.len() / 2;
+            range.select_nth_unstable_by(mid, |a, b| a[dim].total_cmp(&b[dim]));
+            let left = partition.nodes.len();
+            partition.nodes.extend([Node::Bin(0), Node::Bin(0)]);
+            partition.nodes[node] = Node::Split {
+                dim,
+                value: range[mid][dim],
+                left,
+                right: left + 1,
+            };
+            stack.extend([(left, start, start + mid), (left + 1, start + mid, end)]);
+        }
+        partition
+    }
+
+    /// Replaces the contents of `bins` with the bins overlapped by `bbox`, stopping once there are more than [`MAX_BINS`].
+    /// `stack` is scratch space.
+    fn bins_of<N, const D: usize>(
+        &self,
+        bbox: &BoxND<N, D>,
+        bins: &mut Vec<usize>,
+        stack: &mut Vec<usize>,
+    ) where
+        N: Copy + PartialOrd + Into<f64>,
+    {
+        bins.clear();
+        stack.clear();
+        stack.push(0);
+        while let Some(node) = stack.pop() {
+            match self.nodes[node] {
+                Node::Bin(bin) => {
+                    bins.push(bin);
+                    if bins.len() > MAX_BINS {
+                        return;
+                    }
+                }
+                Node::Split {
+                    dim,
+                    value,
+                    left,
+                    right,
+                } => {
+                    if bbox.lo(dim).into() < value {
+                        stack.push(left);
+                    }
+                    // also on equality, in case the conversion to `f64` rounded the high boundary down to `value`
+                    if bbox.hi(dim).into() >= value {
+                        stack.push(right);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the bin containing `point`.
+    fn bin_of<N, const D: usize>(&self, point: &[N; D]) -> usize
+    where
+        N: Copy + Into<f64>,
+    {
+        let mut node = 0;
+        loop {
+            match self.nodes[node] {
+                Node::Bin(bin) => return bin,
+                Node::Split {
+                    dim,
+                    value,
+                    left,
+                    right,
+                } => {
+                    node = if point[dim].into() < value {
+                        left
+                    } else {
+                        right
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A [`Sink`] passing the pairs found in `bin` on to `out` if the lowest corner of their overlap lies in `bin`
+struct InBin<'a, O> {
+    out: &'a mut O,
+    partition: &'a Partition,
+    bin: usize,
+}
+
+impl<N, ID, O, const D: usize> Sink<BoxND<N, D>, ID> for InBin<'_, O>
+where
+    N: Copy + PartialOrd + Into<f64>,
+    O: Sink<BoxND<N, D>, ID>,
+{
+    fn report(&mut self, a_id: ID, a: &BoxND<N, D>, b_id: ID, b: &BoxND<N, D>) {
+        let mut corner = a.min();
+        for (dim, lo) in corner.iter_mut().enumerate() {
+            if b.lo(dim) > *lo {
+                *lo = b.lo(dim);
+            }
+        }
+        if self.partition.bin_of(&corner) == self.bin {
+            self.out.report(a_id, a, b_id, b);
+        }
+    }
+}
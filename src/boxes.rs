@@ -1,6 +1,15 @@
 //! Boxes of various types and dimensions that can be checked for intersection
+
+use std::ops::{Add, Mul, Sub};
+
+use crate::error::{bounds_problem, BoxError, Problem};
+use crate::policy::{HalfOpen, Policy};
+use crate::HasInfinity;
+
 /// Trait for a `DIM`-dimensional box with bounds of type `Num`. More precisely, the
 /// cartesian product of `DIM` half-open intervals.
+/// Bounds may be infinite (see [`HasInfinity`]), e.g. for an infinite ground plane slab,
+/// but must not be NaN.
 /// You probably want to use one of the box types below instead of implementing this yourself.
 pub trait BBox: Copy {
     const DIM: usize;
@@ -13,8 +22,9 @@ pub trait BBox: Copy {
     fn hi(&self, dim: usize) -> Self::Num;
 
     /// Returns `true` if the projection of this box in dimension `dim` contains `point`.
+    /// Uses the default [`HalfOpen`] policy, see [`Policy`] for other semantics.
     fn contains_in(&self, dim: usize, point: Self::Num) -> bool {
-        self.lo(dim) <= point && point < self.hi(dim)
+        HalfOpen.contains_in(self, dim, point)
     }
 
     /// Returns `true` if the projection of this box in dimension `dim` intersects [`lo`, `hi`)
@@ -22,14 +32,35 @@ pub trait BBox: Copy {
         self.lo(dim) < hi && lo < self.hi(dim)
     }
 
-    // Returns `true` if the box intersects the given other box.
+    /// Returns `true` if the box intersects the given other box.
+    /// Uses the default [`HalfOpen`] policy, see [`Policy`] for other semantics.
     fn intersects(&self, other: &Self) -> bool {
-        for dim in 0..Self::DIM {
-            if !self.intersects_in(dim, other.lo(dim), other.hi(dim)) {
-                return false;
-            }
-        }
-        return true;
+        HalfOpen.intersects(self, other)
+    }
+
+    /// Tests this box against each box of `others` at once and sets bit `idx % 64` of `hits[idx / 64]`
+    /// if it intersects `others[idx]`, clearing the other bits. `hits` must have at least `others.len()` bits.
+    /// Uses the default [`HalfOpen`] policy, see [`Policy::intersects_each`] for other semantics.
+    fn intersects_each(&self, others: &[Self], hits: &mut [u64]) {
+        HalfOpen.intersects_each(self, others, hits)
+    }
+
+    /// Returns `true` if this box is empty: its high boundary in dimension 0 is less than its low boundary.
+    /// Empty boxes are never reported as intersecting by any algorithm, and skipped cheaply by the scans.
+    /// Since they keep their low boundary in dimension 0 (unless it is `-∞`), boxes in a sorted set can be made empty
+    /// (see [`BoxND::emptied`]) to remove them without compacting or re-sorting the set.
+    fn is_empty(&self) -> bool {
+        self.hi(0) < self.lo(0)
+    }
+
+    /// Returns `true` if the given other box lies completely inside this box.
+    fn contains(&self, other: &Self) -> bool {
+        (0..Self::DIM).all(|dim| self.lo(dim) <= other.lo(dim) && other.hi(dim) <= self.hi(dim))
+    }
+
+    /// Returns `true` if this box has exactly the same bounds as the given other box.
+    fn coincides(&self, other: &Self) -> bool {
+        (0..Self::DIM).all(|dim| self.lo(dim) == other.lo(dim) && self.hi(dim) == other.hi(dim))
     }
 }
 
@@ -46,6 +77,188 @@ impl<B: Copy, const N: usize> BoxND<B, N> {
     pub fn new(min: [B; N], max: [B; N]) -> Self {
         Self { min, max }
     }
+
+    /// Returns the low boundaries of the box, indexed by dimension.
+    pub fn min(&self) -> [B; N] {
+        self.min
+    }
+
+    /// Returns the high boundaries of the box, indexed by dimension.
+    pub fn max(&self) -> [B; N] {
+        self.max
+    }
+}
+
+impl<B: Copy + HasInfinity, const N: usize> BoxND<B, N> {
+    /// Returns an empty box (see [`BBox::is_empty`]) with the same low boundary in dimension 0,
+    /// to replace this box with in a set as a tombstone. No high boundary is below a low boundary of `-∞` though,
+    /// so a box unbounded below in dimension 0 gets the low boundary `+∞` instead, and a set containing it
+    /// has to be sorted again.
+    pub fn emptied(&self) -> Self
+    where
+        B: PartialOrd,
+    {
+        let (mut min, mut max) = (self.min, self.max);
+        if N > 0 {
+            if min[0] == B::NINFTY {
+                min[0] = B::INFTY;
+            }
+            max[0] = B::NINFTY;
+        }
+        Self { min, max }
+    }
+}
+
+impl<B: Copy + PartialOrd, const N: usize> BoxND<B, N> {
+    /// Creates a new box like [`BoxND::new`], but returns an error if a bound is NaN
+    /// or a low boundary is greater than the corresponding high boundary.
+    pub fn try_new(min: [B; N], max: [B; N]) -> Result<Self, BoxError> {
+        let bbox = Self { min, max };
+        match bounds_problem(&bbox) {
+            Some(Problem::NaN { dim }) => Err(BoxError::NaN { dim }),
+            Some(Problem::Inverted { dim }) => Err(BoxError::Inverted { dim }),
+            _ => Ok(bbox),
+        }
+    }
+
+    /// Creates a new box like [`BoxND::new`], but swaps the boundaries in every dimension
+    /// where the low boundary is greater than the high boundary.
+    pub fn new_normalized(mut min: [B; N], mut max: [B; N]) -> Self {
+        for dim in 0..N {
+            if min[dim] > max[dim] {
+                std::mem::swap(&mut min[dim], &mut max[dim]);
+            }
+        }
+        Self { min, max }
+    }
+
+    /// Returns the smallest box containing both this box and `other`: the minimum of the low boundaries
+    /// and the maximum of the high boundaries in every dimension.
+    pub fn union(&self, other: &Self) -> Self {
+        let mut union = *self;
+        for dim in 0..N {
+            if other.min[dim] < union.min[dim] {
+                union.min[dim] = other.min[dim];
+            }
+            if other.max[dim] > union.max[dim] {
+                union.max[dim] = other.max[dim];
+            }
+        }
+        union
+    }
+
+    /// Returns the box where this box and `other` overlap: the maximum of the low boundaries
+    /// and the minimum of the high boundaries in every dimension.
+    /// Only meaningful if the boxes intersect.
+    pub fn overlap(&self, other: &Self) -> Self {
+        let mut overlap = *self;
+        for dim in 0..N {
+            if other.min[dim] > overlap.min[dim] {
+                overlap.min[dim] = other.min[dim];
+            }
+            if other.max[dim] < overlap.max[dim] {
+                overlap.max[dim] = other.max[dim];
+            }
+        }
+        overlap
+    }
+}
+
+impl<const N: usize> BoxND<f64, N> {
+    /// Returns this box relative to `origin`, with `f32` bounds. Subtracting the origin in `f64` first keeps
+    /// boxes far away from the coordinate origin (e.g. at planetary scale) precise, as long as they are close to `origin`.
+    /// Bounds are rounded outward, so the result contains the exact relative box: intersections are never lost,
+    /// but boxes closer than the `f32` precision may be reported as intersecting.
+    pub fn relative_to(&self, origin: [f64; N]) -> BoxND<f32, N> {
+        let mut min = [0.0; N];
+        let mut max = [0.0; N];
+        for dim in 0..N {
+            let (lo, hi) = (self.min[dim] - origin[dim], self.max[dim] - origin[dim]);
+            min[dim] = lo as f32;
+            if min[dim] as f64 > lo {
+                min[dim] = min[dim].next_down();
+            }
+            max[dim] = hi as f32;
+            if (max[dim] as f64) < hi {
+                max[dim] = max[dim].next_up();
+            }
+        }
+        BoxND { min, max }
+    }
+}
+
+impl<B, const N: usize> BoxND<B, N>
+where
+    B: Copy + Into<f64>,
+{
+    /// Returns the Euclidean distance from `point` to the closest point of the box, `0` if the box contains it.
+    /// The box is closed here, so a point on its high boundary has distance `0` too. Only meaningful for non-empty boxes.
+    pub fn distance_to(&self, point: [f64; N]) -> f64 {
+        let mut squared = 0.0;
+        for (dim, &p) in point.iter().enumerate() {
+            let (lo, hi) = (self.min[dim].into(), self.max[dim].into());
+            let gap = if p < lo {
+                lo - p
+            } else if p > hi {
+                p - hi
+            } else {
+                0.0
+            };
+            squared += gap * gap;
+        }
+        squared.sqrt()
+    }
+}
+
+impl<B, const N: usize> BoxND<B, N>
+where
+    B: Copy + Sub<Output = B>,
+{
+    /// Returns the extent (high minus low boundary) of the box in dimension `dim`.
+    pub fn extent(&self, dim: usize) -> B {
+        self.max[dim] - self.min[dim]
+    }
+}
+
+impl<B, const N: usize> BoxND<B, N>
+where
+    B: Copy + Sub<Output = B> + Mul<Output = B>,
+{
+    /// Returns the measure of the box: its length in 1D, area in 2D, volume in 3D and so on.
+    /// `N` must be at least 1.
+    pub fn measure(&self) -> B {
+        (1..N).fold(self.extent(0), |measure, dim| measure * self.extent(dim))
+    }
+}
+
+impl<B, const N: usize> BoxND<B, N>
+where
+    B: Copy + PartialOrd + Sub<Output = B>,
+{
+    /// Returns how deep this box and `other` penetrate each other along every dimension,
+    /// which is the extent of their [overlap](`BoxND::overlap`). Only meaningful if the boxes intersect.
+    pub fn penetration(&self, other: &Self) -> Penetration<B, N> {
+        let overlap = self.overlap(other);
+        let mut depth = overlap.min;
+        let mut axis = 0;
+        for dim in 0..N {
+            depth[dim] = overlap.extent(dim);
+            if depth[dim] < depth[axis] {
+                axis = dim;
+            }
+        }
+        Penetration { depth, axis }
+    }
+}
+
+/// Penetration depths of two intersecting boxes, see [`BoxND::penetration`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Penetration<B, const N: usize> {
+    /// Penetration depth along each dimension
+    pub depth: [B; N],
+    /// The dimension with the smallest penetration depth, along which the boxes
+    /// can be separated by the shortest translation
+    pub axis: usize,
 }
 
 impl<B, const N: usize> BBox for BoxND<B, N>
@@ -64,6 +277,87 @@ where
     }
 }
 
+/// A box with its own margin, which is added to its bounds on both sides in every dimension
+/// whenever it is compared. Useful for speculative contacts, e.g. giving fast-moving bodies larger margins,
+/// without having to store inflated copies of the boxes.
+#[derive(Clone, Copy, Debug)]
+pub struct Inflated<B: BBox> {
+    /// The box without margin
+    pub bbox: B,
+    /// The margin added to the bounds of `bbox`
+    pub margin: B::Num,
+}
+
+impl<B: BBox> Inflated<B> {
+    /// Creates a box inflated by `margin`.
+    pub fn new(bbox: B, margin: B::Num) -> Self {
+        Self { bbox, margin }
+    }
+}
+
+impl<B> BBox for Inflated<B>
+where
+    B: BBox,
+    B::Num: Add<Output = B::Num> + Sub<Output = B::Num>,
+{
+    const DIM: usize = B::DIM;
+    type Num = B::Num;
+
+    fn lo(&self, dim: usize) -> Self::Num {
+        self.bbox.lo(dim) - self.margin
+    }
+
+    fn hi(&self, dim: usize) -> Self::Num {
+        self.bbox.hi(dim) + self.margin
+    }
+}
+
+/// References to boxes are boxes too, so large box types or boxes owned elsewhere
+/// don't have to be copied into a [`BBoxSet`](crate::set::BBoxSet), see [`ref_set`](crate::slice::ref_set).
+impl<B: BBox> BBox for &B {
+    const DIM: usize = B::DIM;
+    type Num = B::Num;
+
+    fn lo(&self, dim: usize) -> Self::Num {
+        (*self).lo(dim)
+    }
+
+    fn hi(&self, dim: usize) -> Self::Num {
+        (*self).hi(dim)
+    }
+}
+
+/// A 1-dimensional box: the half-open interval [`lo`, `hi`), e.g. a span of time or a region of a chromosome.
+/// Works with all algorithms like any other box, but with no dummy dimensions to compare,
+/// see [`IntervalTree`](crate::interval::IntervalTree) for repeated queries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Interval<B> {
+    /// The low boundary
+    pub lo: B,
+    /// The (exclusive) high boundary
+    pub hi: B,
+}
+
+impl<B> Interval<B> {
+    /// Creates the interval [`lo`, `hi`).
+    pub fn new(lo: B, hi: B) -> Self {
+        Self { lo, hi }
+    }
+}
+
+impl<B: Copy + PartialOrd> BBox for Interval<B> {
+    const DIM: usize = 1;
+    type Num = B;
+
+    fn lo(&self, _dim: usize) -> Self::Num {
+        self.lo
+    }
+
+    fn hi(&self, _dim: usize) -> Self::Num {
+        self.hi
+    }
+}
+
 /// A 2-dimensional box with generic bounds of type `B`
 pub type Box2D<B> = BoxND<B, 2>;
 /// A 2-dimensional box with bounds of type `f32`
@@ -78,6 +372,13 @@ pub type Box3Df32 = Box3D<f32>;
 /// A 3-dimensional box with bounds of type `f64`
 pub type Box3Df64 = Box3D<f64>;
 
+/// A 4-dimensional box with generic bounds of type `B`, e.g. a spacetime box (x, y, z, t)
+pub type Box4D<B> = BoxND<B, 4>;
+/// A 4-dimensional box with bounds of type `f32`
+pub type Box4Df32 = Box4D<f32>;
+/// A 4-dimensional box with bounds of type `f64`
+pub type Box4Df64 = Box4D<f64>;
+
 #[test]
 fn intersect() {
     let box0 = Box3Df32::new([0.0, 0.0, 0.0], [10.0, 10.0, 10.0]);
@@ -98,4 +399,40 @@ fn intersect() {
 
     assert!(!box0.intersects(&box3));
     assert!(!box3.intersects(&box0));
+
+    assert!(box0.contains(&box0));
+    assert!(box0.contains(&Box3Df32::new([1.0; 3], [10.0; 3])));
+    assert!(!box0.contains(&box1) && !box1.contains(&box0));
+
+    assert!(Inflated::new(box0, 0.5).intersects(&Inflated::new(box2, 0.0)));
+    assert!(!Inflated::new(box0, 0.0).intersects(&Inflated::new(box2, 0.0)));
+
+    let overlap = box0.overlap(&box1);
+    assert_eq!((overlap.min(), overlap.max()), ([5.0; 3], [10.0; 3]));
+    assert!(box1.overlap(&box0).coincides(&overlap));
+    assert_eq!(overlap.measure(), 125.0);
+
+    let penetration = box1.penetration(&Box3Df32::new([8.0, 2.0, 4.0], [20.0, 20.0, 9.0]));
+    assert_eq!(penetration.depth, [7.0, 10.0, 4.0]);
+    assert_eq!(penetration.axis, 2);
+}
+
+#[test]
+fn construct() {
+    let bbox = Box2Df32::try_new([0.0, 1.0], [2.0, 1.0]).unwrap();
+    assert!(bbox.coincides(&Box2Df32::new([0.0, 1.0], [2.0, 1.0])));
+    assert_eq!(
+        Box2Df32::try_new([0.0, 3.0], [2.0, 1.0]).unwrap_err(),
+        BoxError::Inverted { dim: 1 }
+    );
+    assert_eq!(
+        Box2Df32::try_new([f32::NAN, 0.0], [2.0, 1.0]).unwrap_err(),
+        BoxError::NaN { dim: 0 }
+    );
+
+    let normalized = Box2Df32::new_normalized([0.0, 3.0], [2.0, 1.0]);
+    assert_eq!(
+        (normalized.min(), normalized.max()),
+        ([0.0, 1.0], [2.0, 3.0])
+    );
 }
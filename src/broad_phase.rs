@@ -0,0 +1,208 @@
+//! Persistent broad phase reporting which pairs start and stop intersecting, see [`BroadPhase`],
+//! and [`PairTracker`] for the same on top of any of the algorithms.
+//!
+//! # Examples
+//! ```
+//! use box_intersect_ze::boxes::Box2Df32;
+//! use box_intersect_ze::broad_phase::BroadPhase;
+//! use rand_chacha::ChaCha8Rng;
+//! use rand::SeedableRng;
+//!
+//! let mut broad_phase = BroadPhase::new(ChaCha8Rng::seed_from_u64(1234));
+//! broad_phase.insert(0, Box2Df32::new([0.0, 0.0], [1.0, 1.0]));
+//! broad_phase.insert(1, Box2Df32::new([0.5, 0.5], [1.5, 1.5]));
+//! let delta = broad_phase.step();
+//! assert_eq!(delta.added, [(0, 1)]);
+//!
+//! broad_phase.update(1, Box2Df32::new([0.6, 0.6], [1.6, 1.6]));
+//! let delta = broad_phase.step();
+//! assert_eq!(delta.persisted, [(0, 1)]);
+//!
+//! broad_phase.update(1, Box2Df32::new([2.0, 2.0], [3.0, 3.0]));
+//! let delta = broad_phase.step();
+//! assert_eq!(delta.removed, [(0, 1)]);
+//! ```
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::boxes::BBox;
+use crate::set::BBoxSet;
+use crate::{intersect_ze, HasInfinity, Rng};
+
+/// Pairs of identifiers of boxes, by how they changed from one frame to the next, see [`PairTracker`].
+/// The smaller identifier of each pair comes first, and each list is sorted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PairDelta<ID> {
+    /// Pairs that intersect now, but didn't in the previous frame
+    pub added: Vec<(ID, ID)>,
+    /// Pairs that intersected in the previous frame, but don't now,
+    /// including pairs with boxes that were removed since
+    pub removed: Vec<(ID, ID)>,
+    /// Pairs that intersected in the previous frame and still do, e.g. to keep their cached contacts
+    pub persisted: Vec<(ID, ID)>,
+}
+
+/// Compares the pairs of intersecting boxes found in each frame with those of the previous frame,
+/// in *O*(*n* log *n*) for *n* pairs, by sorting them instead of hashing.
+/// Use it with any of the algorithms, e.g. [`intersect_ze`] reporting to a `Vec<(ID, ID)>`.
+#[derive(Clone, Debug)]
+pub struct PairTracker<ID> {
+    /// Pairs of the previous frame, normalized and sorted
+    previous: Vec<(ID, ID)>,
+}
+
+impl<ID: Copy + Ord> PairTracker<ID> {
+    /// Creates a tracker with no pairs in the previous frame.
+    pub fn new() -> Self {
+        Self {
+            previous: Vec::new(),
+        }
+    }
+
+    /// Returns the pairs of the previous frame, with the smaller identifier of each pair first, sorted.
+    pub fn previous(&self) -> &[(ID, ID)] {
+        &self.previous
+    }
+
+    /// Takes the pairs of this frame, in any order and with the identifiers of each pair in any order,
+    /// and returns how they differ from those of the previous frame. They are kept for the next frame.
+    pub fn update(&mut self, mut pairs: Vec<(ID, ID)>) -> PairDelta<ID> {
+        for pair in &mut pairs {
+            if pair.1 < pair.0 {
+                *pair = (pair.1, pair.0);
+            }
+        }
+        pairs.sort_unstable();
+        pairs.dedup();
+
+        let mut delta = PairDelta {
+            added: Vec::new(),
+            removed: Vec::new(),
+            persisted: Vec::with_capacity(pairs.len().min(self.previous.len())),
+        };
+        let (mut previous, mut current) =
+            (self.previous.iter().peekable(), pairs.iter().peekable());
+        loop {
+            match (previous.peek(), current.peek()) {
+                (Some(&&old), Some(&&new)) => match old.cmp(&new) {
+                    Ordering::Less => {
+                        delta.removed.push(old);
+                        previous.next();
+                    }
+                    Ordering::Greater => {
+                        delta.added.push(new);
+                        current.next();
+                    }
+                    Ordering::Equal => {
+                        delta.persisted.push(new);
+                        previous.next();
+                        current.next();
+                    }
+                },
+                (Some(_), None) => delta.removed.extend(previous.by_ref()),
+                (None, Some(_)) => delta.added.extend(current.by_ref()),
+                (None, None) => break,
+            }
+        }
+        self.previous = pairs;
+        delta
+    }
+}
+
+impl<ID: Copy + Ord> Default for PairTracker<ID> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A broad phase that owns its boxes, so they can be added, removed and moved between steps,
+/// and keeps the pairs of intersecting boxes found at the previous step, so [`BroadPhase::step`] can report
+/// which pairs were added and removed since (see [`PairTracker`]). This is the bookkeeping physics engines
+/// and simulations need on top of [`intersect_ze`], which every step runs on the boxes, re-sorted incrementally
+/// (see [`BBoxSet::resort_incremental`]).
+pub struct BroadPhase<B: BBox, ID, R> {
+    boxes: BBoxSet<B, ID>,
+    /// Index of the box with each identifier in `boxes`
+    index: HashMap<ID, usize>,
+    pairs: PairTracker<ID>,
+    rand: R,
+}
+
+impl<B, ID, R> BroadPhase<B, ID, R>
+where
+    B: BBox,
+    B::Num: PartialOrd + HasInfinity,
+    ID: Copy + Ord + Hash,
+    R: Rng,
+{
+    /// Creates an empty broad phase, using `rand` for [`intersect_ze`].
+    pub fn new(rand: R) -> Self {
+        Self {
+            boxes: BBoxSet::new(),
+            index: HashMap::new(),
+            pairs: PairTracker::new(),
+            rand,
+        }
+    }
+
+    /// Returns the number of boxes.
+    pub fn len(&self) -> usize {
+        self.boxes.len()
+    }
+
+    /// Returns `true` if there are no boxes.
+    pub fn is_empty(&self) -> bool {
+        self.boxes.is_empty()
+    }
+
+    /// Returns the box with identifier `id`, if there is one.
+    pub fn get(&self, id: ID) -> Option<B> {
+        let idx = *self.index.get(&id)?;
+        Some(self.boxes.boxes()[idx].0)
+    }
+
+    /// Adds a box with identifier `id`, or replaces the box with this identifier if there already is one.
+    pub fn insert(&mut self, id: ID, bbox: B) {
+        if self.update(id, bbox).is_none() {
+            self.index.insert(id, self.boxes.len());
+            self.boxes.push(id, bbox);
+        }
+    }
+
+    /// Replaces the box with identifier `id` with `bbox` and returns the old box,
+    /// or [`None`] if there is no box with this identifier.
+    pub fn update(&mut self, id: ID, bbox: B) -> Option<B> {
+        let idx = *self.index.get(&id)?;
+        Some(std::mem::replace(&mut self.boxes.boxes_mut()[idx].0, bbox))
+    }
+
+    /// Removes the box with identifier `id` and returns it, or [`None`] if there is no box with this identifier.
+    /// Its pairs are reported as removed by the next step.
+    pub fn remove(&mut self, id: ID) -> Option<B> {
+        let idx = self.index.remove(&id)?;
+        let (bbox, _id) = self.boxes.boxes_mut().swap_remove(idx);
+        if let Some(&(_bbox, moved)) = self.boxes.boxes().get(idx) {
+            self.index.insert(moved, idx);
+        }
+        Some(bbox)
+    }
+
+    /// Returns the pairs of identifiers of boxes that intersected at the previous step,
+    /// with the smaller identifier first, sorted.
+    pub fn pairs(&self) -> &[(ID, ID)] {
+        self.pairs.previous()
+    }
+
+    /// Finds the pairs of intersecting boxes and returns how they changed since the previous step.
+    pub fn step(&mut self) -> PairDelta<ID> {
+        self.boxes.resort_incremental();
+        for (idx, &(_bbox, id)) in self.boxes.boxes().iter().enumerate() {
+            self.index.insert(id, idx);
+        }
+        let mut pairs = Vec::with_capacity(self.pairs.previous().len());
+        intersect_ze(&self.boxes, &self.boxes, &mut pairs, &mut self.rand);
+        self.pairs.update(pairs)
+    }
+}
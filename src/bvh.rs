@@ -0,0 +1,572 @@
+//! Bounding volume hierarchy over a set of boxes that is built once and queried many times, see [`Bvh`].
+//!
+//! # Examples
+//! ```
+//! use box_intersect_ze::boxes::Box2Df32;
+//! use box_intersect_ze::bvh::{Bvh, Ray, Split};
+//! use box_intersect_ze::set::BBoxSet;
+//!
+//! let mut level = BBoxSet::new();
+//! level.push(0, Box2Df32::new([0.0, 0.0], [1.0, 1.0]));
+//! level.push(1, Box2Df32::new([4.0, 0.0], [5.0, 1.0]));
+//! let bvh = Bvh::build(&level, Split::Sah);
+//!
+//! let mut hits = Vec::new();
+//! bvh.query(&Box2Df32::new([0.5, 0.5], [2.0, 2.0]), |id, _bbox| hits.push(id));
+//! assert_eq!(hits, [0]);
+//!
+//! let mut hits = Vec::new();
+//! bvh.ray(&Ray::new([-1.0, 0.5], [1.0, 0.0]), 10.0, |id, t| hits.push((id, t)));
+//! hits.sort_by(|a, b| a.1.total_cmp(&b.1));
+//! assert_eq!(hits, [(0, 1.0), (1, 5.0)]);
+//! ```
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use crate::boxes::{BBox, BoxND};
+use crate::region::{self, HalfSpace};
+use crate::sink::Sink;
+use crate::source::BoxSource;
+use crate::HasInfinity;
+
+/// Most boxes in a leaf
+const LEAF_SIZE: usize = 4;
+
+/// How [`Bvh::build`] divides the boxes of a node between its children
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Split {
+    /// Minimize the surface area heuristic: the sum of the surface areas of the children,
+    /// weighted by their number of boxes. Builds slower, but queries visit fewer nodes.
+    Sah,
+    /// Split at the median center along the dimension in which the centers spread the most.
+    /// Builds fast and always balanced.
+    Median,
+}
+
+/// A ray starting at `origin` and pointing along `direction`, see [`Bvh::ray`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ray<const D: usize> {
+    pub origin: [f64; D],
+    /// Need not be normalized, distances along the ray are measured in multiples of it
+    pub direction: [f64; D],
+}
+
+impl<const D: usize> Ray<D> {
+    /// Creates a new ray.
+    pub fn new(origin: [f64; D], direction: [f64; D]) -> Self {
+        Self { origin, direction }
+    }
+
+    /// Returns the distance along the ray, between `0` and `max`, at which it enters `bbox`,
+    /// or [`None`] if it misses it. Boxes are closed here, so grazing a boundary counts as a hit,
+    /// and the distance is `0` if the ray starts inside the box. Empty boxes are never hit.
+    pub fn entry<N>(&self, bbox: &BoxND<N, D>, max: f64) -> Option<f64>
+    where
+        N: Copy + PartialOrd + Into<f64>,
+    {
+        self.entry_between(bbox, 0.0, max)
+    }
+
+    /// Like [`Ray::entry`], but only for the part of the ray between the distances `min` and `max`,
+    /// e.g. to skip the box the ray starts from. The distance is `min` if the ray is inside the box there.
+    pub fn entry_between<N>(&self, bbox: &BoxND<N, D>, min: f64, max: f64) -> Option<f64>
+    where
+        N: Copy + PartialOrd + Into<f64>,
+    {
+        if bbox.is_empty() {
+            return None;
+        }
+        let (mut enter, mut exit) = (min, max);
+        for dim in 0..D {
+            let (lo, hi) = (bbox.lo(dim).into(), bbox.hi(dim).into());
+            let (origin, v) = (self.origin[dim], self.direction[dim]);
+            if v == 0.0 {
+                if origin < lo || origin > hi {
+                    return None;
+                }
+                continue;
+            }
+            let t0 = (lo - origin) / v;
+            let t1 = (hi - origin) / v;
+            let (t0, t1) = if v > 0.0 { (t0, t1) } else { (t1, t0) };
+            if t0 > enter {
+                enter = t0;
+            }
+            if t1 < exit {
+                exit = t1;
+            }
+            if enter > exit {
+                return None;
+            }
+        }
+        Some(enter)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Children {
+    /// The boxes of a leaf are `items[start..end]`
+    Leaf {
+        start: usize,
+        end: usize,
+    },
+    Inner {
+        left: usize,
+        right: usize,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Node<N, const D: usize> {
+    /// Bounds of all non-empty boxes below this node
+    bounds: BoxND<N, D>,
+    children: Children,
+}
+
+/// A binary tree of boxes, each node storing the bounds of the boxes below it, for repeated queries
+/// against the same set of boxes, e.g. static level geometry queried by moving objects.
+/// [`intersect_ze`](crate::intersect_ze) needs no such structure, but does all its work anew on every call.
+/// * Query it with a single box ([`Bvh::query`]), a ray ([`Bvh::ray`]) or a point ([`Bvh::nearest`]), or find the pairs of intersecting boxes
+///   within the tree ([`Bvh::pairs`]) or with the boxes of another tree ([`Bvh::pairs_with`]).
+/// * After small movements, [`Bvh::refit`] updates the bounds without rebuilding the tree.
+///
+/// Like the other algorithms, the projections of boxes are half-open intervals and empty boxes
+/// (see [`BBox::is_empty`]) never intersect. Bounds must not be NaN.
+#[derive(Clone, Debug)]
+pub struct Bvh<N, ID, const D: usize> {
+    /// Nodes in pre-order, root first, so every node comes before its children
+    nodes: Vec<Node<N, D>>,
+    /// Boxes and their identifiers, grouped by leaf
+    items: Vec<(BoxND<N, D>, ID)>,
+}
+
+impl<N, ID, const D: usize> Bvh<N, ID, D>
+where
+    N: Copy + PartialOrd + HasInfinity + Into<f64>,
+    ID: Copy,
+{
+    /// Builds a tree over the boxes of `boxes`, which need not be sorted, dividing them according to `split`.
+    /// Empty boxes are left out.
+    pub fn build(boxes: &impl BoxSource<BoxND<N, D>, ID>, split: Split) -> Self {
+        let mut items: Vec<_> = (0..boxes.len())
+            .map(|idx| boxes.get(idx))
+            .filter(|(bbox, _id)| !bbox.is_empty())
+            .collect();
+        let mut nodes = Vec::new();
+        if !items.is_empty() {
+            nodes.push(Node {
+                bounds: bounds_of(&items),
+                children: Children::Leaf {
+                    start: 0,
+                    end: items.len(),
+                },
+            });
+        }
+        // split leaves until they are small enough, without recursion since SAH trees may be deep
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let (start, end) = match nodes.get(node).map(|node: &Node<N, D>| node.children) {
+                Some(Children::Leaf { start, end }) if end - start > LEAF_SIZE => (start, end),
+                _ => continue,
+            };
+            let range = &mut items[start..end];
+            let mid = start
+                + match split {
+                    Split::Sah => sah_split(range),
+                    Split::Median => median_split(range),
+                };
+            let left = nodes.len();
+            for (start, end) in [(start, mid), (mid, end)] {
+                nodes.push(Node {
+                    bounds: bounds_of(&items[start..end]),
+                    children: Children::Leaf { start, end },
+                });
+            }
+            nodes[node].children = Children::Inner {
+                left,
+                right: left + 1,
+            };
+            stack.extend([left, left + 1]);
+        }
+        Self { nodes, items }
+    }
+
+    /// Returns the number of boxes in the tree.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns `true` if there are no boxes in the tree.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Calls `hit` with the identifier and the box of each box intersecting `bbox`, in no particular order.
+    pub fn query(&self, bbox: &BoxND<N, D>, mut hit: impl FnMut(ID, &BoxND<N, D>)) {
+        if bbox.is_empty() {
+            return;
+        }
+        self.traverse(
+            |bounds| bounds.intersects(bbox),
+            |(other, id)| {
+                if other.intersects(bbox) && !other.is_empty() {
+                    hit(*id, other);
+                }
+            },
+        );
+    }
+
+    /// Calls `hit` with the identifier of each box that `ray` hits within distance `max`
+    /// and the distance at which it enters the box (see [`Ray::entry`]), in no particular order.
+    pub fn ray(&self, ray: &Ray<D>, max: f64, mut hit: impl FnMut(ID, f64)) {
+        self.traverse(
+            |bounds| ray.entry(bounds, max).is_some(),
+            |(bbox, id)| {
+                if let Some(t) = ray.entry(bbox, max) {
+                    hit(*id, t);
+                }
+            },
+        );
+    }
+
+    /// Calls `hit` with the identifier and the box of each box that may intersect the convex region
+    /// where all `half_spaces` overlap (see [`region::intersects`]), in no particular order.
+    /// Subtrees whose bounds lie outside any of the half-spaces are skipped, e.g. for frustum culling.
+    pub fn query_region(
+        &self,
+        half_spaces: &[HalfSpace<D>],
+        mut hit: impl FnMut(ID, &BoxND<N, D>),
+    ) {
+        self.traverse(
+            |bounds| region::intersects(half_spaces, bounds),
+            |(bbox, id)| {
+                if region::intersects(half_spaces, bbox) {
+                    hit(*id, bbox);
+                }
+            },
+        );
+    }
+
+    /// Appends the identifier of each box that `ray` hits between the distances `min` and `max`
+    /// to `out`, with the distance at which it enters the box (see [`Ray::entry_between`]),
+    /// ordered by that distance, nearest first.
+    pub fn query_ray(&self, ray: &Ray<D>, min: f64, max: f64, out: &mut Vec<(ID, f64)>) {
+        let start = out.len();
+        self.traverse(
+            |bounds| ray.entry_between(bounds, min, max).is_some(),
+            |(bbox, id)| {
+                if let Some(t) = ray.entry_between(bbox, min, max) {
+                    out.push((*id, t));
+                }
+            },
+        );
+        sort_by_entry(&mut out[start..]);
+    }
+
+    /// Returns the identifier of the box nearest to `point` and its distance (see [`BoxND::distance_to`]),
+    /// or [`None`] if the tree has no non-empty boxes. Of several boxes at the same distance, returns any one.
+    /// Searches best-first: visits nodes in order of the distance of their bounds, and stops once
+    /// no node left can hold a nearer box.
+    pub fn nearest(&self, point: [f64; D]) -> Option<(ID, f64)> {
+        let mut best: Option<(ID, f64)> = None;
+        let mut heap = BinaryHeap::new();
+        if let Some(root) = self.nodes.first() {
+            if !root.bounds.is_empty() {
+                heap.push(Candidate {
+                    distance: root.bounds.distance_to(point),
+                    item: 0,
+                });
+            }
+        }
+        while let Some(Candidate { distance, item }) = heap.pop() {
+            if matches!(best, Some((_, best)) if best <= distance) {
+                break;
+            }
+            match self.nodes[item].children {
+                Children::Leaf { start, end } => {
+                    for (bbox, id) in &self.items[start..end] {
+                        if bbox.is_empty() {
+                            continue;
+                        }
+                        let distance = bbox.distance_to(point);
+                        if !matches!(best, Some((_, best)) if best <= distance) {
+                            best = Some((*id, distance));
+                        }
+                    }
+                }
+                Children::Inner { left, right } => {
+                    for child in [left, right] {
+                        let bounds = &self.nodes[child].bounds;
+                        if !bounds.is_empty() {
+                            heap.push(Candidate {
+                                distance: bounds.distance_to(point),
+                                item: child,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        best
+    }
+
+    /// Reports each pair of intersecting boxes in the tree to `out` once.
+    pub fn pairs(&self, out: &mut impl Sink<BoxND<N, D>, ID>) {
+        self.pairs_in(self, true, out);
+    }
+
+    /// Reports each pair of a box of this tree and an intersecting box of `other` to `out`,
+    /// the box of this tree first.
+    pub fn pairs_with(&self, other: &Self, out: &mut impl Sink<BoxND<N, D>, ID>) {
+        self.pairs_in(other, false, out);
+    }
+
+    /// Replaces each box with `bbox_of` its identifier and recomputes the bounds of the nodes,
+    /// keeping the structure of the tree. Much faster than rebuilding, but queries slow down
+    /// as boxes move further from where they were when the tree was built, so rebuild it now and then.
+    pub fn refit(&mut self, mut bbox_of: impl FnMut(ID) -> BoxND<N, D>) {
+        for (bbox, id) in &mut self.items {
+            *bbox = bbox_of(*id);
+        }
+        // children come after their parents
+        for idx in (0..self.nodes.len()).rev() {
+            self.nodes[idx].bounds = match self.nodes[idx].children {
+                Children::Leaf { start, end } => bounds_of(&self.items[start..end]),
+                Children::Inner { left, right } => {
+                    self.nodes[left].bounds.union(&self.nodes[right].bounds)
+                }
+            };
+        }
+    }
+
+    /// Visits the leaves of the nodes whose bounds pass `enter`, calling `leaf` with each of their boxes.
+    fn traverse(
+        &self,
+        enter: impl Fn(&BoxND<N, D>) -> bool,
+        mut leaf: impl FnMut(&(BoxND<N, D>, ID)),
+    ) {
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            if !enter(&node.bounds) {
+                continue;
+            }
+            match node.children {
+                Children::Leaf { start, end } => self.items[start..end].iter().for_each(&mut leaf),
+                Children::Inner { left, right } => stack.extend([right, left]),
+            }
+        }
+    }
+
+    /// Descends this tree and `other` at once, from pairs of nodes with intersecting bounds to their children.
+    /// If `same`, `other` is this tree, and pairs of a node with itself yield each pair of its boxes once.
+    fn pairs_in(&self, other: &Self, same: bool, out: &mut impl Sink<BoxND<N, D>, ID>) {
+        let mut report = |(a, a_id): &(BoxND<N, D>, ID), (b, b_id): &(BoxND<N, D>, ID)| {
+            if a.intersects(b) && !a.is_empty() && !b.is_empty() {
+                out.report(*a_id, a, *b_id, b);
+            }
+        };
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() && !other.nodes.is_empty() {
+            stack.push((0, 0));
+        }
+        while let Some((a, b)) = stack.pop() {
+            let (node_a, node_b) = (&self.nodes[a], &other.nodes[b]);
+            if same && a == b {
+                match node_a.children {
+                    Children::Leaf { start, end } => {
+                        for i in start..end {
+                            for j in i + 1..end {
+                                report(&self.items[i], &self.items[j]);
+                            }
+                        }
+                    }
+                    Children::Inner { left, right } => {
+                        stack.extend([(left, left), (right, right), (left, right)])
+                    }
+                }
+                continue;
+            }
+            if !node_a.bounds.intersects(&node_b.bounds) {
+                continue;
+            }
+            match (node_a.children, node_b.children) {
+                (
+                    Children::Leaf { start, end },
+                    Children::Leaf {
+                        start: b_start,
+                        end: b_end,
+                    },
+                ) => {
+                    for item in &self.items[start..end] {
+                        for other_item in &other.items[b_start..b_end] {
+                            report(item, other_item);
+                        }
+                    }
+                }
+                // descend the larger node first, so the bounds of the pairs shrink quickly
+                (Children::Inner { left, right }, Children::Inner { .. })
+                    if half_area(&node_a.bounds) >= half_area(&node_b.bounds) =>
+                {
+                    stack.extend([(left, b), (right, b)])
+                }
+                (Children::Inner { left, right }, Children::Leaf { .. }) => {
+                    stack.extend([(left, b), (right, b)])
+                }
+                (_, Children::Inner { left, right }) => stack.extend([(a, left), (a, right)]),
+            }
+        }
+    }
+}
+
+/// A node or box of a best-first search, ordered so that a [`BinaryHeap`] pops the nearest one first
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Candidate<T> {
+    pub(crate) distance: f64,
+    pub(crate) item: T,
+}
+
+impl<T> PartialEq for Candidate<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for Candidate<T> {}
+
+impl<T> PartialOrd for Candidate<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Candidate<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.distance.total_cmp(&self.distance)
+    }
+}
+
+/// Sorts ray hits by the distance at which the ray enters the boxes, keeping the order of hits at equal distances.
+pub(crate) fn sort_by_entry<ID>(hits: &mut [(ID, f64)]) {
+    hits.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+}
+
+/// Returns the bounds of the non-empty boxes of `items`, an empty box if there are none.
+fn bounds_of<N, ID, const D: usize>(items: &[(BoxND<N, D>, ID)]) -> BoxND<N, D>
+where
+    N: Copy + PartialOrd + HasInfinity,
+{
+    let nothing = BoxND::new([N::INFTY; D], [N::NINFTY; D]);
+    items
+        .iter()
+        .filter(|(bbox, _id)| !bbox.is_empty())
+        .fold(nothing, |bounds, (bbox, _id)| bounds.union(bbox))
+}
+
+/// Returns half the surface area of `bbox`, or its length in 1D. Zero for empty boxes.
+fn half_area<N, const D: usize>(bbox: &BoxND<N, D>) -> f64
+where
+    N: Copy + PartialOrd + Into<f64>,
+{
+    if bbox.is_empty() {
+        return 0.0;
+    }
+    let extent = |dim| (bbox.hi(dim).into() - bbox.lo(dim).into()).max(0.0);
+    if D == 1 {
+        return extent(0);
+    }
+    (0..D)
+        .map(|face| {
+            (0..D)
+                .filter(|&dim| dim != face)
+                .map(extent)
+                .product::<f64>()
+        })
+        .sum()
+}
+
+fn center<N, const D: usize>(bbox: &BoxND<N, D>, dim: usize) -> f64
+where
+    N: Copy + PartialOrd + Into<f64>,
+{
+    bbox.lo(dim).into() / 2.0 + bbox.hi(dim).into() / 2.0
+}
+
+fn sort_by_center<N, ID, const D: usize>(items: &mut [(BoxND<N, D>, ID)], dim: usize)
+where
+    N: Copy + PartialOrd + Into<f64>,
+{
+    items.sort_unstable_by(|(a, _), (b, _)| center(a, dim).total_cmp(&center(b, dim)));
+}
+
+/// Reorders `items` and returns the index at which to split them, see [`Split::Median`].
+fn median_split<N, ID, const D: usize>(items: &mut [(BoxND<N, D>, ID)]) -> usize
+where
+    N: Copy + PartialOrd + Into<f64>,
+{
+    let spread = |dim| {
+        let centers = items.iter().map(|(bbox, _id)| center(bbox, dim));
+        let (min, max) = centers.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), c| {
+            (min.min(c), max.max(c))
+        });
+        max - min
+    };
+    let dim = (0..D)
+        .max_by(|&a, &b| spread(a).total_cmp(&spread(b)))
+        .unwrap_or(0);
+    let mid = items.len() / 2;
+    items.select_nth_unstable_by(mid, |(a, _), (b, _)| {
+        center(a, dim).total_cmp(&center(b, dim))
+    });
+    mid
+}
+
+/// Reorders `items` and returns the index at which to split them, see [`Split::Sah`].
+/// Tries every split of the boxes sorted by their centers in every dimension.
+fn sah_split<N, ID, const D: usize>(items: &mut [(BoxND<N, D>, ID)]) -> usize
+where
+    N: Copy + PartialOrd + HasInfinity + Into<f64>,
+{
+    let n = items.len();
+    // cost, distance from the middle to prefer balanced splits among equal ones, dimension, index
+    let mut best: Option<(f64, usize, usize, usize)> = None;
+    let mut right_areas = vec![0.0; n];
+    for dim in 0..D {
+        sort_by_center(items, dim);
+        let mut right = BoxND::new([N::INFTY; D], [N::NINFTY; D]);
+        for idx in (1..n).rev() {
+            if !items[idx].0.is_empty() {
+                right = right.union(&items[idx].0);
+            }
+            right_areas[idx] = half_area(&right);
+        }
+        let mut left = BoxND::new([N::INFTY; D], [N::NINFTY; D]);
+        for idx in 1..n {
+            if !items[idx - 1].0.is_empty() {
+                left = left.union(&items[idx - 1].0);
+            }
+            let cost = half_area(&left) * idx as f64 + right_areas[idx] * (n - idx) as f64;
+            let candidate = (cost, idx.max(n / 2) - idx.min(n / 2), dim, idx);
+            // infinite or NaN costs (boxes with infinite bounds) are never better
+            let better = match best {
+                _ if !cost.is_finite() => false,
+                None => true,
+                Some(best) => (candidate.0, candidate.1) < (best.0, best.1),
+            };
+            if better {
+                best = Some(candidate);
+            }
+        }
+    }
+    match best {
+        Some((_cost, _balance, dim, idx)) => {
+            sort_by_center(items, dim);
+            idx
+        }
+        None => median_split(items),
+    }
+}
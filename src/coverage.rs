@@ -0,0 +1,288 @@
+//! How a set of boxes covers space, rather than which pairs intersect, see [`max_depth`] and [`union_measure`].
+//!
+//! # Examples
+//! ```
+//! use box_intersect_ze::boxes::Box2Df32;
+//! use box_intersect_ze::coverage::{max_depth, union_measure};
+//! use box_intersect_ze::set::BBoxSet;
+//!
+//! let mut boxes = BBoxSet::new();
+//! boxes.push(0, Box2Df32::new([0.0, 0.0], [4.0, 4.0]));
+//! boxes.push(1, Box2Df32::new([2.0, 2.0], [6.0, 6.0]));
+//! boxes.push(2, Box2Df32::new([3.0, 0.0], [5.0, 3.0]));
+//! // touches box 2, but doesn't overlap it
+//! boxes.push(3, Box2Df32::new([5.0, 0.0], [9.0, 1.0]));
+//! assert_eq!(max_depth(&boxes), 3);
+//! assert_eq!(union_measure(&boxes), 16.0 + 12.0 + 2.0 + 4.0);
+//! ```
+
+use std::cmp::Ordering;
+
+use crate::boxes::{BBox, BoxND};
+use crate::source::BoxSource;
+use crate::HasInfinity;
+
+/// Returns the maximum number of boxes of `boxes` containing any single point, the stabbing depth of the set,
+/// e.g. to detect pathological pile-ups, or as an upper bound for how many boxes a single box can intersect
+/// at a point. Boxes are half-open, so boxes that only touch don't stack, and boxes with zero extent in any
+/// dimension contain no point at all. `boxes` need not be sorted.
+///
+/// Sweeps over the low boundaries in dimension 0, and for each box, finds the deepest point in it among the boxes
+/// overlapping it by sweeping the next dimension, and so on, skipping every box that can't beat the deepest point
+/// found so far. Takes about as long as [`intersect_scan`](crate::intersect_scan) for sparse boxes,
+/// and *O*(*n*² log *n*) for *n* boxes all piled up at one place.
+pub fn max_depth<B, ID>(boxes: &impl BoxSource<B, ID>) -> usize
+where
+    B: BBox,
+    B::Num: HasInfinity,
+{
+    let mut items: Vec<_> = (0..boxes.len())
+        .map(|idx| boxes.get(idx).0)
+        .filter(|bbox| (0..B::DIM).all(|dim| bbox.lo(dim) < bbox.hi(dim)))
+        .collect();
+    let mut region = vec![(B::Num::NINFTY, B::Num::INFTY); B::DIM];
+    let mut best = 0;
+    if B::DIM > 0 {
+        deepest(&mut items, &mut region, 0, 0, &mut best);
+    }
+    best
+}
+
+/// Raises `best` to `above` plus the most boxes of `boxes` containing a common point of `region`,
+/// looking at dimensions `dim..` only. Every box of `boxes` overlaps `region`.
+fn deepest<B>(
+    boxes: &mut [B],
+    region: &mut [(B::Num, B::Num)],
+    dim: usize,
+    above: usize,
+    best: &mut usize,
+) where
+    B: BBox,
+{
+    if above + boxes.len() <= *best {
+        return;
+    }
+    // the boxes above have a common point in `region`
+    *best = (*best).max(above);
+    let (region_lo, region_hi) = region[dim];
+    let lo = |bbox: &B| max(bbox.lo(dim), region_lo);
+    let hi = |bbox: &B| min(bbox.hi(dim), region_hi);
+    if dim + 1 == B::DIM {
+        // ends before starts, so intervals that only touch don't stack
+        let mut events: Vec<_> = boxes
+            .iter()
+            .flat_map(|bbox| [(lo(bbox), 1), (hi(bbox), -1)])
+            .collect();
+        events.sort_by(|(a, a_delta), (b, b_delta)| {
+            a.partial_cmp(b)
+                .unwrap_or(Ordering::Equal)
+                .then(a_delta.cmp(b_delta))
+        });
+        let mut depth: isize = 0;
+        for (_coord, delta) in events {
+            depth += delta;
+            *best = (*best).max(above + depth as usize);
+        }
+        return;
+    }
+    boxes.sort_by(|a, b| lo(a).partial_cmp(&lo(b)).unwrap_or(Ordering::Equal));
+    // the boxes that started before the sweep position, and some that ended before it too,
+    // which are only dropped while looking for the boxes overlapping a new box anyway
+    let mut active: Vec<B> = Vec::new();
+    let mut overlapping = Vec::new();
+    for bbox in boxes.iter() {
+        let start = lo(bbox);
+        // the deepest point with `bbox` on top lies in `bbox`, among the boxes that started before it
+        if above + active.len() < *best {
+            active.push(*bbox);
+            continue;
+        }
+        overlapping.clear();
+        active.retain(|other| {
+            let ongoing = hi(other) > start;
+            if ongoing
+                && (dim + 1..B::DIM).all(|d| other.lo(d) < bbox.hi(d) && bbox.lo(d) < other.hi(d))
+            {
+                overlapping.push(*other);
+            }
+            ongoing
+        });
+        let outer = region.to_vec();
+        for (d, (lo, hi)) in region.iter_mut().enumerate().skip(dim + 1) {
+            *lo = max(*lo, bbox.lo(d));
+            *hi = min(*hi, bbox.hi(d));
+        }
+        deepest(&mut overlapping, region, dim + 1, above + 1, best);
+        region.copy_from_slice(&outer);
+        active.push(*bbox);
+    }
+}
+
+/// Returns the measure of the union of the boxes of `boxes` (see [`BoxND::measure`]): the area they cover in 2D,
+/// the volume in 3D and so on, counting space covered by several boxes once, also known as Klee's measure.
+/// Infinite if a box with a positive measure is infinite. `boxes` need not be sorted. Computed in `f64`.
+///
+/// Sweeps over dimension 0, and between consecutive boundaries, measures the union of the cross-sections
+/// of the boxes there in the remaining dimensions the same way. The last two dimensions are swept with a
+/// segment tree over the boundaries in the last dimension, so it takes *O*(*n* log *n*) for *n* boxes in 2D,
+/// and *O*(*n*² log *n*) in 3D.
+pub fn union_measure<N, ID, const D: usize>(boxes: &impl BoxSource<BoxND<N, D>, ID>) -> f64
+where
+    N: Copy + PartialOrd + Into<f64>,
+{
+    let items: Vec<BoxND<f64, D>> = (0..boxes.len())
+        .map(|idx| {
+            let (bbox, _id) = boxes.get(idx);
+            let (mut min, mut max) = ([0.0; D], [0.0; D]);
+            for dim in 0..D {
+                min[dim] = bbox.lo(dim).into();
+                max[dim] = bbox.hi(dim).into();
+            }
+            BoxND::new(min, max)
+        })
+        .filter(|bbox| (0..D).all(|dim| bbox.lo(dim) < bbox.hi(dim)))
+        .collect();
+    match D {
+        0 => 0.0,
+        1 => union_length(&items),
+        _ => union_in(&items, 0),
+    }
+}
+
+/// Returns the measure of the union of `boxes` in dimensions `dim..`, at least two of them.
+fn union_in<const D: usize>(boxes: &[BoxND<f64, D>], dim: usize) -> f64 {
+    if dim + 2 == D {
+        return union_area(boxes, dim);
+    }
+    let mut starts: Vec<_> = boxes.to_vec();
+    starts.sort_by(|a, b| a.lo(dim).total_cmp(&b.lo(dim)));
+    let mut bounds: Vec<f64> = boxes
+        .iter()
+        .flat_map(|bbox| [bbox.lo(dim), bbox.hi(dim)])
+        .collect();
+    bounds.sort_by(f64::total_cmp);
+    bounds.dedup();
+    let mut starts = starts.into_iter().peekable();
+    let mut active = Vec::new();
+    let mut measure = 0.0;
+    for slab in bounds.windows(2) {
+        let (lo, hi) = (slab[0], slab[1]);
+        active.retain(|bbox: &BoxND<f64, D>| bbox.hi(dim) > lo);
+        while let Some(bbox) = starts.next_if(|bbox| bbox.lo(dim) <= lo) {
+            active.push(bbox);
+        }
+        if !active.is_empty() {
+            measure += (hi - lo) * union_in(&active, dim + 1);
+        }
+    }
+    measure
+}
+
+/// Returns the area of the union of `boxes` in dimensions `dim` and `dim + 1`, sweeping over `dim`
+/// and keeping track of the covered length in `dim + 1` in a segment tree.
+fn union_area<const D: usize>(boxes: &[BoxND<f64, D>], dim: usize) -> f64 {
+    let mut bounds: Vec<f64> = boxes
+        .iter()
+        .flat_map(|bbox| [bbox.lo(dim + 1), bbox.hi(dim + 1)])
+        .collect();
+    bounds.sort_by(f64::total_cmp);
+    bounds.dedup();
+    let index = |coord: f64| bounds.partition_point(|&bound| bound < coord);
+    // the coordinate in `dim`, the range of bounds in `dim + 1`, and whether the box starts or ends
+    let mut events: Vec<(f64, usize, usize, i32)> = Vec::with_capacity(2 * boxes.len());
+    for bbox in boxes {
+        let (lo, hi) = (index(bbox.lo(dim + 1)), index(bbox.hi(dim + 1)));
+        events.push((bbox.lo(dim), lo, hi, 1));
+        events.push((bbox.hi(dim), lo, hi, -1));
+    }
+    events.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let mut tree = CoverTree::new(&bounds);
+    let mut area = 0.0;
+    let mut prev = f64::NEG_INFINITY;
+    for (coord, lo, hi, delta) in events {
+        let covered = tree.covered();
+        // also skips uncovered infinite slabs, which would make the area NaN
+        if covered > 0.0 && coord > prev {
+            area += covered * (coord - prev);
+        }
+        prev = coord;
+        tree.add(1, 0, bounds.len() - 1, lo, hi, delta);
+    }
+    area
+}
+
+/// A segment tree over the gaps between sorted `bounds`, counting how many ranges cover each node
+/// and the length covered below it
+struct CoverTree<'a> {
+    bounds: &'a [f64],
+    count: Vec<i32>,
+    covered: Vec<f64>,
+}
+
+impl<'a> CoverTree<'a> {
+    fn new(bounds: &'a [f64]) -> Self {
+        let nodes = 4 * bounds.len().max(1);
+        Self {
+            bounds,
+            count: vec![0; nodes],
+            covered: vec![0.0; nodes],
+        }
+    }
+
+    /// Returns the total covered length.
+    fn covered(&self) -> f64 {
+        self.covered[1]
+    }
+
+    /// Adds `delta` to the count of the range from bound `lo` to bound `hi`, below `node` spanning `start..end`.
+    fn add(&mut self, node: usize, start: usize, end: usize, lo: usize, hi: usize, delta: i32) {
+        if hi <= start || end <= lo {
+            return;
+        }
+        if lo <= start && end <= hi {
+            self.count[node] += delta;
+        } else {
+            let mid = start + (end - start) / 2;
+            self.add(2 * node, start, mid, lo, hi, delta);
+            self.add(2 * node + 1, mid, end, lo, hi, delta);
+        }
+        self.covered[node] = if self.count[node] > 0 {
+            self.bounds[end] - self.bounds[start]
+        } else if end - start == 1 {
+            0.0
+        } else {
+            self.covered[2 * node] + self.covered[2 * node + 1]
+        };
+    }
+}
+
+/// Returns the length of the union of `boxes` in dimension 0.
+fn union_length<const D: usize>(boxes: &[BoxND<f64, D>]) -> f64 {
+    let mut intervals: Vec<_> = boxes.iter().map(|bbox| (bbox.lo(0), bbox.hi(0))).collect();
+    intervals.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let mut length = 0.0;
+    let mut end = f64::NEG_INFINITY;
+    for (lo, hi) in intervals {
+        if hi > end {
+            length += hi - lo.max(end);
+            end = hi;
+        }
+    }
+    length
+}
+
+fn max<N: PartialOrd>(a: N, b: N) -> N {
+    if b > a {
+        b
+    } else {
+        a
+    }
+}
+
+fn min<N: PartialOrd>(a: N, b: N) -> N {
+    if b < a {
+        b
+    } else {
+        a
+    }
+}
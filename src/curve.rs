@@ -0,0 +1,179 @@
+//! Space-filling curve keys for boxes, to order them so that boxes close in space are close in memory,
+//! see [`curve_keys`] and [`BBoxSet::sort_by_curve`].
+//!
+//! # Examples
+//! ```
+//! use box_intersect_ze::boxes::Box2Df32;
+//! use box_intersect_ze::curve::Curve;
+//! use box_intersect_ze::set::BBoxSet;
+//!
+//! let mut boxes = BBoxSet::new();
+//! boxes.push(0, Box2Df32::new([9.0, 9.0], [10.0, 10.0]));
+//! boxes.push(1, Box2Df32::new([0.0, 0.0], [1.0, 1.0]));
+//! boxes.push(2, Box2Df32::new([8.0, 9.0], [9.0, 10.0]));
+//! boxes.push(3, Box2Df32::new([1.0, 0.0], [2.0, 1.0]));
+//! boxes.sort_by_curve(Curve::Hilbert);
+//! let ids: Vec<_> = boxes.iter().map(|(&id, _)| id).collect();
+//! assert_eq!(ids, [1, 3, 2, 0]);
+//!
+//! // the algorithms need the usual order again
+//! boxes.sort();
+//! ```
+
+use crate::boxes::{BBox, BoxND};
+use crate::set::BBoxSet;
+use crate::source::BoxSource;
+
+/// A space-filling curve, which visits every cell of a grid once
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Curve {
+    /// The Z-order curve: interleaves the bits of the coordinates. Cheap to compute,
+    /// but jumps between distant cells at the boundaries of every power of two.
+    Morton,
+    /// The Hilbert curve: only ever steps to a neighbouring cell, so runs of consecutive keys
+    /// are more compact than with [`Curve::Morton`], at a higher cost per key.
+    Hilbert,
+}
+
+impl Curve {
+    /// Returns the key of `cell` with `bits` bits per coordinate, see [`morton`] and [`hilbert`].
+    pub fn key<const D: usize>(&self, cell: [u32; D], bits: u32) -> u64 {
+        match self {
+            Curve::Morton => morton(cell, bits),
+            Curve::Hilbert => hilbert(cell, bits),
+        }
+    }
+}
+
+/// Returns the position of `cell` along the Z-order curve through a grid of `2^bits` cells in every dimension:
+/// the lowest `bits` bits of the coordinates, interleaved from the highest bit down, dimension 0 first.
+///
+/// # Panics
+/// If the key doesn't fit into 64 bits, i.e. `bits * D > 64`, or `bits > 32`.
+pub fn morton<const D: usize>(cell: [u32; D], bits: u32) -> u64 {
+    interleave(cell, bits)
+}
+
+/// Returns the position of `cell` along the Hilbert curve through a grid of `2^bits` cells in every dimension,
+/// using Skilling's algorithm ([Programming the Hilbert curve](https://doi.org/10.1063/1.1751381)).
+///
+/// # Panics
+/// If the key doesn't fit into 64 bits, i.e. `bits * D > 64`, or `bits > 32`.
+pub fn hilbert<const D: usize>(mut cell: [u32; D], bits: u32) -> u64 {
+    if bits == 0 || D == 0 {
+        return interleave(cell, bits);
+    }
+    let high = 1 << (bits - 1);
+    // undo the rotations and reflections of the curve, from the highest bit down
+    let mut q = high;
+    while q > 1 {
+        let p = q - 1;
+        for dim in 0..D {
+            if cell[dim] & q != 0 {
+                cell[0] ^= p;
+            } else {
+                let t = (cell[0] ^ cell[dim]) & p;
+                cell[0] ^= t;
+                cell[dim] ^= t;
+            }
+        }
+        q >>= 1;
+    }
+    // Gray encode
+    for dim in 1..D {
+        cell[dim] ^= cell[dim - 1];
+    }
+    let mut t = 0;
+    let mut q = high;
+    while q > 1 {
+        if cell[D - 1] & q != 0 {
+            t ^= q - 1;
+        }
+        q >>= 1;
+    }
+    for coord in &mut cell {
+        *coord ^= t;
+    }
+    interleave(cell, bits)
+}
+
+fn interleave<const D: usize>(cell: [u32; D], bits: u32) -> u64 {
+    assert!(
+        bits <= 32 && bits as usize * D <= 64,
+        "curve keys must fit into 64 bits"
+    );
+    let mut key = 0;
+    for bit in (0..bits).rev() {
+        for coord in cell {
+            key = key << 1 | (coord >> bit & 1) as u64;
+        }
+    }
+    key
+}
+
+/// Returns the key of each box of `boxes`, in order, along `curve` through a grid over the bounds of the centers
+/// of all boxes, with as many cells in every dimension as 64-bit keys allow (up to `2^32`).
+/// Infinite centers end up in the outermost cells, empty boxes are treated like the others.
+pub fn curve_keys<N, ID, const D: usize>(
+    boxes: &impl BoxSource<BoxND<N, D>, ID>,
+    curve: Curve,
+) -> Vec<u64>
+where
+    N: Copy + PartialOrd + Into<f64>,
+{
+    let bits = 64usize.checked_div(D).map_or(0, |bits| bits.min(32) as u32);
+    let center = |bbox: &BoxND<N, D>, dim| bbox.lo(dim).into() / 2.0 + bbox.hi(dim).into() / 2.0;
+    let mut min = [f64::INFINITY; D];
+    let mut max = [f64::NEG_INFINITY; D];
+    for idx in 0..boxes.len() {
+        let (bbox, _id) = boxes.get(idx);
+        for dim in 0..D {
+            let c = center(&bbox, dim);
+            if c.is_finite() {
+                min[dim] = min[dim].min(c);
+                max[dim] = max[dim].max(c);
+            }
+        }
+    }
+    let cells = if bits == 0 {
+        1.0
+    } else {
+        ((1u64 << bits) - 1) as f64
+    };
+    let mut scale = [0.0; D];
+    for dim in 0..D {
+        if max[dim] > min[dim] {
+            scale[dim] = cells / (max[dim] - min[dim]);
+        }
+    }
+    (0..boxes.len())
+        .map(|idx| {
+            let (bbox, _id) = boxes.get(idx);
+            let mut cell = [0; D];
+            for dim in 0..D {
+                // NaN, e.g. from infinite bounds on both sides, saturates to 0
+                let c = (center(&bbox, dim) - min[dim]) * scale[dim];
+                cell[dim] = c.clamp(0.0, cells) as u32;
+            }
+            curve.key(cell, bits)
+        })
+        .collect()
+}
+
+impl<N, ID, const D: usize> BBoxSet<BoxND<N, D>, ID>
+where
+    N: Copy + PartialOrd + Into<f64>,
+    ID: Copy,
+{
+    /// Sorts the boxes by their keys along `curve` (see [`curve_keys`]), so that boxes close to each other in space
+    /// are mostly close to each other in the set too, e.g. for cache-friendly traversals,
+    /// or to cut the set into spatially compact chunks of consecutive boxes.
+    /// Afterwards, the set has to be sorted again (see [`BBoxSet::sort`]) before passing it to the algorithms.
+    pub fn sort_by_curve(&mut self, curve: Curve) {
+        let keys = curve_keys(self, curve);
+        let boxes = self.boxes_mut();
+        let mut keyed: Vec<_> = keys.into_iter().zip(boxes.drain(..)).collect();
+        keyed.sort_by_key(|&(key, _)| key);
+        boxes.extend(keyed.into_iter().map(|(_key, item)| item));
+    }
+}
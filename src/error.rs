@@ -0,0 +1,195 @@
+//! Errors reported by the checked entry points, e.g. [`try_intersect_ze`](crate::try_intersect_ze),
+//! invalid boxes rejected by [`BoxND::try_new`](crate::boxes::BoxND::try_new) and reports of problems found by [`BBoxSet::validate`]
+
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use crate::boxes::BBox;
+use crate::set::BBoxSet;
+use crate::source::BoxSource;
+
+/// Which of the two input sets an [`InputError`] refers to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input {
+    A,
+    B,
+}
+
+/// Problem with a box in an input set
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Problem {
+    /// A bound of the box in dimension `dim` is NaN
+    NaN { dim: usize },
+    /// The low bound of the box in dimension `dim` is greater than its high bound
+    Inverted { dim: usize },
+    /// The low bound of the box in dimension 0 is less than that of the previous box,
+    /// so the set is not sorted
+    Unsorted,
+    /// The box has the same identifier as the box at index `first`
+    DuplicateId { first: usize },
+}
+
+/// Error returned when an input set can't be processed correctly
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputError {
+    /// The input set the problematic box belongs to
+    pub input: Input,
+    /// Index of the problematic box in its set
+    pub index: usize,
+    /// What is wrong with the box
+    pub problem: Problem,
+}
+
+impl Display for InputError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let input = match self.input {
+            Input::A => "a",
+            Input::B => "b",
+        };
+        write!(f, "box {} of input {} ", self.index, input)?;
+        match self.problem {
+            Problem::NaN { dim } => write!(f, "has a NaN bound in dimension {}", dim),
+            Problem::Inverted { dim } => write!(
+                f,
+                "is inverted: its low bound is greater than its high bound in dimension {}",
+                dim
+            ),
+            Problem::DuplicateId { first } => {
+                write!(f, "has the same identifier as box {}", first)
+            }
+            Problem::Unsorted => write!(
+                f,
+                "is out of order: its low bound in dimension 0 is less than that of the previous box. Call `sort` first"
+            ),
+        }
+    }
+}
+
+impl Error for InputError {}
+
+/// Error returned by [`BoxND::try_new`](crate::boxes::BoxND::try_new) for invalid bounds
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BoxError {
+    /// A bound in dimension `dim` is NaN
+    NaN { dim: usize },
+    /// The low bound in dimension `dim` is greater than the high bound
+    Inverted { dim: usize },
+}
+
+impl Display for BoxError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            BoxError::NaN { dim } => write!(f, "NaN bound in dimension {}", dim),
+            BoxError::Inverted { dim } => {
+                write!(f, "low bound greater than high bound in dimension {}", dim)
+            }
+        }
+    }
+}
+
+impl Error for BoxError {}
+
+/// Report of all problems in a set, returned by [`BBoxSet::validate`].
+/// Every field lists the indices of the offending boxes in the set, in ascending order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Boxes with a NaN bound
+    pub nan: Vec<usize>,
+    /// Boxes with a low bound greater than their high bound in some dimension
+    pub inverted: Vec<usize>,
+    /// Boxes whose low bound in dimension 0 is less than that of the previous box
+    pub unsorted: Vec<usize>,
+    /// Boxes whose `ID` was already used by a box at a lower index
+    pub duplicate_ids: Vec<usize>,
+}
+
+impl Report {
+    /// Returns `true` if no problems were found.
+    pub fn is_valid(&self) -> bool {
+        self.nan.is_empty()
+            && self.inverted.is_empty()
+            && self.unsorted.is_empty()
+            && self.duplicate_ids.is_empty()
+    }
+}
+
+/// Returns the problem with the bounds of `bbox`, if any.
+pub(crate) fn bounds_problem<B: BBox>(bbox: &B) -> Option<Problem> {
+    for dim in 0..B::DIM {
+        let (lo, hi) = (bbox.lo(dim), bbox.hi(dim));
+        if lo.partial_cmp(&lo).is_none() || hi.partial_cmp(&hi).is_none() {
+            return Some(Problem::NaN { dim });
+        }
+        if lo > hi {
+            return Some(Problem::Inverted { dim });
+        }
+    }
+    None
+}
+
+/// Returns the first problem with a box in `set`, if any.
+pub(crate) fn check<B, ID>(set: &BBoxSet<B, ID>, input: Input) -> Result<(), InputError>
+where
+    B: BBox,
+    ID: Copy + PartialOrd,
+{
+    for (index, (bbox, _id)) in set.boxes().iter().enumerate() {
+        let problem = bounds_problem(bbox).or_else(|| {
+            let unsorted = index > 0 && bbox.lo(0) < set.boxes()[index - 1].0.lo(0);
+            unsorted.then_some(Problem::Unsorted)
+        });
+        if let Some(problem) = problem {
+            return Err(InputError {
+                input,
+                index,
+                problem,
+            });
+        }
+    }
+    match set.duplicate_ids().first() {
+        Some(&(index, first)) => Err(InputError {
+            input,
+            index,
+            problem: Problem::DuplicateId { first },
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Panics with a helpful message if `set` is not sorted. `name` is the name of the parameter
+/// `set` was passed as to `function`. Unlike [`assert_sorted`], always checks, but takes constant time
+/// for sources known to be sorted (see [`BoxSource::is_sorted`]), so it's used by the entry points
+/// that take at least linear time anyway.
+pub(crate) fn require_sorted<B: BBox, ID>(set: &impl BoxSource<B, ID>, name: &str, function: &str) {
+    if set.is_sorted() {
+        return;
+    }
+    for index in 1..set.len() {
+        if set.get(index).0.lo(0) < set.get(index - 1).0.lo(0) {
+            panic!(
+                "`{}` passed to `{}` is not sorted: box {} has a lower low bound in dimension 0 than box {}. Call `sort` first",
+                name,
+                function,
+                index,
+                index - 1
+            );
+        }
+    }
+}
+
+/// Panics with a helpful message if `set` is not sorted, see [`require_sorted`].
+/// Only checks if the `debug-checks` feature is enabled.
+#[cfg(feature = "debug-checks")]
+pub(crate) fn assert_sorted<B: BBox, ID>(set: &impl BoxSource<B, ID>, name: &str, function: &str) {
+    require_sorted(set, name, function);
+}
+
+#[cfg(not(feature = "debug-checks"))]
+#[inline(always)]
+pub(crate) fn assert_sorted<B: BBox, ID>(
+    _set: &impl BoxSource<B, ID>,
+    _name: &str,
+    _function: &str,
+) {
+}
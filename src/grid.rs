@@ -0,0 +1,288 @@
+//! Uniform grid broad phase, hashing boxes into the cells they overlap, see [`GridBroadPhase`].
+//!
+//! # Examples
+//! ```
+//! use box_intersect_ze::boxes::Box2Df32;
+//! use box_intersect_ze::grid::GridBroadPhase;
+//!
+//! let mut grid = GridBroadPhase::new(2.0);
+//! grid.insert(0, Box2Df32::new([0.0, 0.0], [1.5, 1.5]));
+//! grid.insert(1, Box2Df32::new([1.0, 1.0], [2.5, 2.5]));
+//! grid.insert(2, Box2Df32::new([5.0, 5.0], [6.0, 6.0]));
+//!
+//! let mut pairs = Vec::new();
+//! grid.pairs(&mut pairs);
+//! assert_eq!(pairs, [(0, 1)]);
+//!
+//! let mut hits = Vec::new();
+//! grid.query(&Box2Df32::new([4.0, 4.0], [5.5, 5.5]), |id, _bbox| hits.push(id));
+//! assert_eq!(hits, [2]);
+//! ```
+
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hasher};
+
+use crate::boxes::{BBox, BoxND};
+use crate::sink::Sink;
+use crate::source::BoxSource;
+
+/// Boxes overlapping more cells than this are kept in a separate list and tested against all boxes,
+/// e.g. an infinite ground plane
+const MAX_CELLS: f64 = 64.0;
+
+/// End of a list of entries
+const NONE: usize = usize::MAX;
+
+/// A broad phase that hashes every box into the cells of a uniform grid that it overlaps, and only tests
+/// boxes sharing a cell against each other. A pair of boxes sharing several cells is only reported
+/// by the cell containing the lowest corner of their overlap, so every pair is reported exactly once.
+/// * Suited to roughly uniformly spread boxes of similar size, with a cell size of about twice their extent.
+///   Much smaller cells put each box into many cells, much larger ones put many boxes into each cell.
+///   Boxes can be inserted one by one, e.g. while they are generated, with no sorting.
+/// * Boxes overlapping more than 64 cells are tested against every box instead.
+///
+/// Like the other algorithms, the projections of boxes are half-open intervals and empty boxes
+/// (see [`BBox::is_empty`]) never intersect. Bounds must not be NaN.
+#[derive(Clone, Debug)]
+pub struct GridBroadPhase<N, ID, const D: usize> {
+    cell_size: f64,
+    items: Vec<(BoxND<N, D>, ID)>,
+    /// First entry of each non-empty cell in `entries`
+    cells: HashMap<[i64; D], usize, BuildHasherDefault<CellHasher>>,
+    /// Lists of the boxes overlapping each cell: the index of a box in `items`
+    /// and the next entry of the same cell, [`NONE`] at the end
+    entries: Vec<(usize, usize)>,
+    /// Indices in `items` of the boxes overlapping too many cells
+    large: Vec<usize>,
+}
+
+impl<N, ID, const D: usize> GridBroadPhase<N, ID, D>
+where
+    N: Copy + PartialOrd + Into<f64>,
+    ID: Copy,
+{
+    /// Creates an empty grid with cubic cells with edges of length `cell_size`.
+    ///
+    /// # Panics
+    /// If `cell_size` isn't positive and finite.
+    pub fn new(cell_size: f64) -> Self {
+        assert!(
+            cell_size > 0.0 && cell_size.is_finite(),
+            "cell size must be positive and finite"
+        );
+        Self {
+            cell_size,
+            items: Vec::new(),
+            cells: HashMap::default(),
+            entries: Vec::new(),
+            large: Vec::new(),
+        }
+    }
+
+    /// Creates a grid like [`GridBroadPhase::new`] and inserts the boxes of `boxes`, which need not be sorted.
+    pub fn from_source(boxes: &impl BoxSource<BoxND<N, D>, ID>, cell_size: f64) -> Self {
+        let mut grid = Self::new(cell_size);
+        for idx in 0..boxes.len() {
+            let (bbox, id) = boxes.get(idx);
+            grid.insert(id, bbox);
+        }
+        grid
+    }
+
+    /// Returns the length of the edges of the cells.
+    pub fn cell_size(&self) -> f64 {
+        self.cell_size
+    }
+
+    /// Returns the number of boxes in the grid.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns `true` if there are no boxes in the grid.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Adds a box with identifier `id`. Empty boxes are left out.
+    pub fn insert(&mut self, id: ID, bbox: BoxND<N, D>) {
+        if bbox.is_empty() {
+            return;
+        }
+        let idx = self.items.len();
+        self.items.push((bbox, id));
+        let (lo, hi) = (self.cell(&bbox.min()), self.cell(&bbox.max()));
+        if cell_count(&lo, &hi) > MAX_CELLS {
+            self.large.push(idx);
+            return;
+        }
+        // new entries are prepended to the list of their cell, so no cell needs an allocation of its own
+        let (cells, entries) = (&mut self.cells, &mut self.entries);
+        for_each_cell(lo, hi, |cell| {
+            let next = cells.entry(cell).or_insert(NONE);
+            entries.push((idx, *next));
+            *next = entries.len() - 1;
+        });
+    }
+
+    /// Removes all boxes, keeping the cell size.
+    pub fn clear(&mut self) {
+        self.items.clear();
+        self.cells.clear();
+        self.entries.clear();
+        self.large.clear();
+    }
+
+    /// Reports each pair of intersecting boxes to `out` once, the box inserted first first.
+    pub fn pairs(&self, out: &mut impl Sink<BoxND<N, D>, ID>) {
+        for (cell, &first) in &self.cells {
+            let mut entry = first;
+            while entry != NONE {
+                let (b_idx, next) = self.entries[entry];
+                let (b, b_id) = &self.items[b_idx];
+                // earlier boxes come later in the list
+                for a_idx in self.cell_items(next) {
+                    let (a, a_id) = &self.items[a_idx];
+                    if a.intersects(b) && self.cell(&overlap_min(a, b)) == *cell {
+                        out.report(*a_id, a, *b_id, b);
+                    }
+                }
+                entry = next;
+            }
+        }
+        for (i, &a_idx) in self.large.iter().enumerate() {
+            let (a, a_id) = &self.items[a_idx];
+            for (b_idx, (b, b_id)) in self.items.iter().enumerate() {
+                // pairs of large boxes are reported once, by the one inserted first
+                if b_idx == a_idx
+                    || (b_idx < a_idx && self.large[..i].binary_search(&b_idx).is_ok())
+                {
+                    continue;
+                }
+                if a.intersects(b) {
+                    if b_idx < a_idx {
+                        out.report(*b_id, b, *a_id, a);
+                    } else {
+                        out.report(*a_id, a, *b_id, b);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Calls `hit` with the identifier and the box of each box intersecting `bbox`, in no particular order.
+    pub fn query(&self, bbox: &BoxND<N, D>, mut hit: impl FnMut(ID, &BoxND<N, D>)) {
+        if bbox.is_empty() {
+            return;
+        }
+        let (lo, hi) = (self.cell(&bbox.min()), self.cell(&bbox.max()));
+        if cell_count(&lo, &hi) > MAX_CELLS {
+            for (other, id) in &self.items {
+                if other.intersects(bbox) {
+                    hit(*id, other);
+                }
+            }
+            return;
+        }
+        for_each_cell(lo, hi, |cell| {
+            let first = self.cells.get(&cell).copied().unwrap_or(NONE);
+            for idx in self.cell_items(first) {
+                let (other, id) = &self.items[idx];
+                if other.intersects(bbox) && self.cell(&overlap_min(other, bbox)) == cell {
+                    hit(*id, other);
+                }
+            }
+        });
+        for &idx in &self.large {
+            let (other, id) = &self.items[idx];
+            if other.intersects(bbox) {
+                hit(*id, other);
+            }
+        }
+    }
+
+    /// Returns the indices in `items` of the boxes in the list of entries starting at `entry`.
+    fn cell_items(&self, mut entry: usize) -> impl Iterator<Item = usize> + '_ {
+        std::iter::from_fn(move || {
+            let (idx, next) = *self.entries.get(entry)?;
+            entry = next;
+            Some(idx)
+        })
+    }
+
+    /// Returns the coordinates of the cell containing `point`, saturating for huge or infinite coordinates.
+    fn cell(&self, point: &[N; D]) -> [i64; D] {
+        let mut cell = [0; D];
+        for dim in 0..D {
+            cell[dim] = (point[dim].into() / self.cell_size).floor() as i64;
+        }
+        cell
+    }
+}
+
+/// Returns the low corner of the overlap of `a` and `b`.
+fn overlap_min<N, const D: usize>(a: &BoxND<N, D>, b: &BoxND<N, D>) -> [N; D]
+where
+    N: Copy + PartialOrd,
+{
+    let mut min = a.min();
+    for (dim, lo) in min.iter_mut().enumerate() {
+        if b.lo(dim) > *lo {
+            *lo = b.lo(dim);
+        }
+    }
+    min
+}
+
+/// Returns the number of cells from `lo` to `hi`, inclusive, as `f64` so it can't overflow.
+fn cell_count<const D: usize>(lo: &[i64; D], hi: &[i64; D]) -> f64 {
+    (0..D)
+        .map(|dim| (hi[dim] as f64 - lo[dim] as f64) + 1.0)
+        .product()
+}
+
+/// Calls `f` with each cell from `lo` to `hi`, inclusive.
+fn for_each_cell<const D: usize>(lo: [i64; D], hi: [i64; D], mut f: impl FnMut([i64; D])) {
+    let mut cell = lo;
+    loop {
+        f(cell);
+        // advance like an odometer, dimension 0 fastest
+        let mut dim = 0;
+        while dim < D && cell[dim] == hi[dim] {
+            cell[dim] = lo[dim];
+            dim += 1;
+        }
+        if dim == D {
+            return;
+        }
+        cell[dim] += 1;
+    }
+}
+
+/// Multiplicative hasher for cell coordinates, much faster than the default hasher,
+/// which resists collision attacks that don't matter here
+#[derive(Default)]
+struct CellHasher(u64);
+
+impl Hasher for CellHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        // integer arrays are hashed as their bytes
+        for chunk in bytes.chunks(8) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.write_u64(u64::from_le_bytes(word));
+        }
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.0 = (self.0.rotate_left(5) ^ n).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+    }
+
+    fn write_i64(&mut self, n: i64) {
+        self.write_u64(n as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
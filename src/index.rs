@@ -0,0 +1,164 @@
+//! Prebuilt index for repeated queries against a set of boxes that doesn't change, see [`StaticIndex`].
+//!
+//! # Examples
+//! ```
+//! use box_intersect_ze::boxes::Box3Df32;
+//! use box_intersect_ze::index::StaticIndex;
+//! use box_intersect_ze::set::BBoxSet;
+//!
+//! let mut level = BBoxSet::new();
+//! level.push(0, Box3Df32::new([0.0, 0.0, 0.0], [100.0, 100.0, 1.0]));
+//! level.push(1, Box3Df32::new([10.0, 10.0, 1.0], [12.0, 12.0, 5.0]));
+//! level.sort();
+//! let index = StaticIndex::build(&level);
+//!
+//! // every frame, query the level with the moving boxes without rebuilding anything
+//! let mut players = BBoxSet::new();
+//! players.push(2, Box3Df32::new([11.0, 11.0, 0.5], [11.5, 11.5, 2.5]));
+//! let mut pairs = Vec::new();
+//! index.query_set(&players, &mut pairs);
+//! assert_eq!(pairs, [(2, 0), (2, 1)]);
+//! ```
+
+use crate::boxes::BBox;
+use crate::set::sort_order;
+use crate::sink::Sink;
+use crate::source::BoxSource;
+
+/// A static index over boxes of any [`BBox`] type: the boxes sorted by their low boundaries in dimension 0, implicitly
+/// forming a balanced binary tree whose node for each range of boxes is the one in the middle. Every node stores
+/// the bounds of its range in all dimensions, so queries skip ranges that can't contain any hits. This materializes
+/// what [`intersect_scan`](crate::intersect_scan) and [`intersect_ze`](crate::intersect_ze) rebuild for every call.
+/// * Build it once for geometry that doesn't change, then query it with a single box ([`StaticIndex::query_box`])
+///   or a whole set of boxes ([`StaticIndex::query_set`]) as often as needed.
+/// * Queries report the hits in the order of a sorted [`BBoxSet`](crate::set::BBoxSet).
+/// * Uses no memory beyond the boxes and one set of bounds per box. Pruning works best when boxes
+///   close in dimension 0 are close in the other dimensions too, see [`Bvh`](crate::bvh::Bvh)
+///   and [`RTree`](crate::rtree::RTree) for [`BoxND`](crate::boxes::BoxND)s scattered in all dimensions.
+///
+/// Like the other algorithms, the projections of boxes are half-open intervals and empty boxes
+/// (see [`BBox::is_empty`]) never intersect. Bounds must not be NaN.
+#[derive(Clone, Debug)]
+pub struct StaticIndex<B: BBox, ID> {
+    /// Boxes and their identifiers, sorted by low boundary in dimension 0
+    items: Vec<(B, ID)>,
+    /// Lowest low and highest high boundary in each dimension of the range of boxes whose node is at each index,
+    /// `2 * B::DIM` per node
+    bounds: Vec<B::Num>,
+}
+
+impl<B, ID> StaticIndex<B, ID>
+where
+    B: BBox,
+    ID: Copy,
+{
+    /// Builds an index over the boxes of `boxes`, which need not be sorted. Empty boxes are left out.
+    /// # Panics
+    /// If a low boundary in dimension 0 is NaN, like [`BBoxSet::sort`](crate::set::BBoxSet::sort).
+    pub fn build(boxes: &impl BoxSource<B, ID>) -> Self
+    where
+        ID: PartialOrd,
+    {
+        let mut items: Vec<_> = (0..boxes.len())
+            .map(|idx| boxes.get(idx))
+            .filter(|(bbox, _id)| !bbox.is_empty())
+            .collect();
+        items.sort_by(sort_order);
+        let mut bounds = Vec::with_capacity(items.len() * 2 * B::DIM);
+        for (bbox, _id) in &items {
+            for dim in 0..B::DIM {
+                bounds.extend([bbox.lo(dim), bbox.hi(dim)]);
+            }
+        }
+        augment::<B>(&mut bounds, 0, items.len());
+        Self { items, bounds }
+    }
+
+    /// Returns the number of boxes in the index.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns `true` if there are no boxes in the index.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Calls `hit` with the identifier and the box of each box intersecting `bbox`, in order of their low boundaries
+    /// in dimension 0.
+    pub fn query(&self, bbox: &B, mut hit: impl FnMut(ID, &B)) {
+        if !bbox.is_empty() {
+            self.visit(0, self.items.len(), bbox, &mut hit);
+        }
+    }
+
+    /// Appends the identifiers of all boxes intersecting `query` to `out`, like [`query_box`](crate::query_box)
+    /// on the sorted set the index was built from.
+    pub fn query_box(&self, query: &B, out: &mut Vec<ID>) {
+        self.query(query, |id, _bbox| out.push(id));
+    }
+
+    /// Queries the index with each box of `queries` (see [`StaticIndex::query`]) and reports each pair of a box
+    /// of `queries` and an intersecting box of the index to `out`, the box of `queries` first.
+    /// `queries` need not be sorted.
+    pub fn query_set(&self, queries: &impl BoxSource<B, ID>, out: &mut impl Sink<B, ID>) {
+        for idx in 0..queries.len() {
+            let (query, query_id) = queries.get(idx);
+            self.query(&query, |id, bbox| out.report(query_id, &query, id, bbox));
+        }
+    }
+
+    /// Visits the boxes of the range `start..end` that intersect `query` in order.
+    fn visit(&self, start: usize, end: usize, query: &B, hit: &mut impl FnMut(ID, &B)) {
+        if start == end {
+            return;
+        }
+        let mid = start + (end - start) / 2;
+        let bounds = &self.bounds[mid * 2 * B::DIM..(mid + 1) * 2 * B::DIM];
+        let overlaps =
+            (0..B::DIM).all(|dim| query.intersects_in(dim, bounds[2 * dim], bounds[2 * dim + 1]));
+        if !overlaps {
+            return;
+        }
+        self.visit(start, mid, query, hit);
+        let (bbox, id) = &self.items[mid];
+        // the boxes to the right start even later
+        if bbox.lo(0) < query.hi(0) {
+            if bbox.intersects(query) {
+                hit(*id, bbox);
+            }
+            self.visit(mid + 1, end, query, hit);
+        }
+    }
+}
+
+/// Turns the bounds of the boxes in `bounds` into the bounds of the range of `start..end` of each node,
+/// and returns the index of the node of the whole range.
+fn augment<B: BBox>(bounds: &mut [B::Num], start: usize, end: usize) -> Option<usize> {
+    if start == end {
+        return None;
+    }
+    let mid = start + (end - start) / 2;
+    let stride = 2 * B::DIM;
+    for &child in [
+        augment::<B>(bounds, start, mid),
+        augment::<B>(bounds, mid + 1, end),
+    ]
+    .iter()
+    .flatten()
+    {
+        for dim in 0..B::DIM {
+            let (lo, hi) = (
+                bounds[child * stride + 2 * dim],
+                bounds[child * stride + 2 * dim + 1],
+            );
+            if lo < bounds[mid * stride + 2 * dim] {
+                bounds[mid * stride + 2 * dim] = lo;
+            }
+            if hi > bounds[mid * stride + 2 * dim + 1] {
+                bounds[mid * stride + 2 * dim + 1] = hi;
+            }
+        }
+    }
+    Some(mid)
+}
@@ -1,37 +1,117 @@
 //! Implementations of the algorithms provided by this crate. You probably want to call
 //! the wrappers at the [top level of the crate](`crate`) instead.
 
+use std::cmp::Ordering;
+use std::marker::PhantomData;
+
 use crate::boxes::BBox;
-use crate::set::BBoxSet;
-use crate::{HasInfinity, Rng};
+use crate::error::assert_sorted;
+use crate::median::{ApproxMedian, MedianStrategy};
+use crate::points::{Point, PointSet};
+use crate::policy::Policy;
+use crate::sink::{PointSink, Sink};
+use crate::source::{all_overlap_in, BoxSource};
+use crate::{HasInfinity, Rng, DEFAULT_CUTOFF};
 
 /// Reports intersections between `intervals` and `points` by scanning in dimension 0,
 /// treating boxes in `points` as points: intersections are only reported when the low
 /// endpoint in dimension 0 of a box in `points` is inside the projection of a box in `intervals`.
 /// * `intervals` and `points` must be sorted before calling
 /// * `max_dim_check`: highest dimension that should be checked for intersection
-/// * `out` will contain pairs of `ID`s of intersecting boxes.
+/// * `out` will receive the pairs of intersecting boxes.
+/// * `policy` decides how bounds are compared, see [`Policy`].
 pub fn one_way_scan<B, ID>(
-    intervals: &BBoxSet<B, ID>,
-    points: &BBoxSet<B, ID>,
+    intervals: &impl BoxSource<B, ID>,
+    points: &impl BoxSource<B, ID>,
+    max_dim_check: usize,
+    out: &mut impl Sink<B, ID>,
+    policy: impl Policy<B::Num>,
+) where
+    B: BBox,
+    ID: Copy + PartialOrd,
+    B::Num: PartialOrd,
+{
+    let scratch = &mut ScanScratch::new();
+    one_way_scan_with_scratch(intervals, points, max_dim_check, out, policy, scratch);
+}
+
+/// Like [`one_way_scan`], but working in `scratch` instead of allocating memory, see [`ScanScratch`].
+pub fn one_way_scan_with_scratch<B, ID>(
+    intervals: &impl BoxSource<B, ID>,
+    points: &impl BoxSource<B, ID>,
+    max_dim_check: usize,
+    out: &mut impl Sink<B, ID>,
+    policy: impl Policy<B::Num>,
+    scratch: &mut ScanScratch<B>,
+) where
+    B: BBox,
+    ID: Copy + PartialOrd,
+    B::Num: PartialOrd,
+{
+    let ((_, p_keys), order) = scratch.prepare(intervals, points, max_dim_check + 1, policy);
+    one_way_scan_keyed(intervals, points, p_keys, max_dim_check, order, out, policy);
+}
+
+/// Like [`one_way_scan`], with the low boundaries of `points` already computed by [`lo_keys`]
+/// and the dimensions checked in `order`, see [`check_order`].
+pub(crate) fn one_way_scan_keyed<B, ID>(
+    intervals: &impl BoxSource<B, ID>,
+    points: &impl BoxSource<B, ID>,
+    p_keys: &[B::Num],
     max_dim_check: usize,
-    out: &mut Vec<(ID, ID)>,
+    order: Option<&[usize]>,
+    out: &mut impl Sink<B, ID>,
+    policy: impl Policy<B::Num>,
 ) where
     B: BBox,
     ID: Copy + PartialOrd,
     B::Num: PartialOrd,
 {
+    let (i, p, k) = (intervals, points, p_keys);
+    // the loops over the dimensions are unrolled for the common numbers of dimensions
+    match max_dim_check {
+        1 => _one_way_scan::<B, ID, 1>(i, p, k, max_dim_check, order, out, policy),
+        2 => _one_way_scan::<B, ID, 2>(i, p, k, max_dim_check, order, out, policy),
+        _ => _one_way_scan::<B, ID, DYNAMIC>(i, p, k, max_dim_check, order, out, policy),
+    }
+}
+
+/// Value of the `MAX_DIM` parameter of the scans if the highest dimension to check
+/// is only known at runtime. For other values it's known at compile time, so loops over
+/// the dimensions can be unrolled.
+const DYNAMIC: usize = usize::MAX;
+
+fn _one_way_scan<B, ID, const MAX_DIM: usize>(
+    intervals: &impl BoxSource<B, ID>,
+    points: &impl BoxSource<B, ID>,
+    p_keys: &[B::Num],
+    max_dim_check: usize,
+    order: Option<&[usize]>,
+    out: &mut impl Sink<B, ID>,
+    policy: impl Policy<B::Num>,
+) where
+    B: BBox,
+    ID: Copy + PartialOrd,
+    B::Num: PartialOrd,
+{
+    assert_sorted(intervals, "intervals", "one_way_scan");
+    assert_sorted(points, "points", "one_way_scan");
+    let max_dim_check = if MAX_DIM == DYNAMIC {
+        max_dim_check
+    } else {
+        MAX_DIM
+    };
     let p_len = points.len();
     let mut p_min_idx = 0;
 
     // iterate through (sorted) intervals
-    for i in &intervals.boxes {
-        let &(i, i_id) = i;
-        let i_min = i.lo(0);
-        let i_max = i.hi(0);
+    for i_idx in 0..intervals.len() {
+        let (i, i_id) = intervals.get(i_idx);
+        let i_min = policy.lo_of(&i, 0);
+        let i_max = policy.hi_of(&i, 0);
 
         //skip all points that don't have a chance to be in `i`
-        while p_min_idx < p_len && points.boxes[p_min_idx].0.lo(0) < i_min {
+        while p_min_idx < p_len && p_keys[p_min_idx] < i_min {
             p_min_idx += 1;
         }
         // if no point has a chance to be in the current interval,
@@ -40,21 +120,23 @@ pub fn one_way_scan<B, ID>(
             return;
         }
 
-        'points: for p_idx in p_min_idx..p_len {
-            let (p, p_id) = points.boxes[p_idx];
-            let p_min = p.lo(0);
+        'points: for (p_idx, &p_min) in p_keys.iter().enumerate().skip(p_min_idx) {
             if p_min >= i_max {
                 break 'points;
             }
+            let (p, p_id) = points.get(p_idx);
+
+            // skip empty boxes, see `Policy::is_empty`
+            if policy.hi_of(&p, 0) <= p_min {
+                continue 'points;
+            }
 
             if p_id == i_id {
                 continue 'points;
             }
 
-            for dim in 1..max_dim_check + 1 {
-                if !p.intersects_in(dim, i.lo(dim), i.hi(dim)) {
-                    continue 'points;
-                }
+            if !intersects_in_order(&p, &i, max_dim_check + 1, order, policy) {
+                continue 'points;
             }
 
             //if low endpoints are not pairwise different, this is needed to avoid duplicates
@@ -62,7 +144,7 @@ pub fn one_way_scan<B, ID>(
                 continue 'points;
             }
 
-            out.push((i_id, p_id));
+            out.report(i_id, &i, p_id, &p);
         }
     }
 }
@@ -70,42 +152,115 @@ pub fn one_way_scan<B, ID>(
 /// Reports intersections between `intervals` and `points` by scanning in dimension 0 (because that's where boxes are sorted),
 /// but pretends it was scanning in dimension `max_dim_check` by treating `points` as points there, as in [`one_way_scan`]
 pub fn simulated_one_way_scan<B, ID>(
-    intervals: &BBoxSet<B, ID>,
-    points: &BBoxSet<B, ID>,
+    intervals: &impl BoxSource<B, ID>,
+    points: &impl BoxSource<B, ID>,
     max_dim_check: usize,
-    out: &mut Vec<(ID, ID)>,
+    out: &mut impl Sink<B, ID>,
+    policy: impl Policy<B::Num>,
 ) where
     B: BBox,
     ID: Copy + PartialOrd,
     B::Num: PartialOrd,
 {
-    _two_way_scan::<B, ID, true>(intervals, points, max_dim_check, out);
+    let (mut i_keys, mut p_keys) = (Vec::new(), Vec::new());
+    lo_keys(intervals, policy, &mut i_keys);
+    lo_keys(points, policy, &mut p_keys);
+    let keys = (&*i_keys, &*p_keys);
+    let order = check_order(intervals, points, max_dim_check, policy);
+    let order = order.as_deref();
+    simulated_one_way_scan_keyed(intervals, points, keys, max_dim_check, order, out, policy);
+}
+
+/// Like [`simulated_one_way_scan`], with the low boundaries of `intervals` and `points` already computed
+/// by [`lo_keys`] and the dimensions checked in `order`, see [`check_order`].
+pub(crate) fn simulated_one_way_scan_keyed<B, ID>(
+    intervals: &impl BoxSource<B, ID>,
+    points: &impl BoxSource<B, ID>,
+    keys: Keys<'_, B::Num>,
+    max_dim_check: usize,
+    order: Option<&[usize]>,
+    out: &mut impl Sink<B, ID>,
+    policy: impl Policy<B::Num>,
+) where
+    B: BBox,
+    ID: Copy + PartialOrd,
+    B::Num: PartialOrd,
+{
+    let (i, p, k, o) = (intervals, points, keys, order);
+    match max_dim_check {
+        1 => _two_way_scan::<B, ID, _, _, _, true, 1>(i, p, k, max_dim_check, o, out, policy),
+        2 => _two_way_scan::<B, ID, _, _, _, true, 2>(i, p, k, max_dim_check, o, out, policy),
+        _ => _two_way_scan::<B, ID, _, _, _, true, DYNAMIC>(i, p, k, max_dim_check, o, out, policy),
+    }
 }
 
 /// Reports intersections between boxes in `a` and `b` by scanning in dimension 0, treating each
 /// as intervals and points in turn, as if [`one_way_scan`] was called twice, once with intervals and points switched
-/// * `a` and `b` must be distinct [`BBoxSet`]s and must be sorted before calling.
-/// * `out` will contain pairs of `ID`s of intersecting boxes.
-pub fn two_way_scan<B, ID>(a: &BBoxSet<B, ID>, b: &BBoxSet<B, ID>, out: &mut Vec<(ID, ID)>)
-where
+/// * `a` and `b` must be distinct [`BBoxSet`](crate::set::BBoxSet)s (or other [`BoxSource`]s) and must be sorted before calling.
+/// * `out` will receive the pairs of intersecting boxes.
+/// * `policy` decides how bounds are compared, see [`Policy`].
+pub fn two_way_scan<B, ID>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    out: &mut impl Sink<B, ID>,
+    policy: impl Policy<B::Num>,
+) where
     B: BBox,
     ID: Copy,
     B::Num: PartialOrd,
     ID: PartialOrd,
 {
-    _two_way_scan::<B, ID, false>(a, b, B::DIM - 1, out);
+    two_way_scan_with_scratch(a, b, out, policy, &mut ScanScratch::new());
+}
+
+/// Like [`two_way_scan`], but working in `scratch` instead of allocating memory, see [`ScanScratch`].
+pub fn two_way_scan_with_scratch<B, ID>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    out: &mut impl Sink<B, ID>,
+    policy: impl Policy<B::Num>,
+    scratch: &mut ScanScratch<B>,
+) where
+    B: BBox,
+    ID: Copy + PartialOrd,
+    B::Num: PartialOrd,
+{
+    let (k, o) = scratch.prepare(a, b, B::DIM, policy);
+    match B::DIM - 1 {
+        1 => _two_way_scan::<B, ID, _, _, _, false, 1>(a, b, k, 1, o, out, policy),
+        2 => _two_way_scan::<B, ID, _, _, _, false, 2>(a, b, k, 2, o, out, policy),
+        max_dim => {
+            _two_way_scan::<B, ID, _, _, _, false, DYNAMIC>(a, b, k, max_dim, o, out, policy)
+        }
+    }
 }
 
-fn _two_way_scan<B, ID, const SIMULATE_ONE_WAY: bool>(
-    intervals: &BBoxSet<B, ID>,
-    points: &BBoxSet<B, ID>,
+fn _two_way_scan<B, ID, I, S, P, const SIMULATE_ONE_WAY: bool, const MAX_DIM: usize>(
+    intervals: &I,
+    points: &S,
+    (i_keys, p_keys): Keys<'_, B::Num>,
     max_dim_check: usize,
-    out: &mut Vec<(ID, ID)>,
+    order: Option<&[usize]>,
+    out: &mut impl Sink<B, ID>,
+    policy: P,
 ) where
     B: BBox,
     ID: Copy + PartialOrd,
     B::Num: PartialOrd,
+    I: BoxSource<B, ID>,
+    S: BoxSource<B, ID>,
+    P: Policy<B::Num>,
 {
+    assert_sorted(intervals, "intervals", "two_way_scan");
+    assert_sorted(points, "points", "two_way_scan");
+    let max_dim_check = if MAX_DIM == DYNAMIC {
+        max_dim_check
+    } else {
+        MAX_DIM
+    };
+    let lo = |bbox: &B, dim: usize| policy.lo_of(bbox, dim);
+    let hi = |bbox: &B, dim: usize| policy.hi_of(bbox, dim);
+
     let mut i_min_idx = 0;
     let i_len = intervals.len();
     let mut p_min_idx = 0;
@@ -121,60 +276,68 @@ fn _two_way_scan<B, ID, const SIMULATE_ONE_WAY: bool>(
     while i_min_idx < i_len && p_min_idx < p_len {
         let (i_min, i_min_id) = intervals.get(i_min_idx);
         let (p_min, p_min_id) = points.get(p_min_idx);
-        if i_min.lo(0) < p_min.lo(0) {
-            'points: for p_idx in p_min_idx..p_len {
-                let (p, p_id) = points.get(p_idx);
-                if p.lo(0) >= i_min.hi(0) {
+        if i_keys[i_min_idx] < p_keys[p_min_idx] {
+            let i_max = hi(&i_min, 0);
+            'points: for (p_idx, &p_lo) in p_keys.iter().enumerate().skip(p_min_idx) {
+                if p_lo >= i_max {
                     break 'points;
                 }
+                let (p, p_id) = points.get(p_idx);
+
+                if hi(&p, 0) <= lo(&p, 0) {
+                    continue 'points;
+                }
 
                 if p_id == i_min_id {
                     continue 'points;
                 }
 
-                for dim in 1..dim_range_upper {
-                    if !p.intersects_in(dim, i_min.lo(dim), i_min.hi(dim)) {
-                        continue 'points;
-                    }
+                if !intersects_in_order(&p, &i_min, dim_range_upper, order, policy) {
+                    continue 'points;
                 }
 
                 if SIMULATE_ONE_WAY
-                    && (!i_min.contains_in(max_dim_check, p.lo(max_dim_check))
-                        || (i_min.lo(max_dim_check) == p.lo(max_dim_check) && i_min_id > p_id))
+                    && (!policy.contains_in(&i_min, max_dim_check, lo(&p, max_dim_check))
+                        || policy.is_empty_in(&p, max_dim_check)
+                        || (lo(&i_min, max_dim_check) == lo(&p, max_dim_check) && i_min_id > p_id))
                 {
                     continue 'points;
                 }
 
-                out.push((p_id, i_min_id));
+                out.report(p_id, &p, i_min_id, &i_min);
             }
 
             i_min_idx += 1;
         } else {
             //p_min.lo(0) <= i_min.lo(0), so switch the roles of intervals and points
-            'intervals: for i_idx in i_min_idx..i_len {
-                let (i, i_id) = intervals.get(i_idx);
-                if i.lo(0) >= p_min.hi(0) {
+            let p_max = hi(&p_min, 0);
+            'intervals: for (i_idx, &i_lo) in i_keys.iter().enumerate().skip(i_min_idx) {
+                if i_lo >= p_max {
                     break 'intervals;
                 }
+                let (i, i_id) = intervals.get(i_idx);
+
+                if hi(&i, 0) <= lo(&i, 0) {
+                    continue 'intervals;
+                }
 
                 if i_id == p_min_id {
                     continue 'intervals;
                 }
 
-                for dim in 1..dim_range_upper {
-                    if !i.intersects_in(dim, p_min.lo(dim), p_min.hi(dim)) {
-                        continue 'intervals;
-                    }
+                if !intersects_in_order(&i, &p_min, dim_range_upper, order, policy) {
+                    continue 'intervals;
                 }
 
                 if SIMULATE_ONE_WAY
-                    && (!i.contains_in(max_dim_check, p_min.lo(max_dim_check))
-                        || (i.lo(max_dim_check) == p_min.lo(max_dim_check) && i_id > p_min_id))
+                    && (!policy.contains_in(&i, max_dim_check, lo(&p_min, max_dim_check))
+                        || policy.is_empty_in(&p_min, max_dim_check)
+                        || (lo(&i, max_dim_check) == lo(&p_min, max_dim_check) && i_id > p_min_id))
                 {
                     continue 'intervals;
                 }
 
-                out.push((p_min_id, i_id));
+                out.report(p_min_id, &p_min, i_id, &i);
             }
 
             p_min_idx += 1;
@@ -182,88 +345,1269 @@ fn _two_way_scan<B, ID, const SIMULATE_ONE_WAY: bool>(
     }
 }
 
+/// Number of boxes sampled from each input by [`check_order`]
+const ORDER_SAMPLES: usize = 8;
+
+/// Returns the dimensions `1..upper` in the order the scans should check them in, if it's not the natural one:
+/// sorted by how many pairs of a sample of boxes from `a` and `b` intersect in them, fewest first.
+/// So the first comparison already rejects most candidates, e.g. for flat boxes that all overlap
+/// in the other dimensions. Dimensions that are equally selective keep their natural order.
+/// Dimensions in which all boxes of `a` and `b` overlap are left out, since they needn't be checked at all.
+pub(crate) fn check_order<B, ID>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    upper: usize,
+    policy: impl Policy<B::Num>,
+) -> Option<Vec<usize>>
+where
+    B: BBox,
+{
+    let (mut order, mut hits) = (Vec::new(), Vec::new());
+    check_order_in(a, b, upper, policy, &mut order, &mut hits).then_some(order)
+}
+
+/// Like [`check_order`], but writing the order to `order` and counting in `hits`, so it doesn't allocate
+/// if they are large enough. Returns `false` if the natural order should be used.
+fn check_order_in<B, ID>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    upper: usize,
+    policy: impl Policy<B::Num>,
+    order: &mut Vec<usize>,
+    hits: &mut Vec<usize>,
+) -> bool
+where
+    B: BBox,
+{
+    if upper < 2 {
+        return false;
+    }
+    // boxes spread evenly over the inputs, which are sorted in dimension 0
+    let (a_len, b_len) = (a.len(), b.len());
+    let (a_samples, b_samples) = (a_len.min(ORDER_SAMPLES), b_len.min(ORDER_SAMPLES));
+    hits.clear();
+    hits.resize(upper, 0);
+    for k in 0..a_samples {
+        let x = a.get(k * a_len / a_samples).0;
+        for l in 0..b_samples {
+            let y = b.get(l * b_len / b_samples).0;
+            for (dim, hits) in hits.iter_mut().enumerate().skip(1) {
+                *hits += policy.intersects_in(&x, &y, dim) as usize;
+            }
+        }
+    }
+    let pairs = a_samples * b_samples;
+    order.clear();
+    order.extend((1..upper).filter(|&dim| hits[dim] < pairs || !all_overlap_in(a, b, dim, policy)));
+    // sorting by dimension as well keeps equally selective ones in their order, without allocating
+    order.sort_unstable_by_key(|&dim| (hits[dim], dim));
+    order.len() < upper - 1 || order.windows(2).any(|pair| pair[0] > pair[1])
+}
+
+/// Scratch memory for the scans, so that they don't need to allocate: the low boundaries in dimension 0
+/// of both inputs, packed densely, and the order to check the other dimensions in. It grows to the sizes of the inputs it's used for and keeps its memory,
+/// so scanning only allocates until it is large enough, or never if it's created
+/// [`with_capacity`](Self::with_capacity) for the largest inputs. See [`one_way_scan_with_scratch`].
+pub struct ScanScratch<B: BBox> {
+    keys: [Vec<B::Num>; 2],
+    order: Vec<usize>,
+    hits: Vec<usize>,
+}
+
+impl<B: BBox> ScanScratch<B> {
+    /// Creates empty scratch memory, which grows when it's used.
+    pub fn new() -> Self {
+        Self {
+            keys: [Vec::new(), Vec::new()],
+            order: Vec::new(),
+            hits: Vec::new(),
+        }
+    }
+
+    /// Creates scratch memory large enough to scan inputs of up to `len` boxes each without allocating.
+    pub fn with_capacity(len: usize) -> Self {
+        Self {
+            keys: [Vec::with_capacity(len), Vec::with_capacity(len)],
+            order: Vec::with_capacity(B::DIM),
+            hits: Vec::with_capacity(B::DIM),
+        }
+    }
+
+    /// Computes the keys of `intervals` and `points` and the order to check the dimensions `1..upper` in.
+    fn prepare<ID>(
+        &mut self,
+        intervals: &impl BoxSource<B, ID>,
+        points: &impl BoxSource<B, ID>,
+        upper: usize,
+        policy: impl Policy<B::Num>,
+    ) -> (Keys<'_, B::Num>, Option<&[usize]>) {
+        let [i_keys, p_keys] = &mut self.keys;
+        lo_keys(intervals, policy, i_keys);
+        lo_keys(points, policy, p_keys);
+        let (order, hits) = (&mut self.order, &mut self.hits);
+        let reorder = check_order_in(intervals, points, upper, policy, order, hits);
+        ((i_keys, p_keys), reorder.then_some(&**order))
+    }
+}
+
+impl<B: BBox> Default for ScanScratch<B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns `true` if `a` and `b` intersect in the dimensions `1..upper`, checked in the given `order`
+/// (see [`check_order`]) or the natural one. `order` may contain higher dimensions, which are skipped,
+/// so the order computed once for a query serves the scans of all its segment trees.
+#[inline(always)]
+fn intersects_in_order<B: BBox>(
+    a: &B,
+    b: &B,
+    upper: usize,
+    order: Option<&[usize]>,
+    policy: impl Policy<B::Num>,
+) -> bool {
+    match order {
+        None => (1..upper).all(|dim| policy.intersects_in(a, b, dim)),
+        Some(order) => order
+            .iter()
+            .all(|&dim| dim >= upper || policy.intersects_in(a, b, dim)),
+    }
+}
+
+/// Keys of the intervals and points of a scan, see [`lo_keys`]
+pub(crate) type Keys<'k, N> = (&'k [N], &'k [N]);
+
+/// Replaces the contents of `keys` with the effective low boundaries in dimension 0 of the boxes in `source`,
+/// packed densely so the scanning loops can skip over boxes without loading them.
+pub(crate) fn lo_keys<B, ID>(
+    source: &impl BoxSource<B, ID>,
+    policy: impl Policy<B::Num>,
+    keys: &mut Vec<B::Num>,
+) where
+    B: BBox,
+{
+    keys.clear();
+    keys.extend((0..source.len()).map(|idx| policy.lo_of(&source.get(idx).0, 0)));
+}
+
+/// Reports which boxes in `boxes` contain which points in `points` by scanning in dimension 0,
+/// like [`one_way_scan`] with actual points instead of boxes treated as points.
+/// * `boxes` and `points` must be sorted before calling
+/// * `out` will receive the pairs of identifiers, box first.
+/// * `policy` decides how bounds are compared, see [`Policy`].
+pub fn point_scan<B, P, ID>(
+    boxes: &impl BoxSource<B, ID>,
+    points: &PointSet<P, ID>,
+    out: &mut impl PointSink<B, P, ID>,
+    policy: impl Policy<B::Num>,
+) where
+    B: BBox,
+    P: Point<Num = B::Num>,
+    ID: Copy,
+{
+    assert_sorted(boxes, "boxes", "intersect_points");
+    let p_len = points.len();
+    let mut p_min_idx = 0;
+
+    for b_idx in 0..boxes.len() {
+        let (bbox, b_id) = boxes.get(b_idx);
+        let b_min = policy.lo_of(&bbox, 0);
+        let b_max = policy.hi_of(&bbox, 0);
+
+        //skip all points that don't have a chance to be in `bbox`
+        while p_min_idx < p_len && points.get(p_min_idx).0.coord(0) < b_min {
+            p_min_idx += 1;
+        }
+        if p_min_idx == p_len {
+            return;
+        }
+
+        'points: for &(p, p_id) in &points.points()[p_min_idx..] {
+            if p.coord(0) >= b_max {
+                break 'points;
+            }
+            for dim in 1..B::DIM {
+                if !policy.contains_in(&bbox, dim, p.coord(dim)) {
+                    continue 'points;
+                }
+            }
+            out.report(b_id, &bbox, p_id, &p);
+        }
+    }
+}
+
 /// Streams a segment tree to check if the boxes in `intervals` intersect those in `points`,
 /// treating the latter as points in dimension `dim`: intersections are only reported when the low
 /// endpoint in dimension `dim` of a box in `points` is inside the projection of a box in `intervals`.
 /// If `dim > 0`, will recursively stream two segment trees in dimension `dim - 1`, so that
 /// each box will be treated both as an `interval` and as a `point`.
 /// * [`lo`, `hi`) is the segment belonging to this node of the streamed segment tree
-/// * `out` will contain pairs of `ID`s of intersecting boxes.
+/// * `out` will receive the pairs of intersecting boxes.
+/// * `policy` decides how bounds are compared, see [`Policy`].
+///
+/// Copies the boxes once into scratch buffers, which are partitioned in place for the nodes of the segment tree
+/// (like in CGAL), so there are no further copies. The nodes that remain to be processed are kept
+/// on an explicit stack instead of the call stack, so even very deep segment trees can't overflow it.
+#[allow(clippy::too_many_arguments)]
 pub fn hybrid<B, ID, R, const CUTOFF: usize>(
-    intervals: &BBoxSet<B, ID>,
-    points: &BBoxSet<B, ID>,
+    intervals: &impl BoxSource<B, ID>,
+    points: &impl BoxSource<B, ID>,
+    lo: B::Num,
+    hi: B::Num,
+    dim: usize,
+    out: &mut impl Sink<B, ID>,
+    rand: &mut R,
+    policy: impl Policy<B::Num>,
+) where
+    B: BBox,
+    ID: PartialOrd + Copy,
+    B::Num: PartialOrd + HasInfinity,
+    R: Rng,
+{
+    let params = Params {
+        cutoff: CUTOFF,
+        ..Params::default()
+    };
+    hybrid_with_params(intervals, points, lo, hi, dim, out, rand, policy, params);
+}
+
+/// Default for [`Params::max_depth`]
+pub const DEFAULT_MAX_DEPTH: usize = 64;
+
+/// Parameters of the segment trees streamed by [`hybrid_with_params`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Params {
+    /// Nodes with fewer intervals or points than this are scanned instead of being divided further.
+    /// Corresponds to the `CUTOFF` parameter of [`hybrid`].
+    pub cutoff: usize,
+    /// Maximum depth of a segment tree. Nodes at this depth are scanned instead of being divided further,
+    /// which bounds the time spent dividing pathologically clustered inputs that the median can't separate.
+    /// Only counts the nodes of one segment tree, not those of the trees streamed in lower dimensions.
+    pub max_depth: usize,
+    /// If set, nodes are also scanned if scanning them would compare each box with fewer than this many others
+    /// on average, as estimated from a random sample of pairs. This adapts the segment tree to the local density
+    /// of the boxes, scanning sparse regions early while still dividing dense clusters.
+    pub density_cutoff: Option<usize>,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            cutoff: DEFAULT_CUTOFF,
+            max_depth: DEFAULT_MAX_DEPTH,
+            density_cutoff: None,
+        }
+    }
+}
+
+/// Like [`hybrid`], but with the segment trees limited by `params`, which can be chosen at runtime.
+#[allow(clippy::too_many_arguments)]
+pub fn hybrid_with_params<B, ID, R>(
+    intervals: &impl BoxSource<B, ID>,
+    points: &impl BoxSource<B, ID>,
+    lo: B::Num,
+    hi: B::Num,
+    dim: usize,
+    out: &mut impl Sink<B, ID>,
+    rand: &mut R,
+    policy: impl Policy<B::Num>,
+    params: Params,
+) where
+    B: BBox,
+    ID: PartialOrd + Copy,
+    B::Num: PartialOrd + HasInfinity,
+    R: Rng,
+{
+    hybrid_with_median(
+        intervals,
+        points,
+        lo,
+        hi,
+        dim,
+        out,
+        rand,
+        policy,
+        params,
+        ApproxMedian,
+    );
+}
+
+/// Like [`hybrid_with_params`], but dividing segments at the boundaries chosen by `median`, see [`MedianStrategy`].
+#[allow(clippy::too_many_arguments)]
+pub fn hybrid_with_median<B, ID, R>(
+    intervals: &impl BoxSource<B, ID>,
+    points: &impl BoxSource<B, ID>,
+    lo: B::Num,
+    hi: B::Num,
+    dim: usize,
+    out: &mut impl Sink<B, ID>,
+    rand: &mut R,
+    policy: impl Policy<B::Num>,
+    params: Params,
+    median: impl MedianStrategy<B::Num>,
+) where
+    B: BBox,
+    ID: PartialOrd + Copy,
+    B::Num: PartialOrd + HasInfinity,
+    R: Rng,
+{
+    assert_sorted(intervals, "intervals", "hybrid");
+    assert_sorted(points, "points", "hybrid");
+    let mut buffers = [Copies::of(intervals, policy), Copies::of(points, policy)];
+    let order = check_order(intervals, points, B::DIM, policy);
+    hybrid_in_place(
+        &Copies,
+        &mut buffers,
+        lo,
+        hi,
+        dim,
+        out,
+        rand,
+        policy,
+        params,
+        median,
+        order.as_deref(),
+    );
+}
+
+/// Same algorithm as [`hybrid`], but instead of copying the boxes into scratch buffers, it partitions
+/// permutations of their indices (`u32` unless the inputs have more than `u32::MAX` boxes together).
+/// Needs much less memory than [`hybrid`] for large boxes or identifiers,
+/// but is usually slower, since the boxes are no longer accessed in order.
+#[allow(clippy::too_many_arguments)]
+pub fn hybrid_indexed<B, ID, R, const CUTOFF: usize>(
+    intervals: &impl BoxSource<B, ID>,
+    points: &impl BoxSource<B, ID>,
+    lo: B::Num,
+    hi: B::Num,
+    dim: usize,
+    out: &mut impl Sink<B, ID>,
+    rand: &mut R,
+    policy: impl Policy<B::Num>,
+) where
+    B: BBox,
+    ID: PartialOrd + Copy,
+    B::Num: PartialOrd + HasInfinity,
+    R: Rng,
+{
+    assert_sorted(intervals, "intervals", "hybrid_indexed");
+    assert_sorted(points, "points", "hybrid_indexed");
+    let params = Params {
+        cutoff: CUTOFF,
+        ..Params::default()
+    };
+
+    let order = check_order(intervals, points, B::DIM, policy);
+    if intervals.len() + points.len() <= u32::MAX as usize {
+        let scratch = Indexed::<_, _, u32>::new(intervals, points);
+        let (intervals, points) = scratch.permutations(policy);
+        hybrid_in_place(
+            &scratch,
+            &mut [intervals, points],
+            lo,
+            hi,
+            dim,
+            out,
+            rand,
+            policy,
+            params,
+            ApproxMedian,
+            order.as_deref(),
+        );
+    } else {
+        let scratch = Indexed::<_, _, usize>::new(intervals, points);
+        let (intervals, points) = scratch.permutations(policy);
+        hybrid_in_place(
+            &scratch,
+            &mut [intervals, points],
+            lo,
+            hi,
+            dim,
+            out,
+            rand,
+            policy,
+            params,
+            ApproxMedian,
+            order.as_deref(),
+        );
+    }
+}
+
+/// Buffers of items standing for boxes, which [`hybrid_in_place`] partitions in place
+pub(crate) trait Scratch<B: BBox, ID> {
+    type Item: Copy;
+
+    /// Returns the box the item stands for and its identifier.
+    fn resolve(&self, item: Self::Item) -> (B, ID);
+
+    /// Returns `true` if the box `a` stands for comes no later than that of `b`
+    /// in the order of a sorted [`BBoxSet`](crate::set::BBoxSet): by low boundary in dimension 0, then by identifier.
+    fn le(&self, a: Self::Item, b: Self::Item) -> bool;
+}
+
+/// Copies of the boxes and their identifiers
+pub(crate) struct Copies;
+
+impl Copies {
+    /// Returns copies of the boxes of `source` that aren't empty according to `policy`, see [`Policy::is_empty`].
+    pub(crate) fn of<B: BBox, ID>(
+        source: &impl BoxSource<B, ID>,
+        policy: impl Policy<B::Num>,
+    ) -> Vec<(B, ID)> {
+        (0..source.len())
+            .map(|idx| source.get(idx))
+            .filter(|(bbox, _)| !policy.is_empty(bbox))
+            .collect()
+    }
+}
+
+impl<B: BBox, ID> Scratch<B, ID> for Copies
+where
+    ID: Copy + PartialOrd,
+    B::Num: PartialOrd,
+{
+    type Item = (B, ID);
+
+    fn resolve(&self, item: (B, ID)) -> (B, ID) {
+        item
+    }
+
+    fn le(&self, (a, a_id): (B, ID), (b, b_id): (B, ID)) -> bool {
+        match a.lo(0).partial_cmp(&b.lo(0)) {
+            Some(Ordering::Less) => true,
+            Some(Ordering::Equal) => a_id <= b_id,
+            _ => false,
+        }
+    }
+}
+
+/// Index of a box in the inputs of [`hybrid_indexed`]
+trait Index: Copy + Ord {
+    fn from_usize(idx: usize) -> Self;
+    fn to_usize(self) -> usize;
+}
+
+impl Index for u32 {
+    fn from_usize(idx: usize) -> Self {
+        idx as u32
+    }
+
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+}
+
+impl Index for usize {
+    fn from_usize(idx: usize) -> Self {
+        idx
+    }
+
+    fn to_usize(self) -> usize {
+        self
+    }
+}
+
+/// Indices into the concatenation of two sources
+struct Indexed<'a, S, T, I> {
+    a: &'a S,
+    b: &'a T,
+    _index: PhantomData<I>,
+}
+
+impl<'a, S, T, I: Index> Indexed<'a, S, T, I> {
+    fn new(a: &'a S, b: &'a T) -> Self {
+        Self {
+            a,
+            b,
+            _index: PhantomData,
+        }
+    }
+
+    /// Returns the indices of the boxes of the first and second source that aren't empty
+    /// according to `policy`, see [`Policy::is_empty`].
+    fn permutations<B: BBox, ID>(&self, policy: impl Policy<B::Num>) -> (Vec<I>, Vec<I>)
+    where
+        S: BoxSource<B, ID>,
+        T: BoxSource<B, ID>,
+    {
+        let (a_len, b_len) = (self.a.len(), self.b.len());
+        let nonempty = |item: &I| !policy.is_empty(&Scratch::<B, ID>::resolve(self, *item).0);
+        (
+            (0..a_len).map(I::from_usize).filter(nonempty).collect(),
+            (a_len..a_len + b_len)
+                .map(I::from_usize)
+                .filter(nonempty)
+                .collect(),
+        )
+    }
+}
+
+impl<B, ID, S, T, I> Scratch<B, ID> for Indexed<'_, S, T, I>
+where
+    B: BBox,
+    S: BoxSource<B, ID>,
+    T: BoxSource<B, ID>,
+    I: Index,
+{
+    type Item = I;
+
+    fn resolve(&self, item: I) -> (B, ID) {
+        let idx = item.to_usize();
+        let a_len = self.a.len();
+        if idx < a_len {
+            self.a.get(idx)
+        } else {
+            self.b.get(idx - a_len)
+        }
+    }
+
+    fn le(&self, a: I, b: I) -> bool {
+        // the sources are sorted, so their indices are in the same order as their boxes
+        a <= b
+    }
+}
+
+/// A range of a scratch buffer, which is a source of the boxes its items stand for
+pub(crate) struct Run<'a, C, T> {
+    pub(crate) scratch: &'a C,
+    pub(crate) items: &'a [T],
+}
+
+impl<B, ID, C> BoxSource<B, ID> for Run<'_, C, C::Item>
+where
+    B: BBox,
+    C: Scratch<B, ID>,
+{
+    fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    fn get(&self, idx: usize) -> (B, ID) {
+        self.scratch.resolve(self.items[idx])
+    }
+}
+
+/// Moves the items for which `pred` returns `true` to the front of `items` and returns their number,
+/// moving their keys (see [`lo_keys`]) along with them. Both parts keep the order they had in `items`,
+/// the others are held in `spill` in the meantime.
+fn partition_in_place<T: Copy, N: Copy>(
+    (items, keys): (&mut [T], &mut [N]),
+    spill: &mut (Vec<T>, Vec<N>),
+    mut pred: impl FnMut(T) -> bool,
+) -> usize {
+    spill.0.clear();
+    spill.1.clear();
+    let mut n = 0;
+    for idx in 0..items.len() {
+        let (item, key) = (items[idx], keys[idx]);
+        if pred(item) {
+            items[n] = item;
+            keys[n] = key;
+            n += 1;
+        } else {
+            spill.0.push(item);
+            spill.1.push(key);
+        }
+    }
+    items[n..].copy_from_slice(&spill.0);
+    keys[n..].copy_from_slice(&spill.1);
+    n
+}
+
+/// Merges the sorted parts `items[..mid]` and `items[mid..]` and their keys in place, undoing [`partition_in_place`].
+/// `le` compares items as in [`Scratch::le`], `spill` holds the first part in the meantime.
+pub(crate) fn merge_in_place<T: Copy, N: Copy>(
+    (items, keys): (&mut [T], &mut [N]),
+    mid: usize,
+    spill: &mut (Vec<T>, Vec<N>),
+    mut le: impl FnMut(T, T) -> bool,
+) {
+    spill.0.clear();
+    spill.1.clear();
+    spill.0.extend_from_slice(&items[..mid]);
+    spill.1.extend_from_slice(&keys[..mid]);
+    let (mut l, mut r) = (0, mid);
+    while l < mid && r < items.len() {
+        let write = l + r - mid;
+        if le(spill.0[l], items[r]) {
+            items[write] = spill.0[l];
+            keys[write] = spill.1[l];
+            l += 1;
+        } else {
+            items[write] = items[r];
+            keys[write] = keys[r];
+            r += 1;
+        }
+    }
+    // the rest of the second part is already in place
+    let write = l + r - mid;
+    items[write..write + mid - l].copy_from_slice(&spill.0[l..]);
+    keys[write..write + mid - l].copy_from_slice(&spill.1[l..]);
+}
+
+/// A range of one of the two scratch buffers of [`hybrid_in_place`]
+#[derive(Clone, Copy)]
+struct Range {
+    buffer: usize,
+    start: usize,
+    end: usize,
+}
+
+impl Range {
+    /// Returns the first `len` items of this range and the rest.
+    fn split_at(self, len: usize) -> (Range, Range) {
+        let mid = self.start + len;
+        (Range { end: mid, ..self }, Range { start: mid, ..self })
+    }
+}
+
+/// Returns the items (or keys) in the ranges `a` and `b`, which must belong to different buffers.
+fn ranges<'a, T>(buffers: &'a mut [&mut [T]; 2], a: Range, b: Range) -> (&'a mut [T], &'a mut [T]) {
+    debug_assert_ne!(a.buffer, b.buffer);
+    let [first, second] = buffers;
+    let (a_buf, b_buf) = if a.buffer == 0 {
+        (first, second)
+    } else {
+        (second, first)
+    };
+    (&mut a_buf[a.start..a.end], &mut b_buf[b.start..b.end])
+}
+
+/// Number of sampled pairs [`sparse`] expects to overlap in dimension 0 at the density cutoff
+const DENSITY_HITS: usize = 8;
+
+/// Returns `true` if scanning `intervals` and `points` is estimated to compare each box with fewer than
+/// `density_cutoff` others on average, i.e. if fewer than [`DENSITY_HITS`] of a random sample of pairs,
+/// sized so that this many are expected at the cutoff, overlap in dimension 0.
+fn sparse<B, ID, C, R>(
+    scratch: &C,
+    intervals: &[C::Item],
+    points: &[C::Item],
+    density_cutoff: usize,
+    rand: &mut R,
+    policy: impl Policy<B::Num>,
+) -> bool
+where
+    B: BBox,
+    C: Scratch<B, ID>,
+    R: Rng,
+{
+    let (i_len, p_len) = (intervals.len() as f64, points.len() as f64);
+    let samples = DENSITY_HITS as f64 * i_len * p_len / (density_cutoff as f64 * (i_len + p_len));
+    let samples = samples.ceil() as usize;
+    if samples >= intervals.len() * points.len() {
+        // sampling would take as long as scanning
+        return false;
+    }
+
+    let mut hits = 0;
+    for _ in 0..samples {
+        let i = scratch
+            .resolve(intervals[rand.rand_usize(intervals.len())])
+            .0;
+        let p = scratch.resolve(points[rand.rand_usize(points.len())]).0;
+        if policy.intersects_in(&i, &p, 0) {
+            hits += 1;
+            if hits == DENSITY_HITS {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// Work left to do for [`hybrid_in_place`]
+enum Task<N> {
+    /// Process a node of the segment tree, up to Step 4
+    Node {
+        intervals: Range,
+        points: Range,
+        lo: N,
+        hi: N,
+        dim: usize,
+        depth: usize,
+    },
+    /// Steps 5 and 6 of a node, after the segment trees of Step 4 are done
+    Left {
+        intervals_lr: Range,
+        points: Range,
+        lo: N,
+        hi: N,
+        dim: usize,
+        depth: usize,
+    },
+    /// Step 7 of a node, after the left subtree is done
+    Right {
+        intervals_lr: Range,
+        points_r: Range,
+        mi: N,
+        hi: N,
+        dim: usize,
+        depth: usize,
+    },
+    /// Restores the order of a range that was partitioned into its first `len` items and the rest,
+    /// after the subtrees that used the parts are done
+    Merge { range: Range, len: usize },
+}
+
+/// [`hybrid`] on the boxes in two scratch buffers, `buffers[0]` holding the intervals and `buffers[1]` the points,
+/// which must be sorted (see [`Scratch::le`]). The buffers are partitioned in place for the child nodes,
+/// along with the keys the scans need (see [`lo_keys`]), which are computed once for all nodes.
+/// The scans check the dimensions in `order`, which the caller computes once for the query, see [`check_order`].
+/// Partitioning is stable, and ranges are merged again once the child nodes are done with them,
+/// so every node finds its ranges sorted and can scan them right away. The buffers are left sorted.
+///
+/// Instead of recursing, the work that remains to be done is kept on an explicit stack (see [`Traversal`]),
+/// so that deep segment trees can't overflow the call stack.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn hybrid_in_place<B, ID, C, R>(
+    scratch: &C,
+    buffers: &mut [Vec<C::Item>; 2],
     lo: B::Num,
     hi: B::Num,
     dim: usize,
-    out: &mut Vec<(ID, ID)>,
+    out: &mut impl Sink<B, ID>,
     rand: &mut R,
+    policy: impl Policy<B::Num>,
+    params: Params,
+    median: impl MedianStrategy<B::Num>,
+    order: Option<&[usize]>,
 ) where
     B: BBox,
     ID: PartialOrd + Copy,
     B::Num: PartialOrd + HasInfinity,
+    C: Scratch<B, ID>,
     R: Rng,
 {
-    // The steps of the algorithm are numbered as in the paper "Fast software for box intersections":
-    // https://dl.acm.org/doi/10.1145/336154.336192
+    let order = order.map(<[usize]>::to_vec);
+    let mut state = HybridState::new(
+        scratch,
+        std::mem::take(buffers),
+        policy,
+        params,
+        median,
+        order,
+    );
+    state.push_root(0, lo, hi, dim);
+    while state.step(scratch, out, rand) {}
+    *buffers = state.buffers;
+}
+
+/// Returns the keys (see [`lo_keys`]) of the boxes that `items` stand for.
+pub(crate) fn keys_of<B, ID, C>(
+    scratch: &C,
+    items: &[C::Item],
+    policy: impl Policy<B::Num>,
+) -> Vec<B::Num>
+where
+    B: BBox,
+    C: Scratch<B, ID>,
+{
+    let mut keys = Vec::new();
+    lo_keys(&Run { scratch, items }, policy, &mut keys);
+    keys
+}
+
+/// The state of [`hybrid_in_place`]: the scratch buffers, holding items of type `T` (see [`Scratch`]),
+/// their keys and the [`Traversal`] of the segment trees. The algorithm can be suspended after every task,
+/// which is how [`IntersectIter`](crate::iter::IntersectIter) finds the pairs lazily.
+pub(crate) struct HybridState<T, N, P, M> {
+    buffers: [Vec<T>; 2],
+    keys: [Vec<N>; 2],
+    traversal: Traversal<T, N, P, M>,
+}
+
+impl<T, N, P, M> HybridState<T, N, P, M>
+where
+    T: Copy,
+    N: Copy + PartialOrd + HasInfinity,
+    P: Policy<N>,
+    M: MedianStrategy<N>,
+{
+    /// Creates the state for the boxes in `buffers`, which must be sorted, without any tasks yet.
+    /// The scans check the dimensions in `order`, see [`check_order`].
+    pub(crate) fn new<B, ID, C>(
+        scratch: &C,
+        buffers: [Vec<T>; 2],
+        policy: P,
+        params: Params,
+        median: M,
+        order: Option<Vec<usize>>,
+    ) -> Self
+    where
+        B: BBox<Num = N>,
+        C: Scratch<B, ID, Item = T>,
+    {
+        let keys = [
+            keys_of(scratch, &buffers[0], policy),
+            keys_of(scratch, &buffers[1], policy),
+        ];
+        Self {
+            buffers,
+            keys,
+            traversal: Traversal::new(policy, params, median, order),
+        }
+    }
+
+    /// Adds the root of a segment tree for the segment [`lo`, `hi`) in dimension `dim`, with the intervals
+    /// in `buffers[intervals]` and the points in the other buffer. Roots are processed in the reverse order
+    /// they were added in, each once the trees of the roots added after it are done.
+    pub(crate) fn push_root(&mut self, intervals: usize, lo: N, hi: N, dim: usize) {
+        let lens = [self.buffers[0].len(), self.buffers[1].len()];
+        self.traversal.push_root(intervals, lens, lo, hi, dim, 0);
+    }
+
+    /// Does the task on top of the stack, reporting intersections to `out` if it scans boxes.
+    /// Returns `false` if there was no task left.
+    pub(crate) fn step<B, ID, C, R>(
+        &mut self,
+        scratch: &C,
+        out: &mut impl Sink<B, ID>,
+        rand: &mut R,
+    ) -> bool
+    where
+        B: BBox<Num = N>,
+        ID: PartialOrd + Copy,
+        C: Scratch<B, ID, Item = T>,
+        R: Rng,
+    {
+        let [intervals, points] = &mut self.buffers;
+        let [i_keys, p_keys] = &mut self.keys;
+        self.traversal
+            .step(scratch, [intervals, points], [i_keys, p_keys], out, rand)
+    }
+}
+
+/// The tasks that remain to be done to traverse segment trees over two scratch buffers of items of type `T`,
+/// and the memory they reuse. The buffers themselves are passed to every [`Traversal::step`],
+/// so [`intersect_ze_par`](crate::parallel::intersect_ze_par) can traverse parts of larger buffers.
+pub(crate) struct Traversal<T, N, P, M> {
+    tasks: Vec<Task<N>>,
+    samples: Vec<N>,
+    spill: (Vec<T>, Vec<N>),
+    policy: P,
+    params: Params,
+    median: M,
+    order: Option<Vec<usize>>,
+}
+
+impl<T, N, P, M> Traversal<T, N, P, M>
+where
+    T: Copy,
+    N: Copy + PartialOrd + HasInfinity,
+    P: Policy<N>,
+    M: MedianStrategy<N>,
+{
+    /// Creates a traversal without any tasks yet. The scans check the dimensions in `order`, see [`check_order`].
+    pub(crate) fn new(policy: P, params: Params, median: M, order: Option<Vec<usize>>) -> Self {
+        Self {
+            tasks: Vec::new(),
+            samples: Vec::new(),
+            spill: (Vec::new(), Vec::new()),
+            policy,
+            params,
+            median,
+            order,
+        }
+    }
+
+    /// Adds the root of a segment tree for the segment [`lo`, `hi`) in dimension `dim`, with the intervals
+    /// in buffer `intervals` and the points in the other one, whose lengths are `lens`.
+    /// The root counts as being `depth` levels deep, see [`Params::max_depth`].
+    pub(crate) fn push_root(
+        &mut self,
+        intervals: usize,
+        lens: [usize; 2],
+        lo: N,
+        hi: N,
+        dim: usize,
+        depth: usize,
+    ) {
+        let all = |buffer: usize| Range {
+            buffer,
+            start: 0,
+            end: lens[buffer],
+        };
+        self.tasks.push(Task::Node {
+            intervals: all(intervals),
+            points: all(1 - intervals),
+            lo,
+            hi,
+            dim,
+            depth,
+        });
+    }
+
+    /// Does the task on top of the stack on `buffers` and their `keys`, reporting intersections to `out`
+    /// if it scans boxes. Returns `false` if there was no task left.
+    pub(crate) fn step<B, ID, C, R>(
+        &mut self,
+        scratch: &C,
+        mut buffers: [&mut [T]; 2],
+        mut keys: [&mut [N]; 2],
+        out: &mut impl Sink<B, ID>,
+        rand: &mut R,
+    ) -> bool
+    where
+        B: BBox<Num = N>,
+        ID: PartialOrd + Copy,
+        C: Scratch<B, ID, Item = T>,
+        R: Rng,
+    {
+        // The steps of the algorithm are numbered as in the paper "Fast software for box intersections":
+        // https://dl.acm.org/doi/10.1145/336154.336192
+        let task = match self.tasks.pop() {
+            Some(task) => task,
+            None => return false,
+        };
+        let policy = self.policy;
+        let order = self.order.as_deref();
+        let (ninfty, infty) = (N::NINFTY, N::INFTY);
+
+        match task {
+            Task::Node {
+                intervals: intervals_range,
+                points: points_range,
+                lo,
+                hi,
+                dim,
+                depth,
+            } => {
+                let (intervals, points) = ranges(&mut buffers, intervals_range, points_range);
+                let (i_keys, p_keys) = ranges(&mut keys, intervals_range, points_range);
+
+                // Steps 1 to 3
+                let node = (lo, hi, dim, depth);
+                let (i, p) = ((&*intervals, &*i_keys), (&*points, &*p_keys));
+                if leaf(scratch, i, p, node, out, rand, policy, self.params, order) {
+                    return true;
+                }
+
+                // Step 4
+                let i = (intervals, i_keys);
+                let m = spanning(scratch, i, &mut self.spill, lo, hi, dim, policy);
+                let (intervals_m, intervals_lr) = intervals_range.split_at(m);
+
+                // continue with Step 5 once the segment trees of Step 4 are done,
+                // and restore the order of the intervals once the subtrees are done as well
+                self.tasks.push(Task::Merge {
+                    range: intervals_range,
+                    len: m,
+                });
+                self.tasks.push(Task::Left {
+                    intervals_lr,
+                    points: points_range,
+                    lo,
+                    hi,
+                    dim,
+                    depth,
+                });
+                // Step 4: stream two segment trees in the next dimension for the intervals stored at this node
+                self.tasks.push(Task::Node {
+                    intervals: points_range,
+                    points: intervals_m,
+                    lo: ninfty,
+                    hi: infty,
+                    dim: dim - 1,
+                    depth: 0,
+                });
+                self.tasks.push(Task::Node {
+                    intervals: intervals_m,
+                    points: points_range,
+                    lo: ninfty,
+                    hi: infty,
+                    dim: dim - 1,
+                    depth: 0,
+                });
+            }
+            Task::Left {
+                intervals_lr: intervals_range,
+                points: points_range,
+                lo,
+                hi,
+                dim,
+                depth,
+            } => {
+                let (intervals_lr, points) = ranges(&mut buffers, intervals_range, points_range);
+                let (i_keys, p_keys) = ranges(&mut keys, intervals_range, points_range);
+
+                // Step 5
+                let run = Run {
+                    scratch,
+                    items: &*points,
+                };
+                let mi = self.median.median(&run, dim, rand, &mut self.samples);
+                let Some(mi) = divide(policy, mi, lo, hi) else {
+                    let (i, p) = ((&*intervals_lr, &*i_keys), (&*points, &*p_keys));
+                    scan(scratch, i, p, dim, order, out, policy);
+                    return true;
+                };
+                let p = left_points(scratch, (points, p_keys), &mut self.spill, mi, dim, policy);
+                let (points_l, points_r) = points_range.split_at(p);
+                let i = (intervals_lr, i_keys);
+                let l = left_intervals(scratch, i, &mut self.spill, mi, dim, policy);
+                let (intervals_l, _) = intervals_range.split_at(l);
+
+                self.tasks.push(Task::Merge {
+                    range: points_range,
+                    len: p,
+                });
+                self.tasks.push(Task::Right {
+                    intervals_lr: intervals_range,
+                    points_r,
+                    mi,
+                    hi,
+                    dim,
+                    depth,
+                });
+                // the right subtree partitions intervals_lr again, so it has to be sorted again first
+                self.tasks.push(Task::Merge {
+                    range: intervals_range,
+                    len: l,
+                });
+                // Step 6: left subtree
+                self.tasks.push(Task::Node {
+                    intervals: intervals_l,
+                    points: points_l,
+                    lo,
+                    hi: mi,
+                    dim,
+                    depth: depth + 1,
+                });
+            }
+            Task::Right {
+                intervals_lr: intervals_range,
+                points_r,
+                mi,
+                hi,
+                dim,
+                depth,
+            } => {
+                let intervals_lr = ranges(&mut buffers, intervals_range, points_r).0;
+                let i_keys = ranges(&mut keys, intervals_range, points_r).0;
+                let i = (intervals_lr, i_keys);
+                let r = right_intervals(scratch, i, &mut self.spill, mi, dim, policy);
+                let (intervals_r, _) = intervals_range.split_at(r);
+
+                self.tasks.push(Task::Merge {
+                    range: intervals_range,
+                    len: r,
+                });
+                // Step 7: right subtree
+                self.tasks.push(Task::Node {
+                    intervals: intervals_r,
+                    points: points_r,
+                    lo: mi,
+                    hi,
+                    dim,
+                    depth: depth + 1,
+                });
+            }
+            Task::Merge { range, len } => {
+                let items = &mut buffers[range.buffer][range.start..range.end];
+                let keys = &mut keys[range.buffer][range.start..range.end];
+                merge_in_place((items, keys), len, &mut self.spill, |a, b| scratch.le(a, b));
+            }
+        }
+        true
+    }
+}
 
+/// A node of a segment tree: the segment [`lo`, `hi`) in dimension `dim`, and its depth in the tree
+pub(crate) type NodeBounds<N> = (N, N, usize, usize);
+
+/// Items of a scratch buffer and their keys, see [`lo_keys`]
+pub(crate) type Items<'a, T, N> = (&'a [T], &'a [N]);
+
+/// Mutable items of a scratch buffer and their keys, see [`lo_keys`]
+pub(crate) type ItemsMut<'a, T, N> = (&'a mut [T], &'a mut [N]);
+
+/// Steps 1 to 3 of [`hybrid`] for a node with the given `intervals` and `points`:
+/// returns `true` if there is nothing to do for the node, or if it is a leaf, which is then scanned.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn leaf<B, ID, C, R>(
+    scratch: &C,
+    (intervals, i_keys): Items<'_, C::Item, B::Num>,
+    (points, p_keys): Items<'_, C::Item, B::Num>,
+    (lo, hi, dim, depth): NodeBounds<B::Num>,
+    out: &mut impl Sink<B, ID>,
+    rand: &mut R,
+    policy: impl Policy<B::Num>,
+    params: Params,
+    order: Option<&[usize]>,
+) -> bool
+where
+    B: BBox,
+    ID: PartialOrd + Copy,
+    C: Scratch<B, ID>,
+    R: Rng,
+{
     // Step 1: return if input is empty
-    if intervals.empty() || points.empty() || hi <= lo {
-        return;
+    if intervals.is_empty() || points.is_empty() || hi <= lo {
+        return true;
     }
 
     // Step 2: first hybridization method: scan if only dimension 0 is left to check
     if dim == 0 {
-        one_way_scan(intervals, points, 0, out);
-        return;
+        let (intervals, points) = (
+            Run {
+                scratch,
+                items: intervals,
+            },
+            Run {
+                scratch,
+                items: points,
+            },
+        );
+        one_way_scan_keyed(&intervals, &points, p_keys, 0, order, out, policy);
+        return true;
     }
 
     // Step 3: second hybridization method: scan if size of input is smaller than cutoff
-    if intervals.len() < CUTOFF || points.len() < CUTOFF {
-        simulated_one_way_scan(intervals, points, dim, out);
-        return;
-    }
-
-    // Step 4: let intervals_m contain the intervals that would be stored at this node of the segment tree
-    // because they span the segment [lo, hi), meaning it is one of their canonical segments
-    // let intervals_lr contain the intervals not stored at this node
-    let (intervals_m, intervals_lr) =
-        intervals.partition(|(i, _)| i.lo(dim) < lo && i.hi(dim) > hi);
-    let (ninfty, infty) = (B::Num::NINFTY, B::Num::INFTY);
-
-    // Step 4: stream two segment trees in the next dimension for the intervals stored at this node
-    hybrid::<B, ID, R, CUTOFF>(&intervals_m, points, ninfty, infty, dim - 1, out, rand);
-    hybrid::<B, ID, R, CUTOFF>(points, &intervals_m, ninfty, infty, dim - 1, out, rand);
-
-    // Step 5: divide the segment [lo, hi) into segments [lo, mi) and [mi, hi) by computing an approximate median
-    let mi = points.approx_median(dim, rand);
-
-    // if we failed to divide the segment into subsegments, just scan instead
-    if mi == hi || mi == lo {
-        simulated_one_way_scan(&intervals_lr, points, dim, out);
-        return;
-    }
-
-    // let points_l contain the points in the left subsegment [lo, mi),
-    // points_r those in the right subsegment [mi, hi)
-    let (points_l, points_r) = points.partition(|(p, _)| p.lo(dim) < mi);
-    let len = intervals_lr.len();
-    let (mut intervals_l, mut intervals_r) =
-        (BBoxSet::with_capacity(len), BBoxSet::with_capacity(len));
-
-    // let intervals_l/r contain the intervals stored somewhere in the left/right subtree
-    // because they intersect [lo, mi)/[mi, hi) but don't span [lo, hi)
-    // intervals_l and intervals_r are not usually disjoint!
-    for &(i, id) in &intervals_lr.boxes {
-        if i.lo(dim) < mi {
-            intervals_l.push(id, i);
-        }
-
-        if i.hi(dim) > mi {
-            intervals_r.push(id, i);
-        }
+    // (or the segment tree is too deep already, see `Params::max_depth`)
+    // (or the boxes are too sparse for dividing them to pay off, see `Params::density_cutoff`)
+    if intervals.len() < params.cutoff
+        || points.len() < params.cutoff
+        || depth >= params.max_depth
+        || params.density_cutoff.is_some_and(|density_cutoff| {
+            sparse(scratch, intervals, points, density_cutoff, rand, policy)
+        })
+    {
+        let (i, p) = ((intervals, i_keys), (points, p_keys));
+        scan(scratch, i, p, dim, order, out, policy);
+        return true;
     }
+    false
+}
+
+/// Scans `intervals` and `points` as a leaf of a segment tree in dimension `dim` (see [`simulated_one_way_scan`]).
+pub(crate) fn scan<B, ID, C>(
+    scratch: &C,
+    (intervals, i_keys): Items<'_, C::Item, B::Num>,
+    (points, p_keys): Items<'_, C::Item, B::Num>,
+    dim: usize,
+    order: Option<&[usize]>,
+    out: &mut impl Sink<B, ID>,
+    policy: impl Policy<B::Num>,
+) where
+    B: BBox,
+    ID: PartialOrd + Copy,
+    C: Scratch<B, ID>,
+{
+    let (intervals, points) = (
+        Run {
+            scratch,
+            items: intervals,
+        },
+        Run {
+            scratch,
+            items: points,
+        },
+    );
+    simulated_one_way_scan_keyed(
+        &intervals,
+        &points,
+        (i_keys, p_keys),
+        dim,
+        order,
+        out,
+        policy,
+    );
+}
+
+/// Step 4 of [`hybrid`]: moves the intervals that would be stored at the node for the segment [`lo`, `hi`)
+/// of the segment tree in dimension `dim`, because they span it (meaning it is one of their canonical segments),
+/// to the front of `intervals` and returns their number. The others are not stored at this node.
+pub(crate) fn spanning<B, ID, C>(
+    scratch: &C,
+    intervals: ItemsMut<'_, C::Item, B::Num>,
+    spill: &mut (Vec<C::Item>, Vec<B::Num>),
+    lo: B::Num,
+    hi: B::Num,
+    dim: usize,
+    policy: impl Policy<B::Num>,
+) -> usize
+where
+    B: BBox,
+    C: Scratch<B, ID>,
+{
+    partition_in_place(intervals, spill, |i| {
+        let i = scratch.resolve(i).0;
+        policy.lo_of(&i, dim) < lo && policy.hi_of(&i, dim) > hi
+    })
+}
+
+/// Step 5 of [`hybrid`]: returns the boundary `mi` dividing the segment [`lo`, `hi`) into [`lo`, `mi`)
+/// and [`mi`, `hi`), given the `median` of the low boundaries of the points, or `None` if it doesn't divide it.
+/// Then the points are scanned instead.
+pub(crate) fn divide<N: Copy + PartialOrd>(
+    policy: impl Policy<N>,
+    median: N,
+    lo: N,
+    hi: N,
+) -> Option<N> {
+    // the policy's low boundary is monotonic, so it can be applied to the median.
+    // This also covers infinite medians: only a segment with an infinite boundary
+    // can contain points at infinity, so `mi` then equals that boundary
+    let mi = policy.lo(median);
+    (mi != hi && mi != lo).then_some(mi)
+}
 
-    hybrid::<B, ID, R, CUTOFF>(&intervals_l, &points_l, lo, mi, dim, out, rand); // Step 6: left subtree
-    hybrid::<B, ID, R, CUTOFF>(&intervals_r, &points_r, mi, hi, dim, out, rand);
-    // Step 7: right subtree
+/// Step 5 of [`hybrid`]: moves the points in the left subsegment [`lo`, `mi`) to the front of `points`
+/// and returns their number. The others are in the right subsegment [`mi`, `hi`).
+pub(crate) fn left_points<B, ID, C>(
+    scratch: &C,
+    points: ItemsMut<'_, C::Item, B::Num>,
+    spill: &mut (Vec<C::Item>, Vec<B::Num>),
+    mi: B::Num,
+    dim: usize,
+    policy: impl Policy<B::Num>,
+) -> usize
+where
+    B: BBox,
+    C: Scratch<B, ID>,
+{
+    partition_in_place(points, spill, |p| {
+        policy.lo_of(&scratch.resolve(p).0, dim) < mi
+    })
+}
+
+/// Step 6 of [`hybrid`]: moves the intervals not stored at the node (see [`spanning`]) that are stored somewhere
+/// in the left subtree, because they intersect [`lo`, `mi`), to the front of `intervals` and returns their number.
+/// They are not usually disjoint from those of the right subtree (see [`right_intervals`]).
+pub(crate) fn left_intervals<B, ID, C>(
+    scratch: &C,
+    intervals: ItemsMut<'_, C::Item, B::Num>,
+    spill: &mut (Vec<C::Item>, Vec<B::Num>),
+    mi: B::Num,
+    dim: usize,
+    policy: impl Policy<B::Num>,
+) -> usize
+where
+    B: BBox,
+    C: Scratch<B, ID>,
+{
+    partition_in_place(intervals, spill, |i| {
+        policy.lo_of(&scratch.resolve(i).0, dim) < mi
+    })
+}
+
+/// Step 7 of [`hybrid`]: like [`left_intervals`], for the right subtree,
+/// whose intervals intersect [`mi`, `hi`).
+pub(crate) fn right_intervals<B, ID, C>(
+    scratch: &C,
+    intervals: ItemsMut<'_, C::Item, B::Num>,
+    spill: &mut (Vec<C::Item>, Vec<B::Num>),
+    mi: B::Num,
+    dim: usize,
+    policy: impl Policy<B::Num>,
+) -> usize
+where
+    B: BBox,
+    C: Scratch<B, ID>,
+{
+    partition_in_place(intervals, spill, |i| {
+        policy.hi_of(&scratch.resolve(i).0, dim) > mi
+    })
 }
@@ -0,0 +1,155 @@
+//! Static interval tree for overlap and stabbing queries against a set of [`Interval`]s, see [`IntervalTree`].
+//!
+//! # Examples
+//! ```
+//! use box_intersect_ze::boxes::Interval;
+//! use box_intersect_ze::interval::IntervalTree;
+//! use box_intersect_ze::set::BBoxSet;
+//!
+//! let mut genes = BBoxSet::new();
+//! genes.push("a", Interval::new(100, 250));
+//! genes.push("b", Interval::new(200, 300));
+//! genes.push("c", Interval::new(400, 450));
+//! let tree = IntervalTree::build(&genes);
+//!
+//! let mut hits = Vec::new();
+//! tree.query(&Interval::new(240, 420), |id, _interval| hits.push(id));
+//! assert_eq!(hits, ["a", "b", "c"]);
+//!
+//! let mut hits = Vec::new();
+//! tree.query_point(250, |id, _interval| hits.push(id));
+//! assert_eq!(hits, ["b"]);
+//! ```
+
+use std::cmp::Ordering;
+
+use crate::boxes::Interval;
+use crate::policy::{HalfOpen, Policy};
+use crate::source::BoxSource;
+
+/// A static interval tree: the intervals sorted by their low boundaries, implicitly forming a balanced
+/// binary tree whose node for each range of intervals is the one in the middle. Every node stores the highest
+/// high boundary of its range, so queries skip ranges whose intervals all end before the query.
+/// * Queries take *O*(log *n* + *k*) for *k* hits, and report the hits in order of their low boundaries.
+/// * Uses no memory beyond the intervals and one boundary per interval, so it suits huge sets,
+///   e.g. the features of a genome.
+///
+/// Like the other algorithms, intervals are half-open and empty intervals (see [`Policy::is_empty`])
+/// never intersect. Bounds must not be NaN.
+#[derive(Clone, Debug)]
+pub struct IntervalTree<N, ID> {
+    /// Intervals and their identifiers, sorted by low boundary
+    items: Vec<(Interval<N>, ID)>,
+    /// Highest high boundary of the range of intervals whose node is at each index
+    max_hi: Vec<N>,
+}
+
+impl<N, ID> IntervalTree<N, ID>
+where
+    N: Copy + PartialOrd,
+    ID: Copy,
+{
+    /// Builds a tree over the intervals of `intervals`, which need not be sorted. Empty intervals are left out.
+    pub fn build(intervals: &impl BoxSource<Interval<N>, ID>) -> Self {
+        let mut items: Vec<_> = (0..intervals.len())
+            .map(|idx| intervals.get(idx))
+            .filter(|(interval, _id)| !HalfOpen.is_empty(interval))
+            .collect();
+        items.sort_by(|(a, _), (b, _)| a.lo.partial_cmp(&b.lo).unwrap_or(Ordering::Equal));
+        let mut max_hi: Vec<_> = items.iter().map(|(interval, _id)| interval.hi).collect();
+        augment(&mut max_hi, 0, items.len());
+        Self { items, max_hi }
+    }
+
+    /// Returns the number of intervals in the tree.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns `true` if there are no intervals in the tree.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Calls `hit` with the identifier and the interval of each interval overlapping `interval`,
+    /// in order of their low boundaries.
+    pub fn query(&self, interval: &Interval<N>, mut hit: impl FnMut(ID, &Interval<N>)) {
+        if !HalfOpen.is_empty(interval) {
+            self.visit(0, self.items.len(), interval.lo, interval.hi, &mut hit);
+        }
+    }
+
+    /// Calls `hit` with the identifier and the interval of each interval containing `point`,
+    /// in order of their low boundaries.
+    pub fn query_point(&self, point: N, mut hit: impl FnMut(ID, &Interval<N>)) {
+        self.visit_point(0, self.items.len(), point, &mut hit);
+    }
+
+    /// Visits the intervals of the range `start..end` that overlap [`lo`, `hi`) in order.
+    fn visit(
+        &self,
+        start: usize,
+        end: usize,
+        lo: N,
+        hi: N,
+        hit: &mut impl FnMut(ID, &Interval<N>),
+    ) {
+        if start == end {
+            return;
+        }
+        let mid = start + (end - start) / 2;
+        if self.max_hi[mid] <= lo {
+            return;
+        }
+        self.visit(start, mid, lo, hi, hit);
+        let (interval, id) = &self.items[mid];
+        // the intervals to the right start even later
+        if interval.lo < hi {
+            if lo < interval.hi {
+                hit(*id, interval);
+            }
+            self.visit(mid + 1, end, lo, hi, hit);
+        }
+    }
+
+    /// Visits the intervals of the range `start..end` that contain `point` in order.
+    fn visit_point(
+        &self,
+        start: usize,
+        end: usize,
+        point: N,
+        hit: &mut impl FnMut(ID, &Interval<N>),
+    ) {
+        if start == end {
+            return;
+        }
+        let mid = start + (end - start) / 2;
+        if self.max_hi[mid] <= point {
+            return;
+        }
+        self.visit_point(start, mid, point, hit);
+        let (interval, id) = &self.items[mid];
+        if interval.lo <= point {
+            if point < interval.hi {
+                hit(*id, interval);
+            }
+            self.visit_point(mid + 1, end, point, hit);
+        }
+    }
+}
+
+/// Turns the high boundaries in `max_hi[start..end]` into the highest high boundary of the range of each node,
+/// and returns the highest of them all.
+fn augment<N: Copy + PartialOrd>(max_hi: &mut [N], start: usize, end: usize) -> Option<N> {
+    if start == end {
+        return None;
+    }
+    let mid = start + (end - start) / 2;
+    for child in [augment(max_hi, start, mid), augment(max_hi, mid + 1, end)] {
+        match child {
+            Some(hi) if hi > max_hi[mid] => max_hi[mid] = hi,
+            _ => {}
+        }
+    }
+    Some(max_hi[mid])
+}
@@ -0,0 +1,186 @@
+//! Lazy iteration over intersecting pairs, see [`intersect_iter`](crate::intersect_iter)
+
+#[cfg(feature = "futures")]
+use std::task::Poll;
+use std::time::{Duration, Instant};
+
+use crate::boxes::BBox;
+use crate::error::require_sorted;
+use crate::internals::{check_order, Copies, HybridState, Params};
+use crate::median::{ApproxMedian, MedianStrategy};
+use crate::policy::{HalfOpen, Policy};
+use crate::sink::Sink;
+use crate::source::{same, top_dim, BoxSource};
+use crate::{auto_cutoff, HasInfinity, Rng};
+
+/// Iterator over the pairs of `ID`s of intersecting boxes, returned by [`intersect_iter`](crate::intersect_iter).
+/// The algorithm only runs as far as needed to produce the next pair: the streamed segment trees
+/// are traversed one step at a time, each processing part of a node, and only the pairs found
+/// by the most recent scan are buffered. Finds the same pairs in the same order as
+/// [`intersect_ze_with_params`](crate::intersect_ze_with_params) with the same parameters.
+pub struct IntersectIter<B: BBox, ID, R, P = HalfOpen, M = ApproxMedian> {
+    state: HybridState<(B, ID), B::Num, P, M>,
+    rand: R,
+    buffer: Found<B, ID>,
+    pos: usize,
+}
+
+/// The buffered pairs, kept with their boxes so that [`IntersectIter::run_for`] can hand them to any [`Sink`].
+struct Found<B, ID>(Vec<(ID, B, ID, B)>);
+
+impl<B: BBox, ID> Sink<B, ID> for Found<B, ID> {
+    fn report(&mut self, a_id: ID, a: &B, b_id: ID, b: &B) {
+        self.0.push((a_id, *a, b_id, *b));
+    }
+}
+
+impl<B, ID, R> IntersectIter<B, ID, R>
+where
+    B: BBox,
+    B::Num: PartialOrd + HasInfinity,
+    ID: PartialOrd + Copy,
+    R: Rng,
+{
+    /// Creates an iterator over the intersections between `a` and `b`, see [`intersect_iter`](crate::intersect_iter).
+    pub fn new(a: &impl BoxSource<B, ID>, b: &impl BoxSource<B, ID>, rand: R) -> Self {
+        Self::with_policy(a, b, rand, HalfOpen)
+    }
+}
+
+impl<B, ID, R, P> IntersectIter<B, ID, R, P>
+where
+    B: BBox,
+    B::Num: PartialOrd + HasInfinity,
+    ID: PartialOrd + Copy,
+    R: Rng,
+    P: Policy<B::Num>,
+{
+    /// Like [`IntersectIter::new`], but comparing bounds according to `policy`.
+    pub fn with_policy(
+        a: &impl BoxSource<B, ID>,
+        b: &impl BoxSource<B, ID>,
+        rand: R,
+        policy: P,
+    ) -> Self {
+        let params = Params {
+            cutoff: auto_cutoff(a.len().max(b.len())),
+            ..Params::default()
+        };
+        Self::with_params(a, b, rand, policy, params, ApproxMedian)
+    }
+}
+
+impl<B, ID, R, P, M> IntersectIter<B, ID, R, P, M>
+where
+    B: BBox,
+    B::Num: PartialOrd + HasInfinity,
+    ID: PartialOrd + Copy,
+    R: Rng,
+    P: Policy<B::Num>,
+    M: MedianStrategy<B::Num>,
+{
+    /// Like [`IntersectIter::with_policy`], but with the segment trees limited by `params`
+    /// and divided at the boundaries chosen by `median`, see [`Params`] and [`MedianStrategy`].
+    pub fn with_params(
+        a: &impl BoxSource<B, ID>,
+        b: &impl BoxSource<B, ID>,
+        rand: R,
+        policy: P,
+        params: Params,
+        median: M,
+    ) -> Self {
+        require_sorted(a, "a", "intersect_iter");
+        require_sorted(b, "b", "intersect_iter");
+        // no need to stream segment trees in dimensions where all boxes overlap
+        let dim = top_dim(a, b, policy);
+        let order = check_order(a, b, B::DIM, policy);
+        let buffers = [Copies::of(a, policy), Copies::of(b, policy)];
+        let mut state = HybridState::new(&Copies, buffers, policy, params, median, order);
+        let (ninfty, infty) = (B::Num::NINFTY, B::Num::INFTY);
+        if !same(a, b) {
+            // need two trees so that every box is represented as both an interval and a point
+            state.push_root(1, ninfty, infty, dim);
+        }
+        state.push_root(0, ninfty, infty, dim);
+        Self {
+            state,
+            rand,
+            buffer: Found(Vec::new()),
+            pos: 0,
+        }
+    }
+
+    /// Runs the algorithm for about `budget`, reporting the pairs found to `out`, and returns `true`
+    /// once all pairs have been found. The traversal state is kept, so calling it again, e.g. in the
+    /// next frame of a simulation, resumes where it stopped instead of spending all the time in one call.
+    ///
+    /// The time is only checked after each step of the traversal, so a call can exceed `budget` by
+    /// the time needed for one scan. Scans aren't bounded by the cutoff: a leaf is scanned once either side
+    /// is below it, and nodes in the last dimension or whose points the median can't divide, e.g. many
+    /// boxes sharing a low bound, are scanned as a whole, so single scans can take much longer on such inputs.
+    pub fn run_for(&mut self, budget: Duration, out: &mut impl Sink<B, ID>) -> bool {
+        let start = Instant::now();
+        for (a_id, a, b_id, b) in &self.buffer.0[self.pos..] {
+            out.report(*a_id, a, *b_id, b);
+        }
+        self.buffer.0.clear();
+        self.pos = 0;
+        // at least one step is taken, so every call makes progress
+        loop {
+            if !self.state.step(&Copies, out, &mut self.rand) {
+                return true;
+            }
+            if start.elapsed() >= budget {
+                return false;
+            }
+        }
+    }
+
+    /// Like [`Iterator::next`], but takes at most `budget` steps of the traversal, subtracting the steps
+    /// it took from `budget`. Returns [`Poll::Pending`] if the budget ran out before a pair was found.
+    #[cfg(feature = "futures")]
+    pub(crate) fn next_within(&mut self, budget: &mut usize) -> Poll<Option<(ID, ID)>> {
+        while self.pos == self.buffer.0.len() {
+            if *budget == 0 {
+                return Poll::Pending;
+            }
+            *budget -= 1;
+            if !self.refill() {
+                return Poll::Ready(None);
+            }
+        }
+        self.pos += 1;
+        let (a_id, _, b_id, _) = &self.buffer.0[self.pos - 1];
+        Poll::Ready(Some((*a_id, *b_id)))
+    }
+
+    /// Clears the buffer and takes one step, buffering the pairs found. Returns `false` once all pairs have been found.
+    fn refill(&mut self) -> bool {
+        self.buffer.0.clear();
+        self.pos = 0;
+        self.state.step(&Copies, &mut self.buffer, &mut self.rand)
+    }
+}
+
+impl<B, ID, R, P, M> Iterator for IntersectIter<B, ID, R, P, M>
+where
+    B: BBox,
+    B::Num: PartialOrd + HasInfinity,
+    ID: PartialOrd + Copy,
+    R: Rng,
+    P: Policy<B::Num>,
+    M: MedianStrategy<B::Num>,
+{
+    type Item = (ID, ID);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pos == self.buffer.0.len() {
+            if !self.refill() {
+                return None;
+            }
+        }
+        self.pos += 1;
+        let (a_id, _, b_id, _) = &self.buffer.0[self.pos - 1];
+        Some((*a_id, *b_id))
+    }
+}
@@ -0,0 +1,199 @@
+//! Balanced kd-tree over the centers of a set of boxes, for many small queries against the same boxes, see [`KdTree`].
+//!
+//! # Examples
+//! ```
+//! use box_intersect_ze::boxes::Box3Df32;
+//! use box_intersect_ze::kdtree::KdTree;
+//! use box_intersect_ze::set::BBoxSet;
+//!
+//! let mut obstacles = BBoxSet::new();
+//! obstacles.push(0, Box3Df32::new([0.0, 0.0, 0.0], [1.0, 1.0, 1.0]));
+//! obstacles.push(1, Box3Df32::new([2.0, 0.0, 0.0], [9.0, 1.0, 1.0]));
+//! let kdtree = KdTree::build(&obstacles);
+//!
+//! // the center of box 1 is far away, but it extends to the query
+//! let mut hits = Vec::new();
+//! kdtree.query(&Box3Df32::new([1.5, 0.5, 0.5], [2.5, 0.6, 0.6]), |id, _bbox| hits.push(id));
+//! assert_eq!(hits, [1]);
+//! ```
+
+use crate::boxes::{BBox, BoxND};
+use crate::sink::Sink;
+use crate::source::BoxSource;
+use crate::HasInfinity;
+
+/// How a node divides the boxes below it, stored at the index of the box of the node
+#[derive(Clone, Copy, Debug)]
+struct Split<N> {
+    dim: usize,
+    /// Highest high boundary in `dim` of the boxes of the left subtree
+    left_hi: N,
+    /// Lowest low boundary in `dim` of the boxes of the right subtree
+    right_lo: N,
+}
+
+/// A kd-tree over the centers of boxes, stored implicitly: each node is the median box of a range of boxes by
+/// their centers in one dimension, with the boxes with lower centers to its left and those with higher centers to its right.
+/// Every node also stores how far the boxes on either side extend in its dimension, so queries only descend
+/// into a side whose boxes can reach the query, however large the boxes are.
+/// * Builds in *O*(*n* log *n*) and uses no memory beyond the boxes and one split per box, so rebuilding it
+///   for every scene is cheap, e.g. for motion planning issuing thousands of small queries per scene.
+/// * Queries are fast as long as the boxes are small compared to their spacing. Boxes spanning much
+///   of the scene widen the extents of many nodes, see [`Bvh`](crate::bvh::Bvh) for those.
+///
+/// Like the other algorithms, the projections of boxes are half-open intervals and empty boxes
+/// (see [`BBox::is_empty`]) never intersect. Bounds must not be NaN.
+#[derive(Clone, Debug)]
+pub struct KdTree<N, ID, const D: usize> {
+    /// Boxes and their identifiers in kd order, the root at the middle of the whole range
+    items: Vec<(BoxND<N, D>, ID)>,
+    /// The split of the node of each box
+    splits: Vec<Split<N>>,
+}
+
+impl<N, ID, const D: usize> KdTree<N, ID, D>
+where
+    N: Copy + PartialOrd + HasInfinity + Into<f64>,
+    ID: Copy,
+{
+    /// Builds a tree over the boxes of `boxes`, which need not be sorted. Empty boxes are left out.
+    pub fn build(boxes: &impl BoxSource<BoxND<N, D>, ID>) -> Self {
+        let mut items: Vec<_> = (0..boxes.len())
+            .map(|idx| boxes.get(idx))
+            .filter(|(bbox, _id)| !bbox.is_empty())
+            .collect();
+        let mut splits = vec![
+            Split {
+                dim: 0,
+                left_hi: N::NINFTY,
+                right_lo: N::INFTY,
+            };
+            items.len()
+        ];
+        let mut stack = vec![(0, items.len())];
+        while let Some((start, end)) = stack.pop() {
+            if start == end {
+                continue;
+            }
+            let range = &mut items[start..end];
+            let dim = widest_dim(range);
+            let mid = range.len() / 2;
+            range.select_nth_unstable_by(mid, |(a, _), (b, _)| {
+                center(a, dim).total_cmp(&center(b, dim))
+            });
+            let split = &mut splits[start + mid];
+            split.dim = dim;
+            for (bbox, _id) in &range[..mid] {
+                if bbox.hi(dim) > split.left_hi {
+                    split.left_hi = bbox.hi(dim);
+                }
+            }
+            for (bbox, _id) in &range[mid + 1..] {
+                if bbox.lo(dim) < split.right_lo {
+                    split.right_lo = bbox.lo(dim);
+                }
+            }
+            stack.extend([(start, start + mid), (start + mid + 1, end)]);
+        }
+        Self { items, splits }
+    }
+
+    /// Returns the number of boxes in the tree.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns `true` if there are no boxes in the tree.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Calls `hit` with the identifier and the box of each box intersecting `bbox`, in no particular order.
+    pub fn query(&self, bbox: &BoxND<N, D>, mut hit: impl FnMut(ID, &BoxND<N, D>)) {
+        if bbox.is_empty() {
+            return;
+        }
+        self.traverse(
+            |dim| (bbox.lo(dim), bbox.hi(dim)),
+            |other| other.intersects(bbox),
+            &mut hit,
+        );
+    }
+
+    /// Calls `hit` with the identifier and the box of each box containing `point`, in no particular order.
+    pub fn query_point(&self, point: [N; D], mut hit: impl FnMut(ID, &BoxND<N, D>)) {
+        self.traverse(
+            |dim| (point[dim], point[dim]),
+            |other| (0..D).all(|dim| other.contains_in(dim, point[dim])),
+            &mut hit,
+        );
+    }
+
+    /// Queries the tree with each box of `queries` (see [`KdTree::query`]) and reports each pair of a box of `queries`
+    /// and an intersecting box of the tree to `out`, the box of `queries` first.
+    pub fn query_set(
+        &self,
+        queries: &impl BoxSource<BoxND<N, D>, ID>,
+        out: &mut impl Sink<BoxND<N, D>, ID>,
+    ) {
+        for idx in 0..queries.len() {
+            let (query, query_id) = queries.get(idx);
+            self.query(&query, |id, bbox| out.report(query_id, &query, id, bbox));
+        }
+    }
+
+    /// Calls `hit` with each box that passes `accept`, descending into the left side of a node if the boxes there
+    /// extend past the low end of `range` in the dimension of the node, and into the right side if they
+    /// extend before its high end.
+    fn traverse(
+        &self,
+        range: impl Fn(usize) -> (N, N),
+        accept: impl Fn(&BoxND<N, D>) -> bool,
+        hit: &mut impl FnMut(ID, &BoxND<N, D>),
+    ) {
+        let mut stack = vec![(0, self.items.len())];
+        while let Some((start, end)) = stack.pop() {
+            if start == end {
+                continue;
+            }
+            let mid = start + (end - start) / 2;
+            let (bbox, id) = &self.items[mid];
+            if accept(bbox) {
+                hit(*id, bbox);
+            }
+            let split = &self.splits[mid];
+            let (lo, hi) = range(split.dim);
+            if lo < split.left_hi {
+                stack.push((start, mid));
+            }
+            // points lie on the low boundaries of the boxes containing them
+            if split.right_lo <= hi {
+                stack.push((mid + 1, end));
+            }
+        }
+    }
+}
+
+fn center<N, const D: usize>(bbox: &BoxND<N, D>, dim: usize) -> f64
+where
+    N: Copy + PartialOrd + Into<f64>,
+{
+    bbox.lo(dim).into() / 2.0 + bbox.hi(dim).into() / 2.0
+}
+
+/// Returns the dimension in which the centers of the boxes of `items` spread the most.
+fn widest_dim<N, ID, const D: usize>(items: &[(BoxND<N, D>, ID)]) -> usize
+where
+    N: Copy + PartialOrd + Into<f64>,
+{
+    let spread = |dim| {
+        let centers = items.iter().map(|(bbox, _id)| center(bbox, dim));
+        let (min, max) = centers.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), c| {
+            (min.min(c), max.max(c))
+        });
+        max - min
+    };
+    (0..D)
+        .max_by(|&a, &b| spread(a).total_cmp(&spread(b)))
+        .unwrap_or(0)
+}
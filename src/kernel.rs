@@ -0,0 +1,217 @@
+//! Scans testing several candidate boxes per iteration, generic over the [`Kernel`] doing the comparisons.
+//! Shared by the vectorized scans in [`simd`](crate::simd) and [`x86`](crate::x86).
+
+use crate::boxes::BBox;
+use crate::error::assert_sorted;
+use crate::policy::Policy;
+use crate::sink::Sink;
+use crate::source::BoxSource;
+
+/// Compares the effective boundaries of several boxes at once
+pub(crate) trait Kernel<N>: Copy {
+    /// Number of boxes compared per call of [`Kernel::overlap_mask`]
+    const LANES: usize;
+
+    /// Returns a bitmask of the boxes with indices `start..start + LANES` (the lowest bit for `start`)
+    /// that intersect the box with the boundaries `lo` and `hi`, in the dimensions `los` and `his` hold
+    /// the boundaries of. Boundaries are half-open intervals: `[lo, hi)`.
+    fn overlap_mask(self, los: &[Vec<N>], his: &[Vec<N>], start: usize, lo: &[N], hi: &[N]) -> u64;
+}
+
+/// Effective boundaries of the boxes of a source in dimensions `1..=max_dim`, one array per dimension
+struct Bounds<N> {
+    /// Low boundaries in dimension 0, the sort key
+    keys: Vec<N>,
+    los: Vec<Vec<N>>,
+    his: Vec<Vec<N>>,
+}
+
+impl<N: Copy + PartialOrd> Bounds<N> {
+    fn of<B, ID>(source: &impl BoxSource<B, ID>, max_dim: usize, policy: impl Policy<N>) -> Self
+    where
+        B: BBox<Num = N>,
+    {
+        let len = source.len();
+        let mut bounds = Self {
+            keys: Vec::with_capacity(len),
+            los: vec![Vec::with_capacity(len); max_dim],
+            his: vec![Vec::with_capacity(len); max_dim],
+        };
+        for idx in 0..len {
+            let bbox = source.get(idx).0;
+            bounds.keys.push(policy.lo_of(&bbox, 0));
+            for dim in 1..=max_dim {
+                bounds.los[dim - 1].push(policy.lo_of(&bbox, dim));
+                bounds.his[dim - 1].push(policy.hi_of(&bbox, dim));
+            }
+        }
+        bounds
+    }
+
+    /// Calls `hit` with the indices in `start..end`, in increasing order, of the boxes intersecting
+    /// the box with the effective boundaries `lo` and `hi` in dimensions `1..=max_dim`.
+    #[inline]
+    fn candidates<K: Kernel<N>>(
+        &self,
+        kernel: K,
+        start: usize,
+        end: usize,
+        lo: &[N],
+        hi: &[N],
+        mut hit: impl FnMut(usize),
+    ) {
+        let mut idx = start;
+        while idx + K::LANES <= end {
+            let mut mask = kernel.overlap_mask(&self.los, &self.his, idx, lo, hi);
+            while mask != 0 {
+                hit(idx + mask.trailing_zeros() as usize);
+                mask &= mask - 1;
+            }
+            idx += K::LANES;
+        }
+        for idx in idx..end {
+            let overlaps = (0..self.los.len())
+                .all(|dim| self.los[dim][idx] < hi[dim] && lo[dim] < self.his[dim][idx]);
+            if overlaps {
+                hit(idx);
+            }
+        }
+    }
+
+    /// Returns the index of the first box at or after `start` whose key is at least `max`.
+    #[inline]
+    fn end(&self, start: usize, max: N) -> usize {
+        start + self.keys[start..].partition_point(|&key| key < max)
+    }
+}
+
+/// Stores the effective boundaries of `bbox` in dimensions `1..=max_dim` in `lo` and `hi`.
+fn bounds_of<B: BBox>(
+    bbox: &B,
+    max_dim: usize,
+    policy: impl Policy<B::Num>,
+    lo: &mut Vec<B::Num>,
+    hi: &mut Vec<B::Num>,
+) {
+    lo.clear();
+    hi.clear();
+    lo.extend((1..=max_dim).map(|dim| policy.lo_of(bbox, dim)));
+    hi.extend((1..=max_dim).map(|dim| policy.hi_of(bbox, dim)));
+}
+
+/// Like [`one_way_scan`](crate::internals::one_way_scan), but testing several points per iteration with `kernel`.
+#[inline]
+pub(crate) fn one_way_scan<B, ID, K>(
+    intervals: &impl BoxSource<B, ID>,
+    points: &impl BoxSource<B, ID>,
+    max_dim_check: usize,
+    out: &mut impl Sink<B, ID>,
+    policy: impl Policy<B::Num>,
+    kernel: K,
+) where
+    B: BBox,
+    ID: Copy + PartialOrd,
+    K: Kernel<B::Num>,
+{
+    assert_sorted(intervals, "intervals", "one_way_scan");
+    assert_sorted(points, "points", "one_way_scan");
+    let p_bounds = Bounds::of(points, max_dim_check, policy);
+    let p_len = points.len();
+    let mut p_min_idx = 0;
+    let (mut lo, mut hi) = (Vec::new(), Vec::new());
+
+    for i_idx in 0..intervals.len() {
+        let (i, i_id) = intervals.get(i_idx);
+        let i_min = policy.lo_of(&i, 0);
+        let i_max = policy.hi_of(&i, 0);
+
+        //skip all points that don't have a chance to be in `i`
+        while p_min_idx < p_len && p_bounds.keys[p_min_idx] < i_min {
+            p_min_idx += 1;
+        }
+        if p_min_idx == p_len {
+            return;
+        }
+
+        // skip empty boxes, see `Policy::is_empty`
+        if (0..=max_dim_check).any(|dim| policy.is_empty_in(&i, dim)) {
+            continue;
+        }
+        bounds_of(&i, max_dim_check, policy, &mut lo, &mut hi);
+        let end = p_bounds.end(p_min_idx, i_max);
+        p_bounds.candidates(kernel, p_min_idx, end, &lo, &hi, |p_idx| {
+            let (p, p_id) = points.get(p_idx);
+            let p_min = p_bounds.keys[p_idx];
+            // skip empty boxes, see `Policy::is_empty`
+            if (0..=max_dim_check).any(|dim| policy.is_empty_in(&p, dim)) || p_id == i_id {
+                return;
+            }
+            //if low endpoints are not pairwise different, this is needed to avoid duplicates
+            if p_min == i_min && p_id > i_id {
+                return;
+            }
+            out.report(i_id, &i, p_id, &p);
+        });
+    }
+}
+
+/// Like [`two_way_scan`](crate::internals::two_way_scan), but testing several boxes per iteration with `kernel`.
+#[inline]
+pub(crate) fn two_way_scan<B, ID, K>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    out: &mut impl Sink<B, ID>,
+    policy: impl Policy<B::Num>,
+    kernel: K,
+) where
+    B: BBox,
+    ID: Copy + PartialOrd,
+    K: Kernel<B::Num>,
+{
+    assert_sorted(a, "intervals", "two_way_scan");
+    assert_sorted(b, "points", "two_way_scan");
+    let (intervals, points) = (a, b);
+    let max_dim = B::DIM - 1;
+    let i_bounds = Bounds::of(intervals, max_dim, policy);
+    let p_bounds = Bounds::of(points, max_dim, policy);
+
+    let mut i_min_idx = 0;
+    let i_len = intervals.len();
+    let mut p_min_idx = 0;
+    let p_len = points.len();
+    let (mut lo, mut hi) = (Vec::new(), Vec::new());
+
+    while i_min_idx < i_len && p_min_idx < p_len {
+        if i_bounds.keys[i_min_idx] < p_bounds.keys[p_min_idx] {
+            let (i_min, i_min_id) = intervals.get(i_min_idx);
+            i_min_idx += 1;
+            // skip empty boxes, see `Policy::is_empty`
+            if policy.is_empty(&i_min) {
+                continue;
+            }
+            bounds_of(&i_min, max_dim, policy, &mut lo, &mut hi);
+            let end = p_bounds.end(p_min_idx, policy.hi_of(&i_min, 0));
+            p_bounds.candidates(kernel, p_min_idx, end, &lo, &hi, |p_idx| {
+                let (p, p_id) = points.get(p_idx);
+                if !policy.is_empty(&p) && p_id != i_min_id {
+                    out.report(p_id, &p, i_min_id, &i_min);
+                }
+            });
+        } else {
+            //p_min.lo(0) <= i_min.lo(0), so switch the roles of intervals and points
+            let (p_min, p_min_id) = points.get(p_min_idx);
+            p_min_idx += 1;
+            if policy.is_empty(&p_min) {
+                continue;
+            }
+            bounds_of(&p_min, max_dim, policy, &mut lo, &mut hi);
+            let end = i_bounds.end(i_min_idx, policy.hi_of(&p_min, 0));
+            i_bounds.candidates(kernel, i_min_idx, end, &lo, &hi, |i_idx| {
+                let (i, i_id) = intervals.get(i_idx);
+                if !policy.is_empty(&i) && i_id != p_min_id {
+                    out.report(p_min_id, &p_min, i_id, &i);
+                }
+            });
+        }
+    }
+}
@@ -30,16 +30,91 @@
 //! assert!(!result.contains(&(2,0)));
 //! assert!(!result.contains(&(0,2)));
 //! ```
+//!
+//! # Sorting
+//! The algorithms need their inputs sorted by the low boundaries of the boxes in dimension 0.
+//! [`SortedBBoxSet`](set::SortedBBoxSet) encodes that in its type: it is created sorted, only allows modifications
+//! that keep it sorted, and its methods [`intersect_ze`](set::SortedBBoxSet::intersect_ze) and
+//! [`intersect_scan`](set::SortedBBoxSet::intersect_scan) only accept sorted sets.
+//! The free functions accept any [`BoxSource`] instead, e.g. a [`BBoxSet`], a [`BBoxSlice`](slice::BBoxSlice)
+//! or the component storage of an entity component system, whose order the type system can't track.
+//! They check that their inputs are sorted, in *O*(*n*) unless [`BoxSource::is_sorted`] says so, and panic if not,
+//! so unsorted input never silently yields wrong results.
+//!
+//! # Determinism
+//! [`BBoxSet::sort`] breaks ties between equal low boundaries by identifier, so the order of a sorted set
+//! doesn't depend on the order the boxes were added in. All algorithms use the same order to decide
+//! which of two boxes with equal low boundaries is treated as the interval, so every intersecting pair
+//! is reported exactly once, even if thousands of boxes share a low boundary.
+//! Given equal sorted sets (and random number generators in the same state), an algorithm reports
+//! the same pairs in the same order every time. Different algorithms report the same pairs,
+//! but possibly in a different order and with the two identifiers of a pair swapped.
+
+#![cfg_attr(feature = "simd", feature(portable_simd))]
+
+use std::cmp::Ordering;
+use std::ops::{Add, Sub};
+use std::time::{Duration, Instant};
 
-use boxes::BBox;
+use boxes::{BBox, BoxND};
 use set::BBoxSet;
 
-use crate::internals::{hybrid, one_way_scan, two_way_scan};
+use crate::bvh::Ray;
+use crate::error::{check, require_sorted, Input, InputError};
+use crate::internals::{
+    hybrid_with_median, hybrid_with_params, one_way_scan_with_scratch, point_scan,
+    two_way_scan_with_scratch, Params, ScanScratch,
+};
+use crate::iter::IntersectIter;
+use crate::median::{ApproxMedian, MedianStrategy};
+use crate::points::{Point, PointSet};
+use crate::policy::{HalfOpen, Policy, Tolerant};
+use crate::region::HalfSpace;
+use crate::sink::{Merge, PointSink, Sink};
+use crate::source::BoxSource;
 
+#[cfg(feature = "bench")]
+pub mod bench;
+pub mod binned;
 pub mod boxes;
+pub mod broad_phase;
+pub mod bvh;
+pub mod coverage;
+pub mod curve;
+pub mod error;
+pub mod grid;
+pub mod index;
 pub mod internals;
-mod median;
+pub mod interval;
+pub mod iter;
+pub mod kdtree;
+#[cfg(any(target_arch = "x86_64", feature = "simd"))]
+mod kernel;
+pub mod median;
+#[cfg(feature = "parallel")]
+pub mod parallel;
+pub mod periodic;
+pub mod points;
+pub mod policy;
+pub mod quantize;
+pub mod region;
+pub mod rtree;
+pub mod sap;
 pub mod set;
+#[cfg(feature = "simd")]
+pub mod simd;
+pub mod sink;
+pub mod slice;
+pub mod source;
+pub mod spill;
+pub mod split;
+#[cfg(feature = "futures")]
+pub mod stream;
+pub mod swept;
+#[cfg(feature = "verify")]
+pub mod verify;
+#[cfg(target_arch = "x86_64")]
+pub mod x86;
 
 /// Trait for box boundary types
 pub trait HasInfinity {
@@ -49,6 +124,38 @@ pub trait HasInfinity {
     const INFTY: Self;
 }
 
+/// Trait for box boundary types with a total order, used by [`BBoxSet::sort_total`]
+pub trait TotalOrder {
+    /// Compares `self` to `other`, ordering every value including NaN
+    fn total_cmp(&self, other: &Self) -> Ordering;
+}
+
+impl TotalOrder for f32 {
+    fn total_cmp(&self, other: &Self) -> Ordering {
+        f32::total_cmp(self, other)
+    }
+}
+
+impl TotalOrder for f64 {
+    fn total_cmp(&self, other: &Self) -> Ordering {
+        f64::total_cmp(self, other)
+    }
+}
+
+macro_rules! impl_total_order_int {
+    ($($t:ty),*) => {
+        $(
+            impl TotalOrder for $t {
+                fn total_cmp(&self, other: &Self) -> Ordering {
+                    self.cmp(other)
+                }
+            }
+        )*
+    };
+}
+
+impl_total_order_int!(i32, i64, u32, u64);
+
 /// Trait for random number generator used in [`intersect_ze`] for approximate median calculation
 pub trait Rng {
     /// Returns a random `usize` between 0 (inclusive) and `high` (exclusive)
@@ -65,32 +172,167 @@ where
     }
 }
 
+/// Cutoff used where it isn't chosen from the size of the input (see [`auto_cutoff`]),
+/// should give reasonable performance from about 10,000 boxes up to at least several million
+const DEFAULT_CUTOFF: usize = 1000;
+
+/// Largest cutoff chosen by [`auto_cutoff`], which performed best for 10,000,000 uniformly distributed boxes
+const MAX_AUTO_CUTOFF: usize = 30_000;
+
+/// Returns the cutoff used by [`intersect_ze`] for sets with up to `len` boxes, which can also be
+/// passed to [`intersect_ze_with_cutoff`]. Small sets are split further, because scanning them
+/// is only cheaper than building the segment tree for about a tenth of their boxes.
+/// Beyond 10,000 boxes, the best cutoff grows more slowly, about like 10 √`len`: measured on uniformly
+/// distributed boxes, it was about 1000 for 30,000 boxes, 10,000 for 1,000,000 and 30,000 for 10,000,000,
+/// where it took 40% less time than a cutoff of 1000. It is capped at 30,000, since larger inputs weren't measured.
+/// Use [`calibrate`] to tune the cutoff to other data.
+pub fn auto_cutoff(len: usize) -> usize {
+    (len / 10).min(10 * len.isqrt()).clamp(100, MAX_AUTO_CUTOFF)
+}
+
+/// Number of boxes [`calibrate`] times the algorithm on
+const CALIBRATION_SAMPLE: usize = 100_000;
+
+/// Returns the cutoff for which [`intersect_ze_with_cutoff`] performed best on a sample of `set`,
+/// out of a few candidates between 30 and 30,000, to tune the cutoff to the density and dimension of actual data.
+/// The sample is a random range of up to 100,000 consecutive boxes, so it has about the same density as `set`.
+/// Takes about as long as finding the intersections of the sample a dozen times.
+/// * `set` must be sorted.
+pub fn calibrate<B, ID, R>(set: &impl BoxSource<B, ID>, rand: &mut R) -> usize
+where
+    B: BBox,
+    ID: PartialOrd + Copy,
+    B::Num: PartialOrd + HasInfinity,
+    R: Rng,
+{
+    require_sorted(set, "set", "calibrate");
+    let len = set.len().min(CALIBRATION_SAMPLE);
+    let start = rand.rand_usize(set.len() - len + 1);
+    let sample: BBoxSet<B, ID> = (start..start + len)
+        .map(|idx| {
+            let (bbox, id) = set.get(idx);
+            (id, bbox)
+        })
+        .collect();
+
+    let mut best = (Duration::MAX, DEFAULT_CUTOFF);
+    for &cutoff in &[30, 100, 300, 1000, 3000, 10_000, MAX_AUTO_CUTOFF] {
+        if cutoff > len {
+            break;
+        }
+        for _ in 0..2 {
+            let start = Instant::now();
+            intersect_ze_with_cutoff(&sample, &sample, &mut Count(0), rand, cutoff);
+            best = best.min((start.elapsed(), cutoff));
+        }
+    }
+    best.1
+}
+
+/// A [`Sink`] that only counts the pairs
+struct Count(usize);
+
+impl<B, ID> Sink<B, ID> for Count {
+    fn report(&mut self, _a_id: ID, _a: &B, _b_id: ID, _b: &B) {
+        self.0 += 1;
+    }
+}
+
 /// Finds all intersections between boxes in `a` and `b` using Zomorodian and Edelsbrunner's
-/// hybrid algorithm (streamed segment trees pruned with a cutoff).
-/// * `a` and `b` may be either the same or distinct [`BBoxSet`]s and must be sorted before calling.
-/// * `out` will contain pairs of `ID`s of intersecting boxes.
-/// Choose capacity according to the number of intersections you expect to avoid resizing.
+/// hybrid algorithm (streamed segment trees pruned with a cutoff, chosen by [`auto_cutoff`]).
+/// * `a` and `b` may be either the same or distinct [`BBoxSet`]s (or other [`BoxSource`]s) and must be sorted before calling.
+/// * `out` will receive the pairs of intersecting boxes, e.g. a `Vec<(ID, ID)>` (see [`Sink`]).
+///   Choose capacity according to the number of intersections you expect to avoid resizing.
 /// * `rand` must be a random number generator implementing the [`Rng`] trait. (used for approximate median selection)
 pub fn intersect_ze<B, ID, R>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    out: &mut impl Sink<B, ID>,
+    rand: &mut R,
+) where
+    B: BBox,
+    B::Num: PartialOrd + HasInfinity,
+    ID: PartialOrd + Copy,
+    R: Rng,
+{
+    let cutoff = auto_cutoff(a.len().max(b.len()));
+    intersect_ze_with_cutoff(a, b, out, rand, cutoff);
+}
+
+/// Like `intersect_ze`, but needs no random number generator: divides the segments of the segment trees at the
+/// exact median of the low boundaries (see [`ExactMedian`](median::ExactMedian)), so the same input always yields
+/// the same pairs in the same order. Finding the exact medians takes *O*(*n* log *n*) for *n* boxes,
+/// which is usually dwarfed by scanning.
+pub fn intersect_ze_deterministic<B, ID>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    out: &mut impl Sink<B, ID>,
+) where
+    B: BBox,
+    B::Num: PartialOrd + HasInfinity,
+    ID: PartialOrd + Copy,
+{
+    let params = Params {
+        cutoff: auto_cutoff(a.len().max(b.len())),
+        ..Params::default()
+    };
+    intersect_ze_with(
+        a,
+        b,
+        out,
+        &mut FixedRng::new(),
+        HalfOpen,
+        params,
+        median::ExactMedian,
+    );
+}
+
+/// An [`Rng`] for [`intersect_ze_deterministic`]: a xorshift generator with a fixed seed, so any random numbers
+/// drawn, though exact medians need none, are the same on every call
+struct FixedRng(u64);
+
+impl FixedRng {
+    fn new() -> Self {
+        Self(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+impl Rng for FixedRng {
+    fn rand_usize(&mut self, high: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % high as u64) as usize
+    }
+}
+
+/// Like `intersect_ze` but checks the input first: returns an [`InputError`] describing the first
+/// problematic box instead of silently producing wrong results if a set is not sorted,
+/// contains boxes with NaN or inverted bounds, or contains duplicate identifiers.
+/// Nothing is reported to `out` in that case.
+pub fn try_intersect_ze<B, ID, R>(
     a: &BBoxSet<B, ID>,
     b: &BBoxSet<B, ID>,
-    out: &mut Vec<(ID, ID)>,
+    out: &mut impl Sink<B, ID>,
     rand: &mut R,
-) where
+) -> Result<(), InputError>
+where
     B: BBox,
     B::Num: PartialOrd + HasInfinity,
     ID: PartialOrd + Copy,
     R: Rng,
 {
-    const CUTOFF: usize = 1000; // should give reasonable performance for up to 100,000 boxes
-    intersect_ze_custom::<B, ID, R, CUTOFF>(a, b, out, rand);
+    check(a, Input::A)?;
+    check(b, Input::B)?;
+    intersect_ze(a, b, out, rand);
+    Ok(())
 }
 
 /// Like `intersect_ze` but with a customizable cutoff.
 pub fn intersect_ze_custom<B, ID, R, const CUTOFF: usize>(
-    a: &BBoxSet<B, ID>,
-    b: &BBoxSet<B, ID>,
-    out: &mut Vec<(ID, ID)>,
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    out: &mut impl Sink<B, ID>,
     rand: &mut R,
 ) where
     B: BBox,
@@ -99,67 +341,544 @@ pub fn intersect_ze_custom<B, ID, R, const CUTOFF: usize>(
     ID: PartialEq,
     R: Rng,
 {
-    let same = a as *const _ == b as *const _;
+    intersect_ze_with_policy::<B, ID, R, CUTOFF>(a, b, out, rand, HalfOpen);
+}
+
+/// Like `intersect_ze_custom` but comparing bounds according to `policy`, e.g. [`Closed`](policy::Closed)
+/// to treat boxes that only touch as intersecting. See [`Policy`].
+pub fn intersect_ze_with_policy<B, ID, R, const CUTOFF: usize>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    out: &mut impl Sink<B, ID>,
+    rand: &mut R,
+    policy: impl Policy<B::Num>,
+) where
+    B: BBox,
+    ID: PartialOrd + Copy,
+    B::Num: PartialOrd + HasInfinity,
+    R: Rng,
+{
+    let params = Params {
+        cutoff: CUTOFF,
+        ..Params::default()
+    };
+    intersect_ze_with_params(a, b, out, rand, policy, params);
+}
+
+/// Like `intersect_ze_custom` but with the cutoff chosen at runtime, e.g. depending on the size of the input.
+pub fn intersect_ze_with_cutoff<B, ID, R>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    out: &mut impl Sink<B, ID>,
+    rand: &mut R,
+    cutoff: usize,
+) where
+    B: BBox,
+    ID: PartialOrd + Copy,
+    B::Num: PartialOrd + HasInfinity,
+    R: Rng,
+{
+    let params = Params {
+        cutoff,
+        ..Params::default()
+    };
+    intersect_ze_with_params(a, b, out, rand, HalfOpen, params);
+}
+
+/// Like `intersect_ze` but with the depth of the segment trees limited to `max_depth`,
+/// see [`Params::max_depth`](internals::Params::max_depth).
+pub fn intersect_ze_with_max_depth<B, ID, R>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    out: &mut impl Sink<B, ID>,
+    rand: &mut R,
+    max_depth: usize,
+) where
+    B: BBox,
+    ID: PartialOrd + Copy,
+    B::Num: PartialOrd + HasInfinity,
+    R: Rng,
+{
+    let params = Params {
+        max_depth,
+        ..Params::default()
+    };
+    intersect_ze_with_params(a, b, out, rand, HalfOpen, params);
+}
+
+/// Like `intersect_ze_with_policy` but with all parameters of the segment trees chosen at runtime,
+/// see [`Params`].
+pub fn intersect_ze_with_params<B, ID, R>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    out: &mut impl Sink<B, ID>,
+    rand: &mut R,
+    policy: impl Policy<B::Num>,
+    params: Params,
+) where
+    B: BBox,
+    ID: PartialOrd + Copy,
+    B::Num: PartialOrd + HasInfinity,
+    R: Rng,
+{
+    intersect_ze_with(a, b, out, rand, policy, params, ApproxMedian);
+}
+
+/// Like `intersect_ze_custom` but dividing the segments of the segment trees at the boundaries chosen by `median`,
+/// e.g. [`ExactMedian`](median::ExactMedian) to pay for balanced trees on skewed data. See [`MedianStrategy`].
+pub fn intersect_ze_with_median<B, ID, R, const CUTOFF: usize>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    out: &mut impl Sink<B, ID>,
+    rand: &mut R,
+    median: impl MedianStrategy<B::Num>,
+) where
+    B: BBox,
+    ID: PartialOrd + Copy,
+    B::Num: PartialOrd + HasInfinity,
+    R: Rng,
+{
+    let params = Params {
+        cutoff: CUTOFF,
+        ..Params::default()
+    };
+    intersect_ze_with(a, b, out, rand, HalfOpen, params, median);
+}
+
+fn intersect_ze_with<B, ID, R>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    out: &mut impl Sink<B, ID>,
+    rand: &mut R,
+    policy: impl Policy<B::Num>,
+    params: Params,
+    median: impl MedianStrategy<B::Num>,
+) where
+    B: BBox,
+    ID: PartialOrd + Copy,
+    B::Num: PartialOrd + HasInfinity,
+    R: Rng,
+{
+    require_sorted(a, "a", "intersect_ze");
+    require_sorted(b, "b", "intersect_ze");
+    let (ninfty, infty) = (B::Num::NINFTY, B::Num::INFTY);
+    // no need to stream segment trees in dimensions where all boxes overlap
+    let dim = source::top_dim(a, b, policy);
+    let same = source::same(a, b);
     if same {
         // one tree is enough to have every box represented as both an interval and a point
-        hybrid::<B, ID, R, CUTOFF>(a, a, B::Num::NINFTY, B::Num::INFTY, B::DIM - 1, out, rand);
+        hybrid_with_median(a, a, ninfty, infty, dim, out, rand, policy, params, median);
     } else {
         // need two trees so that every box is represented as both an interval and a point
-        hybrid::<B, ID, R, CUTOFF>(a, b, B::Num::NINFTY, B::Num::INFTY, B::DIM - 1, out, rand);
-        hybrid::<B, ID, R, CUTOFF>(b, a, B::Num::NINFTY, B::Num::INFTY, B::DIM - 1, out, rand);
+        hybrid_with_median(a, b, ninfty, infty, dim, out, rand, policy, params, median);
+        hybrid_with_median(b, a, ninfty, infty, dim, out, rand, policy, params, median);
+    }
+}
+
+/// Like `intersect_ze`, but if `a` and `b` are distinct, the two segment trees needed to report
+/// every pair are processed on two threads, each reporting to its own sink. The pairs found by the
+/// second thread are [merged](Merge) into `out` afterwards. The second thread uses a clone of `rand`.
+pub fn intersect_ze_threaded<B, ID, R, S>(
+    a: &(impl BoxSource<B, ID> + Sync),
+    b: &(impl BoxSource<B, ID> + Sync),
+    out: &mut S,
+    rand: &mut R,
+) where
+    B: BBox,
+    B::Num: PartialOrd + HasInfinity,
+    ID: PartialOrd + Copy,
+    R: Rng + Clone + Send,
+    S: Sink<B, ID> + Merge + Default + Send,
+{
+    if source::same(a, b) {
+        intersect_ze(a, b, out, rand);
+        return;
     }
+    require_sorted(a, "a", "intersect_ze_threaded");
+    require_sorted(b, "b", "intersect_ze_threaded");
+    let dim = source::top_dim(a, b, HalfOpen);
+    let params = Params {
+        cutoff: auto_cutoff(a.len().max(b.len())),
+        ..Params::default()
+    };
+    let mut rand_ba = rand.clone();
+    let ba = std::thread::scope(|scope| {
+        let ba = scope.spawn(move || {
+            // the bounds of the root are created here, as `B::Num` need not be `Send`
+            let (ninfty, infty) = (B::Num::NINFTY, B::Num::INFTY);
+            let mut out = S::default();
+            hybrid_with_params(
+                b,
+                a,
+                ninfty,
+                infty,
+                dim,
+                &mut out,
+                &mut rand_ba,
+                HalfOpen,
+                params,
+            );
+            out
+        });
+        let (ninfty, infty) = (B::Num::NINFTY, B::Num::INFTY);
+        hybrid_with_params(a, b, ninfty, infty, dim, &mut *out, rand, HalfOpen, params);
+        ba.join().unwrap()
+    });
+    out.merge(ba);
+}
+
+/// Like `intersect_ze` but treating every box as if it was inflated by `margin` in every dimension,
+/// to absorb floating point error. The sets are not modified. See [`Tolerant`].
+pub fn intersect_ze_with_margin<B, ID, R>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    out: &mut impl Sink<B, ID>,
+    rand: &mut R,
+    margin: B::Num,
+) where
+    B: BBox,
+    ID: PartialOrd + Copy,
+    B::Num: PartialOrd + HasInfinity + Add<Output = B::Num> + Sub<Output = B::Num>,
+    R: Rng,
+{
+    let params = Params {
+        cutoff: auto_cutoff(a.len().max(b.len())),
+        ..Params::default()
+    };
+    intersect_ze_with_params(a, b, out, rand, Tolerant(margin), params);
+}
+
+/// Returns an iterator over all intersections between boxes in `a` and `b`, found lazily using
+/// the same algorithm as [`intersect_ze`]. Useful to stop early or to avoid collecting all pairs.
+/// The boxes are copied, so the iterator doesn't borrow `a` and `b`.
+/// * `a` and `b` may be either the same or distinct [`BBoxSet`]s (or other [`BoxSource`]s) and must be sorted before calling.
+/// * `rand` must be a random number generator implementing the [`Rng`] trait. (used for approximate median selection)
+pub fn intersect_iter<B, ID, R>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    rand: R,
+) -> IntersectIter<B, ID, R>
+where
+    B: BBox,
+    B::Num: PartialOrd + HasInfinity,
+    ID: PartialOrd + Copy,
+    R: Rng,
+{
+    IntersectIter::new(a, b, rand)
+}
+
+/// Returns a [`Stream`](futures_core::Stream) of all intersections between boxes in `a` and `b`,
+/// like [`intersect_iter`] but yielding to the executor after every `yield_every` steps of the traversal of the segment trees,
+/// so it doesn't block an asynchronous runtime for long, see [`IntersectStream`](stream::IntersectStream).
+/// Requires the `futures` feature.
+#[cfg(feature = "futures")]
+pub fn intersect_stream<B, ID, R>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    rand: R,
+    yield_every: usize,
+) -> stream::IntersectStream<B, ID, R>
+where
+    B: BBox,
+    B::Num: PartialOrd + HasInfinity,
+    ID: PartialOrd + Copy,
+    R: Rng,
+{
+    stream::IntersectStream::new(intersect_iter(a, b, rand), yield_every)
 }
 
 /// Finds all intersections between boxes in `a` and `b` using a scanning algorithm.
 /// Should perform reasonably up to approximately 1,000 boxes
-/// * `a` and `b` may be either the same or distinct [`BBoxSet`]s and must be sorted before calling.
-/// * `out` will contain pairs of `ID`s of intersecting boxes.
-pub fn intersect_scan<B, ID>(a: &BBoxSet<B, ID>, b: &BBoxSet<B, ID>, out: &mut Vec<(ID, ID)>)
-where
+/// * `a` and `b` may be either the same or distinct [`BBoxSet`]s (or other [`BoxSource`]s) and must be sorted before calling.
+/// * `out` will receive the pairs of intersecting boxes, e.g. a `Vec<(ID, ID)>` (see [`Sink`]).
+pub fn intersect_scan<B, ID>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    out: &mut impl Sink<B, ID>,
+) where
+    B: BBox,
+    ID: Copy + PartialOrd,
+{
+    intersect_scan_with_policy(a, b, out, HalfOpen);
+}
+
+/// Like `intersect_scan` but comparing bounds according to `policy`. See [`Policy`].
+pub fn intersect_scan_with_policy<B, ID>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    out: &mut impl Sink<B, ID>,
+    policy: impl Policy<B::Num>,
+) where
+    B: BBox,
+    ID: Copy + PartialOrd,
+{
+    scan(a, b, out, policy, &mut ScanScratch::new());
+}
+
+/// Like `intersect_scan` but working in `scratch` instead of allocating memory, for contexts where
+/// allocating is not acceptable, e.g. together with a [`SliceSink`](sink::SliceSink).
+/// `scratch` only allocates while it grows to the sizes of `a` and `b`, see [`ScanScratch`].
+pub fn intersect_scan_with_scratch<B, ID>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    out: &mut impl Sink<B, ID>,
+    scratch: &mut ScanScratch<B>,
+) where
     B: BBox,
     ID: Copy + PartialOrd,
 {
-    let same = a as *const _ == b as *const _; // check if a and b refer to the same BBoxSet
+    scan(a, b, out, HalfOpen, scratch);
+}
+
+fn scan<B, ID>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    out: &mut impl Sink<B, ID>,
+    policy: impl Policy<B::Num>,
+    scratch: &mut ScanScratch<B>,
+) where
+    B: BBox,
+    ID: Copy + PartialOrd,
+{
+    require_sorted(a, "a", "intersect_scan");
+    require_sorted(b, "b", "intersect_scan");
+    let same = source::same(a, b); // check if a and b refer to the same source
     if same {
-        one_way_scan(a, b, B::DIM - 1, out);
+        one_way_scan_with_scratch(a, b, B::DIM - 1, out, policy, scratch);
     } else {
-        two_way_scan(a, b, out);
+        two_way_scan_with_scratch(a, b, out, policy, scratch);
     }
 }
 
+/// Like `intersect_scan` but treating every box as if it was inflated by `margin` in every dimension,
+/// to absorb floating point error. The sets are not modified. See [`Tolerant`].
+pub fn intersect_scan_with_margin<B, ID>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    out: &mut impl Sink<B, ID>,
+    margin: B::Num,
+) where
+    B: BBox,
+    ID: Copy + PartialOrd,
+    B::Num: Add<Output = B::Num> + Sub<Output = B::Num>,
+{
+    intersect_scan_with_policy(a, b, out, Tolerant(margin));
+}
+
+/// Finds all pairs of a box in `boxes` and a point in `points` contained in it, using a scanning algorithm.
+/// Cheaper than representing the points as tiny boxes, and exact: a point on the low boundary
+/// of a box is contained in it, a point on the high boundary isn't.
+/// * `boxes` and `points` must be sorted before calling.
+/// * `out` will receive the pairs, box first, e.g. a `Vec<(ID, ID)>` or a closure taking the two identifiers.
+///   See [`PointSink`].
+pub fn intersect_points<B, P, ID>(
+    boxes: &impl BoxSource<B, ID>,
+    points: &PointSet<P, ID>,
+    out: &mut impl PointSink<B, P, ID>,
+) where
+    B: BBox,
+    P: Point<Num = B::Num>,
+    ID: Copy,
+{
+    assert_eq!(
+        B::DIM,
+        P::DIM,
+        "boxes and points must have the same dimension"
+    );
+    point_scan(boxes, points, out, HalfOpen);
+}
+
+/// Finds all boxes in `set` intersecting the single box `query` and appends their identifiers to `out`, in the order of `set`.
+/// Binary searches the low boundaries in dimension 0 for the first box starting at or after the end of `query`,
+/// and only scans the boxes before it. Cheaper than putting `query` into its own [`BBoxSet`] and calling [`intersect_scan`],
+/// but still takes *O*(*m*) for the *m* boxes starting before the end of `query`: to query the same set many times,
+/// build a [`StaticIndex`](index::StaticIndex) instead.
+/// * `set` must be sorted before calling, which is checked in *O*(*n*) unless it is known to be sorted
+///   (see [`BoxSource::is_sorted`]), like a [`BBoxSet`] that was sorted and only modified in order since.
+/// * An empty `query` (see [`BBox::is_empty`]) intersects nothing.
+pub fn query_box<B, ID>(set: &impl BoxSource<B, ID>, query: &B, out: &mut Vec<ID>)
+where
+    B: BBox,
+    ID: Copy,
+{
+    require_sorted(set, "set", "query_box");
+    if query.is_empty() {
+        return;
+    }
+    let hi = query.hi(0);
+    // boxes in `end..` start too late to intersect `query`
+    let (mut start, mut end) = (0, set.len());
+    while start < end {
+        let mid = start + (end - start) / 2;
+        if set.get(mid).0.lo(0) < hi {
+            start = mid + 1;
+        } else {
+            end = mid;
+        }
+    }
+    for idx in 0..end {
+        let (bbox, id) = set.get(idx);
+        if bbox.intersects(query) {
+            out.push(id);
+        }
+    }
+}
+
+/// Finds all boxes in `boxes` that `ray` hits between the distances `min` and `max` (see [`Ray::entry_between`]),
+/// e.g. for picking or line-of-sight checks, and appends their identifiers to `out` with the distance at which
+/// the ray enters them, ordered by that distance, nearest first. Pass `0.0` and [`f64::INFINITY`] for the whole ray.
+/// Checks every box, *O*(*n* log *n*) for sorting the hits: to cast many rays against the same boxes,
+/// build a [`Bvh`](bvh::Bvh) or [`RTree`](rtree::RTree) and use their `query_ray` instead.
+/// * `boxes` need not be sorted.
+pub fn query_ray<N, ID, const D: usize>(
+    boxes: &impl BoxSource<BoxND<N, D>, ID>,
+    ray: &Ray<D>,
+    min: f64,
+    max: f64,
+    out: &mut Vec<(ID, f64)>,
+) where
+    N: Copy + PartialOrd + Into<f64>,
+    ID: Copy,
+{
+    let start = out.len();
+    for idx in 0..boxes.len() {
+        let (bbox, id) = boxes.get(idx);
+        if let Some(t) = ray.entry_between(&bbox, min, max) {
+            out.push((id, t));
+        }
+    }
+    bvh::sort_by_entry(&mut out[start..]);
+}
+
+/// Finds all boxes in `boxes` that may intersect the convex region where all `half_spaces` overlap,
+/// e.g. the planes of a camera frustum, and appends their identifiers to `out` in the order of `boxes`.
+/// The test is conservative, see [`region::intersects`]. Checks every box: to query the same boxes many times,
+/// build a [`Bvh`](bvh::Bvh) or [`RTree`](rtree::RTree) and use their `query_region` instead.
+/// * `boxes` need not be sorted.
+pub fn query_region<N, ID, const D: usize>(
+    boxes: &impl BoxSource<BoxND<N, D>, ID>,
+    half_spaces: &[HalfSpace<D>],
+    out: &mut Vec<ID>,
+) where
+    N: Copy + PartialOrd + Into<f64>,
+    ID: Copy,
+{
+    for idx in 0..boxes.len() {
+        let (bbox, id) = boxes.get(idx);
+        if region::intersects(half_spaces, &bbox) {
+            out.push(id);
+        }
+    }
+}
+
+/// Returns the identifier of the box in `boxes` nearest to `point` and its distance (see [`BoxND::distance_to`]),
+/// or [`None`] if there are no non-empty boxes. Of several boxes at the same distance, returns the first.
+/// Checks every box: to search the same boxes many times, build a [`Bvh`](bvh::Bvh) or [`RTree`](rtree::RTree)
+/// and use their `nearest` instead.
+/// * `boxes` need not be sorted.
+pub fn nearest<N, ID, const D: usize>(
+    boxes: &impl BoxSource<BoxND<N, D>, ID>,
+    point: [f64; D],
+) -> Option<(ID, f64)>
+where
+    N: Copy + PartialOrd + Into<f64>,
+    ID: Copy,
+{
+    let mut best: Option<(ID, f64)> = None;
+    for idx in 0..boxes.len() {
+        let (bbox, id) = boxes.get(idx);
+        if bbox.is_empty() {
+            continue;
+        }
+        let distance = bbox.distance_to(point);
+        if !matches!(best, Some((_, best)) if best <= distance) {
+            best = Some((id, distance));
+        }
+    }
+    best
+}
+
+/// Like `intersect_scan` but checks the input first, see [`try_intersect_ze`].
+pub fn try_intersect_scan<B, ID>(
+    a: &BBoxSet<B, ID>,
+    b: &BBoxSet<B, ID>,
+    out: &mut impl Sink<B, ID>,
+) -> Result<(), InputError>
+where
+    B: BBox,
+    ID: Copy + PartialOrd,
+{
+    check(a, Input::A)?;
+    check(b, Input::B)?;
+    intersect_scan(a, b, out);
+    Ok(())
+}
+
 /// Finds box intersections by checking every box in `a` against every box in `b`.
 /// Performs well for on the order of 100 boxes. *O*(*n^2*)
-/// * `a` and `b` may be either the same or distinct [`BBoxSet`]s
-/// * `out` will contain pairs of `ID`s of intersecting boxes.
-pub fn intersect_brute_force<B, ID>(a: &BBoxSet<B, ID>, b: &BBoxSet<B, ID>, out: &mut Vec<(ID, ID)>)
-where
+/// * `a` and `b` may be either the same or distinct [`BBoxSet`]s (or other [`BoxSource`]s)
+/// * `out` will receive the pairs of intersecting boxes, e.g. a `Vec<(ID, ID)>` (see [`Sink`]).
+pub fn intersect_brute_force<B, ID>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    out: &mut impl Sink<B, ID>,
+) where
+    B: BBox,
+    ID: Copy,
+{
+    intersect_brute_force_with_policy(a, b, out, HalfOpen);
+}
+
+/// Like `intersect_brute_force` but comparing bounds according to `policy`. See [`Policy`].
+pub fn intersect_brute_force_with_policy<B, ID>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    out: &mut impl Sink<B, ID>,
+    policy: impl Policy<B::Num>,
+) where
     B: BBox,
     ID: Copy,
 {
-    let same = a as *const _ == b as *const _; // check if a and b refer to the same BBoxSet
+    let same = source::same(a, b); // check if a and b refer to the same source
     if same {
         // avoid duplicate intersections
-        let mut start = 1;
-        for &(bbox, id) in &a.boxes {
-            for idx in start..a.boxes.len() {
-                let (bbox2, id2) = a.boxes[idx];
-                if bbox.intersects(&bbox2) {
-                    out.push((id, id2));
+        for start in 0..a.len() {
+            let (bbox, id) = a.get(start);
+            for idx2 in start + 1..a.len() {
+                let (bbox2, id2) = a.get(idx2);
+                if policy.intersects(&bbox, &bbox2) {
+                    out.report(id, &bbox, id2, &bbox2);
                 }
             }
-            start += 1;
         }
     } else {
-        for &(bbox, id) in &a.boxes {
-            for &(bbox2, id2) in &b.boxes {
-                if bbox.intersects(&bbox2) {
-                    out.push((id, id2));
+        for idx in 0..a.len() {
+            let (bbox, id) = a.get(idx);
+            for idx2 in 0..b.len() {
+                let (bbox2, id2) = b.get(idx2);
+                if policy.intersects(&bbox, &bbox2) {
+                    out.report(id, &bbox, id2, &bbox2);
                 }
             }
         }
     }
 }
 
+/// Like `intersect_brute_force` but treating every box as if it was inflated by `margin` in every dimension,
+/// to absorb floating point error. The sets are not modified. See [`Tolerant`].
+pub fn intersect_brute_force_with_margin<B, ID>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    out: &mut impl Sink<B, ID>,
+    margin: B::Num,
+) where
+    B: BBox,
+    ID: Copy,
+    B::Num: Add<Output = B::Num> + Sub<Output = B::Num>,
+{
+    intersect_brute_force_with_policy(a, b, out, Tolerant(margin));
+}
+
 impl HasInfinity for f32 {
     const NINFTY: Self = f32::NEG_INFINITY;
     const INFTY: Self = f32::INFINITY;
@@ -170,5 +889,69 @@ impl HasInfinity for f64 {
     const INFTY: Self = f64::INFINITY;
 }
 
+/// Integers use their minimum and maximum values as infinities, so boxes are only
+/// treated correctly if their bounds lie strictly between them.
+macro_rules! impl_has_infinity_int {
+    ($($t:ty),*) => {
+        $(
+            impl HasInfinity for $t {
+                const NINFTY: Self = <$t>::MIN;
+                const INFTY: Self = <$t>::MAX;
+            }
+        )*
+    };
+}
+
+impl_has_infinity_int!(i32, i64, u16, u32, u64);
+
+/// Like integers, [`Decimal`](rust_decimal::Decimal) uses its minimum and maximum values as infinities.
+/// Requires the `decimal` feature.
+#[cfg(feature = "decimal")]
+impl HasInfinity for rust_decimal::Decimal {
+    const NINFTY: Self = rust_decimal::Decimal::MIN;
+    const INFTY: Self = rust_decimal::Decimal::MAX;
+}
+
+/// Exact rational bounds with `i32` or `i64` numerators and denominators, using the
+/// minimum and maximum integers as infinities. Requires the `rational` feature.
+/// Since bounds must be [`Copy`], arbitrary precision rationals such as `BigRational` are not supported.
+#[cfg(feature = "rational")]
+macro_rules! impl_has_infinity_ratio {
+    ($($t:ty),*) => {
+        $(
+            impl HasInfinity for num_rational::Ratio<$t> {
+                const NINFTY: Self = num_rational::Ratio::new_raw(<$t>::MIN, 1);
+                const INFTY: Self = num_rational::Ratio::new_raw(<$t>::MAX, 1);
+            }
+        )*
+    };
+}
+
+#[cfg(feature = "rational")]
+impl_has_infinity_ratio!(i32, i64);
+
+/// SI quantities with units, e.g. [`Length`](uom::si::f32::Length), use the infinities of their underlying storage type.
+/// Requires the `units` feature.
+#[cfg(feature = "units")]
+impl<D, U, V> HasInfinity for uom::si::Quantity<D, U, V>
+where
+    D: uom::si::Dimension + ?Sized,
+    U: uom::si::Units<V> + ?Sized,
+    V: uom::num::Num + uom::Conversion<V> + HasInfinity,
+{
+    const NINFTY: Self = uom::si::Quantity {
+        dimension: std::marker::PhantomData,
+        units: std::marker::PhantomData,
+        value: V::NINFTY,
+    };
+    const INFTY: Self = uom::si::Quantity {
+        dimension: std::marker::PhantomData,
+        units: std::marker::PhantomData,
+        value: V::INFTY,
+    };
+}
+
 #[cfg(test)]
+// the baseline tests predate these lints
+#[allow(clippy::needless_range_loop, clippy::redundant_closure)]
 mod tests;
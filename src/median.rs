@@ -1,3 +1,14 @@
+//! How [`intersect_ze`](crate::intersect_ze) picks the boundaries at which it splits the segments of its segment trees,
+//! see [`MedianStrategy`].
+
+use std::cmp::Ordering;
+use std::ops::{Add, Div};
+
+use crate::boxes::BBox;
+use crate::source::{approx_median_in, BoxSource};
+use crate::Rng;
+
+/// Returns the median of `a`, `b` and `c`.
 pub fn median_of_3<N: PartialOrd + Copy>(a: N, b: N, c: N) -> N {
     if a > b {
         if b > c {
@@ -26,18 +37,183 @@ pub fn median_of_3<N: PartialOrd + Copy>(a: N, b: N, c: N) -> N {
     }
 }
 
-pub fn approx_median<N: PartialOrd + Copy>(
-    items: &Vec<N>,
-    levels: u8,
-    random_indices: &mut Vec<usize>,
-) -> N {
+/// Returns the median of three medians of three ... of `samples`, which must have `3^levels` elements.
+pub fn approx_median<N: PartialOrd + Copy>(samples: &[N], levels: u8) -> N {
     if levels == 0 {
-        items[random_indices.pop().unwrap()]
+        samples[0]
     } else {
+        let third = samples.len() / 3;
         median_of_3(
-            approx_median(items, levels - 1, random_indices),
-            approx_median(items, levels - 1, random_indices),
-            approx_median(items, levels - 1, random_indices),
+            approx_median(&samples[..third], levels - 1),
+            approx_median(&samples[third..2 * third], levels - 1),
+            approx_median(&samples[2 * third..], levels - 1),
         )
     }
 }
+
+/// Like [`approx_median`], but computes the medians of the three thirds of large `samples` in parallel.
+#[cfg(feature = "parallel")]
+pub fn par_approx_median<N: PartialOrd + Copy + Send + Sync>(samples: &[N], levels: u8) -> N {
+    // below 3^6 samples, spawning tasks costs more than it saves
+    if levels <= 6 {
+        approx_median(samples, levels)
+    } else {
+        let third = samples.len() / 3;
+        let (a, (b, c)) = rayon::join(
+            || par_approx_median(&samples[..third], levels - 1),
+            || {
+                rayon::join(
+                    || par_approx_median(&samples[third..2 * third], levels - 1),
+                    || par_approx_median(&samples[2 * third..], levels - 1),
+                )
+            },
+        );
+        median_of_3(a, b, c)
+    }
+}
+
+/// Chooses the boundary at which a segment of a segment tree is divided, from the low boundaries of the points
+/// in the segment. The closer it is to their median, the more balanced the tree. See
+/// [`intersect_ze_with_median`](crate::intersect_ze_with_median).
+pub trait MedianStrategy<N>: Copy {
+    /// Returns the boundary to divide the low boundaries in dimension `dim` of the boxes of `source` at,
+    /// which is not empty. `samples` is scratch space, reused between calls.
+    fn median<B, ID, R>(
+        &self,
+        source: &impl BoxSource<B, ID>,
+        dim: usize,
+        rand: &mut R,
+        samples: &mut Vec<N>,
+    ) -> N
+    where
+        B: BBox<Num = N>,
+        R: Rng;
+
+    /// Like [`MedianStrategy::median`], but may use the rayon thread pool, as the nodes of
+    /// [`intersect_ze_par`](crate::parallel::intersect_ze_par) do. Must return the same boundary.
+    /// Requires the `parallel` feature.
+    #[cfg(feature = "parallel")]
+    fn par_median<B, ID, R>(
+        &self,
+        source: &(impl BoxSource<B, ID> + Sync),
+        dim: usize,
+        rand: &mut R,
+        samples: &mut Vec<N>,
+    ) -> N
+    where
+        B: BBox<Num = N>,
+        N: Send + Sync,
+        R: Rng,
+    {
+        self.median(source, dim, rand, samples)
+    }
+}
+
+/// The median of three medians of three ... of randomly sampled low boundaries, see
+/// [`BBoxSet::approx_median`](crate::set::BBoxSet::approx_median). Takes constant time for a given number of boxes,
+/// but may end up far from the median on skewed data. The default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ApproxMedian;
+
+impl<N: Copy + PartialOrd> MedianStrategy<N> for ApproxMedian {
+    fn median<B, ID, R>(
+        &self,
+        source: &impl BoxSource<B, ID>,
+        dim: usize,
+        rand: &mut R,
+        samples: &mut Vec<N>,
+    ) -> N
+    where
+        B: BBox<Num = N>,
+        R: Rng,
+    {
+        approx_median_in(source, dim, rand, samples)
+    }
+
+    /// Samples the boundaries and computes their median in parallel, drawing the same random numbers.
+    #[cfg(feature = "parallel")]
+    fn par_median<B, ID, R>(
+        &self,
+        source: &(impl BoxSource<B, ID> + Sync),
+        dim: usize,
+        rand: &mut R,
+        _samples: &mut Vec<N>,
+    ) -> N
+    where
+        B: BBox<Num = N>,
+        N: Send + Sync,
+        R: Rng,
+    {
+        crate::source::par_approx_median(source, dim, rand)
+    }
+}
+
+/// The exact median of the low boundaries, found by quickselect. Takes linear time in the number of boxes
+/// in the segment, which makes building the tree *O*(*n* log *n*) overall, but always splits the points evenly
+/// unless many of them share a low boundary.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExactMedian;
+
+impl<N: Copy + PartialOrd> MedianStrategy<N> for ExactMedian {
+    fn median<B, ID, R>(
+        &self,
+        source: &impl BoxSource<B, ID>,
+        dim: usize,
+        _rand: &mut R,
+        samples: &mut Vec<N>,
+    ) -> N
+    where
+        B: BBox<Num = N>,
+        R: Rng,
+    {
+        samples.clear();
+        samples.extend((0..source.len()).map(|idx| source.get(idx).0.lo(dim)));
+        let mid = samples.len() / 2;
+        samples.select_nth_unstable_by(mid, |a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        samples[mid]
+    }
+}
+
+/// Halfway between the lowest and the highest low boundary, like the split of a kd-tree in space rather than
+/// by count. Balances the tree for uniformly spread boxes and divides clusters that are far apart cleanly, but may
+/// split off very few points. Falls back to [`ApproxMedian`] if there is no value strictly between the two,
+/// e.g. for infinite boundaries or neighbouring integers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Halfway;
+
+impl<N> MedianStrategy<N> for Halfway
+where
+    N: Copy + PartialOrd + Add<Output = N> + Div<Output = N> + From<u8>,
+{
+    fn median<B, ID, R>(
+        &self,
+        source: &impl BoxSource<B, ID>,
+        dim: usize,
+        rand: &mut R,
+        samples: &mut Vec<N>,
+    ) -> N
+    where
+        B: BBox<Num = N>,
+        R: Rng,
+    {
+        let first = source.get(0).0.lo(dim);
+        let (mut min, mut max) = (first, first);
+        for idx in 1..source.len() {
+            let lo = source.get(idx).0.lo(dim);
+            if lo < min {
+                min = lo;
+            }
+            if lo > max {
+                max = lo;
+            }
+        }
+        // halving both first can't overflow
+        let two = N::from(2);
+        let mid = min / two + max / two;
+        if mid > min && mid <= max {
+            mid
+        } else {
+            ApproxMedian.median(source, dim, rand, samples)
+        }
+    }
+}
@@ -0,0 +1,529 @@
+//! Parallel versions of the algorithms, running on the rayon thread pool. Requires the `parallel` feature.
+//!
+//! The random number generator is cloned for every task, so it must implement [`Clone`] and [`Send`].
+//! How the work is split into tasks only depends on the input, so the pairs are reported in an order
+//! that depends on the input and `rand`, but not on the number of threads or on scheduling.
+//! The sequential algorithms split the work differently, so they report the same pairs in another order.
+//! To compare them, bring both into a canonical order, see [`intersect_ze_par_canonical`] and [`sort_pairs`].
+
+use rayon::join;
+use rayon::prelude::*;
+
+use crate::boxes::BBox;
+use crate::error::require_sorted;
+use crate::internals::{
+    check_order, divide, keys_of, leaf, left_intervals, left_points, merge_in_place,
+    right_intervals, scan, spanning, Copies, ItemsMut, Params, Run, Scratch, Traversal,
+};
+use crate::median::{ApproxMedian, MedianStrategy};
+use crate::policy::{HalfOpen, Policy};
+use crate::sink::{Merge, Sink};
+use crate::source::{self, BoxSource};
+use crate::{auto_cutoff, HasInfinity, Rng};
+
+/// Default for [`ParallelConfig::sequential_below`]
+pub const DEFAULT_SEQUENTIAL_BELOW: usize = 20_000;
+
+/// Number of boxes of each set compared by a task of [`intersect_brute_force_par`]
+const TILE: usize = 256;
+
+/// Limits on the concurrency of the parallel algorithms, see [`intersect_ze_par_with_config`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParallelConfig {
+    /// If set, the algorithm runs on a thread pool of its own with this many threads,
+    /// instead of the current (by default the global) rayon thread pool.
+    /// Creating the pool spawns the threads, so for frequent calls it's cheaper to build a pool
+    /// once and call the algorithm inside [`ThreadPool::install`](rayon::ThreadPool::install).
+    pub threads: Option<usize>,
+    /// Nodes this many levels of tasks below the root are processed by a single task,
+    /// so at most about `4^max_task_depth` tasks are created.
+    pub max_task_depth: usize,
+    /// Nodes with fewer boxes than this are processed by a single task.
+    pub sequential_below: usize,
+}
+
+impl Default for ParallelConfig {
+    fn default() -> Self {
+        Self {
+            threads: None,
+            max_task_depth: usize::MAX,
+            sequential_below: DEFAULT_SEQUENTIAL_BELOW,
+        }
+    }
+}
+
+impl ParallelConfig {
+    /// Runs `op` on a new thread pool with [`threads`](Self::threads) threads if set,
+    /// otherwise on the current thread pool. Can be used to cap the threads used by the other
+    /// algorithms in this module, e.g. [`intersect_brute_force_par`].
+    /// # Panics
+    /// If the thread pool can't be created.
+    pub fn install<T: Send>(&self, op: impl FnOnce() -> T + Send) -> T {
+        match self.threads {
+            Some(threads) => rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .expect("failed to create thread pool")
+                .install(op),
+            None => op(),
+        }
+    }
+}
+
+/// Like [`intersect_ze`](crate::intersect_ze), but processes independent parts of the segment trees in parallel:
+/// the two segment trees streamed for the intervals stored at a node, and the left and right subtree.
+/// The nodes divide the same scratch buffers in place as `intersect_ze` does; only nodes processed in parallel
+/// copy the points for the streamed trees and the intervals for the right subtree.
+///
+/// Each task reports to its own sink, created with [`Default`], so no lock is needed around `out`.
+/// When a node's tasks are done, their sinks are [merged](Merge) in a fixed order, and finally into `out`.
+/// Any sink implementing [`Merge`] can be used, e.g. a `Vec<(ID, ID)>` or an [`OverlapSink`](crate::sink::OverlapSink).
+pub fn intersect_ze_par<B, ID, R, S>(
+    a: &(impl BoxSource<B, ID> + Sync),
+    b: &(impl BoxSource<B, ID> + Sync),
+    out: &mut S,
+    rand: &mut R,
+) where
+    B: BBox + Send + Sync,
+    B::Num: PartialOrd + HasInfinity + Send + Sync,
+    ID: PartialOrd + Copy + Send + Sync,
+    R: Rng + Clone + Send,
+    S: Sink<B, ID> + Merge + Default + Send,
+{
+    let params = Params {
+        cutoff: auto_cutoff(a.len().max(b.len())),
+        ..Params::default()
+    };
+    intersect_ze_par_with_params(a, b, out, rand, HalfOpen, params);
+}
+
+/// Like [`intersect_ze_with_params`](crate::intersect_ze_with_params), but in parallel, see [`intersect_ze_par`].
+pub fn intersect_ze_par_with_params<B, ID, R, P, S>(
+    a: &(impl BoxSource<B, ID> + Sync),
+    b: &(impl BoxSource<B, ID> + Sync),
+    out: &mut S,
+    rand: &mut R,
+    policy: P,
+    params: Params,
+) where
+    B: BBox + Send + Sync,
+    B::Num: PartialOrd + HasInfinity + Send + Sync,
+    ID: PartialOrd + Copy + Send + Sync,
+    R: Rng + Clone + Send,
+    P: Policy<B::Num> + Send + Sync,
+    S: Sink<B, ID> + Merge + Default + Send,
+{
+    let config = ParallelConfig::default();
+    intersect_ze_par_with_config(a, b, out, rand, policy, params, config);
+}
+
+/// Like [`intersect_ze_par_with_params`], but with the concurrency limited by `config`.
+pub fn intersect_ze_par_with_config<B, ID, R, P, S>(
+    a: &(impl BoxSource<B, ID> + Sync),
+    b: &(impl BoxSource<B, ID> + Sync),
+    out: &mut S,
+    rand: &mut R,
+    policy: P,
+    params: Params,
+    config: ParallelConfig,
+) where
+    B: BBox + Send + Sync,
+    B::Num: PartialOrd + HasInfinity + Send + Sync,
+    ID: PartialOrd + Copy + Send + Sync,
+    R: Rng + Clone + Send,
+    P: Policy<B::Num> + Send + Sync,
+    S: Sink<B, ID> + Merge + Default + Send,
+{
+    intersect_ze_par_with_median(a, b, out, rand, policy, params, ApproxMedian, config);
+}
+
+/// Like [`intersect_ze_par_with_config`], but dividing the segments of the segment trees at the boundaries
+/// chosen by `median`, see [`MedianStrategy`].
+#[allow(clippy::too_many_arguments)]
+pub fn intersect_ze_par_with_median<B, ID, R, P, M, S>(
+    a: &(impl BoxSource<B, ID> + Sync),
+    b: &(impl BoxSource<B, ID> + Sync),
+    out: &mut S,
+    rand: &mut R,
+    policy: P,
+    params: Params,
+    median: M,
+    config: ParallelConfig,
+) where
+    B: BBox + Send + Sync,
+    B::Num: PartialOrd + HasInfinity + Send + Sync,
+    ID: PartialOrd + Copy + Send + Sync,
+    R: Rng + Clone + Send,
+    P: Policy<B::Num> + Send + Sync,
+    M: MedianStrategy<B::Num> + Send + Sync,
+    S: Sink<B, ID> + Merge + Default + Send,
+{
+    require_sorted(a, "a", "intersect_ze_par");
+    require_sorted(b, "b", "intersect_ze_par");
+    let dim = source::top_dim(a, b, policy);
+    let order = check_order(a, b, B::DIM, policy);
+    let same = source::same(a, b);
+    // empty boxes are skipped up front, see `Policy::is_empty`
+    let mut buffers = [Copies::of(a, policy), Copies::of(b, policy)];
+    let mut keys = [
+        keys_of(&Copies, &buffers[0], policy),
+        keys_of(&Copies, &buffers[1], policy),
+    ];
+    let rand = rand.clone();
+    let config = &config;
+    let shared = Shared {
+        policy,
+        params,
+        median,
+        order: order.as_deref(),
+        config,
+    };
+    config.install(move || {
+        let root = Node {
+            lo: B::Num::NINFTY,
+            hi: B::Num::INFTY,
+            dim,
+            depth: 0,
+            tasks: 0,
+        };
+        let [a_boxes, b_boxes] = &mut buffers;
+        let [a_keys, b_keys] = &mut keys;
+        let (a, b) = (
+            (&mut a_boxes[..], &mut a_keys[..]),
+            (&mut b_boxes[..], &mut b_keys[..]),
+        );
+        if same {
+            // one tree is enough to have every box represented as both an interval and a point
+            out.merge(hybrid_par(&Copies, a, b, root, rand, shared));
+        } else {
+            // need two trees so that every box is represented as both an interval and a point.
+            // They divide the same buffers, so one is processed after the other
+            let ab = hybrid_par(
+                &Copies,
+                (&mut *a.0, &mut *a.1),
+                (&mut *b.0, &mut *b.1),
+                root,
+                rand.clone(),
+                shared,
+            );
+            out.merge(ab);
+            out.merge(hybrid_par(&Copies, b, a, root, rand, shared));
+        }
+    });
+}
+
+/// Like [`intersect_ze_par`], but appends the pairs to `out` in a canonical order: the smaller `ID` of each
+/// pair comes first, and the pairs are sorted. This is not the order in which [`intersect_ze`](crate::intersect_ze)
+/// reports them, even for the same `rand`, since the tasks use clones of it and divide the segments differently.
+/// But sorting the output of `intersect_ze` with [`sort_pairs`] yields exactly the same vector,
+/// so parallel and sequential builds of lockstep simulations agree.
+pub fn intersect_ze_par_canonical<B, ID, R>(
+    a: &(impl BoxSource<B, ID> + Sync),
+    b: &(impl BoxSource<B, ID> + Sync),
+    out: &mut Vec<(ID, ID)>,
+    rand: &mut R,
+) where
+    B: BBox + Send + Sync,
+    B::Num: PartialOrd + HasInfinity + Send + Sync,
+    ID: Ord + Copy + Send + Sync,
+    R: Rng + Clone + Send,
+{
+    let start = out.len();
+    intersect_ze_par(a, b, out, rand);
+    let pairs = &mut out[start..];
+    pairs.par_iter_mut().for_each(|pair| {
+        if pair.1 < pair.0 {
+            *pair = (pair.1, pair.0);
+        }
+    });
+    pairs.par_sort_unstable();
+}
+
+/// Puts `pairs` in the canonical order of [`intersect_ze_par_canonical`]: the smaller `ID` of each
+/// pair first, and the pairs sorted.
+pub fn sort_pairs<ID: Ord + Copy>(pairs: &mut [(ID, ID)]) {
+    for pair in pairs.iter_mut() {
+        if pair.1 < pair.0 {
+            *pair = (pair.1, pair.0);
+        }
+    }
+    pairs.sort_unstable();
+}
+
+/// Like [`intersect_brute_force`](crate::intersect_brute_force), but the comparisons of every box in `a`
+/// with every box in `b` are divided into tiles of 256 × 256 comparisons processed in parallel.
+/// Each tile reports to its own sink, and the sinks are [merged](Merge) into `out` in the order of the tiles.
+pub fn intersect_brute_force_par<B, ID, S>(
+    a: &(impl BoxSource<B, ID> + Sync),
+    b: &(impl BoxSource<B, ID> + Sync),
+    out: &mut S,
+) where
+    B: BBox + Send + Sync,
+    ID: Copy + Send + Sync,
+    S: Sink<B, ID> + Merge + Default + Send,
+{
+    intersect_brute_force_par_with_policy(a, b, out, HalfOpen);
+}
+
+/// Like [`intersect_brute_force_par`] but comparing bounds according to `policy`. See [`Policy`].
+pub fn intersect_brute_force_par_with_policy<B, ID, S, P>(
+    a: &(impl BoxSource<B, ID> + Sync),
+    b: &(impl BoxSource<B, ID> + Sync),
+    out: &mut S,
+    policy: P,
+) where
+    B: BBox + Send + Sync,
+    ID: Copy + Send + Sync,
+    S: Sink<B, ID> + Merge + Default + Send,
+    P: Policy<B::Num> + Send + Sync,
+{
+    // skip empty boxes up front, see `Policy::is_empty`
+    let same = source::same(a, b);
+    let a_boxes = Copies::of(a, policy);
+    let b_boxes = if same {
+        Vec::new()
+    } else {
+        Copies::of(b, policy)
+    };
+    let b_boxes = if same { &a_boxes } else { &b_boxes };
+
+    let (rows, cols) = (a_boxes.chunks(TILE), b_boxes.chunks(TILE));
+    let tiles: Vec<_> = rows
+        .enumerate()
+        .flat_map(|(r, row)| {
+            cols.clone()
+                .enumerate()
+                // avoid duplicate intersections
+                .filter(move |&(c, _)| !same || c >= r)
+                .map(move |(c, col)| (r == c, row, col))
+        })
+        .collect();
+    let outs: Vec<S> = tiles
+        .into_par_iter()
+        .map(|(diagonal, row, col)| {
+            let mut out = S::default();
+            for (i, (bbox, id)) in row.iter().enumerate() {
+                // tiles on the diagonal compare a set with itself, so only pairs above the diagonal count
+                let start = if same && diagonal { i + 1 } else { 0 };
+                for (bbox2, id2) in &col[start..] {
+                    if policy.intersects(bbox, bbox2) {
+                        out.report(*id, bbox, *id2, bbox2);
+                    }
+                }
+            }
+            out
+        })
+        .collect();
+    for tile in outs {
+        out.merge(tile);
+    }
+}
+
+/// What all nodes of [`hybrid_par`] share
+#[derive(Clone, Copy)]
+struct Shared<'a, P, M> {
+    policy: P,
+    params: Params,
+    median: M,
+    /// The order the scans check the dimensions in, see [`check_order`]
+    order: Option<&'a [usize]>,
+    config: &'a ParallelConfig,
+}
+
+/// A node of a segment tree: the segment [`lo`, `hi`) in dimension `dim` and its depth in the tree
+#[derive(Clone, Copy)]
+struct Node<N> {
+    lo: N,
+    hi: N,
+    dim: usize,
+    depth: usize,
+    /// Number of levels of tasks above this node
+    tasks: usize,
+}
+
+/// Processes `node` with `intervals` and `points` like [`hybrid_in_place`](crate::internals::hybrid_in_place),
+/// in parallel as long as it is large enough, and returns a sink with the pairs found. The items are partitioned
+/// in place, along with their keys (see [`lo_keys`](crate::internals::lo_keys)), and left sorted.
+/// The two segment trees streamed for the intervals stored at the node are processed in parallel with
+/// the left and right subtree, which are processed in parallel with each other.
+fn hybrid_par<B, ID, C, R, P, M, S>(
+    scratch: &C,
+    (intervals, i_keys): ItemsMut<'_, C::Item, B::Num>,
+    (points, p_keys): ItemsMut<'_, C::Item, B::Num>,
+    node: Node<B::Num>,
+    mut rand: R,
+    shared: Shared<'_, P, M>,
+) -> S
+where
+    B: BBox,
+    B::Num: PartialOrd + HasInfinity + Send + Sync,
+    ID: PartialOrd + Copy,
+    C: Scratch<B, ID> + Sync,
+    C::Item: Send + Sync,
+    R: Rng + Clone + Send,
+    P: Policy<B::Num> + Send + Sync,
+    M: MedianStrategy<B::Num> + Send + Sync,
+    S: Sink<B, ID> + Merge + Default + Send,
+{
+    let Shared {
+        policy,
+        params,
+        median,
+        order,
+        config,
+    } = shared;
+    let Node {
+        lo,
+        hi,
+        dim,
+        depth,
+        tasks,
+    } = node;
+    let mut out = S::default();
+
+    // small nodes and nodes that are too many levels of tasks down are processed by a single task
+    if intervals.len() + points.len() < config.sequential_below || tasks >= config.max_task_depth {
+        let order = order.map(<[usize]>::to_vec);
+        let mut traversal = Traversal::new(policy, params, median, order);
+        let lens = [intervals.len(), points.len()];
+        traversal.push_root(0, lens, lo, hi, dim, depth);
+        while traversal.step(
+            scratch,
+            [&mut *intervals, &mut *points],
+            [&mut *i_keys, &mut *p_keys],
+            &mut out,
+            &mut rand,
+        ) {}
+        return out;
+    }
+
+    // Steps are numbered as in `Traversal::step`
+    // Steps 1 to 3
+    let node = (lo, hi, dim, depth);
+    let (i, p) = ((&*intervals, &*i_keys), (&*points, &*p_keys));
+    if leaf(
+        scratch, i, p, node, &mut out, &mut rand, policy, params, order,
+    ) {
+        return out;
+    }
+
+    // Step 4
+    let mut spill = (Vec::new(), Vec::new());
+    let i = (&mut *intervals, &mut *i_keys);
+    let m = spanning(scratch, i, &mut spill, lo, hi, dim, policy);
+    let (intervals_m, intervals_lr) = intervals.split_at_mut(m);
+    let (i_keys_m, i_keys_lr) = i_keys.split_at_mut(m);
+    let stream = Node {
+        lo: B::Num::NINFTY,
+        hi: B::Num::INFTY,
+        dim: dim - 1,
+        depth: 0,
+        tasks: tasks + 1,
+    };
+
+    // Step 5
+    let run = Run {
+        scratch,
+        items: &*points,
+    };
+    let mi = median.par_median(&run, dim, &mut rand, &mut Vec::new());
+    let Some(mi) = divide(policy, mi, lo, hi) else {
+        let i = (&mut *intervals_m, &mut *i_keys_m);
+        let p = (&mut *points, &mut *p_keys);
+        out.merge(streams(scratch, i, p, stream, rand, shared));
+        let (i, p) = ((&*intervals_lr, &*i_keys_lr), (&*points, &*p_keys));
+        scan(scratch, i, p, dim, order, &mut out, policy);
+        merge_in_place((intervals, i_keys), m, &mut spill, |a, b| scratch.le(a, b));
+        return out;
+    };
+
+    // the segment trees of Step 4 need all points in order while the subtrees divide them, so they get copies.
+    // The intervals of the two subtrees are not usually disjoint, so the right one gets copies as well
+    let (mut points_4, mut p_keys_4) = (points.to_vec(), p_keys.to_vec());
+    let (mut intervals_r, mut i_keys_r) = (intervals_lr.to_vec(), i_keys_lr.to_vec());
+    let p = left_points(scratch, (points, p_keys), &mut spill, mi, dim, policy);
+    let i = (&mut *intervals_lr, &mut *i_keys_lr);
+    let l = left_intervals(scratch, i, &mut spill, mi, dim, policy);
+    let i = (&mut intervals_r[..], &mut i_keys_r[..]);
+    let r = right_intervals(scratch, i, &mut spill, mi, dim, policy);
+    let (points_l, points_r) = points.split_at_mut(p);
+    let (p_keys_l, p_keys_r) = p_keys.split_at_mut(p);
+    let child = |lo, hi| Node {
+        lo,
+        hi,
+        dim,
+        depth: depth + 1,
+        tasks: tasks + 1,
+    };
+
+    let (rand_4, rand_l) = (rand.clone(), rand.clone());
+    let (a, (b, c)): (S, (S, S)) = join(
+        || {
+            let i = (&mut *intervals_m, &mut *i_keys_m);
+            streams(
+                scratch,
+                i,
+                (&mut points_4, &mut p_keys_4),
+                stream,
+                rand_4,
+                shared,
+            )
+        },
+        || {
+            join(
+                || {
+                    // Step 6
+                    let i = (&mut intervals_lr[..l], &mut i_keys_lr[..l]);
+                    let p = (points_l, p_keys_l);
+                    hybrid_par(scratch, i, p, child(lo, mi), rand_l, shared)
+                },
+                || {
+                    // Step 7
+                    let i = (&mut intervals_r[..r], &mut i_keys_r[..r]);
+                    let p = (points_r, p_keys_r);
+                    hybrid_par(scratch, i, p, child(mi, hi), rand, shared)
+                },
+            )
+        },
+    );
+    out.merge(a);
+    out.merge(b);
+    out.merge(c);
+
+    // restore the order of the boxes
+    let le = |a, b| scratch.le(a, b);
+    merge_in_place((intervals_lr, i_keys_lr), l, &mut spill, le);
+    merge_in_place((intervals, i_keys), m, &mut spill, le);
+    merge_in_place((points, p_keys), p, &mut spill, le);
+    out
+}
+
+/// Step 4: processes the two segment trees streamed for the intervals stored at a node, which are
+/// `intervals`, in the next dimension. They divide the same boxes, so one is processed after the other.
+fn streams<B, ID, C, R, P, M, S>(
+    scratch: &C,
+    (intervals, i_keys): ItemsMut<'_, C::Item, B::Num>,
+    (points, p_keys): ItemsMut<'_, C::Item, B::Num>,
+    stream: Node<B::Num>,
+    rand: R,
+    shared: Shared<'_, P, M>,
+) -> S
+where
+    B: BBox,
+    B::Num: PartialOrd + HasInfinity + Send + Sync,
+    ID: PartialOrd + Copy,
+    C: Scratch<B, ID> + Sync,
+    C::Item: Send + Sync,
+    R: Rng + Clone + Send,
+    P: Policy<B::Num> + Send + Sync,
+    M: MedianStrategy<B::Num> + Send + Sync,
+    S: Sink<B, ID> + Merge + Default + Send,
+{
+    let i = (&mut *intervals, &mut *i_keys);
+    let p = (&mut *points, &mut *p_keys);
+    let mut out: S = hybrid_par(scratch, i, p, stream, rand.clone(), shared);
+    let i = (intervals, i_keys);
+    let p = (points, p_keys);
+    out.merge(hybrid_par(scratch, p, i, stream, rand, shared));
+    out
+}
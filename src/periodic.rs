@@ -0,0 +1,125 @@
+//! Periodic (toroidal) domains, where boxes crossing the high boundary of the domain
+//! wrap around and intersect boxes on the other side, as in molecular dynamics or tile-wrapping worlds.
+//!
+//! [`periodic_set`] generates ghost copies of the boxes crossing a boundary, shifted by one period,
+//! and the [`PeriodicSink`] maps pairs involving ghosts back to the original boxes.
+//!
+//! # Examples
+//! ```
+//! use box_intersect_ze::boxes::Box2Df32;
+//! use box_intersect_ze::periodic::{periodic_set, PeriodicSink};
+//! use box_intersect_ze::set::BBoxSet;
+//!
+//! let mut boxes = BBoxSet::new();
+//! boxes.push(0, Box2Df32::new([9.0, 0.0], [11.0, 1.0])); // wraps around to [0, 1) in dimension 0
+//! boxes.push(1, Box2Df32::new([0.5, 0.5], [1.5, 1.5]));
+//! let set = periodic_set(&boxes, [Some(10.0), None]);
+//!
+//! let mut out = Vec::new();
+//! box_intersect_ze::intersect_scan(&set, &set, &mut PeriodicSink::new(&mut out));
+//! assert_eq!(out.len(), 1);
+//! ```
+
+use std::ops::{Add, Sub};
+
+use crate::boxes::{BBox, BoxND};
+use crate::set::BBoxSet;
+use crate::sink::Sink;
+
+/// Identifier of a periodic image of a box, used in the sets created by [`periodic_set`]
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct Image<ID> {
+    /// Identifier of the original box
+    pub id: ID,
+    /// Bit `d` is set if this image is shifted by one period towards negative infinity in dimension `d`
+    pub shift: u32,
+}
+
+/// Builds a set from `boxes` in a domain that is periodic with the given period in every dimension
+/// where `periods` is [`Some`], adding a ghost copy for every combination of dimensions in which a
+/// box crosses the high boundary of the domain. The set is sorted, and intersections must be
+/// reported to a [`PeriodicSink`].
+/// * In periodic dimensions, the low boundaries of the boxes must be in [`0`, `period`).
+/// * Follows the minimum image convention: boxes must be smaller than half the period,
+///   so that two boxes can only intersect in one way.
+/// # Panics
+/// If a box is not smaller than half the period, or if there are more than 32 dimensions.
+pub fn periodic_set<N, ID, const D: usize>(
+    boxes: &BBoxSet<BoxND<N, D>, ID>,
+    periods: [Option<N>; D],
+) -> BBoxSet<BoxND<N, D>, Image<ID>>
+where
+    N: Copy + PartialOrd + Add<Output = N> + Sub<Output = N>,
+    ID: Copy + PartialOrd,
+{
+    assert!(D <= 32, "at most 32 dimensions are supported");
+    let mut set = BBoxSet::with_capacity(boxes.len());
+    for &(bbox, id) in boxes.boxes() {
+        let mut crossing = 0u32;
+        for (dim, period) in periods.iter().enumerate() {
+            if let Some(period) = *period {
+                let extent = bbox.hi(dim) - bbox.lo(dim);
+                assert!(
+                    extent + extent < period,
+                    "boxes must be smaller than half the period"
+                );
+                if bbox.hi(dim) > period {
+                    crossing |= 1 << dim;
+                }
+            }
+        }
+
+        // iterate over all subsets of the crossed dimensions
+        let mut shift = 0u32;
+        loop {
+            let (mut min, mut max) = (bbox.min(), bbox.max());
+            for dim in 0..D {
+                if shift & (1 << dim) != 0 {
+                    let period = periods[dim].unwrap();
+                    min[dim] = min[dim] - period;
+                    max[dim] = max[dim] - period;
+                }
+            }
+            set.push(Image { id, shift }, BoxND::new(min, max));
+
+            if shift == crossing {
+                break;
+            }
+            shift = (shift.wrapping_sub(crossing)) & crossing;
+        }
+    }
+    set.sort();
+    set
+}
+
+/// A [`Sink`] passing the intersections found in a set created by [`periodic_set`] on to another sink,
+/// with the [`Image`]s replaced by the identifiers of the original boxes. Each pair of boxes
+/// is reported once, along with the images that intersect.
+pub struct PeriodicSink<S> {
+    inner: S,
+}
+
+impl<S> PeriodicSink<S> {
+    /// Creates a sink passing pairs on to `inner`.
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+
+    /// Returns the wrapped sink.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<B, ID, S> Sink<B, Image<ID>> for PeriodicSink<S>
+where
+    ID: PartialEq,
+    S: Sink<B, ID>,
+{
+    fn report(&mut self, a_id: Image<ID>, a: &B, b_id: Image<ID>, b: &B) {
+        // images shifted in the same dimension are duplicates of a pair with fewer shifts
+        if a_id.id != b_id.id && a_id.shift & b_id.shift == 0 {
+            self.inner.report(a_id.id, a, b_id.id, b);
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Sets of points that can be tested for containment in boxes, see [`intersect_points`](crate::intersect_points)
+
+use std::fmt;
+use std::fmt::{Debug, Formatter};
+
+/// Trait for points in `DIM`-dimensional space
+pub trait Point: Copy {
+    /// Number of dimensions
+    const DIM: usize;
+    /// Type of the coordinates, must match the boundary type of the boxes the points are tested against
+    type Num: Copy + PartialOrd;
+
+    /// Returns the coordinate in dimension `dim`
+    fn coord(&self, dim: usize) -> Self::Num;
+}
+
+impl<N, const D: usize> Point for [N; D]
+where
+    N: Copy + PartialOrd,
+{
+    const DIM: usize = D;
+    type Num = N;
+
+    fn coord(&self, dim: usize) -> Self::Num {
+        self[dim]
+    }
+}
+
+#[derive(Clone)]
+/// A generic set of [`Point`]s of type `P` with identifiers of type `ID`
+pub struct PointSet<P: Point, ID> {
+    points: Vec<(P, ID)>,
+}
+
+impl<P, ID> Debug for PointSet<P, ID>
+where
+    P: Point + Debug,
+    ID: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.points.iter().map(|(_p, id)| id))
+            .finish()
+    }
+}
+
+impl<P: Point, ID> Default for PointSet<P, ID> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: Point, ID> PointSet<P, ID> {
+    /// Creates a new, empty set. Prefer [`PointSet::with_capacity`].
+    pub fn new() -> Self {
+        Self { points: Vec::new() }
+    }
+
+    /// Creates a new, empty set with the specified capacity. See [`Vec::with_capacity`].
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            points: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Adds a point with an identifier to the set.
+    pub fn push(&mut self, id: ID, point: P) {
+        self.points.push((point, id));
+    }
+
+    /// Returns the points in the set and their identifiers, in order.
+    pub fn points(&self) -> &[(P, ID)] {
+        &self.points
+    }
+
+    /// Returns the points in the set and their identifiers for arbitrary modification.
+    /// Sort the set again (see [`PointSet::sort`]) before passing it to [`intersect_points`](crate::intersect_points)
+    /// if the coordinates in dimension 0 may have changed order.
+    pub fn points_mut(&mut self) -> &mut Vec<(P, ID)> {
+        &mut self.points
+    }
+
+    /// Removes all points from the set.
+    pub fn clear(&mut self) {
+        self.points.clear();
+    }
+
+    /// Sorts the points in the set by their coordinates in dimension 0.
+    /// Needed for [`intersect_points`](crate::intersect_points).
+    pub fn sort(&mut self) {
+        self.points
+            .sort_by(|(a, _), (b, _)| a.coord(0).partial_cmp(&b.coord(0)).unwrap());
+    }
+
+    /// Returns the number of points in the set.
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    /// Returns `true` if the set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+}
+
+impl<P: Point, ID: Copy> PointSet<P, ID> {
+    /// Returns the point at the given index and its identifier.
+    pub fn get(&self, idx: usize) -> (P, ID) {
+        self.points[idx]
+    }
+}
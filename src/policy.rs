@@ -0,0 +1,221 @@
+//! Policies deciding how the bounds of boxes are compared, e.g. whether boxes that only touch intersect.
+//!
+//! All algorithms work on half-open intervals. A [`Policy`] maps the bounds of a box to
+//! the effective half-open interval used for comparisons, so every algorithm
+//! honors it the same way. Boxes with an empty effective interval in any dimension
+//! (see [`Policy::is_empty`]) intersect nothing, not even boxes enclosing them.
+//! Pass a policy to the `*_with_policy` functions at the [top level of the crate](`crate`).
+
+use std::ops::{Add, Sub};
+
+use crate::boxes::BBox;
+
+/// Trait for policies mapping the bounds of boxes to the effective half-open intervals
+/// used by the algorithms.
+pub trait Policy<N: Copy + PartialOrd>: Copy {
+    /// Returns the effective low boundary of an interval with low boundary `lo`.
+    /// Must be monotonic in `lo`, so that sets sorted by low boundary remain sorted.
+    fn lo(&self, lo: N) -> N;
+
+    /// Returns the effective (exclusive) high boundary of an interval with boundaries `lo` and `hi`.
+    fn hi(&self, lo: N, hi: N) -> N;
+
+    /// Returns the effective low boundary of `bbox` in dimension `dim`.
+    #[inline]
+    fn lo_of<B: BBox<Num = N>>(&self, bbox: &B, dim: usize) -> N {
+        self.lo(bbox.lo(dim))
+    }
+
+    /// Returns the effective high boundary of `bbox` in dimension `dim`.
+    #[inline]
+    fn hi_of<B: BBox<Num = N>>(&self, bbox: &B, dim: usize) -> N {
+        self.hi(bbox.lo(dim), bbox.hi(dim))
+    }
+
+    /// Returns `true` if the effective projection of `bbox` in dimension `dim` is empty,
+    /// i.e. its effective high boundary is not above its effective low boundary.
+    #[inline]
+    fn is_empty_in<B: BBox<Num = N>>(&self, bbox: &B, dim: usize) -> bool {
+        self.hi_of(bbox, dim) <= self.lo_of(bbox, dim)
+    }
+
+    /// Returns `true` if the effective projection of `bbox` is empty in any dimension.
+    /// Such boxes are never reported as intersecting by any algorithm.
+    #[inline]
+    fn is_empty<B: BBox<Num = N>>(&self, bbox: &B) -> bool {
+        (0..B::DIM).any(|dim| self.is_empty_in(bbox, dim))
+    }
+
+    /// Returns `true` if the effective projection of `bbox` in dimension `dim` contains `point`.
+    #[inline]
+    fn contains_in<B: BBox<Num = N>>(&self, bbox: &B, dim: usize, point: N) -> bool {
+        self.lo_of(bbox, dim) <= point && point < self.hi_of(bbox, dim)
+    }
+
+    /// Returns `true` if the effective projections of `a` and `b` in dimension `dim` intersect.
+    /// Empty projections intersect nothing.
+    #[inline]
+    fn intersects_in<B: BBox<Num = N>>(&self, a: &B, b: &B, dim: usize) -> bool {
+        let (a_lo, a_hi) = (self.lo_of(a, dim), self.hi_of(a, dim));
+        let (b_lo, b_hi) = (self.lo_of(b, dim), self.hi_of(b, dim));
+        a_lo < b_hi && b_lo < a_hi && a_lo < a_hi && b_lo < b_hi
+    }
+
+    /// Returns `true` if `a` and `b` intersect in all dimensions.
+    #[inline]
+    fn intersects<B: BBox<Num = N>>(&self, a: &B, b: &B) -> bool {
+        (0..B::DIM).all(|dim| self.intersects_in(a, b, dim))
+    }
+
+    /// Tests `bbox` against each box of `others` and sets bit `idx % 64` of `hits[idx / 64]`
+    /// if it intersects `others[idx]`, clearing the other bits of the first `others.len().div_ceil(64)` words.
+    /// The comparisons don't branch, so the compiler can vectorize them.
+    ///
+    /// # Panics
+    /// If `hits` has fewer than `others.len()` bits.
+    fn intersects_each<B: BBox<Num = N>>(&self, bbox: &B, others: &[B], hits: &mut [u64]) {
+        assert!(
+            hits.len() * 64 >= others.len(),
+            "intersects_each: not enough bits for all boxes"
+        );
+        for (chunk, word) in others.chunks(64).zip(hits.iter_mut()) {
+            let mut mask = 0;
+            for (bit, other) in chunk.iter().enumerate() {
+                let mut hit = true;
+                for dim in 0..B::DIM {
+                    let (a_lo, a_hi) = (self.lo_of(bbox, dim), self.hi_of(bbox, dim));
+                    let (b_lo, b_hi) = (self.lo_of(other, dim), self.hi_of(other, dim));
+                    hit &= (a_lo < b_hi) & (b_lo < a_hi) & (a_lo < a_hi) & (b_lo < b_hi);
+                }
+                mask |= (hit as u64) << bit;
+            }
+            *word = mask;
+        }
+    }
+}
+
+/// The default policy: the projections of boxes are half-open intervals [`lo`, `hi`),
+/// so boxes that only touch don't intersect.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HalfOpen;
+
+impl<N: Copy + PartialOrd> Policy<N> for HalfOpen {
+    fn lo(&self, lo: N) -> N {
+        lo
+    }
+
+    fn hi(&self, _lo: N, hi: N) -> N {
+        hi
+    }
+}
+
+/// The projections of boxes are closed intervals [`lo`, `hi`], so boxes that share a boundary intersect.
+/// Implemented by treating [`lo`, `hi`] as [`lo`, `hi.next_up()`), see [`NextUp`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Closed;
+
+impl<N: Copy + PartialOrd + NextUp> Policy<N> for Closed {
+    fn lo(&self, lo: N) -> N {
+        lo
+    }
+
+    fn hi(&self, _lo: N, hi: N) -> N {
+        hi.next_up()
+    }
+}
+
+/// Comparisons tolerant of floating point error: every box is treated as if it was inflated by
+/// the given margin in every dimension, on both sides. So boxes intersect if the gap between
+/// them is smaller than twice the margin.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Tolerant<N>(pub N);
+
+impl<N> Policy<N> for Tolerant<N>
+where
+    N: Copy + PartialOrd + Add<Output = N> + Sub<Output = N>,
+{
+    fn lo(&self, lo: N) -> N {
+        lo - self.0
+    }
+
+    fn hi(&self, _lo: N, hi: N) -> N {
+        hi + self.0
+    }
+}
+
+/// Wraps another policy `P`, additionally treating degenerate intervals (with effectively `lo == hi`)
+/// as containing their single point, so that boxes with zero extent (e.g. points of a point cloud)
+/// intersect the boxes enclosing them, consistently across all algorithms.
+/// Without this, such boxes are empty and never intersect anything.
+/// Inverted intervals (`lo > hi`) remain empty.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Degenerate<P = HalfOpen>(pub P);
+
+impl<N, P> Policy<N> for Degenerate<P>
+where
+    N: Copy + PartialOrd + NextUp,
+    P: Policy<N>,
+{
+    fn lo(&self, lo: N) -> N {
+        self.0.lo(lo)
+    }
+
+    fn hi(&self, lo: N, hi: N) -> N {
+        let (lo, hi) = (self.0.lo(lo), self.0.hi(lo, hi));
+        if lo == hi {
+            hi.next_up()
+        } else {
+            hi
+        }
+    }
+}
+
+/// Trait for box boundary types that have a smallest value greater than any given value
+pub trait NextUp {
+    /// Returns the smallest value greater than `self`, or `self` if there is none.
+    fn next_up(self) -> Self;
+}
+
+impl NextUp for f32 {
+    fn next_up(self) -> Self {
+        f32::next_up(self)
+    }
+}
+
+impl NextUp for f64 {
+    fn next_up(self) -> Self {
+        f64::next_up(self)
+    }
+}
+
+macro_rules! impl_next_up_int {
+    ($($t:ty),*) => {
+        $(
+            impl NextUp for $t {
+                fn next_up(self) -> Self {
+                    self.saturating_add(1)
+                }
+            }
+        )*
+    };
+}
+
+impl_next_up_int!(i32, i64, u16, u32, u64);
+
+/// SI quantities step to the next value of their underlying storage type.
+/// Requires the `units` feature.
+#[cfg(feature = "units")]
+impl<D, U, V> NextUp for uom::si::Quantity<D, U, V>
+where
+    D: uom::si::Dimension + ?Sized,
+    U: uom::si::Units<V> + ?Sized,
+    V: uom::num::Num + uom::Conversion<V> + NextUp,
+{
+    fn next_up(self) -> Self {
+        Self {
+            dimension: self.dimension,
+            units: self.units,
+            value: self.value.next_up(),
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! Conservative reduced-precision copies of boxes, to reduce the memory traffic of the algorithms on huge sets.
+//! The algorithms run on the copies, and every pair they find is verified against the original boxes.
+//! See [`intersect_quantized`] and [`intersect_f32`].
+
+use std::marker::PhantomData;
+
+use crate::boxes::{BBox, BoxND};
+use crate::intersect_ze;
+use crate::policy::{HalfOpen, Policy};
+use crate::set::BBoxSet;
+use crate::sink::Sink;
+use crate::source::{self, BoxSource};
+use crate::{HasInfinity, Rng};
+
+/// Lowest quantized boundary. Integers are only treated correctly by the algorithms if their
+/// boundaries lie strictly between their minimum and maximum value, see [`HasInfinity`](crate::HasInfinity).
+const Q_MIN: f64 = 1.0;
+/// Highest quantized low boundary, so that high boundaries, which are one more, stay below `u16::MAX`
+const Q_MAX: f64 = (u16::MAX - 2) as f64;
+
+/// Like [`intersect_ze`], but runs the algorithm on copies of the boxes with their boundaries
+/// quantized to 16-bit integers relative to the bounds of all boxes, e.g. 12 instead of 24 bytes
+/// per [`Box3Df32`](crate::boxes::Box3Df32), with 32-bit indices as identifiers.
+/// Low boundaries are rounded down and high boundaries up, so every pair of intersecting boxes
+/// also intersects after quantization. Each pair found is checked against the original boxes,
+/// so only pairs that intersect at full precision are reported to `out`.
+/// * Worth it for sets too large for the caches, where the boxes are spread fairly evenly.
+///   If most boxes are much smaller than 1/65533 of the extent of all boxes, many pairs that only
+///   intersect after quantization have to be checked.
+/// * `a` and `b` may be either the same or distinct [`BBoxSet`]s (or other [`BoxSource`]s) and must be sorted before calling.
+///   Boundaries must be finite.
+///
+/// # Panics
+/// If `a` and `b` together hold more than `u32::MAX` boxes.
+pub fn intersect_quantized<N, ID, R, const D: usize>(
+    a: &impl BoxSource<BoxND<N, D>, ID>,
+    b: &impl BoxSource<BoxND<N, D>, ID>,
+    out: &mut impl Sink<BoxND<N, D>, ID>,
+    rand: &mut R,
+) where
+    N: Copy + PartialOrd + Into<f64>,
+    ID: PartialOrd + Copy,
+    R: Rng,
+{
+    let b_len = if source::same(a, b) { 0 } else { b.len() };
+
+    // bounds of all boxes, the range quantized to Q_MIN..=Q_MAX
+    let mut min = [f64::INFINITY; D];
+    let mut max = [f64::NEG_INFINITY; D];
+    let all = (0..a.len())
+        .map(|idx| a.get(idx).0)
+        .chain((0..b_len).map(|idx| b.get(idx).0));
+    for bbox in all {
+        for dim in 0..D {
+            min[dim] = min[dim].min(bbox.lo(dim).into());
+            max[dim] = max[dim].max(bbox.hi(dim).into());
+        }
+    }
+    let mut scale = [0.0; D];
+    for dim in 0..D {
+        if max[dim] > min[dim] {
+            scale[dim] = (Q_MAX - Q_MIN) / (max[dim] - min[dim]);
+        }
+    }
+    let quantize = |x: N, dim: usize| {
+        let q = Q_MIN + (x.into() - min[dim]) * scale[dim];
+        q.floor().clamp(Q_MIN, Q_MAX)
+    };
+    let quantized = |bbox: &BoxND<N, D>| {
+        let mut lo = [0; D];
+        let mut hi = [0; D];
+        for dim in 0..D {
+            lo[dim] = quantize(bbox.lo(dim), dim) as u16;
+            hi[dim] = quantize(bbox.hi(dim), dim) as u16 + 1;
+        }
+        BoxND::new(lo, hi)
+    };
+    intersect_copies(a, b, out, rand, quantized);
+}
+
+/// Like [`intersect_ze`], but runs the algorithm on copies of the `f64` boxes with `f32` bounds
+/// relative to the center of all boxes, with 32-bit indices as identifiers, and so with half the memory traffic.
+/// Bounds are rounded outward (see [`BoxND::relative_to`]), so every pair of intersecting boxes
+/// also intersects in the copies. Each pair found is checked against the original boxes,
+/// so only pairs that intersect at full precision are reported to `out`.
+/// * `a` and `b` may be either the same or distinct [`BBoxSet`]s (or other [`BoxSource`]s) and must be sorted before calling.
+///
+/// # Panics
+/// If `a` and `b` together hold more than `u32::MAX` boxes.
+pub fn intersect_f32<ID, R, const D: usize>(
+    a: &impl BoxSource<BoxND<f64, D>, ID>,
+    b: &impl BoxSource<BoxND<f64, D>, ID>,
+    out: &mut impl Sink<BoxND<f64, D>, ID>,
+    rand: &mut R,
+) where
+    ID: PartialOrd + Copy,
+    R: Rng,
+{
+    // center of the finite bounds of all boxes, where `f32` is most precise
+    let b_len = if source::same(a, b) { 0 } else { b.len() };
+    let mut min = [f64::INFINITY; D];
+    let mut max = [f64::NEG_INFINITY; D];
+    let all = (0..a.len())
+        .map(|idx| a.get(idx).0)
+        .chain((0..b_len).map(|idx| b.get(idx).0));
+    for bbox in all {
+        for dim in 0..D {
+            for x in [bbox.lo(dim), bbox.hi(dim)] {
+                if x.is_finite() {
+                    min[dim] = min[dim].min(x);
+                    max[dim] = max[dim].max(x);
+                }
+            }
+        }
+    }
+    let mut origin = [0.0; D];
+    for dim in 0..D {
+        if min[dim] <= max[dim] {
+            origin[dim] = min[dim] / 2.0 + max[dim] / 2.0;
+        }
+    }
+    intersect_copies(a, b, out, rand, |bbox| bbox.relative_to(origin));
+}
+
+/// Runs [`intersect_ze`] on copies of the boxes of `a` and `b` made by `copy`, with indices as identifiers,
+/// passing the pairs whose original boxes intersect on to `out`. `copy` must round outward
+/// and preserve the order of the low boundaries in dimension 0.
+fn intersect_copies<B, Q, ID, R>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    out: &mut impl Sink<B, ID>,
+    rand: &mut R,
+    copy: impl Fn(&B) -> Q,
+) where
+    B: BBox,
+    Q: BBox,
+    Q::Num: HasInfinity,
+    ID: PartialOrd + Copy,
+    R: Rng,
+{
+    let same = source::same(a, b);
+    let b_len = if same { 0 } else { b.len() };
+    assert!(
+        a.len() + b_len <= u32::MAX as usize,
+        "too many boxes for 32-bit indices"
+    );
+    let copies = |source: &dyn Fn(usize) -> (B, ID), len, offset| {
+        let mut set = BBoxSet::with_capacity(len);
+        for idx in 0..len {
+            set.push((offset + idx) as u32, copy(&source(idx).0));
+        }
+        // the copies preserve the order of the low boundaries in dimension 0, and boxes that
+        // end up with equal ones are still ordered by their identifiers, so the set is sorted
+        set
+    };
+
+    let c_a = copies(&|idx| a.get(idx), a.len(), 0);
+    let mut verify = Verify {
+        a,
+        b,
+        a_len: a.len(),
+        out,
+        original: PhantomData,
+    };
+    if same {
+        intersect_ze(&c_a, &c_a, &mut verify, rand);
+    } else {
+        let c_b = copies(&|idx| b.get(idx), b_len, a.len());
+        intersect_ze(&c_a, &c_b, &mut verify, rand);
+    }
+}
+
+/// A [`Sink`] for pairs of copies of boxes, passing those whose original boxes of type `B`
+/// intersect on to `out`
+struct Verify<'a, S, T, O, B, ID> {
+    a: &'a S,
+    b: &'a T,
+    /// Identifiers of the quantized boxes of `b` start here
+    a_len: usize,
+    out: &'a mut O,
+    original: PhantomData<fn() -> (B, ID)>,
+}
+
+impl<S, T, O, B, ID> Verify<'_, S, T, O, B, ID>
+where
+    B: BBox,
+    S: BoxSource<B, ID>,
+    T: BoxSource<B, ID>,
+{
+    /// Returns the original box of the copy with identifier `idx` and its identifier.
+    fn get(&self, idx: u32) -> (B, ID) {
+        let idx = idx as usize;
+        if idx < self.a_len {
+            self.a.get(idx)
+        } else {
+            self.b.get(idx - self.a_len)
+        }
+    }
+}
+
+impl<B, Q, ID, S, T, O> Sink<Q, u32> for Verify<'_, S, T, O, B, ID>
+where
+    B: BBox,
+    S: BoxSource<B, ID>,
+    T: BoxSource<B, ID>,
+    O: Sink<B, ID>,
+{
+    fn report(&mut self, a_idx: u32, _a: &Q, b_idx: u32, _b: &Q) {
+        let (a, a_id) = self.get(a_idx);
+        let (b, b_id) = self.get(b_idx);
+        // empty boxes may not be empty in the copies, see `Policy::is_empty`
+        if HalfOpen.intersects(&a, &b) {
+            self.out.report(a_id, &a, b_id, &b);
+        }
+    }
+}
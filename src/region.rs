@@ -0,0 +1,78 @@
+//! Convex regions given by half-spaces, e.g. camera frustums, to query boxes with, see [`HalfSpace`]
+//! and [`query_region`](crate::query_region).
+//!
+//! # Examples
+//! ```
+//! use box_intersect_ze::boxes::Box2Df32;
+//! use box_intersect_ze::region::HalfSpace;
+//! use box_intersect_ze::set::BBoxSet;
+//!
+//! let mut scene = BBoxSet::new();
+//! scene.push(0, Box2Df32::new([2.0, -0.5], [3.0, 0.5]));
+//! scene.push(1, Box2Df32::new([2.0, 5.0], [3.0, 6.0]));
+//! scene.push(2, Box2Df32::new([-3.0, -0.5], [-2.0, 0.5]));
+//!
+//! // a camera at the origin looking along x, with a field of view of 90 degrees and a far plane at 10
+//! let frustum = [
+//!     HalfSpace::new([-1.0, 1.0], 0.0),
+//!     HalfSpace::new([-1.0, -1.0], 0.0),
+//!     HalfSpace::new([1.0, 0.0], 10.0),
+//! ];
+//! let mut visible = Vec::new();
+//! box_intersect_ze::query_region(&scene, &frustum, &mut visible);
+//! assert_eq!(visible, [0]);
+//! ```
+
+use crate::boxes::{BBox, BoxND};
+
+/// The points `p` with `normal · p <= offset`, i.e. the normal points out of the half-space
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HalfSpace<const D: usize> {
+    /// Need not be normalized
+    pub normal: [f64; D],
+    pub offset: f64,
+}
+
+impl<const D: usize> HalfSpace<D> {
+    /// Creates a new half-space.
+    pub fn new(normal: [f64; D], offset: f64) -> Self {
+        Self { normal, offset }
+    }
+
+    /// Returns `true` if `bbox` reaches into the half-space, i.e. its corner furthest against the normal lies in it.
+    /// Boxes are closed here, so touching the boundary counts, and empty boxes never intersect.
+    pub fn intersects<N>(&self, bbox: &BoxND<N, D>) -> bool
+    where
+        N: Copy + PartialOrd + Into<f64>,
+    {
+        if bbox.is_empty() {
+            return false;
+        }
+        let mut dot = 0.0;
+        for dim in 0..D {
+            let n = self.normal[dim];
+            // skip zero components, so infinite bounds don't turn the product into NaN
+            if n > 0.0 {
+                dot += n * bbox.lo(dim).into();
+            } else if n < 0.0 {
+                dot += n * bbox.hi(dim).into();
+            }
+        }
+        // infinite bounds in opposite directions make `dot` NaN, and the box is kept
+        dot <= self.offset || dot.is_nan()
+    }
+}
+
+/// Returns `true` if `bbox` may intersect the convex region where all `half_spaces` overlap, i.e. it intersects
+/// every one of them (see [`HalfSpace::intersects`]). Conservative: a box near an edge or corner of the region
+/// can intersect every half-space without intersecting the region, which is fine for culling.
+/// An empty slice is all of space.
+pub fn intersects<N, const D: usize>(half_spaces: &[HalfSpace<D>], bbox: &BoxND<N, D>) -> bool
+where
+    N: Copy + PartialOrd + Into<f64>,
+{
+    !bbox.is_empty()
+        && half_spaces
+            .iter()
+            .all(|half_space| half_space.intersects(bbox))
+}
@@ -0,0 +1,354 @@
+//! R-tree over a set of boxes, bulk loaded by Sort-Tile-Recursive packing, for repeated region queries
+//! against the same boxes, see [`RTree`].
+//!
+//! # Examples
+//! ```
+//! use box_intersect_ze::boxes::Box2Df64;
+//! use box_intersect_ze::rtree::RTree;
+//! use box_intersect_ze::set::BBoxSet;
+//!
+//! let mut parcels = BBoxSet::new();
+//! parcels.push("a", Box2Df64::new([0.0, 0.0], [10.0, 10.0]));
+//! parcels.push("b", Box2Df64::new([10.0, 0.0], [20.0, 10.0]));
+//! let rtree = RTree::bulk_load(&parcels);
+//!
+//! let mut hits = Vec::new();
+//! rtree.query(&Box2Df64::new([8.0, 8.0], [12.0, 9.0]), |id, _bbox| hits.push(id));
+//! hits.sort();
+//! assert_eq!(hits, ["a", "b"]);
+//!
+//! let mut hits = Vec::new();
+//! rtree.query_point([10.0, 5.0], |id, _bbox| hits.push(id));
+//! assert_eq!(hits, ["b"]);
+//! ```
+
+use std::collections::BinaryHeap;
+
+use crate::boxes::{BBox, BoxND};
+use crate::bvh::{sort_by_entry, Candidate, Ray};
+use crate::region::{self, HalfSpace};
+use crate::sink::Sink;
+use crate::source::BoxSource;
+use crate::HasInfinity;
+
+/// Default most children of a node, see [`RTree::bulk_load_with_node_size`]
+pub const NODE_SIZE: usize = 16;
+
+#[derive(Clone, Copy, Debug)]
+struct Node<N, const D: usize> {
+    /// Bounds of all boxes below this node
+    bounds: BoxND<N, D>,
+    /// Children in the level below, or boxes for leaves
+    start: usize,
+    end: usize,
+}
+
+/// An R-tree: a tree of boxes with up to a fixed number of children per node, each node storing
+/// the bounds of the boxes below it. It is bulk loaded with Sort-Tile-Recursive packing: the boxes are
+/// sorted by their centers in dimension 0 and cut into slabs, each slab is sorted and cut in dimension 1, and so on,
+/// then every run of boxes becomes a full leaf, and the leaves are packed into the next level the same way.
+/// The tree is static, build a new one when the boxes change.
+/// * Query it with a box ([`RTree::query`]) or a point ([`RTree::query_point`]), or find the pairs of intersecting boxes
+///   within the tree ([`RTree::pairs`]) or with the boxes of another tree ([`RTree::pairs_with`]).
+/// * Each query only visits the nodes whose bounds it intersects, so many small region queries are far cheaper
+///   than running [`intersect_ze`](crate::intersect_ze) on the whole set each time.
+///
+/// Like the other algorithms, the projections of boxes are half-open intervals and empty boxes
+/// (see [`BBox::is_empty`]) never intersect. Bounds must not be NaN.
+#[derive(Clone, Debug)]
+pub struct RTree<N, ID, const D: usize> {
+    /// Nodes by level, leaves first, the root alone in the last level
+    levels: Vec<Vec<Node<N, D>>>,
+    /// Boxes and their identifiers, grouped by leaf
+    items: Vec<(BoxND<N, D>, ID)>,
+}
+
+impl<N, ID, const D: usize> RTree<N, ID, D>
+where
+    N: Copy + PartialOrd + HasInfinity + Into<f64>,
+    ID: Copy,
+{
+    /// Builds a tree over the boxes of `boxes`, which need not be sorted, with up to [`NODE_SIZE`] children per node.
+    /// Empty boxes are left out.
+    pub fn bulk_load(boxes: &impl BoxSource<BoxND<N, D>, ID>) -> Self {
+        Self::bulk_load_with_node_size(boxes, NODE_SIZE)
+    }
+
+    /// Like [`RTree::bulk_load`], but with up to `node_size` children per node.
+    /// Small nodes prune more precisely, large nodes make shallower trees.
+    ///
+    /// # Panics
+    /// If `node_size` is less than 2.
+    pub fn bulk_load_with_node_size(
+        boxes: &impl BoxSource<BoxND<N, D>, ID>,
+        node_size: usize,
+    ) -> Self {
+        assert!(node_size >= 2, "nodes must hold at least 2 children");
+        let mut items: Vec<_> = (0..boxes.len())
+            .map(|idx| boxes.get(idx))
+            .filter(|(bbox, _id)| !bbox.is_empty())
+            .collect();
+        let mut levels = Vec::new();
+        if items.is_empty() {
+            return Self { levels, items };
+        }
+
+        str_order(&mut items, &|(bbox, _id)| bbox, node_size, 0);
+        let mut level = pack(&items, &|(bbox, _id)| bbox, node_size);
+        while level.len() > 1 {
+            str_order(&mut level, &|node| &node.bounds, node_size, 0);
+            let parents = pack(&level, &|node| &node.bounds, node_size);
+            levels.push(level);
+            level = parents;
+        }
+        levels.push(level);
+        Self { levels, items }
+    }
+
+    /// Returns the number of boxes in the tree.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns `true` if there are no boxes in the tree.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Returns the number of levels of nodes, `0` for an empty tree.
+    pub fn height(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Calls `hit` with the identifier and the box of each box intersecting `bbox`, in no particular order.
+    pub fn query(&self, bbox: &BoxND<N, D>, mut hit: impl FnMut(ID, &BoxND<N, D>)) {
+        if bbox.is_empty() {
+            return;
+        }
+        self.traverse(|bounds| bounds.intersects(bbox), &mut hit);
+    }
+
+    /// Calls `hit` with the identifier and the box of each box containing `point`, in no particular order.
+    pub fn query_point(&self, point: [N; D], mut hit: impl FnMut(ID, &BoxND<N, D>)) {
+        self.traverse(
+            |bounds| (0..D).all(|dim| bounds.contains_in(dim, point[dim])),
+            &mut hit,
+        );
+    }
+
+    /// Calls `hit` with the identifier and the box of each box that may intersect the convex region
+    /// where all `half_spaces` overlap (see [`region::intersects`]), in no particular order.
+    /// Subtrees whose bounds lie outside any of the half-spaces are skipped, e.g. for frustum culling.
+    pub fn query_region(
+        &self,
+        half_spaces: &[HalfSpace<D>],
+        mut hit: impl FnMut(ID, &BoxND<N, D>),
+    ) {
+        self.traverse(|bounds| region::intersects(half_spaces, bounds), &mut hit);
+    }
+
+    /// Appends the identifier of each box that `ray` hits between the distances `min` and `max`
+    /// to `out`, with the distance at which it enters the box (see [`Ray::entry_between`]),
+    /// ordered by that distance, nearest first.
+    pub fn query_ray(&self, ray: &Ray<D>, min: f64, max: f64, out: &mut Vec<(ID, f64)>) {
+        let start = out.len();
+        self.traverse(
+            |bounds| ray.entry_between(bounds, min, max).is_some(),
+            &mut |id, bbox| {
+                if let Some(t) = ray.entry_between(bbox, min, max) {
+                    out.push((id, t));
+                }
+            },
+        );
+        sort_by_entry(&mut out[start..]);
+    }
+
+    /// Returns the identifier of the box nearest to `point` and its distance (see [`BoxND::distance_to`]),
+    /// or [`None`] if the tree is empty. Of several boxes at the same distance, returns any one.
+    /// Searches best-first: visits nodes in order of the distance of their bounds, and stops once
+    /// no node left can hold a nearer box.
+    pub fn nearest(&self, point: [f64; D]) -> Option<(ID, f64)> {
+        let mut best: Option<(ID, f64)> = None;
+        // nodes given by level and index
+        let mut heap = BinaryHeap::new();
+        if let Some(root) = self.levels.last() {
+            heap.extend(root.iter().enumerate().map(|(idx, node)| Candidate {
+                distance: node.bounds.distance_to(point),
+                item: (self.levels.len() - 1, idx),
+            }));
+        }
+        while let Some(Candidate { distance, item }) = heap.pop() {
+            if matches!(best, Some((_, best)) if best <= distance) {
+                break;
+            }
+            let (level, idx) = item;
+            let node = &self.levels[level][idx];
+            if level > 0 {
+                for child in node.start..node.end {
+                    heap.push(Candidate {
+                        distance: self.levels[level - 1][child].bounds.distance_to(point),
+                        item: (level - 1, child),
+                    });
+                }
+            } else {
+                for (bbox, id) in &self.items[node.start..node.end] {
+                    let distance = bbox.distance_to(point);
+                    if !matches!(best, Some((_, best)) if best <= distance) {
+                        best = Some((*id, distance));
+                    }
+                }
+            }
+        }
+        best
+    }
+
+    /// Reports each pair of intersecting boxes in the tree to `out` once.
+    pub fn pairs(&self, out: &mut impl Sink<BoxND<N, D>, ID>) {
+        self.pairs_in(self, true, out);
+    }
+
+    /// Reports each pair of a box of this tree and an intersecting box of `other` to `out`,
+    /// the box of this tree first.
+    pub fn pairs_with(&self, other: &Self, out: &mut impl Sink<BoxND<N, D>, ID>) {
+        self.pairs_in(other, false, out);
+    }
+
+    /// Calls `hit` with each box that passes `enter`, visiting only the nodes whose bounds pass it.
+    fn traverse(
+        &self,
+        enter: impl Fn(&BoxND<N, D>) -> bool,
+        hit: &mut impl FnMut(ID, &BoxND<N, D>),
+    ) {
+        let mut stack = Vec::new();
+        if let Some(root) = self.levels.last() {
+            stack.push((self.levels.len() - 1, 0..root.len()));
+        }
+        while let Some((level, children)) = stack.pop() {
+            for node in &self.levels[level][children] {
+                if !enter(&node.bounds) {
+                    continue;
+                }
+                if level > 0 {
+                    stack.push((level - 1, node.start..node.end));
+                } else {
+                    for (bbox, id) in &self.items[node.start..node.end] {
+                        if enter(bbox) {
+                            hit(*id, bbox);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Descends this tree and `other` at once, from pairs of nodes with intersecting bounds to their children.
+    /// If `same`, `other` is this tree, and pairs of a node with itself yield each pair of its boxes once.
+    fn pairs_in(&self, other: &Self, same: bool, out: &mut impl Sink<BoxND<N, D>, ID>) {
+        let (a_root, b_root) = match (self.levels.last(), other.levels.last()) {
+            (Some(_), Some(_)) => (self.levels.len() - 1, other.levels.len() - 1),
+            _ => return,
+        };
+        // pairs of nodes given by level and index
+        let mut stack = vec![((a_root, 0), (b_root, 0))];
+        while let Some(((a_level, a), (b_level, b))) = stack.pop() {
+            let (node_a, node_b) = (&self.levels[a_level][a], &other.levels[b_level][b]);
+            if same && (a_level, a) == (b_level, b) {
+                for i in node_a.start..node_a.end {
+                    for j in i + 1..node_a.end {
+                        if a_level == 0 {
+                            report(&self.items[i], &self.items[j], out);
+                        } else {
+                            stack.push(((a_level - 1, i), (a_level - 1, j)));
+                        }
+                    }
+                    if a_level > 0 {
+                        stack.push(((a_level - 1, i), (a_level - 1, i)));
+                    }
+                }
+                continue;
+            }
+            if !node_a.bounds.intersects(&node_b.bounds) {
+                continue;
+            }
+            if a_level == 0 && b_level == 0 {
+                for item in &self.items[node_a.start..node_a.end] {
+                    for other_item in &other.items[node_b.start..node_b.end] {
+                        report(item, other_item, out);
+                    }
+                }
+            } else if a_level >= b_level {
+                // descend the higher node, whose children are closer in size to the other node
+                for child in node_a.start..node_a.end {
+                    stack.push(((a_level - 1, child), (b_level, b)));
+                }
+            } else {
+                for child in node_b.start..node_b.end {
+                    stack.push(((a_level, a), (b_level - 1, child)));
+                }
+            }
+        }
+    }
+}
+
+fn report<N, ID, const D: usize>(
+    (a, a_id): &(BoxND<N, D>, ID),
+    (b, b_id): &(BoxND<N, D>, ID),
+    out: &mut impl Sink<BoxND<N, D>, ID>,
+) where
+    N: Copy + PartialOrd,
+    ID: Copy,
+{
+    if a.intersects(b) {
+        out.report(*a_id, a, *b_id, b);
+    }
+}
+
+/// Sorts `entries` into Sort-Tile-Recursive order from dimension `dim` on, so that every run of `node_size`
+/// entries is a compact group: sorts them by the centers of their boxes in `dim`, cuts them into slabs
+/// of whole runs, and orders each slab by the next dimension.
+fn str_order<T, N, const D: usize>(
+    entries: &mut [T],
+    bbox: &impl Fn(&T) -> &BoxND<N, D>,
+    node_size: usize,
+    dim: usize,
+) where
+    N: Copy + PartialOrd + Into<f64>,
+{
+    if dim >= D || entries.len() <= node_size {
+        return;
+    }
+    let center = |entry: &T| {
+        let bbox = bbox(entry);
+        bbox.lo(dim).into() / 2.0 + bbox.hi(dim).into() / 2.0
+    };
+    entries.sort_unstable_by(|a, b| center(a).total_cmp(&center(b)));
+    // the runs are spread over `slabs` slabs in each of the remaining dimensions
+    let runs = entries.len().div_ceil(node_size);
+    let slabs = (runs as f64).powf(1.0 / (D - dim) as f64).ceil() as usize;
+    let slab_len = runs.div_ceil(slabs) * node_size;
+    for slab in entries.chunks_mut(slab_len) {
+        str_order(slab, bbox, node_size, dim + 1);
+    }
+}
+
+/// Groups every run of `node_size` entries into a node, the entries being boxes for leaves or nodes of the level below.
+fn pack<T, N, const D: usize>(
+    entries: &[T],
+    bbox: &impl Fn(&T) -> &BoxND<N, D>,
+    node_size: usize,
+) -> Vec<Node<N, D>>
+where
+    N: Copy + PartialOrd + HasInfinity,
+{
+    (0..entries.len())
+        .step_by(node_size)
+        .map(|start| {
+            let end = (start + node_size).min(entries.len());
+            let nothing = BoxND::new([N::INFTY; D], [N::NINFTY; D]);
+            let bounds = entries[start..end]
+                .iter()
+                .fold(nothing, |bounds, entry| bounds.union(bbox(entry)));
+            Node { bounds, start, end }
+        })
+        .collect()
+}
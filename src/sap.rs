@@ -0,0 +1,266 @@
+//! Persistent sweep and prune broad phase, see [`SweepAndPrune`].
+//!
+//! # Examples
+//! ```
+//! use box_intersect_ze::boxes::Box2Df32;
+//! use box_intersect_ze::sap::SweepAndPrune;
+//!
+//! let mut sap = SweepAndPrune::new();
+//! sap.insert(0, Box2Df32::new([0.0, 0.0], [1.0, 1.0]));
+//! sap.insert(1, Box2Df32::new([2.0, 0.0], [3.0, 1.0]));
+//! assert_eq!(sap.pairs().count(), 0);
+//!
+//! // box 1 moves a bit to the left every frame
+//! sap.update(1, Box2Df32::new([0.5, 0.0], [1.5, 1.0]));
+//! let pairs: Vec<_> = sap.pairs().collect();
+//! assert!(pairs == [(0, 1)] || pairs == [(1, 0)]);
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use crate::boxes::BBox;
+use crate::sink::Sink;
+use crate::HasInfinity;
+
+/// A low or high boundary of a box in the sorted lists of [`SweepAndPrune`]
+#[derive(Clone, Copy, Debug)]
+struct Endpoint<N> {
+    value: N,
+    slot: usize,
+    is_hi: bool,
+}
+
+impl<N: PartialOrd> Endpoint<N> {
+    /// Returns `true` if this endpoint belongs before `other`. At equal values high boundaries
+    /// come first, since boxes that only touch don't intersect.
+    fn before(&self, other: &Self) -> bool {
+        self.value < other.value || (self.value == other.value && self.is_hi && !other.is_hi)
+    }
+}
+
+/// A broad phase that keeps its boxes and the pairs of intersecting boxes between calls. For every dimension,
+/// it maintains a list of the boundaries of all boxes, sorted by insertion sort whenever a box changes.
+/// A pair is found or dropped when a low boundary of one box passes a high boundary of the other.
+/// * If boxes only move a little from frame to frame, an update only swaps a few neighbouring boundaries,
+///   and boxes that don't move cost nothing, e.g. in scenes where most bodies are at rest.
+/// * If most boxes move far, or the projections of many boxes overlap in every dimension,
+///   running [`intersect_ze`](crate::intersect_ze) every frame is faster.
+///
+/// Like the other algorithms, the projections of boxes are half-open intervals and empty boxes
+/// (see [`BBox::is_empty`]) never intersect. Bounds must not be NaN.
+pub struct SweepAndPrune<B: BBox, ID> {
+    /// Boxes and identifiers by slot, [`None`] for free slots
+    boxes: Vec<Option<(B, ID)>>,
+    free: Vec<usize>,
+    slots: HashMap<ID, usize>,
+    /// The sorted boundaries, by dimension
+    axes: Vec<Vec<Endpoint<B::Num>>>,
+    /// Indices of the low and high boundary of each slot in `axes`, by dimension
+    positions: Vec<Vec<[usize; 2]>>,
+    /// Pairs of slots of intersecting boxes, the lower slot first
+    pairs: HashSet<(usize, usize)>,
+}
+
+impl<B, ID> SweepAndPrune<B, ID>
+where
+    B: BBox,
+    B::Num: HasInfinity,
+    ID: Copy + Eq + Hash,
+{
+    /// Creates an empty broad phase.
+    pub fn new() -> Self {
+        Self {
+            boxes: Vec::new(),
+            free: Vec::new(),
+            slots: HashMap::new(),
+            axes: vec![Vec::new(); B::DIM],
+            positions: vec![Vec::new(); B::DIM],
+            pairs: HashSet::new(),
+        }
+    }
+
+    /// Returns the number of boxes.
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Returns `true` if there are no boxes.
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Returns the box with identifier `id`, if there is one.
+    pub fn get(&self, id: ID) -> Option<B> {
+        let slot = *self.slots.get(&id)?;
+        self.boxes[slot].map(|(bbox, _id)| bbox)
+    }
+
+    /// Adds a box with identifier `id`, or moves the box with this identifier to `bbox`
+    /// if there already is one, like [`SweepAndPrune::update`].
+    pub fn insert(&mut self, id: ID, bbox: B) {
+        if self.slots.contains_key(&id) {
+            self.update(id, bbox);
+            return;
+        }
+        // the new box starts out beyond all others, then moves to its place like an updated box
+        let slot = self.free.pop().unwrap_or(self.boxes.len());
+        if slot == self.boxes.len() {
+            self.boxes.push(None);
+            for positions in &mut self.positions {
+                positions.push([0, 0]);
+            }
+        }
+        for dim in 0..B::DIM {
+            let axis = &mut self.axes[dim];
+            for is_hi in [false, true] {
+                self.positions[dim][slot][is_hi as usize] = axis.len();
+                axis.push(Endpoint {
+                    value: B::Num::INFTY,
+                    slot,
+                    is_hi,
+                });
+            }
+        }
+        self.slots.insert(id, slot);
+        self.boxes[slot] = Some((bbox, id));
+        self.sift(slot);
+    }
+
+    /// Moves the box with identifier `id` to `bbox`, finding and dropping pairs by swapping
+    /// its boundaries with their neighbours. Returns its previous bounds,
+    /// or [`None`] if there is no box with this identifier, in which case nothing is changed.
+    pub fn update(&mut self, id: ID, bbox: B) -> Option<B> {
+        let slot = *self.slots.get(&id)?;
+        let (previous, _id) = self.boxes[slot].replace((bbox, id))?;
+        self.sift(slot);
+        // the boundaries of a box passing each other don't touch its pairs in `passed`
+        if bbox.is_empty() && !previous.is_empty() {
+            self.pairs.retain(|&(a, b)| a != slot && b != slot);
+        } else if !bbox.is_empty() && previous.is_empty() {
+            for (other, entry) in self.boxes.iter().enumerate() {
+                if let Some((other_bbox, _id)) = entry {
+                    if other != slot && bbox.intersects(other_bbox) && !other_bbox.is_empty() {
+                        self.pairs.insert((slot.min(other), slot.max(other)));
+                    }
+                }
+            }
+        }
+        Some(previous)
+    }
+
+    /// Removes the box with identifier `id` and all its pairs and returns it,
+    /// or [`None`] if there is no box with this identifier.
+    pub fn remove(&mut self, id: ID) -> Option<B> {
+        let slot = self.slots.remove(&id)?;
+        let (bbox, _id) = self.boxes[slot].take()?;
+        self.pairs.retain(|&(a, b)| a != slot && b != slot);
+        for dim in 0..B::DIM {
+            // remove the high boundary first, it's behind the low one unless the box is inverted
+            let [lo, hi] = self.positions[dim][slot];
+            for idx in [lo.max(hi), lo.min(hi)] {
+                self.axes[dim].remove(idx);
+            }
+            for (idx, endpoint) in self.axes[dim].iter().enumerate().skip(lo.min(hi)) {
+                self.positions[dim][endpoint.slot][endpoint.is_hi as usize] = idx;
+            }
+        }
+        self.free.push(slot);
+        Some(bbox)
+    }
+
+    /// Removes all boxes.
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Returns the pairs of identifiers of intersecting boxes, in no particular order.
+    pub fn pairs(&self) -> impl Iterator<Item = (ID, ID)> + '_ {
+        self.pairs
+            .iter()
+            .map(move |&(a, b)| (self.entry(a).1, self.entry(b).1))
+    }
+
+    /// Reports the pairs of intersecting boxes to `out`, in no particular order.
+    pub fn report(&self, out: &mut impl Sink<B, ID>) {
+        for &(a, b) in &self.pairs {
+            let ((a, a_id), (b, b_id)) = (self.entry(a), self.entry(b));
+            out.report(a_id, &a, b_id, &b);
+        }
+    }
+
+    fn entry(&self, slot: usize) -> (B, ID) {
+        self.boxes[slot].expect("slot of a pair is occupied")
+    }
+
+    /// Moves the boundaries of the box in `slot` to their sorted positions in every dimension,
+    /// updating the pairs of the box on the way.
+    fn sift(&mut self, slot: usize) {
+        let (bbox, _id) = self.entry(slot);
+        for dim in 0..B::DIM {
+            // move the low boundary first if it decreases, so the boundaries don't pass each other needlessly
+            let lo_first = bbox.lo(dim) < self.axes[dim][self.positions[dim][slot][0]].value;
+            let order = if lo_first {
+                [false, true]
+            } else {
+                [true, false]
+            };
+            for is_hi in order {
+                let value = if is_hi { bbox.hi(dim) } else { bbox.lo(dim) };
+                let idx = self.positions[dim][slot][is_hi as usize];
+                self.axes[dim][idx].value = value;
+                self.sift_endpoint(dim, idx);
+            }
+        }
+    }
+
+    /// Moves the endpoint at `idx` in dimension `dim` to its sorted position by swapping it with its neighbours.
+    fn sift_endpoint(&mut self, dim: usize, mut idx: usize) {
+        let endpoint = self.axes[dim][idx];
+        while idx > 0 && endpoint.before(&self.axes[dim][idx - 1]) {
+            let other = self.axes[dim][idx - 1];
+            self.passed(endpoint, other);
+            self.axes[dim][idx] = other;
+            self.positions[dim][other.slot][other.is_hi as usize] = idx;
+            idx -= 1;
+        }
+        while idx + 1 < self.axes[dim].len() && self.axes[dim][idx + 1].before(&endpoint) {
+            let other = self.axes[dim][idx + 1];
+            self.passed(other, endpoint);
+            self.axes[dim][idx] = other;
+            self.positions[dim][other.slot][other.is_hi as usize] = idx;
+            idx += 1;
+        }
+        self.axes[dim][idx] = endpoint;
+        self.positions[dim][endpoint.slot][endpoint.is_hi as usize] = idx;
+    }
+
+    /// Updates the pairs after `first` moved before `second`, which was before it.
+    fn passed(&mut self, first: Endpoint<B::Num>, second: Endpoint<B::Num>) {
+        if first.slot == second.slot || first.is_hi == second.is_hi {
+            return;
+        }
+        let pair = (first.slot.min(second.slot), first.slot.max(second.slot));
+        if first.is_hi {
+            // a high boundary moved before a low one, so the boxes no longer overlap in this dimension
+            self.pairs.remove(&pair);
+        } else {
+            // a low boundary moved before a high one, so the boxes may intersect now
+            let ((a, _), (b, _)) = (self.entry(pair.0), self.entry(pair.1));
+            if a.intersects(&b) && !a.is_empty() && !b.is_empty() {
+                self.pairs.insert(pair);
+            }
+        }
+    }
+}
+
+impl<B, ID> Default for SweepAndPrune<B, ID>
+where
+    B: BBox,
+    B::Num: HasInfinity,
+    ID: Copy + Eq + Hash,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
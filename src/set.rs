@@ -1,15 +1,45 @@
 //! Sets of boxes that can be passed to the intersection finding algorithms
 
+// the deprecated `boxes` field is still where the set stores its boxes
+#![allow(deprecated)]
+
+use std::cmp::Ordering;
 use std::fmt;
 use std::fmt::{Debug, Formatter};
+use std::iter::{FromIterator, Map};
+use std::ops::Deref;
+use std::{slice, vec};
 
-use crate::boxes::BBox;
-use crate::{median, Rng};
+use crate::boxes::{BBox, BoxND};
+use crate::error::{bounds_problem, Problem, Report};
+use crate::sink::Sink;
+use crate::source;
+use crate::source::BoxSource;
+use crate::{HasInfinity, Rng, TotalOrder};
 
 #[derive(Clone)]
 /// A generic set of [`BBox`]es of type `B` with identifiers of type `ID`
 pub struct BBoxSet<B: BBox, ID> {
+    /// The boxes in the set and their identifiers.
+    /// Modifying them directly can break the sorting without the set noticing (see [`BBoxSet::is_sorted`]),
+    /// so call [`BBoxSet::ensure_sorted`] afterwards.
+    #[deprecated(note = "use `boxes()` to read the boxes and `boxes_mut()` to modify them")]
     pub boxes: Vec<(B, ID)>,
+    /// `true` while the boxes are known to be sorted by their low boundaries in dimension 0,
+    /// see [`BBoxSet::is_sorted`]
+    sorted: bool,
+}
+
+/// The order established by [`BBoxSet::sort`]
+pub(crate) fn sort_order<B, ID>((a, a_id): &(B, ID), (b, b_id): &(B, ID)) -> Ordering
+where
+    B: BBox,
+    ID: PartialOrd,
+{
+    a.lo(0)
+        .partial_cmp(&b.lo(0))
+        .unwrap()
+        .then_with(|| a_id.partial_cmp(b_id).unwrap_or(Ordering::Equal))
 }
 
 impl<B, ID> Debug for BBoxSet<B, ID>
@@ -24,6 +54,127 @@ where
     }
 }
 
+impl<B, ID> Default for BBoxSet<B, ID>
+where
+    B: BBox,
+    ID: Copy + PartialEq,
+    B::Num: PartialOrd,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B: BBox, ID> FromIterator<(ID, B)> for BBoxSet<B, ID> {
+    fn from_iter<I: IntoIterator<Item = (ID, B)>>(iter: I) -> Self {
+        let boxes: Vec<_> = iter.into_iter().map(|(id, bbox)| (bbox, id)).collect();
+        let sorted = in_order(&boxes);
+        Self::from_boxes(boxes, sorted)
+    }
+}
+
+impl<B: BBox, ID> Extend<(ID, B)> for BBoxSet<B, ID> {
+    fn extend<I: IntoIterator<Item = (ID, B)>>(&mut self, iter: I) {
+        let start = self.boxes.len().saturating_sub(1);
+        self.boxes
+            .extend(iter.into_iter().map(|(id, bbox)| (bbox, id)));
+        self.sorted = self.sorted && in_order(&self.boxes[start..]);
+    }
+}
+
+/// Returns `true` if `boxes` are sorted by their low boundaries in dimension 0, none of which is NaN.
+fn in_order<B: BBox, ID>(boxes: &[(B, ID)]) -> bool {
+    boxes.windows(2).all(|w| w[0].0.lo(0) <= w[1].0.lo(0))
+        && boxes.iter().all(|(bbox, _id)| bbox.lo(0) == bbox.lo(0))
+}
+
+/// Iterator over the identifiers and boxes of a [`BBoxSet`], returned by [`BBoxSet::iter`]
+pub type Iter<'a, B, ID> = Map<slice::Iter<'a, (B, ID)>, fn(&'a (B, ID)) -> (&'a ID, &'a B)>;
+
+/// Iterator over the identifiers and mutable boxes of a [`BBoxSet`], returned by [`BBoxSet::iter_mut`]
+pub type IterMut<'a, B, ID> =
+    Map<slice::IterMut<'a, (B, ID)>, fn(&'a mut (B, ID)) -> (&'a ID, &'a mut B)>;
+
+/// Iterator over the identifiers and boxes of a [`BBoxSet`], consuming the set
+pub type IntoIter<B, ID> = Map<vec::IntoIter<(B, ID)>, fn((B, ID)) -> (ID, B)>;
+
+impl<B: BBox, ID> IntoIterator for BBoxSet<B, ID> {
+    type Item = (ID, B);
+    type IntoIter = IntoIter<B, ID>;
+
+    /// Consumes the set, returning its boxes and their identifiers in order.
+    fn into_iter(self) -> Self::IntoIter {
+        self.boxes.into_iter().map(|(bbox, id)| (id, bbox))
+    }
+}
+
+impl<'a, B: BBox, ID> IntoIterator for &'a BBoxSet<B, ID> {
+    type Item = (&'a ID, &'a B);
+    type IntoIter = Iter<'a, B, ID>;
+
+    /// Returns the boxes and their identifiers in order. See [`BBoxSet::iter`].
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, B: BBox, ID> IntoIterator for &'a mut BBoxSet<B, ID> {
+    type Item = (&'a ID, &'a mut B);
+    type IntoIter = IterMut<'a, B, ID>;
+
+    /// Returns the boxes and their identifiers in order. See [`BBoxSet::iter_mut`].
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<B: BBox, ID> BBoxSet<B, ID> {
+    /// Creates a set of `boxes`, which are known to be sorted if `sorted` is `true`.
+    pub(crate) fn from_boxes(boxes: Vec<(B, ID)>, sorted: bool) -> Self {
+        Self { boxes, sorted }
+    }
+
+    /// Returns the boxes in the set and their identifiers, in order.
+    pub fn boxes(&self) -> &[(B, ID)] {
+        &self.boxes
+    }
+
+    /// Returns the boxes in the set and their identifiers for arbitrary modification.
+    /// The set is no longer known to be sorted afterwards (see [`BBoxSet::is_sorted`]).
+    pub fn boxes_mut(&mut self) -> &mut Vec<(B, ID)> {
+        self.sorted = false;
+        &mut self.boxes
+    }
+
+    /// Returns `true` if the set is known to be sorted by the low boundaries of its boxes in dimension 0,
+    /// as required by the intersection finding algorithms: it was sorted (see [`BBoxSet::sort`]) or built in order,
+    /// and hasn't been modified in a way that may break the order since. Takes constant time.
+    /// The algorithms only check the order of a set not known to be sorted, see [`BoxSource::is_sorted`].
+    /// Modifications through the deprecated `boxes` field go unnoticed.
+    pub fn is_sorted(&self) -> bool {
+        self.sorted
+    }
+
+    /// Returns an iterator over the identifiers and boxes in the set, in order.
+    pub fn iter(&self) -> Iter<'_, B, ID> {
+        self.boxes.iter().map(|(bbox, id)| (id, bbox))
+    }
+
+    /// Returns an iterator over the identifiers and boxes in the set, in order, allowing the boxes to be modified.
+    /// Changing the low boundaries in dimension 0 can break the sorting, so the set is no longer known to be sorted.
+    pub fn iter_mut(&mut self) -> IterMut<'_, B, ID> {
+        self.sorted = false;
+        self.boxes.iter_mut().map(|(bbox, id)| (&*id, bbox))
+    }
+
+    /// Returns an iterator over the indices, identifiers and boxes in the set, in order.
+    pub fn iter_indexed(&self) -> impl Iterator<Item = (usize, &ID, &B)> + '_ {
+        self.iter()
+            .enumerate()
+            .map(|(idx, (id, bbox))| (idx, id, bbox))
+    }
+}
+
 impl<B, ID> BBoxSet<B, ID>
 where
     B: BBox,
@@ -32,34 +183,133 @@ where
 {
     /// Creates a new, empty set. Prefer [`BBoxSet::with_capacity`].
     pub fn new() -> Self {
-        Self { boxes: Vec::new() }
+        Self::from_boxes(Vec::new(), true)
     }
 
     /// Creates a new, empty set with the specified capacity. See [`Vec::with_capacity`].
     pub fn with_capacity(capacity: usize) -> Self {
-        Self {
-            boxes: Vec::with_capacity(capacity),
-        }
+        Self::from_boxes(Vec::with_capacity(capacity), true)
     }
 
     /// Adds a box with an identifier to the set.
     /// * `id` must be a unique identifier for that box.
-    /// If you want to use algorithms other than [brute force](`crate::intersect_brute_force`)
-    /// to find intersections, `ID` must be [`PartialOrd`]
+    ///   If you want to use algorithms other than [brute force](`crate::intersect_brute_force`)
+    ///   to find intersections, `ID` must be [`PartialOrd`]
     pub fn push(&mut self, id: ID, bbox: B) {
+        // pushing boxes in order keeps the set sorted
+        self.sorted = self.sorted
+            && bbox.lo(0) == bbox.lo(0)
+            && self
+                .boxes
+                .last()
+                .is_none_or(|(last, _id)| last.lo(0) <= bbox.lo(0));
         self.boxes.push((bbox, id));
     }
 
+    /// Inserts a box with an identifier at the position that keeps the set sorted, found by binary search.
+    /// The set must already be sorted (see [`BBoxSet::sort`]). Shifts all boxes after it,
+    /// so for many boxes, pushing them and sorting again is faster.
+    /// # Panics
+    /// If the low boundary of `bbox` in dimension 0 is NaN, like [`BBoxSet::sort`].
+    pub fn insert_sorted(&mut self, id: ID, bbox: B)
+    where
+        ID: PartialOrd,
+    {
+        let entry = (bbox, id);
+        let idx = self
+            .boxes
+            .partition_point(|other| sort_order(other, &entry) != Ordering::Greater);
+        self.boxes.insert(idx, entry);
+    }
+
     /// Removes all boxes from the set.
     pub fn clear(&mut self) {
         self.boxes.clear();
+        self.sorted = true;
     }
 
-    /// Sorts the boxes in the set by their low boundaries in dimension 0.
+    /// Sorts the boxes in the set by their low boundaries in dimension 0, breaking ties by identifier.
     /// Needed for the intersection finding algorithms.
-    pub fn sort(&mut self) {
-        self.boxes
-            .sort_by(|(a, _), (b, _)| a.lo(0).partial_cmp(&b.lo(0)).unwrap());
+    /// The resulting order only depends on the boxes and their identifiers, not on the order they were added in,
+    /// so the algorithms produce the same output for the same boxes (see [the crate documentation](crate#determinism)).
+    /// The sort is stable, so boxes with equal low boundaries and identifiers keep their relative order,
+    /// and the result is the same on every platform.
+    pub fn sort(&mut self)
+    where
+        ID: PartialOrd,
+    {
+        self.boxes.sort_by(sort_order);
+        self.sorted = true;
+    }
+
+    /// Like [`BBoxSet::sort`], but faster for sets which are almost sorted, e.g. because they were
+    /// sorted in the previous frame and the boxes have only moved slightly since.
+    /// Uses insertion sort, which takes *O*(*n* + *k*) for *k* pairs of boxes that are out of order,
+    /// and falls back to [`BBoxSet::sort`] if there are too many of them.
+    /// Produces the same order as [`BBoxSet::sort`].
+    pub fn resort_incremental(&mut self)
+    where
+        ID: PartialOrd,
+    {
+        let mut budget = 8 * self.boxes.len();
+        for i in 1..self.boxes.len() {
+            let mut j = i;
+            while j > 0 && sort_order(&self.boxes[j - 1], &self.boxes[j]) == Ordering::Greater {
+                if budget == 0 {
+                    self.sort();
+                    return;
+                }
+                budget -= 1;
+                self.boxes.swap(j - 1, j);
+                j -= 1;
+            }
+        }
+        self.sorted = true;
+    }
+
+    /// Like [`BBoxSet::sort`], but sorts in parallel on the rayon thread pool. Requires the `rayon` feature.
+    /// Produces the same order as [`BBoxSet::sort`], except that boxes with equal low boundaries
+    /// and identifiers may end up in any order, since the sort is not stable.
+    #[cfg(feature = "rayon")]
+    pub fn par_sort(&mut self)
+    where
+        B: Send,
+        ID: PartialOrd + Send,
+    {
+        use rayon::slice::ParallelSliceMut;
+        self.boxes.par_sort_unstable_by(sort_order);
+        self.sorted = true;
+    }
+
+    /// Sorts the set (see [`BBoxSet::sort`]) unless it is already sorted, which is checked in *O*(*n*) first.
+    /// The order is checked even if the set is known to be sorted (see [`BBoxSet::is_sorted`]),
+    /// as the boxes may have been modified through the deprecated `boxes` field.
+    pub fn ensure_sorted(&mut self)
+    where
+        ID: PartialOrd,
+    {
+        if in_order(&self.boxes) {
+            self.sorted = true;
+        } else {
+            self.sort();
+        }
+    }
+
+    /// Like [`BBoxSet::sort`], but using a total order, so it doesn't panic if a low boundary is NaN.
+    /// For floating point numbers, boxes with positive NaN low boundaries end up at the end of the set,
+    /// those with negative NaN at the start.
+    pub fn sort_total(&mut self)
+    where
+        B::Num: TotalOrder,
+        ID: PartialOrd,
+    {
+        self.boxes.sort_by(|(a, a_id), (b, b_id)| {
+            a.lo(0)
+                .total_cmp(&b.lo(0))
+                .then_with(|| a_id.partial_cmp(b_id).unwrap_or(Ordering::Equal))
+        });
+        // NaN boundaries are sorted to the ends, but they aren't in order for the algorithms
+        self.sorted = in_order(&self.boxes);
     }
 
     /// Returns the number of boxes in the set.
@@ -75,15 +325,84 @@ where
     /// Performs a linear search for the box with the given identifier.
     /// Returns [`Some`] if found, [`None`] otherwise.
     pub fn find(&self, id: ID) -> Option<B> {
-        self.boxes
-            .iter()
-            .find(|x| x.1 == id)
-            .and_then(|x| Some(x.0))
+        self.boxes.iter().find(|x| x.1 == id).map(|x| x.0)
+    }
+
+    /// Removes the box with the given identifier from the set and returns it.
+    /// Shifts all boxes after it, so if the set is sorted, it stays sorted.
+    /// Returns [`None`] if there is no box with that identifier.
+    pub fn remove(&mut self, id: ID) -> Option<B> {
+        let idx = self.boxes.iter().position(|x| x.1 == id)?;
+        Some(self.remove_at(idx).0)
+    }
+
+    /// Removes the box with the given identifier from the set and returns it, replacing it with the last box.
+    /// Faster than [`BBoxSet::remove`], but doesn't preserve the sorting.
+    /// Returns [`None`] if there is no box with that identifier.
+    pub fn swap_remove(&mut self, id: ID) -> Option<B> {
+        let idx = self.boxes.iter().position(|x| x.1 == id)?;
+        if idx + 1 < self.boxes.len() {
+            self.sorted = false;
+        }
+        Some(self.boxes.swap_remove(idx).0)
+    }
+
+    /// Replaces the box with the given identifier with `bbox` and returns the old box.
+    /// If the set is sorted, the box is moved to keep it sorted.
+    /// Returns [`None`] if there is no box with that identifier.
+    /// # Panics
+    /// If the low boundary of `bbox` in dimension 0 is NaN, like [`BBoxSet::sort`].
+    pub fn update(&mut self, id: ID, bbox: B) -> Option<B>
+    where
+        ID: PartialOrd,
+    {
+        let mut idx = self.boxes.iter().position(|x| x.1 == id)?;
+        let old = std::mem::replace(&mut self.boxes[idx].0, bbox);
+        while idx > 0 && sort_order(&self.boxes[idx - 1], &self.boxes[idx]) == Ordering::Greater {
+            self.boxes.swap(idx - 1, idx);
+            idx -= 1;
+        }
+        while idx + 1 < self.boxes.len()
+            && sort_order(&self.boxes[idx], &self.boxes[idx + 1]) == Ordering::Greater
+        {
+            self.boxes.swap(idx, idx + 1);
+            idx += 1;
+        }
+        Some(old)
+    }
+
+    /// Removes the box at the given index from the set and returns it and its identifier.
+    /// If the set is sorted, it stays sorted.
+    /// # Panics
+    /// If `idx` is out of bounds.
+    pub fn remove_at(&mut self, idx: usize) -> (B, ID) {
+        self.boxes.remove(idx)
     }
 
     /// Returns `true` if the set is empty.
     pub fn empty(&self) -> bool {
-        self.len() == 0
+        self.is_empty()
+    }
+
+    /// Returns `true` if the set is empty. Same as [`BBoxSet::empty`].
+    pub fn is_empty(&self) -> bool {
+        self.boxes.is_empty()
+    }
+
+    /// Removes all boxes with NaN or inverted bounds (see [`BBoxSet::validate`]) from the set in a single pass,
+    /// so the remaining boxes are safe for all algorithms, and returns them.
+    /// The order of the remaining boxes is preserved. Call this before sorting,
+    /// since [`BBoxSet::sort`] panics on NaN.
+    pub fn sanitize(&mut self) -> Self {
+        let mut removed = Self::new();
+        self.boxes.retain(|&(bbox, id)| {
+            let valid = bounds_problem(&bbox).is_none();
+            if !valid {
+                removed.push(id, bbox);
+            }
+            valid
+        });
+        removed
     }
 
     /// Returns a subset of the set, containing only those boxes that match the given predicate.
@@ -92,9 +411,19 @@ where
     where
         P: FnMut(&&(B, ID)) -> bool,
     {
-        Self {
-            boxes: self.boxes.iter().filter(pred).cloned().collect(),
-        }
+        Self::from_boxes(
+            self.boxes.iter().filter(pred).cloned().collect(),
+            self.sorted,
+        )
+    }
+
+    /// Removes all boxes for which the given predicate returns `false`, in place. See [`Vec::retain`].
+    /// If the set is sorted, it stays sorted.
+    pub fn retain<P>(&mut self, mut pred: P)
+    where
+        P: FnMut(&ID, &B) -> bool,
+    {
+        self.boxes.retain(|(bbox, id)| pred(id, bbox));
     }
 
     /// Returns a pair of subsets of the set, containing:
@@ -107,25 +436,327 @@ where
         P: FnMut(&&(B, ID)) -> bool,
     {
         let (tr, fls) = self.boxes.iter().partition(pred);
-        (Self { boxes: tr }, Self { boxes: fls })
+        (
+            Self::from_boxes(tr, self.sorted),
+            Self::from_boxes(fls, self.sorted),
+        )
     }
 
     /// Returns an approximate median of the low boundaries in dimension `dim` of the boxes,
     /// obtained by recursively calculating medians of three (medians of ...) random elements
     /// * `rand` must be a random number generator implementing the [`Rng`] trait.
     pub fn approx_median<R: Rng>(&self, dim: usize, rand: &mut R) -> B::Num {
-        // magic formula for the number of levels from CGAL: https://github.com/CGAL/cgal/blob/f513a791e2f474b002564e2e9300293877d6d91e/Box_intersection_d/include/CGAL/Box_intersection_d/segment_tree.h#L263
-        let mut levels = (0.91 * ((self.len() as f64) / 137.0 + 1.0).ln().floor()) as u32;
-        if levels == 0 {
-            levels = 1;
+        source::approx_median(self, dim, rand)
+    }
+}
+
+impl<B, ID> BBoxSet<B, ID>
+where
+    B: BBox,
+    ID: Copy + PartialOrd,
+{
+    /// Merges this set and `other`, which must both be sorted (see [`BBoxSet::sort`]), into a new sorted set
+    /// in *O*(*n* + *m*), instead of concatenating and sorting them again.
+    /// # Panics
+    /// If a low boundary in dimension 0 is NaN, like [`BBoxSet::sort`].
+    pub fn merge_sorted(&self, other: &Self) -> Self {
+        let mut merged = Vec::with_capacity(self.boxes.len() + other.boxes.len());
+        let (mut i, mut j) = (0, 0);
+        while i < self.boxes.len() && j < other.boxes.len() {
+            if sort_order(&self.boxes[i], &other.boxes[j]) == Ordering::Greater {
+                merged.push(other.boxes[j]);
+                j += 1;
+            } else {
+                merged.push(self.boxes[i]);
+                i += 1;
+            }
+        }
+        merged.extend_from_slice(&self.boxes[i..]);
+        merged.extend_from_slice(&other.boxes[j..]);
+        Self::from_boxes(merged, self.sorted && other.sorted)
+    }
+
+    /// Like [`BBoxSet::merge_sorted`], but merges `other` into this set in place.
+    pub fn append_merge(&mut self, other: &Self) {
+        let (mut i, mut j) = (self.boxes.len(), other.boxes.len());
+        self.boxes.extend_from_slice(&other.boxes);
+        // merge from the back, so boxes of this set are only moved after they have been read
+        let mut k = self.boxes.len();
+        while j > 0 {
+            k -= 1;
+            if i > 0 && sort_order(&self.boxes[i - 1], &other.boxes[j - 1]) == Ordering::Greater {
+                self.boxes[k] = self.boxes[i - 1];
+                i -= 1;
+            } else {
+                self.boxes[k] = other.boxes[j - 1];
+                j -= 1;
+            }
         }
-        let cap = 3usize.pow(levels);
-        let mut random_indices = Vec::<usize>::with_capacity(cap);
+        self.sorted = self.sorted && other.sorted;
+    }
 
-        let points: Vec<B::Num> = self.boxes.iter().map(|&(bbox, _id)| bbox.lo(dim)).collect();
-        for _ in 0..cap {
-            random_indices.push(rand.rand_usize(points.len()));
+    /// Checks the set for problems that keep the algorithms from working correctly:
+    /// NaN or inverted bounds, unsorted order and duplicate identifiers.
+    /// Returns a [`Report`] listing the indices of the offending boxes.
+    pub fn validate(&self) -> Report {
+        let mut report = Report::default();
+        for (index, (bbox, _id)) in self.boxes.iter().enumerate() {
+            match bounds_problem(bbox) {
+                Some(Problem::NaN { .. }) => report.nan.push(index),
+                Some(_) => report.inverted.push(index),
+                None => {}
+            }
+            if index > 0 && bbox.lo(0) < self.boxes[index - 1].0.lo(0) {
+                report.unsorted.push(index);
+            }
         }
-        median::approx_median(&points, levels as u8, &mut random_indices)
+
+        report.duplicate_ids = self
+            .duplicate_ids()
+            .into_iter()
+            .map(|(index, _first)| index)
+            .collect();
+        report
+    }
+
+    /// Finds boxes with the same identifier as a box at a lower index. Returns the index of
+    /// every such box along with the index of the first box with its identifier, ordered by index.
+    /// The algorithms rely on identifiers being unique, e.g. to skip pairs of a box with itself.
+    pub fn duplicate_ids(&self) -> Vec<(usize, usize)> {
+        // find duplicates by sorting the indices by identifier
+        let mut indices: Vec<usize> = (0..self.len()).collect();
+        indices.sort_by(|&i, &j| {
+            let (a, b) = (self.boxes[i].1, self.boxes[j].1);
+            a.partial_cmp(&b).unwrap_or(Ordering::Equal).then(i.cmp(&j))
+        });
+        let mut duplicates = Vec::new();
+        let mut first = 0;
+        for (k, &index) in indices.iter().enumerate() {
+            if k > 0 && self.boxes[indices[k - 1]].1 == self.boxes[index].1 {
+                duplicates.push((index, first));
+            } else {
+                first = index;
+            }
+        }
+        duplicates.sort_unstable();
+        duplicates
+    }
+}
+
+impl<N, ID, const D: usize> BBoxSet<BoxND<N, D>, ID>
+where
+    N: Copy + PartialOrd,
+    ID: Copy + PartialOrd,
+{
+    /// Returns the smallest box containing all non-empty boxes in the set (see [`BoxND::union`]),
+    /// or [`None`] if there are none. Computed in *O*(*n*) on every call.
+    pub fn bounds(&self) -> Option<BoxND<N, D>> {
+        self.boxes
+            .iter()
+            .map(|(bbox, _id)| bbox)
+            .filter(|bbox| !bbox.is_empty())
+            .fold(None, |bounds: Option<BoxND<N, D>>, bbox| {
+                Some(bounds.map_or(*bbox, |bounds| bounds.union(bbox)))
+            })
+    }
+
+    /// Merges all boxes with the same identifier (see [`BBoxSet::duplicate_ids`]) into one box, the smallest
+    /// box containing all of them (see [`BoxND::union`]), at the position of the first of them.
+    /// Returns the number of boxes removed. The set has to be sorted again afterwards.
+    pub fn merge_duplicate_ids(&mut self) -> usize {
+        let duplicates = self.duplicate_ids();
+        for &(index, first) in &duplicates {
+            self.boxes[first].0 = self.boxes[first].0.union(&self.boxes[index].0);
+        }
+        let mut index = 0;
+        let mut duplicates = duplicates.iter().peekable();
+        self.boxes.retain(|_| {
+            let keep = duplicates.next_if(|&&(i, _)| i == index).is_none();
+            index += 1;
+            keep
+        });
+        self.sorted = false;
+        index - self.boxes.len()
+    }
+
+    /// Returns the bounds of all boxes in the set, in order, packed into one flat buffer, e.g. for
+    /// uploading them to a GPU. Box `idx` occupies the elements `2 * D * idx..2 * D * (idx + 1)`:
+    /// its `D` low boundaries followed by its `D` high boundaries, e.g. `[lo_x, lo_y, lo_z, hi_x, hi_y, hi_z]`
+    /// for a [`Box3Df32`](crate::boxes::Box3Df32). The identifiers are in [`BBoxSet::packed_ids`].
+    pub fn to_packed(&self) -> Vec<N> {
+        let mut packed = Vec::with_capacity(2 * D * self.boxes.len());
+        for (bbox, _id) in &self.boxes {
+            packed.extend_from_slice(&bbox.min());
+            packed.extend_from_slice(&bbox.max());
+        }
+        packed
+    }
+
+    /// Returns the identifiers of all boxes in the set, in order, so element `idx` is the identifier
+    /// of the box at index `idx` of [`BBoxSet::to_packed`].
+    pub fn packed_ids(&self) -> Vec<ID> {
+        self.boxes.iter().map(|&(_bbox, id)| id).collect()
+    }
+}
+
+impl<ID, const N: usize> BBoxSet<BoxND<f64, N>, ID>
+where
+    ID: Copy + PartialEq,
+{
+    /// Returns a set of the boxes rebased around the floating origin `origin`, with `f32` bounds
+    /// (see [`BoxND::relative_to`]). Identifiers are kept, so the intersections found in the rebased set
+    /// refer to the original boxes. If the set is sorted, the sorting is preserved.
+    pub fn relative_to(&self, origin: [f64; N]) -> BBoxSet<BoxND<f32, N>, ID> {
+        let boxes = self
+            .boxes
+            .iter()
+            .map(|&(bbox, id)| (bbox.relative_to(origin), id))
+            .collect();
+        // rounding to `f32` is monotonic
+        BBoxSet::from_boxes(boxes, self.sorted)
+    }
+}
+
+/// A [`BBoxSet`] which is known to be sorted (see [`BBoxSet::sort`]), created by [`BBoxSet::into_sorted`].
+/// It only allows modifications that keep it sorted, so passing it to the intersection finding algorithms
+/// never fails because the set wasn't sorted.
+/// Dereferences to the underlying [`BBoxSet`] for read access.
+///
+/// [`SortedBBoxSet::intersect_ze`] and [`SortedBBoxSet::intersect_scan`] only accept this type, so unsorted input
+/// is rejected at compile time. The free functions like [`intersect_ze`](crate::intersect_ze) accept any [`BoxSource`],
+/// since slices and custom sources can't prove in their type that they are sorted. Instead, they check
+/// [`BoxSource::is_sorted`] when called and panic if an input isn't sorted.
+/// A `SortedBBoxSet` always passes that check without scanning its boxes.
+#[derive(Clone)]
+pub struct SortedBBoxSet<B: BBox, ID> {
+    set: BBoxSet<B, ID>,
+}
+
+impl<B, ID> BBoxSet<B, ID>
+where
+    B: BBox,
+    ID: Copy + PartialOrd,
+    B::Num: PartialOrd,
+{
+    /// Sorts the set (see [`BBoxSet::ensure_sorted`]) and returns it as a [`SortedBBoxSet`].
+    /// # Panics
+    /// If a low boundary in dimension 0 is NaN, like [`BBoxSet::sort`].
+    pub fn into_sorted(mut self) -> SortedBBoxSet<B, ID> {
+        self.ensure_sorted();
+        SortedBBoxSet { set: self }
+    }
+}
+
+impl<B, ID> SortedBBoxSet<B, ID>
+where
+    B: BBox,
+    ID: Copy + PartialOrd,
+    B::Num: PartialOrd,
+{
+    /// Creates a new, empty set.
+    pub fn new() -> Self {
+        BBoxSet::new().into_sorted()
+    }
+
+    /// Adds a box with the given identifier at the position that keeps the set sorted,
+    /// see [`BBoxSet::insert_sorted`].
+    pub fn insert(&mut self, id: ID, bbox: B) {
+        self.set.insert_sorted(id, bbox);
+    }
+
+    /// Removes the box with the given identifier from the set and returns it, see [`BBoxSet::remove`].
+    pub fn remove(&mut self, id: ID) -> Option<B> {
+        self.set.remove(id)
+    }
+
+    /// Replaces the box with the given identifier with `bbox` and returns the old box,
+    /// see [`BBoxSet::update`].
+    pub fn update(&mut self, id: ID, bbox: B) -> Option<B> {
+        self.set.update(id, bbox)
+    }
+
+    /// Keeps only the boxes for which `pred` returns `true`, see [`BBoxSet::retain`].
+    pub fn retain<P>(&mut self, pred: P)
+    where
+        P: FnMut(&ID, &B) -> bool,
+    {
+        self.set.retain(pred);
+    }
+
+    /// Removes all boxes from the set.
+    pub fn clear(&mut self) {
+        self.set.clear();
+    }
+
+    /// Returns the underlying [`BBoxSet`], which stays sorted until it is modified.
+    pub fn into_inner(self) -> BBoxSet<B, ID> {
+        self.set
+    }
+}
+
+impl<B, ID> SortedBBoxSet<B, ID>
+where
+    B: BBox,
+    B::Num: PartialOrd + HasInfinity,
+    ID: PartialOrd + Copy,
+{
+    /// Finds all intersections between boxes in this set and `other`, which may be the same set,
+    /// see [`intersect_ze`](crate::intersect_ze). Both are sorted, so unlike the free function it never panics over the order.
+    pub fn intersect_ze<R: Rng>(&self, other: &Self, out: &mut impl Sink<B, ID>, rand: &mut R) {
+        crate::intersect_ze(self, other, out, rand);
+    }
+
+    /// Finds all intersections between boxes in this set and `other`, which may be the same set,
+    /// using a scanning algorithm, see [`intersect_scan`](crate::intersect_scan).
+    pub fn intersect_scan(&self, other: &Self, out: &mut impl Sink<B, ID>) {
+        crate::intersect_scan(self, other, out);
+    }
+}
+
+impl<B, ID> Default for SortedBBoxSet<B, ID>
+where
+    B: BBox,
+    ID: Copy + PartialOrd,
+    B::Num: PartialOrd,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B, ID> Debug for SortedBBoxSet<B, ID>
+where
+    B: BBox + Debug,
+    ID: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.set.fmt(f)
+    }
+}
+
+impl<B: BBox, ID> Deref for SortedBBoxSet<B, ID> {
+    type Target = BBoxSet<B, ID>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.set
+    }
+}
+
+impl<B: BBox, ID: Copy> BoxSource<B, ID> for SortedBBoxSet<B, ID> {
+    fn len(&self) -> usize {
+        self.set.boxes.len()
+    }
+
+    fn get(&self, idx: usize) -> (B, ID) {
+        self.set.boxes[idx]
+    }
+
+    fn is_sorted(&self) -> bool {
+        debug_assert!(self.set.is_sorted());
+        true
+    }
+
+    fn addr(&self) -> *const () {
+        self.set.addr()
     }
 }
@@ -0,0 +1,106 @@
+//! Scans testing several candidate boxes per iteration with portable SIMD (`std::simd`).
+//! Requires the `simd` feature and a nightly compiler.
+//!
+//! The functions in this module report the same pairs in the same order as their scalar counterparts
+//! in [`internals`](crate::internals). The effective bounds (see [`Policy`]) of the candidates are
+//! copied into one array per dimension first, so [`Lanes::LANES`] candidates can be loaded at once
+//! and compared against the current interval in all dimensions but 0, where the scan already
+//! guarantees they intersect.
+
+use std::simd::prelude::*;
+use std::simd::SimdElement;
+
+use crate::boxes::BBox;
+use crate::kernel::{self, Kernel};
+use crate::policy::Policy;
+use crate::sink::Sink;
+use crate::source::BoxSource;
+
+/// Trait for box boundary types that can be compared with SIMD instructions
+pub trait Lanes: Copy + PartialOrd {
+    /// Number of boundaries compared per instruction
+    const LANES: usize;
+
+    /// Returns a bitmask of the boxes with indices `start..start + LANES` (the lowest bit for `start`)
+    /// that intersect the box with the boundaries `lo` and `hi`, in the dimensions `los` and `his` hold
+    /// the boundaries of. Boundaries are half-open intervals: `[lo, hi)`.
+    fn overlap_mask(
+        los: &[Vec<Self>],
+        his: &[Vec<Self>],
+        start: usize,
+        lo: &[Self],
+        hi: &[Self],
+    ) -> u64;
+}
+
+macro_rules! impl_lanes {
+    ($($t:ty: $n:literal),*) => {
+        $(
+            impl Lanes for $t {
+                const LANES: usize = $n;
+
+                #[inline]
+                fn overlap_mask(
+                    los: &[Vec<Self>],
+                    his: &[Vec<Self>],
+                    start: usize,
+                    lo: &[Self],
+                    hi: &[Self],
+                ) -> u64 {
+                    let mut mask = Mask::<<$t as SimdElement>::Mask, $n>::splat(true);
+                    for dim in 0..los.len() {
+                        let p_lo = Simd::<$t, $n>::from_slice(&los[dim][start..start + $n]);
+                        let p_hi = Simd::<$t, $n>::from_slice(&his[dim][start..start + $n]);
+                        mask &= p_lo.simd_lt(Simd::splat(hi[dim]))
+                            & Simd::splat(lo[dim]).simd_lt(p_hi);
+                    }
+                    mask.to_bitmask()
+                }
+            }
+        )*
+    };
+}
+
+impl_lanes!(f32: 8, f64: 4, i32: 8, i64: 4, u32: 8, u64: 4);
+
+/// The [`Kernel`] comparing boundaries of type `N` with portable SIMD
+#[derive(Clone, Copy)]
+struct Portable;
+
+impl<N: Lanes> Kernel<N> for Portable {
+    const LANES: usize = N::LANES;
+
+    #[inline]
+    fn overlap_mask(self, los: &[Vec<N>], his: &[Vec<N>], start: usize, lo: &[N], hi: &[N]) -> u64 {
+        N::overlap_mask(los, his, start, lo, hi)
+    }
+}
+
+/// Like [`one_way_scan`](crate::internals::one_way_scan), but testing several points per iteration.
+pub fn one_way_scan<B, ID>(
+    intervals: &impl BoxSource<B, ID>,
+    points: &impl BoxSource<B, ID>,
+    max_dim_check: usize,
+    out: &mut impl Sink<B, ID>,
+    policy: impl Policy<B::Num>,
+) where
+    B: BBox,
+    ID: Copy + PartialOrd,
+    B::Num: Lanes,
+{
+    kernel::one_way_scan(intervals, points, max_dim_check, out, policy, Portable);
+}
+
+/// Like [`two_way_scan`](crate::internals::two_way_scan), but testing several boxes per iteration.
+pub fn two_way_scan<B, ID>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    out: &mut impl Sink<B, ID>,
+    policy: impl Policy<B::Num>,
+) where
+    B: BBox,
+    ID: Copy + PartialOrd,
+    B::Num: Lanes,
+{
+    kernel::two_way_scan(a, b, out, policy, Portable);
+}
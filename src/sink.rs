@@ -0,0 +1,439 @@
+//! Destinations for the intersecting pairs reported by the algorithms in this crate
+
+use std::convert::TryInto;
+use std::ops::{Mul, Sub};
+
+use crate::boxes::{BBox, BoxND, Penetration};
+
+/// Trait for receivers of the pairs of intersecting boxes found by the algorithms.
+/// Implemented for `Vec<(ID, ID)>`, which simply collects the pairs of `ID`s.
+pub trait Sink<B, ID> {
+    /// Receives the intersecting boxes `a` and `b` with identifiers `a_id` and `b_id`.
+    fn report(&mut self, a_id: ID, a: &B, b_id: ID, b: &B);
+}
+
+impl<B, ID> Sink<B, ID> for Vec<(ID, ID)> {
+    fn report(&mut self, a_id: ID, _a: &B, b_id: ID, _b: &B) {
+        self.push((a_id, b_id));
+    }
+}
+
+/// Trait for receivers of the pairs of a box and a point contained in it, found by
+/// [`intersect_points`](crate::intersect_points). Implemented for `Vec<(ID, ID)>`, which collects the pairs of `ID`s,
+/// and for closures taking the two `ID`s.
+pub trait PointSink<B, P, ID> {
+    /// Receives the box `bbox` with identifier `b_id` and the point `point` with identifier `p_id` contained in it.
+    fn report(&mut self, b_id: ID, bbox: &B, p_id: ID, point: &P);
+}
+
+impl<B, P, ID> PointSink<B, P, ID> for Vec<(ID, ID)> {
+    fn report(&mut self, b_id: ID, _bbox: &B, p_id: ID, _point: &P) {
+        self.push((b_id, p_id));
+    }
+}
+
+impl<B, P, ID, F> PointSink<B, P, ID> for F
+where
+    F: FnMut(ID, ID),
+{
+    fn report(&mut self, b_id: ID, _bbox: &B, p_id: ID, _point: &P) {
+        self(b_id, p_id);
+    }
+}
+
+/// Trait for [`Sink`]s whose results can be combined, so that independent parts of a query can
+/// report to separate sinks on different threads. See [`intersect_ze_threaded`](crate::intersect_ze_threaded).
+pub trait Merge {
+    /// Appends everything collected by `other` to `self`.
+    fn merge(&mut self, other: Self);
+}
+
+impl<T> Merge for Vec<T> {
+    fn merge(&mut self, mut other: Self) {
+        self.append(&mut other);
+    }
+}
+
+impl<B, ID, S> Sink<B, ID> for &mut S
+where
+    S: Sink<B, ID> + ?Sized,
+{
+    fn report(&mut self, a_id: ID, a: &B, b_id: ID, b: &B) {
+        (**self).report(a_id, a, b_id, b);
+    }
+}
+
+/// Policy for reporting pairs of coincident boxes, i.e. boxes with identical bounds
+/// (see [`BBox::coincides`]).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Coincident {
+    /// Report the pair once, like any other pair of intersecting boxes
+    Once,
+    /// Report the pair in both orders, `(a, b)` and `(b, a)`
+    Both,
+    /// Don't report the pair at all
+    Skip,
+}
+
+/// A [`Sink`] that applies a [`Coincident`] policy before passing pairs on to another sink.
+/// All algorithms report every intersecting pair exactly once, so wrapping the output
+/// in this sink handles coincident boxes the same way regardless of the algorithm.
+pub struct CoincidentSink<S> {
+    inner: S,
+    policy: Coincident,
+}
+
+impl<S> CoincidentSink<S> {
+    /// Creates a sink passing pairs on to `inner`, handling coincident boxes according to `policy`.
+    pub fn new(inner: S, policy: Coincident) -> Self {
+        Self { inner, policy }
+    }
+
+    /// Returns the wrapped sink.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<B, ID, S> Sink<B, ID> for CoincidentSink<S>
+where
+    B: BBox,
+    ID: Copy,
+    S: Sink<B, ID>,
+{
+    fn report(&mut self, a_id: ID, a: &B, b_id: ID, b: &B) {
+        if self.policy == Coincident::Once || !a.coincides(b) {
+            self.inner.report(a_id, a, b_id, b);
+        } else if self.policy == Coincident::Both {
+            self.inner.report(a_id, a, b_id, b);
+            self.inner.report(b_id, b, a_id, a);
+        }
+    }
+}
+
+/// A [`Sink`] writing pairs of `ID`s into a caller-provided slice, for contexts where
+/// allocating memory is not acceptable. Pairs that don't fit are counted, but discarded.
+/// To scan without allocating, call [`intersect_scan_with_scratch`](crate::intersect_scan_with_scratch)
+/// with a [`ScanScratch`](crate::internals::ScanScratch) created at startup. The other algorithms,
+/// e.g. [`intersect_ze`](crate::intersect_ze), allocate internally.
+pub struct SliceSink<'a, ID> {
+    slice: &'a mut [(ID, ID)],
+    total: usize,
+}
+
+impl<'a, ID> SliceSink<'a, ID> {
+    /// Creates a sink writing pairs to `slice`, starting at index 0.
+    pub fn new(slice: &'a mut [(ID, ID)]) -> Self {
+        Self { slice, total: 0 }
+    }
+
+    /// Returns the number of pairs written to the slice.
+    pub fn written(&self) -> usize {
+        self.total.min(self.slice.len())
+    }
+
+    /// Returns `true` if there were more pairs than fit in the slice.
+    pub fn overflowed(&self) -> bool {
+        self.total > self.slice.len()
+    }
+
+    /// Returns the total number of pairs received, including those that didn't fit.
+    /// This is the slice length needed to receive all pairs.
+    pub fn total(&self) -> usize {
+        self.total
+    }
+}
+
+impl<B, ID> Sink<B, ID> for SliceSink<'_, ID> {
+    fn report(&mut self, a_id: ID, _a: &B, b_id: ID, _b: &B) {
+        if let Some(slot) = self.slice.get_mut(self.total) {
+            *slot = (a_id, b_id);
+        }
+        self.total += 1;
+    }
+}
+
+/// A [`Sink`] collecting the pairs of `ID`s converted to `u32`, e.g. for `usize` indices into sets
+/// with fewer than `u32::MAX` boxes. Needs half the memory of a `Vec<(usize, usize)>` on 64-bit targets,
+/// which matters at hundreds of millions of pairs.
+///
+/// # Panics
+/// When receiving an `ID` that doesn't fit into a `u32`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CompactSink {
+    /// Pairs of `ID`s of intersecting boxes
+    pub pairs: Vec<(u32, u32)>,
+}
+
+impl CompactSink {
+    /// Creates an empty sink.
+    pub fn new() -> Self {
+        Self { pairs: Vec::new() }
+    }
+
+    /// Creates an empty sink with the specified capacity. See [`Vec::with_capacity`].
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            pairs: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Returns the pairs packed into one flat buffer, `[a_0, b_0, a_1, b_1, ...]`, e.g. for uploading them
+    /// to a GPU. See [`BBoxSet::to_packed`](crate::set::BBoxSet::to_packed) for the boxes.
+    pub fn to_packed(&self) -> Vec<u32> {
+        self.pairs.iter().flat_map(|&(a, b)| [a, b]).collect()
+    }
+}
+
+impl Merge for CompactSink {
+    fn merge(&mut self, mut other: Self) {
+        self.pairs.append(&mut other.pairs);
+    }
+}
+
+impl<B, ID> Sink<B, ID> for CompactSink
+where
+    ID: TryInto<u32>,
+{
+    fn report(&mut self, a_id: ID, _a: &B, b_id: ID, _b: &B) {
+        let compact = |id: ID| {
+            id.try_into()
+                .unwrap_or_else(|_| panic!("CompactSink: ID does not fit into u32"))
+        };
+        self.pairs.push((compact(a_id), compact(b_id)));
+    }
+}
+
+/// A [`Sink`] collecting the pairs of `ID`s along with the box where the two boxes overlap
+/// (see [`BoxND::overlap`]), so it doesn't have to be recomputed by the consumer.
+#[derive(Clone, Debug)]
+pub struct OverlapSink<B, ID> {
+    /// Pairs of `ID`s of intersecting boxes and their overlap
+    pub pairs: Vec<(ID, ID, B)>,
+}
+
+impl<B, ID> OverlapSink<B, ID> {
+    /// Creates an empty sink.
+    pub fn new() -> Self {
+        Self { pairs: Vec::new() }
+    }
+
+    /// Creates an empty sink with the specified capacity. See [`Vec::with_capacity`].
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            pairs: Vec::with_capacity(capacity),
+        }
+    }
+}
+
+impl<B, ID> Default for OverlapSink<B, ID> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B, ID> Merge for OverlapSink<B, ID> {
+    fn merge(&mut self, mut other: Self) {
+        self.pairs.append(&mut other.pairs);
+    }
+}
+
+impl<N, ID, const D: usize> Sink<BoxND<N, D>, ID> for OverlapSink<BoxND<N, D>, ID>
+where
+    N: Copy + PartialOrd,
+{
+    fn report(&mut self, a_id: ID, a: &BoxND<N, D>, b_id: ID, b: &BoxND<N, D>) {
+        self.pairs.push((a_id, b_id, a.overlap(b)));
+    }
+}
+
+/// A [`Sink`] collecting the pairs of `ID`s along with the measure (area in 2D, volume in 3D)
+/// of the box where the two boxes overlap (see [`BoxND::measure`]).
+#[derive(Clone, Debug)]
+pub struct MeasureSink<N, ID> {
+    /// Pairs of `ID`s of intersecting boxes and the measure of their overlap
+    pub pairs: Vec<(ID, ID, N)>,
+}
+
+impl<N, ID> MeasureSink<N, ID> {
+    /// Creates an empty sink.
+    pub fn new() -> Self {
+        Self { pairs: Vec::new() }
+    }
+
+    /// Creates an empty sink with the specified capacity. See [`Vec::with_capacity`].
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            pairs: Vec::with_capacity(capacity),
+        }
+    }
+}
+
+impl<N, ID> Default for MeasureSink<N, ID> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<N, ID> Merge for MeasureSink<N, ID> {
+    fn merge(&mut self, mut other: Self) {
+        self.pairs.append(&mut other.pairs);
+    }
+}
+
+impl<N, ID, const D: usize> Sink<BoxND<N, D>, ID> for MeasureSink<N, ID>
+where
+    N: Copy + PartialOrd + Sub<Output = N> + Mul<Output = N>,
+{
+    fn report(&mut self, a_id: ID, a: &BoxND<N, D>, b_id: ID, b: &BoxND<N, D>) {
+        self.pairs.push((a_id, b_id, a.overlap(b).measure()));
+    }
+}
+
+/// A [`Sink`] collecting the pairs of `ID`s along with the penetration depths of the two boxes
+/// (see [`BoxND::penetration`]), which is enough for simple resolvers of AABB-only physics.
+#[derive(Clone, Debug)]
+pub struct PenetrationSink<N, ID, const D: usize> {
+    /// Pairs of `ID`s of intersecting boxes and their penetration depths
+    pub pairs: Vec<(ID, ID, Penetration<N, D>)>,
+}
+
+impl<N, ID, const D: usize> PenetrationSink<N, ID, D> {
+    /// Creates an empty sink.
+    pub fn new() -> Self {
+        Self { pairs: Vec::new() }
+    }
+
+    /// Creates an empty sink with the specified capacity. See [`Vec::with_capacity`].
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            pairs: Vec::with_capacity(capacity),
+        }
+    }
+}
+
+impl<N, ID, const D: usize> Default for PenetrationSink<N, ID, D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<N, ID, const D: usize> Merge for PenetrationSink<N, ID, D> {
+    fn merge(&mut self, mut other: Self) {
+        self.pairs.append(&mut other.pairs);
+    }
+}
+
+impl<N, ID, const D: usize> Sink<BoxND<N, D>, ID> for PenetrationSink<N, ID, D>
+where
+    N: Copy + PartialOrd + Sub<Output = N>,
+{
+    fn report(&mut self, a_id: ID, a: &BoxND<N, D>, b_id: ID, b: &BoxND<N, D>) {
+        self.pairs.push((a_id, b_id, a.penetration(b)));
+    }
+}
+
+/// Minimum overlap of two boxes required by [`MinOverlapSink`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MinOverlap<N> {
+    /// The extent of the overlap must exceed this value in every dimension
+    Extent(N),
+    /// The measure (area in 2D, volume in 3D) of the overlap must exceed this value
+    Measure(N),
+}
+
+/// A [`Sink`] that only passes on pairs of boxes that overlap by more than a [`MinOverlap`]
+/// threshold, filtering out sliver overlaps such as those of adjacent tiles.
+pub struct MinOverlapSink<S, N> {
+    inner: S,
+    min: MinOverlap<N>,
+}
+
+impl<S, N> MinOverlapSink<S, N> {
+    /// Creates a sink passing pairs overlapping by more than `min` on to `inner`.
+    pub fn new(inner: S, min: MinOverlap<N>) -> Self {
+        Self { inner, min }
+    }
+
+    /// Returns the wrapped sink.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+/// Returns the extent of the overlap of `a` and `b` in dimension `dim`.
+fn overlap_extent<B>(a: &B, b: &B, dim: usize) -> B::Num
+where
+    B: BBox,
+    B::Num: Sub<Output = B::Num>,
+{
+    let lo = if a.lo(dim) > b.lo(dim) {
+        a.lo(dim)
+    } else {
+        b.lo(dim)
+    };
+    let hi = if a.hi(dim) < b.hi(dim) {
+        a.hi(dim)
+    } else {
+        b.hi(dim)
+    };
+    hi - lo
+}
+
+impl<B, ID, S> Sink<B, ID> for MinOverlapSink<S, B::Num>
+where
+    B: BBox,
+    B::Num: Sub<Output = B::Num> + Mul<Output = B::Num>,
+    S: Sink<B, ID>,
+{
+    fn report(&mut self, a_id: ID, a: &B, b_id: ID, b: &B) {
+        let enough = match self.min {
+            MinOverlap::Extent(min) => (0..B::DIM).all(|dim| overlap_extent(a, b, dim) > min),
+            MinOverlap::Measure(min) => {
+                let measure = (1..B::DIM).fold(overlap_extent(a, b, 0), |measure, dim| {
+                    measure * overlap_extent(a, b, dim)
+                });
+                measure > min
+            }
+        };
+        if enough {
+            self.inner.report(a_id, a, b_id, b);
+        }
+    }
+}
+
+/// A [`Sink`] that only passes on pairs where one box contains the other (see [`BBox::contains`]),
+/// ordered so that the containing box comes first.
+/// Containment implies intersection for boxes with a positive extent in every dimension, so running any algorithm
+/// with this sink finds all such pairs. Boxes with zero extent (e.g. points) are empty under the default
+/// [`HalfOpen`](crate::policy::HalfOpen) policy though, so they are never reported.
+/// Use a policy wrapped in [`Degenerate`](crate::policy::Degenerate) to find them too.
+pub struct ContainmentSink<S> {
+    inner: S,
+}
+
+impl<S> ContainmentSink<S> {
+    /// Creates a sink passing pairs where one box contains the other on to `inner`.
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+
+    /// Returns the wrapped sink.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<B, ID, S> Sink<B, ID> for ContainmentSink<S>
+where
+    B: BBox,
+    S: Sink<B, ID>,
+{
+    fn report(&mut self, a_id: ID, a: &B, b_id: ID, b: &B) {
+        if a.contains(b) {
+            self.inner.report(a_id, a, b_id, b);
+        } else if b.contains(a) {
+            self.inner.report(b_id, b, a_id, a);
+        }
+    }
+}
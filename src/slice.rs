@@ -0,0 +1,141 @@
+//! Sets of boxes borrowed from data owned by the caller, so that large datasets don't have to be copied
+//! into a [`BBoxSet`] just to find intersections. A [`BBoxSlice`] only stores a sort permutation of the boxes,
+//! a set created by [`ref_set`] references to them, and the identifiers reported are the indices into the borrowed slices.
+//!
+//! # Examples
+//! ```
+//! use box_intersect_ze::slice::BBoxSlice;
+//!
+//! // coordinates stored separately, e.g. in a structure of arrays
+//! let min = [[0.0, 0.0], [5.0, 5.0], [20.0, 20.0]];
+//! let max = [[10.0, 10.0], [15.0, 15.0], [30.0, 30.0]];
+//! let set = BBoxSlice::new(&min, &max);
+//!
+//! let mut out = Vec::new();
+//! box_intersect_ze::intersect_scan(&set, &set, &mut out);
+//! assert_eq!(out.len(), 1);
+//! ```
+
+use crate::boxes::BBox;
+use crate::set::BBoxSet;
+use crate::source::BoxSource;
+
+/// A box borrowing its boundaries from a [`BBoxSlice`]
+#[derive(Clone, Copy, Debug)]
+pub struct BoxRef<'a, N, const D: usize> {
+    min: &'a [N; D],
+    max: &'a [N; D],
+}
+
+impl<'a, N, const D: usize> BoxRef<'a, N, D> {
+    /// Returns the low boundaries of the box, indexed by dimension.
+    pub fn min(&self) -> &'a [N; D] {
+        self.min
+    }
+
+    /// Returns the high boundaries of the box, indexed by dimension.
+    pub fn max(&self) -> &'a [N; D] {
+        self.max
+    }
+}
+
+impl<N, const D: usize> BBox for BoxRef<'_, N, D>
+where
+    N: Copy + PartialOrd,
+{
+    const DIM: usize = D;
+    type Num = N;
+
+    fn lo(&self, dim: usize) -> Self::Num {
+        self.min[dim]
+    }
+
+    fn hi(&self, dim: usize) -> Self::Num {
+        self.max[dim]
+    }
+}
+
+/// `D`-dimensional boxes stored by the caller as separate slices of low and high boundaries,
+/// where the box with index `i` has the boundaries `min[i]` and `max[i]`.
+/// A [`BoxSource`] of the boxes in sorted order, with their indices as identifiers:
+/// the order is kept as a separate permutation of `u32` indices, so the boxes aren't copied.
+#[derive(Clone, Debug)]
+pub struct BBoxSlice<'a, N, const D: usize> {
+    min: &'a [[N; D]],
+    max: &'a [[N; D]],
+    /// Indices of the boxes, sorted by their low boundaries in dimension 0, then by index
+    order: Vec<u32>,
+}
+
+impl<'a, N, const D: usize> BBoxSlice<'a, N, D>
+where
+    N: Copy + PartialOrd,
+{
+    /// Creates a view of the boxes with the low boundaries `min` and the high boundaries `max`,
+    /// sorting a permutation of their indices in *O*(*n* log *n*).
+    /// # Panics
+    /// If `min` and `max` don't have the same length, if there are more than `u32::MAX` boxes,
+    /// or if a low boundary in dimension 0 is NaN, like [`BBoxSet::sort`](crate::set::BBoxSet::sort).
+    pub fn new(min: &'a [[N; D]], max: &'a [[N; D]]) -> Self {
+        assert_eq!(
+            min.len(),
+            max.len(),
+            "`min` and `max` must have the same length"
+        );
+        assert!(
+            min.len() <= u32::MAX as usize,
+            "at most `u32::MAX` boxes are supported"
+        );
+        let mut order: Vec<u32> = (0..min.len() as u32).collect();
+        // the sort is stable, so boxes with equal low boundaries stay ordered by index
+        order.sort_by(|&a, &b| min[a as usize][0].partial_cmp(&min[b as usize][0]).unwrap());
+        Self { min, max, order }
+    }
+
+    /// Returns the number of boxes.
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    /// Returns `true` if there are no boxes.
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// Returns the box with index `idx` in the slices.
+    pub fn bbox(&self, idx: usize) -> BoxRef<'a, N, D> {
+        BoxRef {
+            min: &self.min[idx],
+            max: &self.max[idx],
+        }
+    }
+}
+
+impl<'a, N, const D: usize> BoxSource<BoxRef<'a, N, D>, usize> for BBoxSlice<'a, N, D>
+where
+    N: Copy + PartialOrd,
+{
+    fn len(&self) -> usize {
+        BBoxSlice::len(self)
+    }
+
+    /// Returns the box at position `idx` in sorted order and its index in the slices.
+    fn get(&self, idx: usize) -> (BoxRef<'a, N, D>, usize) {
+        let idx = self.order[idx] as usize;
+        (self.bbox(idx), idx)
+    }
+
+    fn is_sorted(&self) -> bool {
+        true
+    }
+}
+
+/// Returns a sorted set referencing the boxes in `boxes`, with their indices as identifiers.
+/// Useful if `B` is large, e.g. a user type implementing [`BBox`] that stores more than its boundaries.
+/// # Panics
+/// If a low boundary in dimension 0 is NaN, like [`BBoxSet::sort`].
+pub fn ref_set<B: BBox>(boxes: &[B]) -> BBoxSet<&B, usize> {
+    let mut set: BBoxSet<_, _> = boxes.iter().enumerate().collect();
+    set.sort();
+    set
+}
@@ -0,0 +1,215 @@
+//! Storage the algorithms read boxes from, see [`BoxSource`]
+
+use crate::boxes::BBox;
+use crate::policy::Policy;
+use crate::set::BBoxSet;
+use crate::{median, Rng};
+
+/// Trait for indexable storage of boxes of type `B` with identifiers of type `ID`.
+/// The algorithms read their input through this trait, so they can run directly on boxes stored elsewhere,
+/// e.g. in the component storage of an entity component system, without copying them into a [`BBoxSet`],
+/// which is the canonical implementation.
+/// * Like a [`BBoxSet`], a source must be sorted by the low boundaries of its boxes in dimension 0
+///   before passing it to the algorithms, which check it unless [`BoxSource::is_sorted`] says so.
+/// * [`intersect_ze`](crate::intersect_ze) still copies the boxes once, into the scratch buffers it partitions
+///   (see [`hybrid_indexed`](crate::internals::hybrid_indexed) for a variant that doesn't).
+pub trait BoxSource<B: BBox, ID> {
+    /// Returns the number of boxes.
+    fn len(&self) -> usize;
+
+    /// Returns the box at the given index and its identifier.
+    fn get(&self, idx: usize) -> (B, ID);
+
+    /// Returns `true` if there are no boxes.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns `true` if the boxes are known to be sorted, so the algorithms needn't check their order.
+    /// Otherwise, the algorithms that take at least linear time check it in *O*(*n*) and panic if it is wrong.
+    /// The default returns `false`.
+    fn is_sorted(&self) -> bool {
+        false
+    }
+
+    /// Returns the address of the storage of the boxes. The algorithms treat two sources with the same address
+    /// as the same boxes, e.g. to report every pair only once if the same set is passed as both inputs.
+    /// The default returns the address of the source itself, wrappers like references return that of the source they wrap.
+    fn addr(&self) -> *const () {
+        self as *const Self as *const ()
+    }
+}
+
+impl<B: BBox, ID, S: BoxSource<B, ID> + ?Sized> BoxSource<B, ID> for &S {
+    fn len(&self) -> usize {
+        (**self).len()
+    }
+
+    fn get(&self, idx: usize) -> (B, ID) {
+        (**self).get(idx)
+    }
+
+    fn is_empty(&self) -> bool {
+        (**self).is_empty()
+    }
+
+    fn is_sorted(&self) -> bool {
+        (**self).is_sorted()
+    }
+
+    fn addr(&self) -> *const () {
+        (**self).addr()
+    }
+}
+
+impl<B: BBox, ID: Copy> BoxSource<B, ID> for BBoxSet<B, ID> {
+    fn len(&self) -> usize {
+        self.boxes().len()
+    }
+
+    fn get(&self, idx: usize) -> (B, ID) {
+        self.boxes()[idx]
+    }
+
+    fn is_sorted(&self) -> bool {
+        BBoxSet::is_sorted(self)
+    }
+}
+
+/// Returns `true` if `a` and `b` refer to the same source, see [`BoxSource::addr`].
+pub(crate) fn same<B: BBox, ID>(
+    a: &(impl BoxSource<B, ID> + ?Sized),
+    b: &(impl BoxSource<B, ID> + ?Sized),
+) -> bool {
+    std::ptr::eq(a.addr(), b.addr())
+}
+
+/// Returns `true` if every box of `a` intersects every box of `b` in dimension `dim`, comparing bounds
+/// according to `policy`: the greatest low boundary of either lies below the least high boundary of both,
+/// so no box is empty in `dim` either (see [`Policy::is_empty_in`]). The algorithms needn't check such dimensions.
+pub(crate) fn all_overlap_in<B, ID>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    dim: usize,
+    policy: impl Policy<B::Num>,
+) -> bool
+where
+    B: BBox,
+{
+    // greatest low and least high boundary of `source`, `None` if it's empty
+    let extremes = |source: &dyn Fn(usize) -> B, len: usize| {
+        let mut extremes: Option<(B::Num, B::Num)> = None;
+        for idx in 0..len {
+            let bbox = source(idx);
+            let (lo, hi) = (policy.lo_of(&bbox, dim), policy.hi_of(&bbox, dim));
+            extremes = Some(match extremes {
+                Some((max_lo, min_hi)) => (
+                    if lo > max_lo { lo } else { max_lo },
+                    if hi < min_hi { hi } else { min_hi },
+                ),
+                None => (lo, hi),
+            });
+        }
+        extremes
+    };
+    let a_extremes = extremes(&|idx| a.get(idx).0, a.len());
+    let b_extremes = if same(a, b) {
+        a_extremes
+    } else {
+        extremes(&|idx| b.get(idx).0, b.len())
+    };
+    match (a_extremes, b_extremes) {
+        (Some((a_max_lo, a_min_hi)), Some((b_max_lo, b_min_hi))) => {
+            a_max_lo < a_min_hi && b_max_lo < b_min_hi && a_max_lo < b_min_hi && b_max_lo < a_min_hi
+        }
+        // no pairs to check
+        _ => true,
+    }
+}
+
+/// Returns the highest dimension in which not every box of `a` intersects every box of `b`,
+/// or 0 if there is none. The algorithms can start at that dimension instead of the highest one,
+/// e.g. for boxes in a thin layer, like the buildings of a city, which all overlap in height.
+pub(crate) fn top_dim<B, ID>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    policy: impl Policy<B::Num>,
+) -> usize
+where
+    B: BBox,
+{
+    (1..B::DIM)
+        .rev()
+        .find(|&dim| !all_overlap_in(a, b, dim, policy))
+        .unwrap_or(0)
+}
+
+/// Returns an approximate median of the low boundaries of the boxes of `source` in dimension `dim`,
+/// see [`BBoxSet::approx_median`].
+pub(crate) fn approx_median<B, ID, R>(
+    source: &impl BoxSource<B, ID>,
+    dim: usize,
+    rand: &mut R,
+) -> B::Num
+where
+    B: BBox,
+    R: Rng,
+{
+    approx_median_in(source, dim, rand, &mut Vec::new())
+}
+
+/// Like [`approx_median`], but reuses `samples` to store the sampled boundaries,
+/// so calling it repeatedly doesn't allocate.
+pub(crate) fn approx_median_in<B, ID, R>(
+    source: &impl BoxSource<B, ID>,
+    dim: usize,
+    rand: &mut R,
+    samples: &mut Vec<B::Num>,
+) -> B::Num
+where
+    B: BBox,
+    R: Rng,
+{
+    let levels = median_levels(source.len());
+    let cap = 3usize.pow(levels);
+
+    samples.clear();
+    for _ in 0..cap {
+        let idx = rand.rand_usize(source.len());
+        samples.push(source.get(idx).0.lo(dim));
+    }
+    median::approx_median(samples, levels as u8)
+}
+
+/// Like [`approx_median`], but reads the sampled boundaries and computes their median in parallel.
+/// Draws the same random numbers and returns the same value.
+#[cfg(feature = "parallel")]
+pub(crate) fn par_approx_median<B, ID, R>(
+    source: &(impl BoxSource<B, ID> + Sync),
+    dim: usize,
+    rand: &mut R,
+) -> B::Num
+where
+    B: BBox,
+    B::Num: Send + Sync,
+    R: Rng,
+{
+    use rayon::prelude::*;
+
+    let levels = median_levels(source.len());
+    let indices: Vec<_> = (0..3usize.pow(levels))
+        .map(|_| rand.rand_usize(source.len()))
+        .collect();
+    let samples: Vec<_> = indices
+        .par_iter()
+        .map(|&idx| source.get(idx).0.lo(dim))
+        .collect();
+    median::par_approx_median(&samples, levels as u8)
+}
+
+/// Returns the number of levels of medians of three used to approximate the median of `len` boxes.
+fn median_levels(len: usize) -> u32 {
+    // magic formula for the number of levels from CGAL: https://github.com/CGAL/cgal/blob/f513a791e2f474b002564e2e9300293877d6d91e/Box_intersection_d/include/CGAL/Box_intersection_d/segment_tree.h#L263
+    let levels = (0.91 * ((len as f64) / 137.0 + 1.0).ln().floor()) as u32;
+    levels.max(1)
+}
@@ -0,0 +1,244 @@
+//! A [`Sink`] that spills pairs to a temporary file once an in-memory threshold is exceeded,
+//! for outputs too large to be kept in memory.
+//!
+//! Spilled pairs are stored in a compact binary format: every pair is written as the two `ID`s
+//! in little endian byte order, using [`Packed::SIZE`] bytes each, without any header or padding.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::sink::Sink;
+
+/// Trait for `ID` types that can be stored in the compact binary format
+pub trait Packed: Copy {
+    /// Number of bytes used to store one `ID`
+    const SIZE: usize;
+
+    /// Writes the little endian representation of `self` to the first [`Packed::SIZE`] bytes of `buf`.
+    fn write_le(self, buf: &mut [u8]);
+
+    /// Reads an `ID` from the first [`Packed::SIZE`] bytes of `buf`.
+    fn read_le(buf: &[u8]) -> Self;
+}
+
+macro_rules! impl_packed {
+    ($($t:ty),*) => {
+        $(
+            impl Packed for $t {
+                const SIZE: usize = std::mem::size_of::<$t>();
+
+                fn write_le(self, buf: &mut [u8]) {
+                    buf[..Self::SIZE].copy_from_slice(&self.to_le_bytes());
+                }
+
+                fn read_le(buf: &[u8]) -> Self {
+                    let mut bytes = [0; std::mem::size_of::<$t>()];
+                    bytes.copy_from_slice(&buf[..Self::SIZE]);
+                    Self::from_le_bytes(bytes)
+                }
+            }
+        )*
+    };
+}
+
+impl_packed!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+// usize is always stored as 8 bytes, so files are portable between platforms
+impl Packed for usize {
+    const SIZE: usize = 8;
+
+    fn write_le(self, buf: &mut [u8]) {
+        (self as u64).write_le(buf);
+    }
+
+    fn read_le(buf: &[u8]) -> Self {
+        u64::read_le(buf) as usize
+    }
+}
+
+/// Bytes of stack space for one pair, enough for all the `ID` types implemented here
+const PAIR_BUF: usize = 2 * 16;
+
+/// Returns a buffer for one pair of `ID`s of `size` bytes each, on the stack unless `size` is unusually large.
+fn pair_buf<'a>(stack: &'a mut [u8; PAIR_BUF], heap: &'a mut Vec<u8>, size: usize) -> &'a mut [u8] {
+    if 2 * size <= PAIR_BUF {
+        &mut stack[..2 * size]
+    } else {
+        heap.resize(2 * size, 0);
+        heap
+    }
+}
+
+/// Writes `pairs` to `writer` in the compact binary format.
+pub fn write_pairs<ID: Packed, W: Write>(writer: &mut W, pairs: &[(ID, ID)]) -> io::Result<()> {
+    let (mut stack, mut heap) = ([0; PAIR_BUF], Vec::new());
+    let buf = pair_buf(&mut stack, &mut heap, ID::SIZE);
+    for &(a, b) in pairs {
+        a.write_le(buf);
+        b.write_le(&mut buf[ID::SIZE..]);
+        writer.write_all(buf)?;
+    }
+    Ok(())
+}
+
+/// Reads the next pair in the compact binary format from `reader`.
+/// Returns `Ok(None)` if the reader is exhausted.
+pub fn read_pair<ID: Packed, R: Read>(reader: &mut R) -> io::Result<Option<(ID, ID)>> {
+    let (mut stack, mut heap) = ([0; PAIR_BUF], Vec::new());
+    let buf = pair_buf(&mut stack, &mut heap, ID::SIZE);
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..])? {
+            0 if filled == 0 => return Ok(None),
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => filled += n,
+        }
+    }
+    Ok(Some((ID::read_le(buf), ID::read_le(&buf[ID::SIZE..]))))
+}
+
+static SPILL_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Collects pairs of `ID`s in memory until `threshold` pairs are buffered,
+/// then writes them to a temporary file and starts over. The file is removed when the sink is dropped.
+/// Once writing to the file fails, all further pairs are dropped, see [`SpillSink::pairs`].
+pub struct SpillSink<ID> {
+    memory: Vec<(ID, ID)>,
+    threshold: usize,
+    dir: PathBuf,
+    file: Option<(PathBuf, BufWriter<File>)>,
+    spilled: usize,
+    /// Kind and message of the error that made writing to the file fail, if it did
+    failed: Option<(io::ErrorKind, String)>,
+}
+
+impl<ID: Packed> SpillSink<ID> {
+    /// Creates a sink that keeps at most `threshold` pairs in memory
+    /// and spills to the system's temporary directory.
+    pub fn new(threshold: usize) -> Self {
+        Self::in_dir(threshold, std::env::temp_dir())
+    }
+
+    /// Like [`SpillSink::new`], but spills to a file in `dir`.
+    pub fn in_dir<P: AsRef<Path>>(threshold: usize, dir: P) -> Self {
+        Self {
+            // grows as needed, so a large threshold doesn't reserve memory that may never be used
+            memory: Vec::new(),
+            threshold: threshold.max(1),
+            dir: dir.as_ref().to_path_buf(),
+            file: None,
+            spilled: 0,
+            failed: None,
+        }
+    }
+
+    /// Returns the total number of pairs received.
+    pub fn len(&self) -> usize {
+        self.spilled + self.memory.len()
+    }
+
+    /// Returns `true` if no pairs were received.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of pairs that were written to disk.
+    pub fn spilled(&self) -> usize {
+        self.spilled
+    }
+
+    /// Returns an iterator over all received pairs, spilled ones first.
+    /// Fails if writing to the temporary file failed at any point, on every call, since pairs were lost.
+    pub fn pairs(&mut self) -> io::Result<SpillIter<'_, ID>> {
+        if let Some((kind, message)) = &self.failed {
+            return Err(io::Error::new(
+                *kind,
+                format!("spilling pairs to disk failed: {}", message),
+            ));
+        }
+        let file = match &mut self.file {
+            Some((path, writer)) => {
+                writer.flush()?;
+                Some(BufReader::new(File::open(path)?))
+            }
+            None => None,
+        };
+        Ok(SpillIter {
+            file,
+            memory: self.memory.iter(),
+        })
+    }
+
+    fn spill(&mut self) -> io::Result<()> {
+        while self.file.is_none() {
+            let name = format!(
+                "box_intersect_ze-{}-{}.pairs",
+                std::process::id(),
+                SPILL_COUNTER.fetch_add(1, Ordering::Relaxed)
+            );
+            let path = self.dir.join(name);
+            // never truncate an existing file, e.g. one left behind by an earlier process with the same id
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => self.file = Some((path, BufWriter::new(file))),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        let (_, writer) = self.file.as_mut().unwrap();
+        write_pairs(writer, &self.memory)?;
+        self.spilled += self.memory.len();
+        self.memory.clear();
+        Ok(())
+    }
+}
+
+impl<B, ID: Packed> Sink<B, ID> for SpillSink<ID> {
+    fn report(&mut self, a_id: ID, _a: &B, b_id: ID, _b: &B) {
+        if self.failed.is_some() {
+            return;
+        }
+        self.memory.push((a_id, b_id));
+        if self.memory.len() >= self.threshold {
+            if let Err(err) = self.spill() {
+                self.failed = Some((err.kind(), err.to_string()));
+            }
+        }
+    }
+}
+
+impl<ID> Drop for SpillSink<ID> {
+    fn drop(&mut self) {
+        if let Some((path, mut writer)) = self.file.take() {
+            // close the file first, removing an open file fails on Windows
+            let _ = writer.flush();
+            drop(writer);
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Iterator over the pairs collected by a [`SpillSink`]
+pub struct SpillIter<'a, ID> {
+    file: Option<BufReader<File>>,
+    memory: std::slice::Iter<'a, (ID, ID)>,
+}
+
+impl<ID: Packed> Iterator for SpillIter<'_, ID> {
+    type Item = io::Result<(ID, ID)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(reader) = &mut self.file {
+            match read_pair(reader) {
+                Ok(Some(pair)) => return Some(Ok(pair)),
+                Ok(None) => self.file = None,
+                Err(err) => {
+                    self.file = None;
+                    return Some(Err(err));
+                }
+            }
+        }
+        self.memory.next().map(|&pair| Ok(pair))
+    }
+}
@@ -0,0 +1,166 @@
+//! Splitting an intersection query into independent jobs, for running it on threads or executors
+//! managed by the caller instead of the rayon thread pool used with the `parallel` feature.
+//!
+//! [`split`] cuts dimension 0 into slabs holding about the same number of boxes and creates a [`Job`]
+//! for each slab, with copies of the boxes overlapping it. Boxes overlapping several slabs are copied
+//! into several jobs, but every pair is only reported by the job whose slab contains the low boundary
+//! in dimension 0 of their overlap, so the jobs together report each pair exactly once.
+//! The results of the jobs can be combined with [`merge`].
+//!
+//! # Examples
+//! ```
+//! use box_intersect_ze::boxes::Box2Df32;
+//! use box_intersect_ze::set::BBoxSet;
+//! use box_intersect_ze::split;
+//!
+//! let mut set = BBoxSet::new();
+//! for i in 0..100 {
+//!     let x = i as f32;
+//!     set.push(i, Box2Df32::new([x, 0.0], [x + 1.5, 1.0]));
+//! }
+//! set.sort();
+//!
+//! let jobs = split::split(&set, &set, 4);
+//! let outs: Vec<Vec<(usize, usize)>> = std::thread::scope(|scope| {
+//!     let handles: Vec<_> = jobs
+//!         .iter()
+//!         .map(|job| {
+//!             scope.spawn(move || {
+//!                 let mut out = Vec::new();
+//!                 job.run(&mut out, &mut rand::thread_rng());
+//!                 out
+//!             })
+//!         })
+//!         .collect();
+//!     handles.into_iter().map(|h| h.join().unwrap()).collect()
+//! });
+//! assert_eq!(split::merge(outs).len(), 99);
+//! ```
+
+use crate::boxes::BBox;
+use crate::set::BBoxSet;
+use crate::sink::{Merge, Sink};
+use crate::source::{self, BoxSource};
+use crate::{intersect_ze, HasInfinity, Rng};
+
+/// Part of an intersection query created by [`split`]: the boxes overlapping a slab of dimension 0
+#[derive(Clone, Debug)]
+pub struct Job<B: BBox, ID> {
+    a: BBoxSet<B, ID>,
+    /// `None` if intersections are searched within `a`
+    b: Option<BBoxSet<B, ID>>,
+    lo: B::Num,
+    hi: B::Num,
+}
+
+impl<B, ID> Job<B, ID>
+where
+    B: BBox,
+    B::Num: PartialOrd + HasInfinity,
+    ID: PartialOrd + Copy,
+{
+    /// Reports the pairs of intersecting boxes this job is responsible for to `out`, using [`intersect_ze`].
+    pub fn run<R: Rng>(&self, out: &mut impl Sink<B, ID>, rand: &mut R) {
+        let mut out = Slab {
+            inner: out,
+            lo: self.lo,
+            hi: self.hi,
+        };
+        match &self.b {
+            None => intersect_ze(&self.a, &self.a, &mut out, rand),
+            Some(b) => intersect_ze(&self.a, b, &mut out, rand),
+        }
+    }
+
+    /// Returns the number of boxes copied into this job, an estimate of its cost.
+    pub fn len(&self) -> usize {
+        self.a.len() + self.b.as_ref().map_or(0, |b| b.len())
+    }
+
+    /// Returns `true` if this job has no boxes.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Splits the query for intersections between boxes in `a` and `b` into at most `jobs` independent [`Job`]s.
+/// * `a` and `b` may be either the same or distinct [`BBoxSet`]s (or other [`BoxSource`]s) and must be sorted before calling.
+/// * Fewer jobs are created if many boxes have the same low boundary in dimension 0.
+/// * `jobs` of 0 is treated as 1, so there is always at least one job.
+pub fn split<B, ID>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    jobs: usize,
+) -> Vec<Job<B, ID>>
+where
+    B: BBox,
+    B::Num: PartialOrd + HasInfinity,
+    ID: PartialOrd + Copy,
+{
+    let same = source::same(a, b);
+
+    // boundaries of the slabs, chosen so the larger set is split evenly
+    let mut bounds = vec![B::Num::NINFTY];
+    let (len, lo_at): (usize, &dyn Fn(usize) -> B::Num) = if a.len() >= b.len() {
+        (a.len(), &|idx| a.get(idx).0.lo(0))
+    } else {
+        (b.len(), &|idx| b.get(idx).0.lo(0))
+    };
+    for job in (1..jobs).filter(|_| len > 0) {
+        let bound = lo_at(job * len / jobs);
+        if bound > bounds[bounds.len() - 1] {
+            bounds.push(bound);
+        }
+    }
+    bounds.push(B::Num::INFTY);
+
+    let overlapping = |source: &dyn Fn(usize) -> (B, ID), len, lo, hi| {
+        (0..len)
+            .map(source)
+            .filter(|(bbox, _)| bbox.lo(0) < hi && bbox.hi(0) > lo)
+            .map(|(bbox, id)| (id, bbox))
+            .collect::<BBoxSet<B, ID>>()
+    };
+    bounds
+        .windows(2)
+        .map(|slab| {
+            let (lo, hi) = (slab[0], slab[1]);
+            Job {
+                a: overlapping(&|idx| a.get(idx), a.len(), lo, hi),
+                b: (!same).then(|| overlapping(&|idx| b.get(idx), b.len(), lo, hi)),
+                lo,
+                hi,
+            }
+        })
+        .collect()
+}
+
+/// Combines the sinks `outs` the jobs reported to into one, in the order given.
+pub fn merge<S: Merge + Default>(outs: impl IntoIterator<Item = S>) -> S {
+    let mut merged = S::default();
+    for out in outs {
+        merged.merge(out);
+    }
+    merged
+}
+
+/// A [`Sink`] passing on the pairs whose overlap starts inside the slab `[lo, hi)` in dimension 0
+struct Slab<'a, S, N> {
+    inner: &'a mut S,
+    lo: N,
+    hi: N,
+}
+
+impl<B, ID, S> Sink<B, ID> for Slab<'_, S, B::Num>
+where
+    B: BBox,
+    B::Num: PartialOrd,
+    S: Sink<B, ID>,
+{
+    fn report(&mut self, a_id: ID, a: &B, b_id: ID, b: &B) {
+        let start = if a.lo(0) < b.lo(0) { b.lo(0) } else { a.lo(0) };
+        if start >= self.lo && start < self.hi {
+            self.inner.report(a_id, a, b_id, b);
+        }
+    }
+}
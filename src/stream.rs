@@ -0,0 +1,59 @@
+//! Asynchronous streaming of intersecting pairs, see [`intersect_stream`](crate::intersect_stream).
+//! Requires the `futures` feature.
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+
+use crate::boxes::BBox;
+use crate::iter::IntersectIter;
+use crate::median::{ApproxMedian, MedianStrategy};
+use crate::policy::{HalfOpen, Policy};
+use crate::{HasInfinity, Rng};
+
+/// [`Stream`] of the pairs of `ID`s of intersecting boxes, returned by [`intersect_stream`](crate::intersect_stream).
+/// Wraps an [`IntersectIter`], yielding control back to the executor after every `yield_every` steps
+/// of the traversal of the segment trees, whether or not they produced any pairs, so even a query without
+/// any intersections doesn't hold on to the executor thread. Note that a node that is scanned runs to completion
+/// before the stream can yield, which takes long if either of its sides is large.
+pub struct IntersectStream<B: BBox, ID, R, P = HalfOpen, M = ApproxMedian> {
+    iter: IntersectIter<B, ID, R, P, M>,
+    yield_every: usize,
+    /// Steps left to take before yielding
+    budget: usize,
+}
+
+impl<B: BBox, ID, R, P, M> IntersectStream<B, ID, R, P, M> {
+    /// Creates a stream producing the pairs of `iter`, yielding after every `yield_every` steps of the traversal.
+    pub fn new(iter: IntersectIter<B, ID, R, P, M>, yield_every: usize) -> Self {
+        let yield_every = yield_every.max(1);
+        Self {
+            iter,
+            yield_every,
+            budget: yield_every,
+        }
+    }
+}
+
+impl<B, ID, R, P, M> Stream for IntersectStream<B, ID, R, P, M>
+where
+    B: BBox + Unpin,
+    B::Num: PartialOrd + HasInfinity + Unpin,
+    ID: PartialOrd + Copy + Unpin,
+    R: Rng + Unpin,
+    P: Policy<B::Num> + Unpin,
+    M: MedianStrategy<B::Num> + Unpin,
+{
+    type Item = (ID, ID);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let next = this.iter.next_within(&mut this.budget);
+        if next.is_pending() {
+            this.budget = this.yield_every;
+            cx.waker().wake_by_ref();
+        }
+        next
+    }
+}
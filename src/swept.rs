@@ -0,0 +1,170 @@
+//! Broad phase for continuous collision detection: boxes moving linearly during a timestep
+//! are represented by their swept boxes, covering both their start and end positions.
+//!
+//! # Examples
+//! ```
+//! use box_intersect_ze::boxes::Box2Df32;
+//! use box_intersect_ze::set::BBoxSet;
+//! use box_intersect_ze::swept::{swept_set, ImpactSink};
+//!
+//! let mut boxes = BBoxSet::new();
+//! boxes.push(0, Box2Df32::new([0.0, 0.0], [1.0, 1.0]));
+//! boxes.push(1, Box2Df32::new([4.0, 0.0], [5.0, 1.0]));
+//! // box 0 moves through box 1 during the timestep
+//! let swept = swept_set(&boxes, &[[10.0, 0.0], [0.0, 0.0]]);
+//!
+//! let mut out = ImpactSink::new();
+//! box_intersect_ze::intersect_scan(&swept, &swept, &mut out);
+//! assert_eq!(out.pairs.len(), 1);
+//! let (_, _, enter, exit) = out.pairs[0];
+//! assert_eq!((enter, exit), (0.3, 0.5));
+//! ```
+
+use std::ops::{Add, Div, Sub};
+
+use crate::boxes::{BBox, BoxND};
+use crate::set::BBoxSet;
+use crate::sink::Sink;
+
+/// A box moving by `displacement` during a timestep. Compares as its swept box,
+/// the smallest box containing it at both the start and the end of the timestep.
+#[derive(Clone, Copy, Debug)]
+pub struct Swept<N, const D: usize> {
+    /// The box at the start of the timestep
+    pub bbox: BoxND<N, D>,
+    /// How far the box moves during the timestep
+    pub displacement: [N; D],
+}
+
+impl<N, const D: usize> Swept<N, D> {
+    /// Creates a box moving by `displacement`.
+    pub fn new(bbox: BoxND<N, D>, displacement: [N; D]) -> Self {
+        Self { bbox, displacement }
+    }
+}
+
+impl<N, const D: usize> BBox for Swept<N, D>
+where
+    N: Copy + PartialOrd + Add<Output = N>,
+{
+    const DIM: usize = D;
+    type Num = N;
+
+    fn lo(&self, dim: usize) -> Self::Num {
+        let lo = self.bbox.lo(dim);
+        let moved = lo + self.displacement[dim];
+        if moved < lo {
+            moved
+        } else {
+            lo
+        }
+    }
+
+    fn hi(&self, dim: usize) -> Self::Num {
+        let hi = self.bbox.hi(dim);
+        let moved = hi + self.displacement[dim];
+        if moved > hi {
+            moved
+        } else {
+            hi
+        }
+    }
+}
+
+impl<N, const D: usize> Swept<N, D>
+where
+    N: Copy + PartialOrd + Add<Output = N> + Sub<Output = N> + Div<Output = N> + From<f32>,
+{
+    /// Returns the interval of time, as fractions of the timestep from `0` to `1`, during which
+    /// this box and `other` overlap while moving. The interval is closed, so it includes the
+    /// times at which the boxes only touch. Returns [`None`] if they never do, even if their swept boxes intersect.
+    pub fn time_of_impact(&self, other: &Self) -> Option<(N, N)> {
+        let zero = N::from(0.0);
+        let (mut enter, mut exit) = (zero, N::from(1.0));
+        for dim in 0..D {
+            // move in the frame of reference of `other`
+            let v = self.displacement[dim] - other.displacement[dim];
+            let (a, b) = (&self.bbox, &other.bbox);
+            if v == zero {
+                if a.lo(dim) > b.hi(dim) || b.lo(dim) > a.hi(dim) {
+                    return None;
+                }
+                continue;
+            }
+            let t0 = (b.lo(dim) - a.hi(dim)) / v;
+            let t1 = (b.hi(dim) - a.lo(dim)) / v;
+            let (t0, t1) = if v > zero { (t0, t1) } else { (t1, t0) };
+            if t0 > enter {
+                enter = t0;
+            }
+            if t1 < exit {
+                exit = t1;
+            }
+            if enter > exit {
+                return None;
+            }
+        }
+        Some((enter, exit))
+    }
+}
+
+/// Builds a set of [`Swept`] boxes from `boxes` and the displacement of each box,
+/// in the same order, then sorts it.
+/// # Panics
+/// If there are not as many displacements as boxes.
+pub fn swept_set<N, ID, const D: usize>(
+    boxes: &BBoxSet<BoxND<N, D>, ID>,
+    displacements: &[[N; D]],
+) -> BBoxSet<Swept<N, D>, ID>
+where
+    N: Copy + PartialOrd + Add<Output = N>,
+    ID: Copy + PartialOrd,
+{
+    assert_eq!(boxes.len(), displacements.len());
+    let mut set = BBoxSet::with_capacity(boxes.len());
+    for (&(bbox, id), &displacement) in boxes.boxes().iter().zip(displacements) {
+        set.push(id, Swept::new(bbox, displacement));
+    }
+    set.sort();
+    set
+}
+
+/// A [`Sink`] collecting the pairs of `ID`s of moving boxes that actually touch during the timestep,
+/// along with the interval of time they do, see [`Swept::time_of_impact`].
+/// Pairs whose swept boxes intersect but that never touch are dropped.
+#[derive(Clone, Debug)]
+pub struct ImpactSink<N, ID> {
+    /// Pairs of `ID`s of colliding boxes and the start and end of their contact
+    pub pairs: Vec<(ID, ID, N, N)>,
+}
+
+impl<N, ID> ImpactSink<N, ID> {
+    /// Creates an empty sink.
+    pub fn new() -> Self {
+        Self { pairs: Vec::new() }
+    }
+
+    /// Creates an empty sink with the specified capacity. See [`Vec::with_capacity`].
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            pairs: Vec::with_capacity(capacity),
+        }
+    }
+}
+
+impl<N, ID> Default for ImpactSink<N, ID> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<N, ID, const D: usize> Sink<Swept<N, D>, ID> for ImpactSink<N, ID>
+where
+    N: Copy + PartialOrd + Add<Output = N> + Sub<Output = N> + Div<Output = N> + From<f32>,
+{
+    fn report(&mut self, a_id: ID, a: &Swept<N, D>, b_id: ID, b: &Swept<N, D>) {
+        if let Some((enter, exit)) = a.time_of_impact(b) {
+            self.pairs.push((a_id, b_id, enter, exit));
+        }
+    }
+}
@@ -1,10 +1,65 @@
-use crate::boxes::Box3Df32;
-use crate::intersect_brute_force;
+use crate::binned::{intersect_binned, intersect_binned_with_bin_size};
+use crate::boxes::{
+    BBox, Box2Df32, Box3D, Box3Df32, Box3Df64, Box4Df64, BoxND, Inflated, Interval,
+};
+use crate::broad_phase::{BroadPhase, PairTracker};
+use crate::bvh::{Bvh, Ray, Split};
+use crate::coverage::{max_depth, union_measure};
+use crate::curve::{curve_keys, hilbert, morton, Curve};
+use crate::error::{Input, InputError, Problem};
+use crate::grid::GridBroadPhase;
+use crate::index::StaticIndex;
+use crate::internals::{Params, ScanScratch};
+use crate::interval::IntervalTree;
+use crate::iter::IntersectIter;
+use crate::kdtree::KdTree;
+use crate::median::{ApproxMedian, ExactMedian, Halfway, MedianStrategy};
+use crate::periodic::{periodic_set, PeriodicSink};
+use crate::points::PointSet;
+use crate::policy::{Closed, Degenerate, HalfOpen, Policy, Tolerant};
+use crate::region::HalfSpace;
+use crate::rtree::RTree;
+use crate::sap::SweepAndPrune;
 use crate::set::BBoxSet;
+use crate::sink::{
+    Coincident, CoincidentSink, CompactSink, ContainmentSink, MeasureSink, MinOverlap,
+    MinOverlapSink, OverlapSink, PenetrationSink, Sink, SliceSink,
+};
+use crate::spill::SpillSink;
+use crate::swept::{swept_set, ImpactSink, Swept};
+use crate::{intersect_brute_force, nearest, query_box, query_ray, query_region};
 use once_cell::sync::Lazy;
 use rand::{Rng as OtherRng, SeedableRng};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
 use std::fmt::Debug;
 
+/// Counts the allocations of each thread, so tests can check that code doesn't allocate
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // the thread local may already be gone while the thread exits
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(Cell::get)
+}
+
 fn same<ID: Eq + Copy + Debug>(correct: &Vec<(ID, ID)>, actual: &Vec<(ID, ID)>) -> bool {
     let mut missing = false;
     for el in correct {
@@ -84,7 +139,7 @@ fn test_data() -> TestData {
 #[test]
 fn one_way_scan() {
     let mut res = Vec::<(usize, usize)>::with_capacity(80);
-    crate::internals::one_way_scan(&TEST_DATA.boxes1, &TEST_DATA.boxes1, 2, &mut res);
+    crate::internals::one_way_scan(&TEST_DATA.boxes1, &TEST_DATA.boxes1, 2, &mut res, HalfOpen);
 
     assert!(same(&TEST_DATA.complete, &res));
 }
@@ -92,7 +147,13 @@ fn one_way_scan() {
 #[test]
 fn simulated_one_way_scan() {
     let mut res = Vec::<(usize, usize)>::with_capacity(80);
-    crate::internals::simulated_one_way_scan(&TEST_DATA.boxes1, &TEST_DATA.boxes1, 2, &mut res);
+    crate::internals::simulated_one_way_scan(
+        &TEST_DATA.boxes1,
+        &TEST_DATA.boxes1,
+        2,
+        &mut res,
+        HalfOpen,
+    );
 
     assert!(same(&TEST_DATA.complete, &res));
 }
@@ -100,7 +161,7 @@ fn simulated_one_way_scan() {
 #[test]
 fn two_way_scan() {
     let mut res = Vec::<(usize, usize)>::with_capacity(80);
-    crate::internals::two_way_scan(&TEST_DATA.boxes1, &TEST_DATA.boxes2, &mut res);
+    crate::internals::two_way_scan(&TEST_DATA.boxes1, &TEST_DATA.boxes2, &mut res, HalfOpen);
 
     assert!(same(&TEST_DATA.bipartite, &res));
 }
@@ -143,3 +204,3252 @@ fn box_intersect() {
 
     assert!(same(&TEST_DATA.bipartite, &res));
 }
+
+/// Returns `set` with every box added a second time under a new `ID`,
+/// and every original `ID` offset by `offset`
+fn with_duplicates(set: &BBoxSet<Box3Df32, usize>, offset: usize) -> BBoxSet<Box3Df32, usize> {
+    let mut dup = BBoxSet::with_capacity(2 * set.len());
+    for (idx, &(bbox, id)) in set.boxes().iter().enumerate() {
+        dup.push(offset + id, bbox);
+        dup.push(offset + set.len() + idx, bbox);
+    }
+    dup.sort();
+    dup
+}
+
+#[test]
+fn coincident() {
+    let boxes1 = with_duplicates(&TEST_DATA.boxes1, 0);
+    let boxes2 = with_duplicates(&TEST_DATA.boxes1, 1000);
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(12345);
+
+    for &policy in &[Coincident::Once, Coincident::Both, Coincident::Skip] {
+        for b in [&boxes1, &boxes2] {
+            let mut correct = CoincidentSink::new(Vec::<(usize, usize)>::new(), policy);
+            intersect_brute_force(&boxes1, b, &mut correct);
+            let correct = correct.into_inner();
+
+            let mut res = CoincidentSink::new(Vec::new(), policy);
+            crate::intersect_scan(&boxes1, b, &mut res);
+            assert!(same(&correct, &res.into_inner()));
+
+            let mut res = CoincidentSink::new(Vec::new(), policy);
+            crate::intersect_ze_custom::<_, _, _, 5>(&boxes1, b, &mut res, &mut r);
+            assert!(same(&correct, &res.into_inner()));
+        }
+    }
+}
+
+#[test]
+fn spill() {
+    let mut sink = SpillSink::new(16);
+    crate::intersect_scan(&TEST_DATA.boxes1, &TEST_DATA.boxes1, &mut sink);
+    assert!(sink.spilled() > 0);
+    assert_eq!(sink.len(), TEST_DATA.complete.len());
+
+    let res = sink.pairs().unwrap().collect::<std::io::Result<Vec<_>>>();
+    assert!(same(&TEST_DATA.complete, &res.unwrap()));
+
+    // spills exactly when the threshold is reached
+    let bbox = Box3Df32::new([0.0; 3], [1.0; 3]);
+    let mut sink = SpillSink::new(4);
+    for id in 0..3 {
+        Sink::<Box3Df32, usize>::report(&mut sink, id, &bbox, id, &bbox);
+    }
+    assert_eq!(sink.spilled(), 0);
+    Sink::<Box3Df32, usize>::report(&mut sink, 3, &bbox, 3, &bbox);
+    assert_eq!((sink.spilled(), sink.len()), (4, 4));
+
+    // doesn't reserve the threshold up front
+    let sink = SpillSink::<u64>::new(usize::MAX);
+    assert!(sink.is_empty());
+
+    // a failed write is reported by every call
+    let dir = std::env::temp_dir().join("box_intersect_ze-missing-dir");
+    let mut sink = SpillSink::in_dir(4, dir);
+    crate::intersect_scan(&TEST_DATA.boxes1, &TEST_DATA.boxes1, &mut sink);
+    assert_eq!(sink.spilled(), 0);
+    assert!(sink.pairs().is_err());
+    assert!(sink.pairs().is_err());
+
+    // doesn't overwrite existing files
+    let dir = std::env::temp_dir();
+    let taken: Vec<_> = (0..16)
+        .map(|counter| {
+            let name = format!("box_intersect_ze-{}-{}.pairs", std::process::id(), counter);
+            dir.join(name)
+        })
+        .filter(|path| !path.exists())
+        .collect();
+    for path in &taken {
+        std::fs::write(path, b"keep").unwrap();
+    }
+    let mut sink = SpillSink::in_dir(1, &dir);
+    Sink::<Box3Df32, usize>::report(&mut sink, 1, &bbox, 2, &bbox);
+    let res = sink.pairs().unwrap().collect::<std::io::Result<Vec<_>>>();
+    assert_eq!(res.unwrap(), [(1, 2)]);
+    for path in &taken {
+        assert_eq!(std::fs::read(path).unwrap(), b"keep");
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn slice_sink() {
+    let len = TEST_DATA.complete.len();
+    let mut buf = vec![(0, 0); len + 1];
+
+    let mut sink = SliceSink::new(&mut buf);
+    crate::intersect_scan(&TEST_DATA.boxes1, &TEST_DATA.boxes1, &mut sink);
+    assert_eq!((sink.written(), sink.overflowed()), (len, false));
+    assert!(same(&TEST_DATA.complete, &buf[..len].to_vec()));
+
+    let mut sink = SliceSink::new(&mut buf[..len / 2]);
+    crate::intersect_scan(&TEST_DATA.boxes1, &TEST_DATA.boxes1, &mut sink);
+    assert_eq!((sink.written(), sink.overflowed()), (len / 2, true));
+    assert_eq!(sink.total(), len);
+}
+
+#[test]
+fn scan_without_allocating() {
+    let (a, b) = (&TEST_DATA.boxes1, &TEST_DATA.boxes2);
+    let (len, bipartite_len) = (TEST_DATA.complete.len(), TEST_DATA.bipartite.len());
+    let (mut buf, mut bipartite_buf) = (vec![(0, 0); len], vec![(0, 0); bipartite_len]);
+    let mut scratch = ScanScratch::with_capacity(a.len().max(b.len()));
+
+    let before = allocations();
+    let mut sink = SliceSink::new(&mut buf);
+    crate::intersect_scan_with_scratch(a, a, &mut sink, &mut scratch);
+    assert_eq!(sink.written(), len);
+    let mut sink = SliceSink::new(&mut bipartite_buf);
+    crate::intersect_scan_with_scratch(a, b, &mut sink, &mut scratch);
+    assert_eq!(sink.written(), bipartite_len);
+    assert_eq!(allocations(), before);
+
+    assert!(same(&TEST_DATA.complete, &buf));
+    assert!(same(&TEST_DATA.bipartite, &bipartite_buf));
+}
+
+#[test]
+fn compact_sink() {
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(39);
+    let mut sink = CompactSink::new();
+    crate::intersect_ze(&TEST_DATA.boxes1, &TEST_DATA.boxes2, &mut sink, &mut r);
+    let pairs = sink.pairs.iter().map(|&(a, b)| (a as usize, b as usize));
+    assert!(same(&TEST_DATA.bipartite, &pairs.collect()));
+
+    let mut set = BBoxSet::new();
+    set.push(u32::MAX as usize + 1, Box3Df32::new([0.0; 3], [1.0; 3]));
+    set.push(0, Box3Df32::new([0.0; 3], [1.0; 3]));
+    set.sort();
+    let overflow = std::panic::catch_unwind(move || {
+        crate::intersect_scan(&set, &set, &mut CompactSink::new());
+    });
+    assert!(overflow.is_err());
+}
+
+#[test]
+fn packed() {
+    let mut set = BBoxSet::new();
+    set.push(7, Box3Df32::new([1.0, 2.0, 3.0], [4.0, 5.0, 6.0]));
+    set.push(3, Box3Df32::new([2.0, 0.0, 0.0], [7.0, 8.0, 9.0]));
+    let packed = set.to_packed();
+    assert_eq!(
+        packed,
+        [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 2.0, 0.0, 0.0, 7.0, 8.0, 9.0]
+    );
+    assert_eq!(set.packed_ids(), [7, 3]);
+
+    let mut sink = CompactSink::new();
+    crate::intersect_scan(&set, &set, &mut sink);
+    let mut packed = sink.to_packed();
+    packed.sort();
+    assert_eq!(packed, [3, 7]);
+}
+
+#[cfg(feature = "bench")]
+#[test]
+fn bench_scenarios() {
+    use crate::bench::{run, Algorithm, Scenario};
+
+    let scenarios = [
+        Scenario::Uniform,
+        Scenario::Clustered(4),
+        Scenario::BipartiteLayers,
+        Scenario::IdenticalLows,
+    ];
+    for &scenario in &scenarios {
+        let expected = run(scenario, Algorithm::BruteForce, 500, 1).pairs;
+        assert_ne!(expected, 0);
+        for &algorithm in &[Algorithm::Ze10, Algorithm::Ze, Algorithm::Scan] {
+            assert_eq!(run(scenario, algorithm, 500, 1).pairs, expected);
+        }
+    }
+}
+
+#[test]
+fn iter() {
+    let r = rand_chacha::ChaCha8Rng::seed_from_u64(12345);
+    let params = Params {
+        cutoff: 5,
+        ..Params::default()
+    };
+    let (a, b) = (&TEST_DATA.boxes1, &TEST_DATA.boxes2);
+    let res: Vec<_> =
+        IntersectIter::with_params(a, a, r.clone(), HalfOpen, params, ApproxMedian).collect();
+    assert!(same(&TEST_DATA.complete, &res));
+
+    let res: Vec<_> =
+        IntersectIter::with_params(a, b, r.clone(), HalfOpen, params, ApproxMedian).collect();
+    assert!(same(&TEST_DATA.bipartite, &res));
+
+    // the same pairs in the same order as `intersect_ze_with_params`, with the segment trees limited the same way
+    let limited = [
+        params,
+        Params {
+            max_depth: 2,
+            ..params
+        },
+        Params {
+            density_cutoff: Some(50),
+            ..params
+        },
+    ];
+    for params in limited {
+        let mut expected = Vec::new();
+        let mut r2 = r.clone();
+        crate::intersect_ze_with_params(a, b, &mut expected, &mut r2, HalfOpen, params);
+        let res: Vec<_> =
+            IntersectIter::with_params(a, b, r.clone(), HalfOpen, params, ApproxMedian).collect();
+        assert_eq!(res, expected);
+    }
+    let mut expected = Vec::new();
+    crate::intersect_ze_deterministic(a, b, &mut expected);
+    let res: Vec<_> =
+        IntersectIter::with_params(a, b, r.clone(), HalfOpen, params, ExactMedian).collect();
+    assert!(same(&expected, &res));
+
+    let (id1, id2) = crate::intersect_iter(&TEST_DATA.boxes1, &TEST_DATA.boxes2, r)
+        .next()
+        .unwrap();
+    assert!(TEST_DATA.bipartite.contains(&(id1, id2)) || TEST_DATA.bipartite.contains(&(id2, id1)));
+}
+
+#[cfg(feature = "futures")]
+#[test]
+fn stream() {
+    use futures::StreamExt;
+
+    let r = rand_chacha::ChaCha8Rng::seed_from_u64(12345);
+    let stream = crate::intersect_stream(&TEST_DATA.boxes1, &TEST_DATA.boxes2, r, 10);
+    let res: Vec<_> = futures::executor::block_on(stream.collect());
+    assert!(same(&TEST_DATA.bipartite, &res));
+
+    // yields while walking the tree, even if no pairs are found
+    let mut far = BBoxSet::new();
+    for &(bbox, id) in TEST_DATA.boxes2.boxes() {
+        let (mut min, mut max) = (bbox.min(), bbox.max());
+        min[0] += 1e6;
+        max[0] += 1e6;
+        far.push(id, Box3Df32::new(min, max));
+    }
+    far.sort();
+    let r = rand_chacha::ChaCha8Rng::seed_from_u64(12345);
+    let mut stream = crate::intersect_stream(&TEST_DATA.boxes1, &far, r, 1);
+    let waker = futures::task::noop_waker();
+    let mut cx = std::task::Context::from_waker(&waker);
+    let mut pending = 0;
+    loop {
+        match stream.poll_next_unpin(&mut cx) {
+            std::task::Poll::Pending => pending += 1,
+            std::task::Poll::Ready(next) => {
+                assert_eq!(next, None);
+                break;
+            }
+        }
+    }
+    assert!(pending > 0);
+}
+
+#[test]
+fn overlap_sink() {
+    let mut sink = OverlapSink::new();
+    crate::intersect_scan(&TEST_DATA.boxes1, &TEST_DATA.boxes2, &mut sink);
+
+    let pairs: Vec<_> = sink.pairs.iter().map(|&(a, b, _)| (a, b)).collect();
+    assert!(same(&TEST_DATA.bipartite, &pairs));
+    for &(a, b, overlap) in &sink.pairs {
+        let a = TEST_DATA
+            .boxes1
+            .find(a)
+            .or_else(|| TEST_DATA.boxes2.find(a));
+        let b = TEST_DATA
+            .boxes1
+            .find(b)
+            .or_else(|| TEST_DATA.boxes2.find(b));
+        assert!(overlap.coincides(&a.unwrap().overlap(&b.unwrap())));
+    }
+}
+
+#[test]
+fn measure_sink() {
+    let mut overlaps = OverlapSink::new();
+    crate::intersect_scan(&TEST_DATA.boxes1, &TEST_DATA.boxes1, &mut overlaps);
+    let mut measures = MeasureSink::new();
+    crate::intersect_scan(&TEST_DATA.boxes1, &TEST_DATA.boxes1, &mut measures);
+
+    assert_eq!(overlaps.pairs.len(), measures.pairs.len());
+    for (&(a, b, overlap), &(a2, b2, measure)) in overlaps.pairs.iter().zip(&measures.pairs) {
+        assert_eq!((a, b, overlap.measure()), (a2, b2, measure));
+        assert!(measure > 0.0);
+    }
+}
+
+#[test]
+fn penetration_sink() {
+    let mut sink = PenetrationSink::new();
+    crate::intersect_scan(&TEST_DATA.boxes1, &TEST_DATA.boxes2, &mut sink);
+
+    assert_eq!(sink.pairs.len(), TEST_DATA.bipartite.len());
+    for &(_, _, penetration) in &sink.pairs {
+        let min = penetration.depth[penetration.axis];
+        assert!(min > 0.0 && penetration.depth.iter().all(|&depth| depth >= min));
+    }
+}
+
+#[test]
+fn closed() {
+    let mut touching = BBoxSet::new();
+    touching.push(0, Box3Df32::new([0.0; 3], [1.0; 3]));
+    touching.push(1, Box3Df32::new([1.0, 0.0, 0.0], [2.0, 1.0, 1.0]));
+    let mut res = Vec::new();
+    crate::intersect_brute_force_with_policy(&touching, &touching, &mut res, Closed);
+    assert_eq!(res, vec![(0, 1)]);
+
+    // also for every integer type the boxes may have, e.g. grid cells
+    let mut cells = BBoxSet::new();
+    cells.push(0, BoxND::<u16, 2>::new([0, 0], [4, 4]));
+    cells.push(1, BoxND::<u16, 2>::new([4, 2], [8, 6]));
+    let mut res = Vec::new();
+    crate::intersect_scan_with_policy(&cells, &cells, &mut res, Closed);
+    assert_eq!(res, vec![(0, 1)]);
+
+    // the random boxes have integer coordinates, so many of them touch
+    let (boxes1, boxes2) = (&TEST_DATA.boxes1, &TEST_DATA.boxes2);
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(12345);
+    for b in [boxes1, boxes2] {
+        let mut correct = Vec::new();
+        crate::intersect_brute_force_with_policy(boxes1, b, &mut correct, Closed);
+        let mut half_open = Vec::new();
+        intersect_brute_force(boxes1, b, &mut half_open);
+        assert!(correct.len() > half_open.len());
+
+        let mut res = Vec::new();
+        crate::intersect_scan_with_policy(boxes1, b, &mut res, Closed);
+        assert!(same(&correct, &res));
+
+        let mut res = Vec::new();
+        crate::intersect_ze_with_policy::<_, _, _, 5>(boxes1, b, &mut res, &mut r, Closed);
+        assert!(same(&correct, &res));
+
+        let iter = IntersectIter::with_policy(boxes1, b, r.clone(), Closed);
+        assert!(same(&correct, &iter.collect()));
+    }
+}
+
+#[test]
+fn tolerant() {
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(12345);
+    let policy = Tolerant(0.25);
+
+    // a margin smaller than the grid spacing of the random boxes gives the same results as touching boxes
+    let mut correct = Vec::new();
+    crate::intersect_brute_force_with_policy(
+        &TEST_DATA.boxes1,
+        &TEST_DATA.boxes2,
+        &mut correct,
+        Closed,
+    );
+    let mut res = Vec::new();
+    crate::intersect_brute_force_with_policy(
+        &TEST_DATA.boxes1,
+        &TEST_DATA.boxes2,
+        &mut res,
+        policy,
+    );
+    assert!(same(&correct, &res));
+
+    let mut res = Vec::new();
+    crate::intersect_ze_with_policy::<_, _, _, 5>(
+        &TEST_DATA.boxes1,
+        &TEST_DATA.boxes2,
+        &mut res,
+        &mut r,
+        policy,
+    );
+    assert!(same(&correct, &res));
+}
+
+#[test]
+fn margin() {
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(12345);
+    let mut correct = Vec::new();
+    crate::intersect_brute_force_with_margin(
+        &TEST_DATA.boxes1,
+        &TEST_DATA.boxes1,
+        &mut correct,
+        2.0,
+    );
+    let mut touching = Vec::new();
+    crate::intersect_brute_force_with_policy(
+        &TEST_DATA.boxes1,
+        &TEST_DATA.boxes1,
+        &mut touching,
+        Closed,
+    );
+    assert!(correct.len() > touching.len());
+
+    let mut res = Vec::new();
+    crate::intersect_scan_with_margin(&TEST_DATA.boxes1, &TEST_DATA.boxes1, &mut res, 2.0);
+    assert!(same(&correct, &res));
+
+    let mut res = Vec::new();
+    crate::intersect_ze_with_margin(&TEST_DATA.boxes1, &TEST_DATA.boxes1, &mut res, &mut r, 2.0);
+    assert!(same(&correct, &res));
+}
+
+#[test]
+fn inflated() {
+    // margins of 1.0 for even IDs and 0.0 for odd IDs
+    let mut boxes = BBoxSet::with_capacity(TEST_DATA.boxes1.len());
+    for &(bbox, id) in TEST_DATA.boxes1.boxes() {
+        boxes.push(id, Inflated::new(bbox, (id % 2) as f32));
+    }
+    boxes.sort();
+
+    let mut correct = Vec::new();
+    intersect_brute_force(&boxes, &boxes, &mut correct);
+    assert!(correct.len() > TEST_DATA.complete.len());
+
+    let mut res = Vec::new();
+    crate::intersect_ze_custom::<_, _, _, 5>(
+        &boxes,
+        &boxes,
+        &mut res,
+        &mut rand_chacha::ChaCha8Rng::seed_from_u64(1),
+    );
+    assert!(same(&correct, &res));
+}
+
+#[test]
+fn min_overlap() {
+    let mut measures = MeasureSink::new();
+    intersect_brute_force(&TEST_DATA.boxes1, &TEST_DATA.boxes2, &mut measures);
+    let mut penetrations = PenetrationSink::new();
+    intersect_brute_force(&TEST_DATA.boxes1, &TEST_DATA.boxes2, &mut penetrations);
+
+    let mut res = MinOverlapSink::new(Vec::new(), MinOverlap::Measure(100.0));
+    crate::intersect_scan(&TEST_DATA.boxes1, &TEST_DATA.boxes2, &mut res);
+    let correct = measures
+        .pairs
+        .iter()
+        .filter(|p| p.2 > 100.0)
+        .map(|p| (p.0, p.1));
+    assert!(same(&correct.collect(), &res.into_inner()));
+
+    let mut res = MinOverlapSink::new(Vec::new(), MinOverlap::Extent(3.0));
+    crate::intersect_scan(&TEST_DATA.boxes1, &TEST_DATA.boxes2, &mut res);
+    let correct = penetrations
+        .pairs
+        .iter()
+        .filter(|p| p.2.depth[p.2.axis] > 3.0);
+    assert!(same(
+        &correct.map(|p| (p.0, p.1)).collect(),
+        &res.into_inner()
+    ));
+}
+
+#[test]
+fn containment() {
+    // add a shrunken copy of every tenth box
+    let mut boxes = TEST_DATA.boxes1.clone();
+    for &(bbox, id) in TEST_DATA.boxes1.boxes().iter().step_by(10) {
+        let (mut min, mut max) = ([0.0; 3], [0.0; 3]);
+        for dim in 0..3 {
+            min[dim] = bbox.lo(dim) + 0.25;
+            max[dim] = bbox.hi(dim) - 0.25;
+        }
+        boxes.push(1000 + id, Box3Df32::new(min, max));
+    }
+    boxes.sort();
+    let boxes = &boxes;
+
+    let mut correct = Vec::new();
+    for &(a, a_id) in boxes.boxes() {
+        for &(b, b_id) in boxes.boxes() {
+            if a_id != b_id && a.contains(&b) {
+                correct.push((a_id, b_id));
+            }
+        }
+    }
+    assert_ne!(correct.len(), 0);
+
+    let mut res = ContainmentSink::new(Vec::new());
+    crate::intersect_scan(boxes, boxes, &mut res);
+    let res = res.into_inner();
+    assert_eq!(correct.len(), res.len());
+    assert!(res.iter().all(|pair| correct.contains(pair)));
+
+    // points inside a box, the second one on its boundary, which only `Degenerate` finds,
+    // since they are empty otherwise, see `Policy::is_empty`
+    let mut boxes = BBoxSet::new();
+    boxes.push(0, Box3Df32::new([0.0; 3], [2.0; 3]));
+    boxes.push(1, Box3Df32::new([1.0; 3], [1.0; 3]));
+    boxes.push(2, Box3Df32::new([0.0, 1.0, 1.0], [0.0, 1.0, 1.0]));
+    boxes.sort();
+    let mut res = ContainmentSink::new(Vec::new());
+    crate::intersect_scan(&boxes, &boxes, &mut res);
+    assert_eq!(res.into_inner(), []);
+    let mut res = ContainmentSink::new(Vec::new());
+    crate::intersect_scan_with_policy(&boxes, &boxes, &mut res, Degenerate(HalfOpen));
+    let res = res.into_inner();
+    assert_eq!(res.len(), 2);
+    assert!(res.contains(&(0, 1)) && res.contains(&(0, 2)));
+}
+
+#[test]
+fn degenerate() {
+    // add a point box at the low corner of every fifth box
+    let mut boxes = TEST_DATA.boxes1.clone();
+    for &(bbox, id) in TEST_DATA.boxes1.boxes().iter().step_by(5) {
+        let point = [bbox.lo(0), bbox.lo(1), bbox.lo(2)];
+        boxes.push(1000 + id, Box3Df32::new(point, point));
+    }
+    boxes.sort();
+
+    let policy = Degenerate(HalfOpen);
+    let mut correct = Vec::new();
+    crate::intersect_brute_force_with_policy(&boxes, &boxes, &mut correct, policy);
+    assert!(
+        correct
+            .iter()
+            .filter(|&&(a, b)| a >= 1000 || b >= 1000)
+            .count()
+            >= 30
+    );
+
+    let mut res = Vec::new();
+    crate::intersect_scan_with_policy(&boxes, &boxes, &mut res, policy);
+    assert!(same(&correct, &res));
+
+    let mut res = Vec::new();
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(12345);
+    crate::intersect_ze_with_policy::<_, _, _, 5>(&boxes, &boxes, &mut res, &mut r, policy);
+    assert!(same(&correct, &res));
+}
+
+#[test]
+fn zero_extent() {
+    // boxes with zero extent in one dimension are empty under `HalfOpen`, even inside or on
+    // the boundary of another box
+    let cases = [
+        (
+            Box2Df32::new([4.0, 3.0], [7.0, 6.0]),
+            Box2Df32::new([4.0, 4.0], [4.0, 6.0]),
+        ),
+        (
+            Box2Df32::new([11.0, 16.0], [15.0, 16.0]),
+            Box2Df32::new([8.0, 16.0], [13.0, 21.0]),
+        ),
+    ];
+    for (a, b) in cases {
+        let mut set = BBoxSet::new();
+        set.push(1, a);
+        set.push(0, b);
+        set.sort();
+        let params = Params {
+            cutoff: 1,
+            ..Params::default()
+        };
+        let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(12345);
+        let mut res = Vec::new();
+        intersect_brute_force(&set, &set, &mut res);
+        crate::intersect_scan(&set, &set, &mut res);
+        crate::intersect_ze(&set, &set, &mut res, &mut r);
+        crate::intersect_ze_with_params(&set, &set, &mut res, &mut r, HalfOpen, params);
+        assert_eq!(res, Vec::<(usize, usize)>::new());
+    }
+
+    // flatten every fifth box in one of its dimensions, at its low, middle or high boundary
+    let mut boxes = TEST_DATA.boxes1.clone();
+    for (n, &(bbox, id)) in TEST_DATA.boxes1.boxes().iter().step_by(5).enumerate() {
+        let (mut lo, mut hi) = ([0.0; 3], [0.0; 3]);
+        for dim in 0..3 {
+            (lo[dim], hi[dim]) = (bbox.lo(dim), bbox.hi(dim));
+        }
+        let dim = n % 3;
+        let at = [lo[dim], (lo[dim] + hi[dim]) / 2.0, hi[dim]][n / 3 % 3];
+        (lo[dim], hi[dim]) = (at, at);
+        boxes.push(1000 + id, Box3Df32::new(lo, hi));
+    }
+    boxes.sort();
+    let (a, b) = (&boxes, &TEST_DATA.boxes2);
+
+    for b in [a, b] {
+        let mut correct = Vec::new();
+        intersect_brute_force(a, b, &mut correct);
+        assert!(correct.iter().all(|&(x, y)| x < 1000 && y < 1000));
+
+        let mut res = Vec::new();
+        crate::intersect_scan(a, b, &mut res);
+        assert!(same(&correct, &res));
+
+        let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(12345);
+        let mut res = Vec::new();
+        crate::intersect_ze(a, b, &mut res, &mut r);
+        assert!(same(&correct, &res));
+
+        let params = Params {
+            cutoff: 1,
+            ..Params::default()
+        };
+        let mut res = Vec::new();
+        crate::intersect_ze_with_params(a, b, &mut res, &mut r, HalfOpen, params);
+        assert!(same(&correct, &res));
+
+        let iter = IntersectIter::with_params(a, b, r.clone(), HalfOpen, params, ExactMedian);
+        assert!(same(&correct, &iter.collect()));
+    }
+}
+
+#[test]
+fn points() {
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(4321);
+    let mut points = PointSet::with_capacity(500);
+    for id in 0..500 {
+        let point: [f32; 3] = [0; 3].map(|_| r.gen_range(0..100) as f32);
+        points.push(id, point);
+    }
+    // points on the boundaries of some boxes
+    for &(bbox, id) in TEST_DATA.boxes1.boxes().iter().step_by(10) {
+        points.push(1000 + id, [bbox.lo(0), bbox.lo(1), bbox.lo(2)]);
+        points.push(2000 + id, [bbox.hi(0), bbox.lo(1), bbox.lo(2)]);
+    }
+    points.sort();
+    let boxes = &TEST_DATA.boxes1;
+
+    let mut correct = Vec::new();
+    for &(bbox, b_id) in boxes.boxes() {
+        for &(p, p_id) in points.points() {
+            if (0..3).all(|dim| bbox.contains_in(dim, p[dim])) {
+                correct.push((b_id, p_id));
+            }
+        }
+    }
+    assert!(correct.iter().any(|&(_, p)| (1000..2000).contains(&p)));
+
+    let mut res = Vec::new();
+    crate::intersect_points(boxes, &points, &mut res);
+    correct.sort_unstable();
+    res.sort_unstable();
+    assert_eq!(correct, res);
+
+    let mut count = 0;
+    crate::intersect_points(boxes, &points, &mut |_, _| count += 1);
+    assert_eq!(count, correct.len());
+}
+
+#[test]
+fn swept() {
+    let boxes = &TEST_DATA.boxes1;
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(2468);
+    let displacements: Vec<[f32; 3]> = (0..boxes.len())
+        .map(|_| [0; 3].map(|_| r.gen_range(-10..10) as f32))
+        .collect();
+    let swept = swept_set(boxes, &displacements);
+
+    let mut correct = Vec::new();
+    intersect_brute_force(&swept, &swept, &mut correct);
+    let mut res = Vec::new();
+    crate::intersect_ze_custom::<_, _, _, 5>(&swept, &swept, &mut res, &mut r);
+    assert!(same(&correct, &res));
+
+    let mut impacts = ImpactSink::new();
+    crate::intersect_ze_custom::<_, _, _, 5>(&swept, &swept, &mut impacts, &mut r);
+    assert!(!impacts.pairs.is_empty() && impacts.pairs.len() < correct.len());
+    for &(a_id, b_id, enter, exit) in &impacts.pairs {
+        assert!(0.0 <= enter && enter <= exit && exit <= 1.0);
+        // at the middle of the contact, the moved boxes must overlap
+        let t = (enter + exit) / 2.0;
+        let at = |id| {
+            let s: Swept<f32, 3> = swept.find(id).unwrap();
+            let d = s.displacement.map(|d| d * t);
+            [0, 1, 2].map(|dim| (s.bbox.lo(dim) + d[dim], s.bbox.hi(dim) + d[dim]))
+        };
+        let (a, b) = (at(a_id), at(b_id));
+        assert!((0..3).all(|dim| a[dim].0 <= b[dim].1 && b[dim].0 <= a[dim].1));
+    }
+}
+
+#[test]
+fn periodic() {
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(1357);
+    let mut boxes = BBoxSet::with_capacity(300);
+    for id in 0..300 {
+        let min: [f32; 3] = [0; 3].map(|_| r.gen_range(0..100) as f32);
+        let max = min.map(|lo| lo + r.gen_range(1..20) as f32);
+        boxes.push(id, Box3Df32::new(min, max));
+    }
+    let periods = [Some(100.0), Some(100.0), None];
+
+    let mut correct = Vec::new();
+    for (i, &(a, a_id)) in boxes.boxes().iter().enumerate() {
+        for &(b, b_id) in &boxes.boxes()[i + 1..] {
+            let intersects = (0..3).all(|dim| match periods[dim] {
+                Some(period) => [-period, 0.0, period]
+                    .iter()
+                    .any(|k| a.lo(dim) < b.hi(dim) + k && b.lo(dim) + k < a.hi(dim)),
+                None => a.lo(dim) < b.hi(dim) && b.lo(dim) < a.hi(dim),
+            });
+            if intersects {
+                correct.push((a_id, b_id));
+            }
+        }
+    }
+
+    let set = periodic_set(&boxes, periods);
+    assert!(set.len() > boxes.len());
+    let mut res = Vec::new();
+    crate::intersect_ze_custom::<_, _, _, 5>(&set, &set, &mut PeriodicSink::new(&mut res), &mut r);
+    assert_eq!(correct.len(), res.len());
+    assert!(same(&correct, &res));
+}
+
+#[test]
+fn unbounded() {
+    let inf = f32::INFINITY;
+    let mut boxes = TEST_DATA.boxes1.clone();
+    // infinite ground slab, half-infinite walls and a box covering everything
+    boxes.push(1000, Box3Df32::new([-inf, -inf, 0.0], [inf, inf, 10.0]));
+    boxes.push(1001, Box3Df32::new([-inf, 20.0, -inf], [30.0, 25.0, inf]));
+    boxes.push(1002, Box3Df32::new([40.0, -inf, 0.0], [inf, 60.0, 50.0]));
+    boxes.push(1003, Box3Df32::new([-inf; 3], [inf; 3]));
+    boxes.push(1004, Box3Df32::new([inf; 3], [inf; 3]));
+    for id in 0..300 {
+        boxes.push(
+            1100 + id,
+            Box3Df32::new([-inf, 0.0, 0.0], [id as f32, 1.0, 1.0]),
+        );
+    }
+    boxes.sort();
+
+    // too many pairs for `same`
+    let normalized = |mut pairs: Vec<(usize, usize)>| {
+        for pair in &mut pairs {
+            *pair = (pair.0.min(pair.1), pair.0.max(pair.1));
+        }
+        pairs.sort_unstable();
+        pairs
+    };
+    let mut correct = Vec::new();
+    intersect_brute_force(&boxes, &boxes, &mut correct);
+    let correct = normalized(correct);
+    for cutoff in 0..3 {
+        let mut res = Vec::new();
+        let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(cutoff);
+        match cutoff {
+            0 => crate::intersect_ze_custom::<_, _, _, 1>(&boxes, &boxes, &mut res, &mut r),
+            1 => crate::intersect_ze_custom::<_, _, _, 5>(&boxes, &boxes, &mut res, &mut r),
+            _ => crate::intersect_ze(&boxes, &boxes, &mut res, &mut r),
+        }
+        assert_eq!(correct, normalized(res));
+        let res = crate::intersect_iter(&boxes, &boxes, &mut r).collect();
+        assert_eq!(correct, normalized(res));
+    }
+}
+
+#[test]
+fn four_dimensions() {
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(8642);
+    let mut random_set = |n: usize, start: usize| {
+        let mut set = BBoxSet::with_capacity(n);
+        for id in start..start + n {
+            let min: [f64; 4] = [0; 4].map(|_| r.gen_range(0..200) as f64);
+            let max = min.map(|lo| lo + r.gen_range(1..40) as f64);
+            set.push(id, Box4Df64::new(min, max));
+        }
+        set.sort();
+        set
+    };
+    let a = random_set(2500, 0);
+    let b = random_set(1500, 2500);
+
+    for (a, b) in [(&a, &a), (&a, &b)] {
+        let mut correct = Vec::new();
+        intersect_brute_force(a, b, &mut correct);
+        let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(1);
+        for cutoff in 0..3 {
+            let mut res = Vec::new();
+            match cutoff {
+                0 => crate::intersect_ze_custom::<_, _, _, 1>(a, b, &mut res, &mut r),
+                1 => crate::intersect_ze_custom::<_, _, _, 100>(a, b, &mut res, &mut r),
+                _ => crate::intersect_ze(a, b, &mut res, &mut r),
+            }
+            assert!(same(&correct, &res));
+        }
+    }
+}
+
+#[test]
+fn integers() {
+    // the test data has integer coordinates, so converting it must not change the result
+    fn convert<N: Copy + PartialOrd>(
+        set: &BBoxSet<Box3Df32, usize>,
+        f: impl Fn(f32) -> N,
+    ) -> BBoxSet<Box3D<N>, usize> {
+        let mut converted = BBoxSet::with_capacity(set.len());
+        for &(bbox, id) in set.boxes() {
+            converted.push(id, Box3D::new(bbox.min().map(&f), bbox.max().map(&f)));
+        }
+        converted.sort();
+        converted
+    }
+    let a = &TEST_DATA.boxes1;
+    let b = &TEST_DATA.boxes2;
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(9753);
+
+    let (a_i32, b_i32) = (convert(a, |x| x as i32 - 75), convert(b, |x| x as i32 - 75));
+    let (a_u64, b_u64) = (convert(a, |x| x as u64), convert(b, |x| x as u64));
+
+    let mut res = Vec::new();
+    crate::intersect_ze_custom::<_, _, _, 5>(&a_i32, &a_i32, &mut res, &mut r);
+    assert!(same(&TEST_DATA.complete, &res));
+    let mut res = Vec::new();
+    crate::intersect_ze_custom::<_, _, _, 5>(&a_u64, &b_u64, &mut res, &mut r);
+    assert!(same(&TEST_DATA.bipartite, &res));
+
+    // closed intervals
+    let mut correct = Vec::new();
+    crate::intersect_brute_force_with_policy(a, b, &mut correct, Closed);
+    let mut res = Vec::new();
+    crate::intersect_ze_with_policy::<_, _, _, 5>(&a_i32, &b_i32, &mut res, &mut r, Closed);
+    assert!(same(&correct, &res));
+
+    // bounds at the extremes of the integer range
+    let mut extreme = a_u64.clone();
+    extreme.push(1000, Box3D::new([0; 3], [u64::MAX - 1; 3]));
+    extreme.push(1001, Box3D::new([1; 3], [2; 3]));
+    extreme.sort();
+    let mut correct = Vec::new();
+    intersect_brute_force(&extreme, &extreme, &mut correct);
+    let mut res = Vec::new();
+    crate::intersect_ze_custom::<_, _, _, 1>(&extreme, &extreme, &mut res, &mut r);
+    assert!(same(&correct, &res));
+}
+
+#[cfg(feature = "decimal")]
+#[test]
+fn decimal() {
+    use rust_decimal::Decimal;
+
+    // scale the integer test data down to exact decimals with two fractional digits
+    let convert = |set: &BBoxSet<Box3Df32, usize>| {
+        let mut converted = BBoxSet::with_capacity(set.len());
+        let to_decimal = |x: f32| Decimal::new(x as i64, 2);
+        for &(bbox, id) in set.boxes() {
+            let (min, max) = (bbox.min().map(to_decimal), bbox.max().map(to_decimal));
+            converted.push(id, Box3D::new(min, max));
+        }
+        converted.sort();
+        converted
+    };
+    let (a, b) = (convert(&TEST_DATA.boxes1), convert(&TEST_DATA.boxes2));
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(1111);
+
+    let mut res = Vec::new();
+    crate::intersect_ze_custom::<_, _, _, 5>(&a, &a, &mut res, &mut r);
+    assert!(same(&TEST_DATA.complete, &res));
+    let mut res = Vec::new();
+    crate::intersect_ze_custom::<_, _, _, 5>(&a, &b, &mut res, &mut r);
+    assert!(same(&TEST_DATA.bipartite, &res));
+}
+
+#[cfg(feature = "rational")]
+#[test]
+fn rational() {
+    use num_rational::Ratio;
+
+    // divide the integer test data by 3, which is not exact in floating point
+    let convert = |set: &BBoxSet<Box3Df32, usize>| {
+        let mut converted = BBoxSet::with_capacity(set.len());
+        let to_ratio = |x: f32| Ratio::new(x as i64, 3);
+        for &(bbox, id) in set.boxes() {
+            let (min, max) = (bbox.min().map(to_ratio), bbox.max().map(to_ratio));
+            converted.push(id, Box3D::new(min, max));
+        }
+        converted.sort();
+        converted
+    };
+    let (a, b) = (convert(&TEST_DATA.boxes1), convert(&TEST_DATA.boxes2));
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(2222);
+
+    let mut res = Vec::new();
+    crate::intersect_ze_custom::<_, _, _, 5>(&a, &a, &mut res, &mut r);
+    assert!(same(&TEST_DATA.complete, &res));
+    let mut res = Vec::new();
+    crate::intersect_ze_custom::<_, _, _, 5>(&a, &b, &mut res, &mut r);
+    assert!(same(&TEST_DATA.bipartite, &res));
+}
+
+#[cfg(feature = "units")]
+#[test]
+fn units() {
+    use uom::si::f32::Length;
+    use uom::si::length::{centimeter, meter};
+
+    // the test data in centimeters
+    let convert = |set: &BBoxSet<Box3Df32, usize>| {
+        let mut converted = BBoxSet::with_capacity(set.len());
+        let to_length = Length::new::<centimeter>;
+        for &(bbox, id) in set.boxes() {
+            let (min, max) = (bbox.min().map(to_length), bbox.max().map(to_length));
+            converted.push(id, Box3D::new(min, max));
+        }
+        converted.sort();
+        converted
+    };
+    let (a, b) = (convert(&TEST_DATA.boxes1), convert(&TEST_DATA.boxes2));
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(3333);
+
+    let mut res = Vec::new();
+    crate::intersect_ze_custom::<_, _, _, 5>(&a, &a, &mut res, &mut r);
+    assert!(same(&TEST_DATA.complete, &res));
+    let mut res = Vec::new();
+    crate::intersect_ze_custom::<_, _, _, 5>(&a, &b, &mut res, &mut r);
+    assert!(same(&TEST_DATA.bipartite, &res));
+
+    // policies work with quantities too
+    let mut correct = Vec::new();
+    crate::intersect_brute_force_with_policy(&a, &b, &mut correct, Closed);
+    let mut res = Vec::new();
+    crate::intersect_ze_with_policy::<_, _, _, 5>(&a, &b, &mut res, &mut r, Closed);
+    assert!(same(&correct, &res));
+    let margin = Length::new::<meter>(0.01);
+    let mut correct = Vec::new();
+    crate::intersect_brute_force_with_margin(&a, &b, &mut correct, margin);
+    let mut res = Vec::new();
+    crate::intersect_ze_with_margin(&a, &b, &mut res, &mut r, margin);
+    assert!(same(&correct, &res));
+}
+
+#[test]
+fn floating_origin() {
+    // boxes with sub-meter sizes around a point about as far from the origin as the moon
+    let origin = [3.8e8, -1.2e8, 2.5e7];
+    let mut world = BBoxSet::with_capacity(TEST_DATA.boxes1.len());
+    for &(bbox, id) in TEST_DATA.boxes1.boxes() {
+        let place = |b: [f32; 3]| [0, 1, 2].map(|dim| origin[dim] + b[dim] as f64 / 64.0);
+        world.push(id, Box3Df64::new(place(bbox.min()), place(bbox.max())));
+    }
+    world.sort();
+    let mut correct = Vec::new();
+    intersect_brute_force(&world, &world, &mut correct);
+    assert!(same(&TEST_DATA.complete, &correct));
+
+    // f32 can't even distinguish most of the boxes at this distance
+    let mut naive = BBoxSet::with_capacity(world.len());
+    for &(bbox, id) in world.boxes() {
+        naive.push(
+            id,
+            Box3Df32::new(bbox.min().map(|x| x as f32), bbox.max().map(|x| x as f32)),
+        );
+    }
+    let mut res = Vec::new();
+    intersect_brute_force(&naive, &naive, &mut res);
+    assert!(!same(&correct, &res));
+
+    let rebased = world.relative_to(origin);
+    let mut res = Vec::new();
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(4444);
+    crate::intersect_ze_custom::<_, _, _, 5>(&rebased, &rebased, &mut res, &mut r);
+    assert!(same(&correct, &res));
+}
+
+#[test]
+fn try_intersect() {
+    let a = &TEST_DATA.boxes1;
+    let b = &TEST_DATA.boxes2;
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(5555);
+
+    let mut res = Vec::new();
+    assert_eq!(crate::try_intersect_ze(a, b, &mut res, &mut r), Ok(()));
+    assert!(same(&TEST_DATA.bipartite, &res));
+    let mut res = Vec::new();
+    assert_eq!(crate::try_intersect_scan(a, a, &mut res), Ok(()));
+    assert!(same(&TEST_DATA.complete, &res));
+
+    let error = |input, index, problem| {
+        Err(InputError {
+            input,
+            index,
+            problem,
+        })
+    };
+    let mut unsorted = a.clone();
+    let last = unsorted.len() - 1;
+    unsorted.boxes_mut().swap(0, last);
+    let mut res = Vec::new();
+    assert_eq!(
+        crate::try_intersect_ze(a, &unsorted, &mut res, &mut r),
+        error(Input::B, 1, Problem::Unsorted)
+    );
+    assert!(res.is_empty());
+
+    let mut nan = b.clone();
+    nan.boxes_mut()[7].0 = Box3Df32::new([0.0, f32::NAN, 0.0], [1.0; 3]);
+    assert_eq!(
+        crate::try_intersect_scan(&nan, b, &mut res),
+        error(Input::A, 7, Problem::NaN { dim: 1 })
+    );
+
+    let mut inverted = b.clone();
+    inverted.boxes_mut()[0].0 = Box3Df32::new([0.0, 0.0, 2.0], [1.0; 3]);
+    let result = crate::try_intersect_scan(a, &inverted, &mut res);
+    assert_eq!(result, error(Input::B, 0, Problem::Inverted { dim: 2 }));
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "box 0 of input b is inverted: its low bound is greater than its high bound in dimension 2"
+    );
+    assert!(res.is_empty());
+}
+
+#[test]
+fn validate() {
+    assert!(TEST_DATA.boxes1.validate().is_valid());
+
+    let mut set = TEST_DATA.boxes1.clone();
+    let last = set.len() - 1;
+    set.boxes_mut().swap(0, last);
+    set.boxes_mut()[10].0 = Box3Df32::new([f32::NAN; 3], [1.0; 3]);
+    set.boxes_mut()[20].0 = Box3Df32::new([0.0, 5.0, 0.0], [1.0; 3]);
+    set.boxes_mut()[30].1 = set.boxes()[5].1;
+    set.boxes_mut()[40].1 = set.boxes()[5].1;
+    let report = set.validate();
+    assert!(!report.is_valid());
+    assert_eq!(report.nan, vec![10]);
+    assert_eq!(report.inverted, vec![20]);
+    assert_eq!(report.unsorted[0], 1);
+    assert_eq!(report.duplicate_ids, vec![30, 40]);
+}
+
+#[cfg(feature = "debug-checks")]
+#[test]
+#[should_panic(
+    expected = "`b` passed to `intersect_ze` is not sorted: box 1 has a lower low bound"
+)]
+fn debug_checks() {
+    let mut unsorted = TEST_DATA.boxes2.clone();
+    let last = unsorted.len() - 1;
+    unsorted.boxes_mut().swap(0, last);
+    let mut res = Vec::new();
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(6666);
+    crate::intersect_ze(&TEST_DATA.boxes1, &unsorted, &mut res, &mut r);
+}
+
+#[test]
+fn sort_total() {
+    let mut set = TEST_DATA.boxes1.clone();
+    set.boxes_mut()[3].0 = Box3Df32::new([f32::NAN; 3], [1.0; 3]);
+    set.boxes_mut()[8].0 = Box3Df32::new([-f32::NAN; 3], [1.0; 3]);
+    let (nan_id, neg_nan_id) = (set.boxes()[3].1, set.boxes()[8].1);
+    set.sort_total();
+    assert_eq!(set.boxes()[0].1, neg_nan_id);
+    assert_eq!(set.boxes()[set.len() - 1].1, nan_id);
+    assert_eq!(set.validate().unsorted, vec![]);
+
+    // same order as `sort` without NaN
+    let mut sorted = TEST_DATA.boxes1.clone();
+    let mut sorted_total = sorted.clone();
+    sorted.sort();
+    sorted_total.sort_total();
+    let ids = |set: &BBoxSet<Box3Df32, usize>| set.boxes().iter().map(|b| b.1).collect::<Vec<_>>();
+    assert_eq!(ids(&sorted), ids(&sorted_total));
+}
+
+#[test]
+fn sanitize() {
+    let mut set = TEST_DATA.boxes1.clone();
+    set.boxes_mut()[10].0 = Box3Df32::new([f32::NAN; 3], [1.0; 3]);
+    set.boxes_mut()[20].0 = Box3Df32::new([0.0, 5.0, 0.0], [1.0; 3]);
+    set.boxes_mut()[30].0 = Box3Df32::new([0.0; 3], [1.0, f32::NAN, 1.0]);
+    let invalid: Vec<usize> = [10, 20, 30].iter().map(|&i| set.boxes()[i].1).collect();
+
+    let removed = set.sanitize();
+    assert_eq!(
+        removed.boxes().iter().map(|b| b.1).collect::<Vec<_>>(),
+        invalid
+    );
+    assert_eq!(set.len(), TEST_DATA.boxes1.len() - 3);
+    assert!(set.validate().is_valid());
+    assert!(set.sanitize().is_empty());
+}
+
+#[test]
+fn empty_boxes() {
+    let mut set = TEST_DATA.boxes1.clone();
+    let mut other = TEST_DATA.boxes2.clone();
+    // a box unbounded below in dimension 0, which comes first
+    let unbounded = 1_000_000;
+    set.push(
+        unbounded,
+        Box3Df32::new([f32::NEG_INFINITY, 0.0, 0.0], [10.0; 3]),
+    );
+    set.sort();
+    assert_eq!(set.boxes()[0].1, unbounded);
+    // tombstone every third box, and invert some others in dimension 0
+    for (idx, (bbox, _id)) in set.boxes_mut().iter_mut().enumerate() {
+        if idx % 3 == 0 {
+            *bbox = bbox.emptied();
+        }
+    }
+    // the tombstone of the unbounded box can't keep its low boundary, so it moves to the end
+    assert!(set.boxes()[0].0.is_empty());
+    set.sort();
+    assert_eq!(set.boxes().last().unwrap().1, unbounded);
+    for (idx, (bbox, _id)) in other.boxes_mut().iter_mut().enumerate() {
+        if idx % 4 == 0 {
+            let (mut min, mut max) = (bbox.min(), bbox.max());
+            std::mem::swap(&mut min[0], &mut max[0]);
+            *bbox = Box3Df32::new(min, max);
+        }
+    }
+    other.sort();
+    assert!(set.validate().unsorted.is_empty());
+    let empty: Vec<usize> = (set.boxes().iter().chain(other.boxes()))
+        .filter(|(bbox, _id)| bbox.is_empty())
+        .map(|&(_bbox, id)| id)
+        .collect();
+
+    for (a, b) in [(&set, &set), (&set, &other)] {
+        let mut correct = Vec::new();
+        intersect_brute_force(a, b, &mut correct);
+        assert!(!correct.is_empty());
+        assert!(correct
+            .iter()
+            .all(|(a_id, b_id)| !empty.contains(a_id) && !empty.contains(b_id)));
+        let mut res = Vec::new();
+        crate::intersect_scan(a, b, &mut res);
+        assert!(same(&correct, &res));
+        let mut res = Vec::new();
+        let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(7777);
+        crate::intersect_ze_custom::<_, _, _, 5>(a, b, &mut res, &mut r);
+        assert!(same(&correct, &res));
+    }
+}
+
+#[test]
+fn duplicate_ids() {
+    let mut set = TEST_DATA.boxes1.clone();
+    let (id, first) = (set.boxes()[5].1, set.boxes()[5].0);
+    set.boxes_mut()[30].1 = id;
+    set.boxes_mut()[40].1 = id;
+    assert_eq!(set.duplicate_ids(), vec![(30, 5), (40, 5)]);
+
+    let mut res = Vec::new();
+    assert_eq!(
+        crate::try_intersect_scan(&TEST_DATA.boxes1, &set, &mut res),
+        Err(InputError {
+            input: Input::B,
+            index: 30,
+            problem: Problem::DuplicateId { first: 5 }
+        })
+    );
+
+    let union = first.union(&set.boxes()[30].0).union(&set.boxes()[40].0);
+    assert_eq!(set.merge_duplicate_ids(), 2);
+    assert_eq!(set.len(), TEST_DATA.boxes1.len() - 2);
+    assert!(set.find(id).unwrap().coincides(&union));
+    set.sort();
+    assert!(set.validate().is_valid());
+}
+
+#[test]
+fn deterministic() {
+    // quantized coordinates, so many boxes share low boundaries
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(8888);
+    let mut boxes: Vec<(Box3Df32, usize)> = (0..3000)
+        .map(|id| {
+            let min: [f32; 3] = [0; 3].map(|_| r.gen_range(0..10) as f32);
+            (Box3Df32::new(min, min.map(|lo| lo + 1.0)), id)
+        })
+        .collect();
+
+    let mut results = Vec::new();
+    for _ in 0..2 {
+        // shuffle insertion order
+        for i in (1..boxes.len()).rev() {
+            boxes.swap(i, r.gen_range(0..i + 1));
+        }
+        let mut set: BBoxSet<_, _> = boxes.iter().map(|&(bbox, id)| (id, bbox)).collect();
+        set.sort();
+        let mut ze = Vec::new();
+        let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(1);
+        crate::intersect_ze_custom::<_, _, _, 100>(&set, &set, &mut ze, &mut r);
+        let mut scan = Vec::new();
+        crate::intersect_scan(&set, &set, &mut scan);
+        results.push((ze, scan));
+    }
+    assert_eq!(results[0], results[1]);
+    let (ze, scan) = &results[0];
+    let normalized = |pairs: &Vec<(usize, usize)>| {
+        let mut pairs: Vec<_> = pairs.iter().map(|&(a, b)| (a.min(b), a.max(b))).collect();
+        pairs.sort_unstable();
+        pairs
+    };
+    assert_eq!(normalized(ze), normalized(scan));
+    assert_eq!(normalized(ze).len(), ze.len());
+}
+
+#[cfg(feature = "verify")]
+#[test]
+fn verify() {
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(4242);
+    let mut res = Vec::new();
+    crate::verify::intersect_ze_verified(
+        &TEST_DATA.boxes1,
+        &TEST_DATA.boxes1,
+        &mut res,
+        &mut r,
+        50,
+    );
+    assert!(same(&TEST_DATA.complete, &res));
+
+    let mut res = Vec::new();
+    crate::verify::intersect_scan_verified(
+        &TEST_DATA.boxes1,
+        &TEST_DATA.boxes2,
+        &mut res,
+        &mut r,
+        50,
+    );
+    assert!(same(&TEST_DATA.bipartite, &res));
+}
+
+#[cfg(feature = "verify")]
+#[test]
+fn verify_overlapping_ids() {
+    // distinct sets may use the same identifiers, a0 intersects b1 and a1 intersects b0
+    let mut a = BBoxSet::new();
+    a.push(0, Box2Df32::new([0.0, 0.0], [2.0, 1.0]));
+    a.push(1, Box2Df32::new([4.0, 0.0], [6.0, 1.0]));
+    a.sort();
+    let mut b = BBoxSet::new();
+    b.push(0, Box2Df32::new([5.0, 0.0], [7.0, 1.0]));
+    b.push(1, Box2Df32::new([1.0, 0.0], [3.0, 1.0]));
+    b.sort();
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(4242);
+    let mut res = Vec::new();
+    crate::verify::intersect_ze_verified(&a, &b, &mut res, &mut r, 2);
+    crate::verify::intersect_scan_verified(&a, &b, &mut res, &mut r, 2);
+    res.sort_unstable();
+    assert_eq!(res, vec![(0, 1), (0, 1), (1, 0), (1, 0)]);
+}
+
+#[cfg(feature = "verify")]
+#[test]
+#[should_panic(expected = "intersect_ze reported the pair (0, 1) twice")]
+fn verify_duplicate() {
+    // the identifier 0 is used twice, so its pair with 1 is reported for both boxes
+    let mut set = BBoxSet::new();
+    set.push(0, Box2Df32::new([0.0, 0.0], [2.0, 2.0]));
+    set.push(0, Box2Df32::new([1.0, 1.0], [3.0, 3.0]));
+    set.push(1, Box2Df32::new([0.5, 0.5], [2.5, 2.5]));
+    set.sort();
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(4242);
+    let mut res = Vec::new();
+    crate::verify::intersect_ze_verified(&set, &set, &mut res, &mut r, 3);
+}
+
+#[cfg(feature = "verify")]
+#[test]
+#[should_panic(expected = "intersect_ze missed the pair of box")]
+fn verify_clone() {
+    // a copy of the same set shares its identifiers, so the algorithms skip every box paired with its copy
+    // like a box paired with itself, though they intersect
+    let copy = TEST_DATA.boxes1.clone();
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(4242);
+    let mut res = Vec::new();
+    crate::verify::intersect_ze_verified(&TEST_DATA.boxes1, &copy, &mut res, &mut r, 10);
+}
+
+#[cfg(feature = "verify")]
+#[test]
+#[should_panic(expected = "intersect_scan missed the pair of box")]
+fn verify_unsorted() {
+    // wrongly claims to be sorted, so the algorithms don't notice
+    struct Unsorted(BBoxSet<Box3Df32, usize>);
+    impl crate::source::BoxSource<Box3Df32, usize> for Unsorted {
+        fn len(&self) -> usize {
+            self.0.len()
+        }
+        fn get(&self, idx: usize) -> (Box3Df32, usize) {
+            self.0.boxes()[idx]
+        }
+        fn is_sorted(&self) -> bool {
+            true
+        }
+    }
+    let mut boxes = TEST_DATA.boxes2.clone();
+    boxes.boxes_mut().reverse();
+    let unsorted = Unsorted(boxes);
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(4242);
+    let mut res = Vec::new();
+    crate::verify::intersect_scan_verified(&TEST_DATA.boxes1, &unsorted, &mut res, &mut r, 100);
+}
+
+#[test]
+fn remove() {
+    let mut set = TEST_DATA.boxes1.clone();
+    let removed: Vec<usize> = [5, 17, 42].iter().map(|&i| set.boxes()[i].1).collect();
+    let min = set.boxes()[5].0.min();
+    assert_eq!(set.remove(removed[0]).map(|b| b.min()), Some(min));
+    assert!(set.remove(removed[0]).is_none());
+    assert_eq!(set.remove_at(16).1, removed[1]);
+    assert!(set.validate().is_valid());
+    set.swap_remove(removed[2]).unwrap();
+    assert_eq!(set.len(), TEST_DATA.boxes1.len() - 3);
+    assert!(!set.validate().is_valid());
+    set.sort();
+
+    let mut res = Vec::new();
+    crate::intersect_scan(&set, &set, &mut res);
+    let expected: Vec<_> = TEST_DATA
+        .complete
+        .iter()
+        .filter(|(a, b)| !removed.contains(a) && !removed.contains(b))
+        .cloned()
+        .collect();
+    assert!(same(&expected, &res));
+}
+
+#[test]
+fn update() {
+    let mut set = TEST_DATA.boxes1.clone();
+    let moved: Vec<usize> = [3, 50].iter().map(|&i| set.boxes()[i].1).collect();
+    let mut expected = TEST_DATA.boxes1.clone();
+    expected.boxes_mut()[3].0 = Box3Df32::new([90.0; 3], [95.0; 3]);
+    expected.boxes_mut()[50].0 = Box3Df32::new([-5.0; 3], [1.0; 3]);
+
+    assert!(set.update(moved[0], expected.boxes()[3].0).is_some());
+    assert!(set.update(moved[1], expected.boxes()[50].0).is_some());
+    assert!(set.update(usize::MAX, expected.boxes()[3].0).is_none());
+    assert!(set.validate().is_valid());
+    expected.sort();
+    let ids = |set: &BBoxSet<Box3Df32, usize>| set.boxes().iter().map(|b| b.1).collect::<Vec<_>>();
+    assert_eq!(ids(&set), ids(&expected));
+}
+
+#[test]
+fn retain() {
+    let mut set = TEST_DATA.boxes1.clone();
+    let filtered = set.filter(|&&(bbox, id)| id % 3 != 0 && bbox.lo(1) < 50.0);
+    set.retain(|&id, bbox| id % 3 != 0 && bbox.lo(1) < 50.0);
+    let ids = |set: &BBoxSet<Box3Df32, usize>| set.boxes().iter().map(|b| b.1).collect::<Vec<_>>();
+    assert_eq!(ids(&set), ids(&filtered));
+    assert!(set.len() < TEST_DATA.boxes1.len());
+    assert!(set.validate().is_valid());
+}
+
+#[test]
+fn iterators() {
+    let set: BBoxSet<_, _> = TEST_DATA.boxes1.clone().into_iter().collect();
+    assert_eq!(set.len(), TEST_DATA.boxes1.len());
+
+    let mut extended = BBoxSet::new();
+    extended.extend(
+        (&TEST_DATA.boxes1)
+            .into_iter()
+            .map(|(&id, &bbox)| (id, bbox)),
+    );
+    extended.extend(
+        (&TEST_DATA.boxes2)
+            .into_iter()
+            .map(|(&id, &bbox)| (id, bbox)),
+    );
+    assert_eq!(
+        extended.len(),
+        TEST_DATA.boxes1.len() + TEST_DATA.boxes2.len()
+    );
+    for ((id, bbox), &(expected, expected_id)) in
+        (&extended).into_iter().zip(TEST_DATA.boxes1.boxes())
+    {
+        assert_eq!(*id, expected_id);
+        assert_eq!(bbox.min(), expected.min());
+    }
+
+    let mut res = Vec::new();
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(77);
+    crate::intersect_ze(&set, &set, &mut res, &mut r);
+    assert!(same(&TEST_DATA.complete, &res));
+}
+
+#[test]
+fn set_iter() {
+    let mut set = TEST_DATA.boxes1.clone();
+    for (id, bbox) in set.iter_mut() {
+        if id % 2 == 0 {
+            *bbox = bbox.emptied();
+        }
+    }
+    let empty: Vec<usize> = set
+        .iter()
+        .filter(|(_id, bbox)| bbox.is_empty())
+        .map(|(&id, _bbox)| id)
+        .collect();
+    let even = TEST_DATA.boxes1.iter().filter(|(id, _bbox)| *id % 2 == 0);
+    assert_eq!(empty.len(), even.count());
+    assert!(empty.iter().all(|id| id % 2 == 0));
+    for (idx, &id, _bbox) in set.iter_indexed() {
+        assert_eq!(id, set.boxes()[idx].1);
+    }
+}
+
+#[test]
+fn merge_sorted() {
+    let (a, b) = (&TEST_DATA.boxes1, &TEST_DATA.boxes2);
+    let mut expected = a.clone();
+    expected.extend(b.iter().map(|(&id, &bbox)| (id, bbox)));
+    expected.sort();
+    let ids = |set: &BBoxSet<Box3Df32, usize>| set.boxes().iter().map(|b| b.1).collect::<Vec<_>>();
+
+    assert_eq!(ids(&a.merge_sorted(b)), ids(&expected));
+    assert_eq!(ids(&b.merge_sorted(a)), ids(&expected));
+    let mut merged = a.clone();
+    merged.append_merge(b);
+    assert_eq!(ids(&merged), ids(&expected));
+    merged.append_merge(&BBoxSet::new());
+    assert_eq!(ids(&merged), ids(&expected));
+}
+
+#[test]
+fn bounds() {
+    let mut set = BBoxSet::new();
+    assert!(set.bounds().is_none());
+    set.push(0, Box3Df32::new([0.0, 1.0, 2.0], [3.0, 4.0, 5.0]));
+    set.push(1, Box3Df32::new([-1.0, 2.0, 0.0], [1.0, 6.0, 1.0]));
+    set.push(2, Box3Df32::new([-10.0; 3], [10.0; 3]).emptied());
+    let bounds = set.bounds().unwrap();
+    assert_eq!(bounds.min(), [-1.0, 1.0, 0.0]);
+    assert_eq!(bounds.max(), [3.0, 6.0, 5.0]);
+
+    let bounds = TEST_DATA.boxes1.bounds().unwrap();
+    for (_id, bbox) in &TEST_DATA.boxes1 {
+        assert!(bounds.contains(bbox));
+    }
+}
+
+#[test]
+fn insert_sorted() {
+    let mut set = BBoxSet::new();
+    for (id, bbox) in &TEST_DATA.boxes2 {
+        set.insert_sorted(*id, *bbox);
+    }
+    // ties are broken by identifier
+    set.insert_sorted(usize::MAX, set.boxes()[10].0);
+    set.insert_sorted(0, set.boxes()[10].0);
+    let mut expected = set.clone();
+    expected.sort();
+    let ids = |set: &BBoxSet<Box3Df32, usize>| set.boxes().iter().map(|b| b.1).collect::<Vec<_>>();
+    assert_eq!(ids(&set), ids(&expected));
+}
+
+#[test]
+fn is_sorted() {
+    let mut set = TEST_DATA.boxes1.clone();
+    assert!(set.is_sorted());
+    set.push(usize::MAX, Box3Df32::new([0.0; 3], [1.0; 3]));
+    assert!(!set.is_sorted());
+    set.ensure_sorted();
+    assert!(set.is_sorted());
+    assert_eq!(set.boxes()[0].1, usize::MAX);
+    assert!(BBoxSet::<Box3Df32, usize>::new().is_sorted());
+
+    // modifications that keep the order keep the set known to be sorted
+    let bbox = |lo: f32| Box3Df32::new([lo; 3], [lo + 1.0; 3]);
+    let mut set = BBoxSet::new();
+    for id in 0..10 {
+        set.push(id, bbox(id as f32));
+    }
+    assert!(set.is_sorted());
+    set.insert_sorted(10, bbox(4.5));
+    set.remove(3);
+    set.remove_at(0);
+    set.update(5, bbox(20.0));
+    set.retain(|&id, _bbox| id != 7);
+    set.swap_remove(5);
+    assert!(set.is_sorted());
+    set.swap_remove(1);
+    assert!(!set.is_sorted());
+    set.sort();
+    assert!(set.is_sorted());
+    set.iter_mut().next();
+    assert!(!set.is_sorted());
+    set.clear();
+    assert!(set.is_sorted());
+    let collected: BBoxSet<_, _> = (0..3).rev().map(|id| (id, bbox(id as f32))).collect();
+    assert!(!collected.is_sorted());
+}
+
+#[test]
+#[should_panic(
+    expected = "`b` passed to `intersect_scan` is not sorted: box 1 has a lower low bound"
+)]
+fn unsorted_input() {
+    let mut unsorted = TEST_DATA.boxes2.clone();
+    let last = unsorted.len() - 1;
+    unsorted.boxes_mut().swap(0, last);
+    crate::intersect_scan(&TEST_DATA.boxes1, &unsorted, &mut Vec::new());
+}
+
+#[test]
+#[should_panic(expected = "`set` passed to `query_box` is not sorted")]
+fn unsorted_query_box() {
+    let mut unsorted = TEST_DATA.boxes2.clone();
+    let last = unsorted.len() - 1;
+    unsorted.boxes_mut().swap(0, last);
+    query_box(
+        &unsorted,
+        &Box3Df32::new([0.0; 3], [10.0; 3]),
+        &mut Vec::new(),
+    );
+}
+
+#[test]
+#[allow(deprecated)]
+fn unsorted_through_field() {
+    // modifications through the deprecated field go unnoticed by the set, so it has to be sorted again
+    let mut set = TEST_DATA.boxes2.clone();
+    set.boxes.reverse();
+    assert!(set.is_sorted());
+    set.ensure_sorted();
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(3);
+    let mut res = Vec::new();
+    crate::intersect_ze(&set, &TEST_DATA.boxes1, &mut res, &mut r);
+    assert!(same(&TEST_DATA.bipartite, &res));
+}
+
+#[test]
+fn sort_stable() {
+    // boxes with equal low boundaries and identifiers keep their order
+    let mut set = BBoxSet::new();
+    for i in 0..100 {
+        let lo = (i % 3) as f32;
+        set.push(i % 2, Box3Df32::new([lo; 3], [10.0 + i as f32; 3]));
+    }
+    let expected: Vec<(usize, f32)> = (0..3)
+        .flat_map(|lo| (0..2).map(move |id| (lo, id)))
+        .flat_map(|(lo, id)| {
+            (0..100)
+                .filter(move |i| i % 3 == lo && i % 2 == id)
+                .map(move |i| (id, 10.0 + i as f32))
+        })
+        .collect();
+    set.sort();
+    let actual: Vec<(usize, f32)> = set.iter().map(|(&id, bbox)| (id, bbox.hi(0))).collect();
+    assert_eq!(actual, expected);
+}
+
+#[test]
+fn slices() {
+    // structure of arrays, indexed like the boxes in `boxes1` in reverse, so they have to be sorted
+    let min: Vec<[f32; 3]> = TEST_DATA
+        .boxes1
+        .iter()
+        .rev()
+        .map(|(_id, b)| b.min())
+        .collect();
+    let max: Vec<[f32; 3]> = TEST_DATA
+        .boxes1
+        .iter()
+        .rev()
+        .map(|(_id, b)| b.max())
+        .collect();
+    let ids: Vec<usize> = TEST_DATA.boxes1.iter().rev().map(|(&id, _b)| id).collect();
+    let to_ids = |res: Vec<(usize, usize)>| res.iter().map(|&(a, b)| (ids[a], ids[b])).collect();
+
+    let set = crate::slice::BBoxSlice::new(&min, &max);
+    let mut res = Vec::new();
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(31);
+    crate::intersect_ze_custom::<_, _, _, 5>(&set, &set, &mut res, &mut r);
+    assert!(same(&TEST_DATA.complete, &to_ids(res)));
+
+    let mut res = Vec::new();
+    crate::intersect_scan(&set, &set, &mut res);
+    assert!(same(&TEST_DATA.complete, &to_ids(res)));
+
+    let boxes: Vec<Box3Df32> = TEST_DATA.boxes1.iter().rev().map(|(_id, &b)| b).collect();
+    let set = crate::slice::ref_set(&boxes);
+    let mut res = Vec::new();
+    crate::intersect_scan(&set, &set, &mut res);
+    assert!(same(&TEST_DATA.complete, &to_ids(res)));
+}
+
+/// Component storage of an entity component system, with a sorted index on top
+struct Components {
+    aabbs: Vec<Option<Box3Df32>>,
+    sorted: Vec<usize>,
+}
+
+impl crate::source::BoxSource<Box3Df32, usize> for Components {
+    fn len(&self) -> usize {
+        self.sorted.len()
+    }
+
+    fn get(&self, idx: usize) -> (Box3Df32, usize) {
+        let entity = self.sorted[idx];
+        (self.aabbs[entity].unwrap(), entity)
+    }
+}
+
+#[test]
+fn box_source() {
+    let mut aabbs = vec![None; TEST_DATA.boxes1.len()];
+    for (&id, &bbox) in &TEST_DATA.boxes1 {
+        aabbs[id] = Some(bbox);
+    }
+    let sorted = TEST_DATA.boxes1.iter().map(|(&id, _)| id).collect();
+    let components = Components { aabbs, sorted };
+
+    let mut res = Vec::new();
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(2);
+    crate::intersect_ze_custom::<_, _, _, 5>(&components, &components, &mut res, &mut r);
+    assert!(same(&TEST_DATA.complete, &res));
+
+    let mut res = Vec::new();
+    crate::intersect_scan(&components, &TEST_DATA.boxes2, &mut res);
+    assert!(same(&TEST_DATA.bipartite, &res));
+
+    let mut res = Vec::new();
+    crate::intersect_brute_force(&components, &components, &mut res);
+    assert!(same(&TEST_DATA.complete, &res));
+
+    let res: Vec<_> = crate::intersect_iter(&components, &TEST_DATA.boxes2, r).collect();
+    assert!(same(&TEST_DATA.bipartite, &res));
+}
+
+#[test]
+fn box_source_ref() {
+    // references to sources are sources, and different references to the same source are the same source
+    let boxes: &BBoxSet<Box3Df32, usize> = &TEST_DATA.boxes1;
+    let again = &TEST_DATA.boxes1;
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(3);
+    let mut res = Vec::new();
+    crate::intersect_ze(&boxes, &again, &mut res, &mut r);
+    assert!(same(&TEST_DATA.complete, &res));
+
+    let mut res = Vec::new();
+    crate::intersect_scan(&boxes, &TEST_DATA.boxes1, &mut res);
+    assert!(same(&TEST_DATA.complete, &res));
+
+    let sorted = TEST_DATA.boxes1.clone().into_sorted();
+    let mut res = Vec::new();
+    crate::intersect_brute_force(&sorted, &&*sorted, &mut res);
+    assert!(same(&TEST_DATA.complete, &res));
+}
+
+#[test]
+fn hybrid_indexed() {
+    let (ninfty, infty) = (f32::NEG_INFINITY, f32::INFINITY);
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(8);
+    let (a, b) = (&TEST_DATA.boxes1, &TEST_DATA.boxes2);
+
+    let mut res = Vec::new();
+    crate::internals::hybrid_indexed::<_, _, _, 5>(
+        a, a, ninfty, infty, 2, &mut res, &mut r, HalfOpen,
+    );
+    assert!(same(&TEST_DATA.complete, &res));
+
+    let mut res = Vec::new();
+    crate::internals::hybrid_indexed::<_, _, _, 5>(
+        a, b, ninfty, infty, 2, &mut res, &mut r, HalfOpen,
+    );
+    crate::internals::hybrid_indexed::<_, _, _, 5>(
+        b, a, ninfty, infty, 2, &mut res, &mut r, HalfOpen,
+    );
+    assert!(same(&TEST_DATA.bipartite, &res));
+}
+
+#[test]
+fn hybrid_deep() {
+    // a cutoff of 1 builds the whole segment tree down to single boxes
+    let (ninfty, infty) = (f32::NEG_INFINITY, f32::INFINITY);
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(9);
+    let a = &TEST_DATA.boxes1;
+    let mut res = Vec::new();
+    crate::internals::hybrid::<_, _, _, 1>(a, a, ninfty, infty, 2, &mut res, &mut r, HalfOpen);
+    assert!(same(&TEST_DATA.complete, &res));
+}
+
+#[test]
+fn hybrid_in_place_sorted() {
+    // the scratch buffers are partitioned and merged again in place, leaving them as they were
+    let (ninfty, infty) = (f32::NEG_INFINITY, f32::INFINITY);
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(37);
+    let (a, b) = (&TEST_DATA.boxes1, &TEST_DATA.boxes2);
+    let mut buffers = [a.boxes().to_vec(), b.boxes().to_vec()];
+    let params = crate::internals::Params {
+        cutoff: 2,
+        ..Default::default()
+    };
+    let mut res = Vec::new();
+    crate::internals::hybrid_in_place(
+        &crate::internals::Copies,
+        &mut buffers,
+        ninfty,
+        infty,
+        2,
+        &mut res,
+        &mut r,
+        HalfOpen,
+        params,
+        ApproxMedian,
+        None,
+    );
+    let ids = |boxes: &[(Box3Df32, usize)]| boxes.iter().map(|&(_, id)| id).collect::<Vec<_>>();
+    assert_eq!(ids(&buffers[0]), ids(a.boxes()));
+    assert_eq!(ids(&buffers[1]), ids(b.boxes()));
+}
+
+#[test]
+fn params() {
+    let (ninfty, infty) = (f32::NEG_INFINITY, f32::INFINITY);
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(10);
+    let a = &TEST_DATA.boxes1;
+    for max_depth in 0..4 {
+        let mut res = Vec::new();
+        let params = crate::internals::Params {
+            cutoff: 1,
+            max_depth,
+            density_cutoff: None,
+        };
+        crate::internals::hybrid_with_params(
+            a, a, ninfty, infty, 2, &mut res, &mut r, HalfOpen, params,
+        );
+        assert!(same(&TEST_DATA.complete, &res));
+    }
+
+    for &density_cutoff in &[1, 10, 100] {
+        let mut res = Vec::new();
+        let params = crate::internals::Params {
+            cutoff: 1,
+            density_cutoff: Some(density_cutoff),
+            ..Default::default()
+        };
+        crate::intersect_ze_with_params(a, a, &mut res, &mut r, HalfOpen, params);
+        assert!(same(&TEST_DATA.complete, &res));
+    }
+
+    let mut res = Vec::new();
+    crate::intersect_ze_with_max_depth(a, &TEST_DATA.boxes2, &mut res, &mut r, 0);
+    assert!(same(&TEST_DATA.bipartite, &res));
+}
+
+#[test]
+fn runtime_cutoff() {
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(11);
+    let (a, b) = (&TEST_DATA.boxes1, &TEST_DATA.boxes2);
+    for &cutoff in &[0, 1, 10, 100, 1000] {
+        let mut res = Vec::new();
+        crate::intersect_ze_with_cutoff(a, a, &mut res, &mut r, cutoff);
+        assert!(same(&TEST_DATA.complete, &res));
+
+        let mut res = Vec::new();
+        crate::intersect_ze_with_cutoff(a, b, &mut res, &mut r, cutoff);
+        assert!(same(&TEST_DATA.bipartite, &res));
+    }
+}
+
+#[test]
+fn auto_cutoff() {
+    assert_eq!(crate::auto_cutoff(0), 100);
+    assert_eq!(crate::auto_cutoff(3000), 300);
+    assert_eq!(crate::auto_cutoff(10_000), 1000);
+    assert_eq!(crate::auto_cutoff(1_000_000), 10_000);
+    assert_eq!(crate::auto_cutoff(10_000_000), 30_000);
+}
+
+#[test]
+fn calibrate() {
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(12);
+    let cutoff = crate::calibrate(&TEST_DATA.boxes1, &mut r);
+    assert!(cutoff == 30 || cutoff == 100);
+    let mut res = Vec::new();
+    crate::intersect_ze_with_cutoff(
+        &TEST_DATA.boxes1,
+        &TEST_DATA.boxes1,
+        &mut res,
+        &mut r,
+        cutoff,
+    );
+    assert!(same(&TEST_DATA.complete, &res));
+    assert_eq!(
+        crate::calibrate(&BBoxSet::<Box3Df32, usize>::new(), &mut r),
+        1000
+    );
+}
+
+#[test]
+fn intersect_ze_threaded() {
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(16);
+    let mut res = Vec::new();
+    crate::intersect_ze_threaded(&TEST_DATA.boxes1, &TEST_DATA.boxes2, &mut res, &mut r);
+    assert!(same(&TEST_DATA.bipartite, &res));
+    let mut res = Vec::new();
+    crate::intersect_ze_threaded(&TEST_DATA.boxes1, &TEST_DATA.boxes1, &mut res, &mut r);
+    assert!(same(&TEST_DATA.complete, &res));
+    let mut overlaps = OverlapSink::new();
+    crate::intersect_ze_threaded(&TEST_DATA.boxes1, &TEST_DATA.boxes2, &mut overlaps, &mut r);
+    assert_eq!(overlaps.pairs.len(), TEST_DATA.bipartite.len());
+}
+
+#[cfg(feature = "simd")]
+#[test]
+fn simd_scans() {
+    use crate::internals::{one_way_scan, two_way_scan};
+    use crate::policy::Closed;
+
+    let mut a = random_boxes(1000, 0, 31);
+    let mut b = random_boxes(700, 1000, 32);
+    a.sort();
+    b.sort();
+    let mut expected = Vec::new();
+    let mut res = Vec::new();
+    for max_dim in 0..3 {
+        expected.clear();
+        res.clear();
+        one_way_scan(&a, &b, max_dim, &mut expected, HalfOpen);
+        crate::simd::one_way_scan(&a, &b, max_dim, &mut res, HalfOpen);
+        assert_eq!(expected, res);
+    }
+    expected.clear();
+    res.clear();
+    one_way_scan(&a, &a, 2, &mut expected, Closed);
+    crate::simd::one_way_scan(&a, &a, 2, &mut res, Closed);
+    assert_eq!(expected, res);
+    expected.clear();
+    res.clear();
+    two_way_scan(&a, &b, &mut expected, HalfOpen);
+    crate::simd::two_way_scan(&a, &b, &mut res, HalfOpen);
+    assert_eq!(expected, res);
+    expected.clear();
+    res.clear();
+    two_way_scan(&TEST_DATA.boxes1, &TEST_DATA.boxes2, &mut expected, Closed);
+    crate::simd::two_way_scan(&TEST_DATA.boxes1, &TEST_DATA.boxes2, &mut res, Closed);
+    assert_eq!(expected, res);
+
+    // integer boundaries, with many ties
+    let int_set = |set: &BBoxSet<Box3Df32, usize>| {
+        let mut ints: BBoxSet<crate::boxes::Box2D<i64>, usize> = set
+            .iter()
+            .map(|(&id, b)| {
+                let (min, max) = (b.min(), b.max());
+                let min = [min[0] as i64 / 8, min[1] as i64 / 8];
+                (
+                    id,
+                    crate::boxes::Box2D::new(min, [max[0] as i64 / 8 + 1, max[1] as i64 / 8 + 1]),
+                )
+            })
+            .collect();
+        ints.sort();
+        ints
+    };
+    let (a, b) = (int_set(&a), int_set(&b));
+    expected.clear();
+    res.clear();
+    two_way_scan(&a, &b, &mut expected, HalfOpen);
+    crate::simd::two_way_scan(&a, &b, &mut res, HalfOpen);
+    assert_eq!(expected, res);
+    expected.clear();
+    res.clear();
+    one_way_scan(&a, &a, 1, &mut expected, HalfOpen);
+    crate::simd::one_way_scan(&a, &a, 1, &mut res, HalfOpen);
+    assert_eq!(expected, res);
+}
+
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn x86_kernels() {
+    use crate::internals::{one_way_scan, two_way_scan};
+    use crate::policy::Closed;
+
+    let mut a = random_boxes(1000, 0, 33);
+    let mut b = random_boxes(700, 1000, 34);
+    a.sort();
+    b.sort();
+    for (x, y) in [(&a, &b), (&TEST_DATA.boxes1, &TEST_DATA.boxes2)] {
+        for max_dim in 0..3 {
+            let (mut expected, mut res) = (Vec::new(), Vec::new());
+            one_way_scan(x, y, max_dim, &mut expected, HalfOpen);
+            crate::x86::one_way_scan(x, y, max_dim, &mut res, HalfOpen);
+            assert_eq!(expected, res);
+        }
+        let (mut expected, mut res) = (Vec::new(), Vec::new());
+        two_way_scan(x, y, &mut expected, Closed);
+        crate::x86::two_way_scan(x, y, &mut res, Closed);
+        assert_eq!(expected, res);
+        // the SSE kernel, in case the AVX one was used
+        res.clear();
+        crate::kernel::two_way_scan(x, y, &mut res, Closed, crate::x86::Sse);
+        assert_eq!(expected, res);
+        for (bx, _) in x.boxes().iter().take(50) {
+            for (by, _) in y.boxes().iter() {
+                assert_eq!(bx.intersects(by), crate::x86::intersects(bx, by));
+            }
+        }
+    }
+}
+
+#[test]
+fn run_for() {
+    use std::time::Duration;
+
+    let r = rand_chacha::ChaCha8Rng::seed_from_u64(29);
+    let mut iter = crate::intersect_iter(&TEST_DATA.boxes1, &TEST_DATA.boxes2, r);
+    // start with a partially consumed iterator
+    let mut res: Vec<_> = iter.by_ref().take(3).collect();
+    // the pairs still buffered by the iterator must reach the sink with their boxes
+    let mut sink = OverlapSink::new();
+    let mut calls = 0;
+    while !iter.run_for(Duration::ZERO, &mut sink) {
+        calls += 1;
+    }
+    assert!(calls >= 1);
+    let find = |id| {
+        let found = TEST_DATA.boxes1.find(id);
+        found.or_else(|| TEST_DATA.boxes2.find(id)).unwrap()
+    };
+    for &(a, b, overlap) in &sink.pairs {
+        assert!(overlap.coincides(&find(a).overlap(&find(b))));
+    }
+    res.extend(sink.pairs.iter().map(|&(a, b, _)| (a, b)));
+    assert!(same(&TEST_DATA.bipartite, &res));
+    assert!(iter.run_for(Duration::ZERO, &mut res));
+    assert_eq!(iter.next(), None);
+
+    let r = rand_chacha::ChaCha8Rng::seed_from_u64(30);
+    let mut iter = crate::intersect_iter(&TEST_DATA.boxes1, &TEST_DATA.boxes1, r);
+    let mut res = Vec::new();
+    assert!(iter.run_for(Duration::from_secs(60), &mut res));
+    assert!(same(&TEST_DATA.complete, &res));
+}
+
+#[test]
+fn intersect_quantized() {
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(35);
+    let mut res = Vec::new();
+    crate::quantize::intersect_quantized(&TEST_DATA.boxes1, &TEST_DATA.boxes1, &mut res, &mut r);
+    assert!(same(&TEST_DATA.complete, &res));
+    res.clear();
+    crate::quantize::intersect_quantized(&TEST_DATA.boxes1, &TEST_DATA.boxes2, &mut res, &mut r);
+    assert!(same(&TEST_DATA.bipartite, &res));
+
+    // boxes much smaller than the quantization step, not intersecting each other
+    let mut set = BBoxSet::new();
+    for i in 0..100 {
+        let x = 1.0 + i as f64 * 2e-9;
+        set.push(i, Box3Df64::new([x, 0.0, 0.0], [x + 1e-9, 1.0, 1.0]));
+    }
+    set.push(100, Box3Df64::new([0.0, 0.0, 0.0], [1e6, 2.0, 2.0]));
+    set.push(
+        101,
+        Box3Df64::new([1.0, 0.5, 0.5], [1.0, 0.5, 0.5]).emptied(),
+    );
+    set.sort();
+    let mut expected = Vec::new();
+    intersect_brute_force(&set, &set, &mut expected);
+    res.clear();
+    crate::quantize::intersect_quantized(&set, &set, &mut res, &mut r);
+    assert_eq!(expected.len(), 100);
+    assert!(same(&expected, &res));
+}
+
+#[test]
+fn intersect_f32() {
+    // boxes far from the origin, touching and nearly touching closer than `f32` can resolve
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(40);
+    let boxes = |start: usize, r: &mut rand_chacha::ChaCha8Rng| {
+        let mut set = BBoxSet::new();
+        for id in start..start + 300 {
+            let mut lo = [0.0; 3];
+            let mut hi = [0.0; 3];
+            for dim in 0..3 {
+                lo[dim] = 1e3 + r.gen_range(0..20) as f64 * 1e-3;
+                hi[dim] = lo[dim] + 5e-3 + r.gen_range(0..3) as f64 * 1e-11;
+            }
+            set.push(id, Box3Df64::new(lo, hi));
+        }
+        set.sort();
+        set
+    };
+    let (a, b) = (boxes(0, &mut r), boxes(300, &mut r));
+    for (x, y) in [(&a, &a), (&a, &b)] {
+        let mut expected = Vec::new();
+        intersect_brute_force(x, y, &mut expected);
+        let mut res = Vec::new();
+        crate::quantize::intersect_f32(x, y, &mut res, &mut r);
+        assert!(same(&expected, &res));
+    }
+}
+
+#[test]
+fn split() {
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(21);
+    let mut boxes1 = TEST_DATA.boxes1.clone();
+    let mut boxes2 = TEST_DATA.boxes2.clone();
+    boxes1.sort();
+    boxes2.sort();
+    for jobs in [0, 1, 3, 8, 1000] {
+        let run = |jobs: Vec<crate::split::Job<Box3Df32, usize>>| {
+            let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(22);
+            let outs = jobs.iter().map(|job| {
+                let mut out = Vec::new();
+                job.run(&mut out, &mut r);
+                out
+            });
+            crate::split::merge(outs.collect::<Vec<_>>())
+        };
+        let res = run(crate::split::split(&boxes1, &boxes1, jobs));
+        assert!(same(&TEST_DATA.complete, &res));
+        let res = run(crate::split::split(&boxes1, &boxes2, jobs));
+        assert!(same(&TEST_DATA.bipartite, &res));
+    }
+    assert_eq!(crate::split::split(&boxes1, &boxes2, 0).len(), 1);
+    let empty = BBoxSet::<Box3Df32, usize>::new();
+    let jobs = crate::split::split(&empty, &empty, 4);
+    let mut res = Vec::new();
+    jobs.iter().for_each(|job| job.run(&mut res, &mut r));
+    assert!(res.is_empty());
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn intersect_ze_par() {
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(13);
+    let mut a = random_boxes(30_000, 0, 14);
+    let mut b = random_boxes(30_000, 30_000, 15);
+    a.sort();
+    b.sort();
+    for (x, y) in [(&a, &a), (&a, &b)] {
+        let mut expected = Vec::new();
+        crate::intersect_ze(x, y, &mut expected, &mut r);
+        let mut res = Vec::new();
+        crate::parallel::intersect_ze_par(x, y, &mut res, &mut r);
+        assert!(same(&expected, &res));
+        let mut overlaps = OverlapSink::new();
+        crate::parallel::intersect_ze_par(x, y, &mut overlaps, &mut r);
+        let res = overlaps.pairs.iter().map(|&(a, b, _)| (a, b)).collect();
+        assert!(same(&expected, &res));
+    }
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn par_approx_median() {
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(27);
+    let samples: Vec<f32> = (0..3usize.pow(8)).map(|_| r.gen()).collect();
+    assert_eq!(
+        crate::median::approx_median(&samples, 8),
+        crate::median::par_approx_median(&samples, 8)
+    );
+    for n in [10, 1000, 20_000] {
+        let set = random_boxes(n, 0, 25);
+        let mut r1 = rand_chacha::ChaCha8Rng::seed_from_u64(26);
+        let mut r2 = r1.clone();
+        for dim in 0..3 {
+            assert_eq!(
+                crate::source::approx_median(&set, dim, &mut r1),
+                crate::source::par_approx_median(&set, dim, &mut r2)
+            );
+        }
+    }
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn intersect_brute_force_par() {
+    let a = random_boxes(1000, 0, 23);
+    let b = random_boxes(700, 1000, 24);
+    for (x, y) in [(&a, &a), (&a, &b)] {
+        let mut expected = Vec::new();
+        intersect_brute_force(x, y, &mut expected);
+        let mut res = Vec::new();
+        crate::parallel::intersect_brute_force_par(x, y, &mut res);
+        assert_eq!(expected.len(), res.len());
+        assert!(same(&expected, &res));
+    }
+    let mut res = Vec::new();
+    crate::parallel::intersect_brute_force_par(&TEST_DATA.boxes1, &TEST_DATA.boxes1, &mut res);
+    assert!(same(&TEST_DATA.complete, &res));
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn parallel_config() {
+    use crate::parallel::ParallelConfig;
+
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(28);
+    let params = Params {
+        cutoff: 10,
+        ..Params::default()
+    };
+    let configs = [
+        ParallelConfig::default(),
+        ParallelConfig {
+            threads: Some(2),
+            sequential_below: 0,
+            ..ParallelConfig::default()
+        },
+        ParallelConfig {
+            max_task_depth: 0,
+            sequential_below: 0,
+            ..ParallelConfig::default()
+        },
+        ParallelConfig {
+            threads: Some(1),
+            max_task_depth: 2,
+            sequential_below: 0,
+        },
+    ];
+    for config in configs {
+        for (b, expected) in [
+            (&TEST_DATA.boxes1, &TEST_DATA.complete),
+            (&TEST_DATA.boxes2, &TEST_DATA.bipartite),
+        ] {
+            let mut res = Vec::new();
+            crate::parallel::intersect_ze_par_with_config(
+                &TEST_DATA.boxes1,
+                b,
+                &mut res,
+                &mut r,
+                HalfOpen,
+                params,
+                config,
+            );
+            assert!(same(expected, &res));
+        }
+    }
+    let config = ParallelConfig {
+        threads: Some(3),
+        ..ParallelConfig::default()
+    };
+    assert_eq!(config.install(rayon::current_num_threads), 3);
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn intersect_ze_par_with_median() {
+    use crate::parallel::ParallelConfig;
+
+    fn check<M: MedianStrategy<f32> + Send + Sync>(median: M) {
+        let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(29);
+        let config = ParallelConfig {
+            sequential_below: 0,
+            ..ParallelConfig::default()
+        };
+        for density_cutoff in [None, Some(4)] {
+            let params = Params {
+                cutoff: 10,
+                density_cutoff,
+                ..Params::default()
+            };
+            for (b, expected) in [
+                (&TEST_DATA.boxes1, &TEST_DATA.complete),
+                (&TEST_DATA.boxes2, &TEST_DATA.bipartite),
+            ] {
+                let mut res = Vec::new();
+                crate::parallel::intersect_ze_par_with_median(
+                    &TEST_DATA.boxes1,
+                    b,
+                    &mut res,
+                    &mut r,
+                    HalfOpen,
+                    params,
+                    median,
+                    config,
+                );
+                assert!(same(expected, &res));
+            }
+        }
+    }
+    check(ApproxMedian);
+    check(ExactMedian);
+    check(Halfway);
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn intersect_ze_par_canonical() {
+    let mut a = random_boxes(15_000, 0, 18);
+    let mut b = random_boxes(15_000, 15_000, 19);
+    a.sort();
+    b.sort();
+    for (x, y) in [(&a, &a), (&a, &b)] {
+        // the sequential output in its own order, with the same random number generator
+        let rand = || rand_chacha::ChaCha8Rng::seed_from_u64(20);
+        let mut sequential = Vec::new();
+        crate::intersect_ze(x, y, &mut sequential, &mut rand());
+        let mut expected = sequential.clone();
+        crate::parallel::sort_pairs(&mut expected);
+        for threads in [1, 4] {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            let mut res = Vec::new();
+            pool.install(|| {
+                crate::parallel::intersect_ze_par_canonical(x, y, &mut res, &mut rand())
+            });
+            assert!(same(&sequential, &res));
+            assert_eq!(expected, res);
+        }
+    }
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn par_sort() {
+    let mut expected = TEST_DATA.boxes1.clone();
+    expected.sort();
+    let mut set = TEST_DATA.boxes1.clone();
+    set.par_sort();
+    let ids = |s: &BBoxSet<Box3Df32, usize>| s.iter().map(|(&id, _b)| id).collect::<Vec<_>>();
+    assert_eq!(ids(&set), ids(&expected));
+}
+
+#[test]
+fn resort_incremental() {
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(17);
+    let mut set = TEST_DATA.boxes1.clone();
+    set.sort();
+    let ids = |s: &BBoxSet<Box3Df32, usize>| s.iter().map(|(&id, _b)| id).collect::<Vec<_>>();
+
+    // boxes shifted slightly since the last sort
+    for (_id, bbox) in set.iter_mut() {
+        let shift = r.gen_range(-2.0..2.0);
+        *bbox = Box3Df32::new(bbox.min().map(|x| x + shift), bbox.max().map(|x| x + shift));
+    }
+    let mut expected = set.clone();
+    expected.sort();
+    set.resort_incremental();
+    assert_eq!(ids(&set), ids(&expected));
+
+    // too many boxes out of order
+    set.boxes_mut().reverse();
+    set.resort_incremental();
+    assert_eq!(ids(&set), ids(&expected));
+}
+
+#[test]
+fn sorted_set() {
+    let mut unsorted = TEST_DATA.boxes2.clone();
+    unsorted.boxes_mut().reverse();
+    let mut set = unsorted.into_sorted();
+    assert!(set.is_sorted());
+
+    set.insert(usize::MAX, Box3Df32::new([0.0; 3], [1.0; 3]));
+    let (bbox, id) = set.boxes()[10];
+    set.update(id, Box3Df32::new(bbox.max(), bbox.max().map(|x| x + 1.0)));
+    set.remove(set.boxes()[20].1);
+    assert!(set.is_sorted());
+    // the algorithms trust `is_sorted`, so the boxes must really be in order
+    assert!(set.boxes().windows(2).all(|w| w[0].0.lo(0) <= w[1].0.lo(0)));
+
+    let mut correct = Vec::new();
+    intersect_brute_force(&*set, &TEST_DATA.boxes1, &mut correct);
+    let mut res = Vec::new();
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(7);
+    crate::intersect_ze(&set, &TEST_DATA.boxes1, &mut res, &mut r);
+    assert!(same(&correct, &res));
+
+    let other = TEST_DATA.boxes1.clone().into_sorted();
+    let mut res = Vec::new();
+    set.intersect_ze(&other, &mut res, &mut r);
+    assert!(same(&correct, &res));
+    let mut res = Vec::new();
+    set.intersect_scan(&other, &mut res);
+    assert!(same(&correct, &res));
+    let mut correct = Vec::new();
+    intersect_brute_force(&*set, &*set, &mut correct);
+    let mut res = Vec::new();
+    set.intersect_ze(&set, &mut res, &mut r);
+    assert!(same(&correct, &res));
+}
+
+#[test]
+fn intersects_each() {
+    let others: Vec<Box3Df32> = TEST_DATA.boxes2.boxes().iter().map(|&(b, _)| b).collect();
+    let mut hits = vec![0; others.len().div_ceil(64)];
+    for &(bbox, _) in &TEST_DATA.boxes1.boxes()[..50] {
+        hits.fill(u64::MAX);
+        bbox.intersects_each(&others, &mut hits);
+        for (idx, other) in others.iter().enumerate() {
+            assert_eq!(
+                hits[idx / 64] >> (idx % 64) & 1 == 1,
+                bbox.intersects(other)
+            );
+        }
+        // bits without a box are cleared
+        assert_eq!(hits.last().unwrap() >> (others.len() % 64), 0);
+
+        Closed.intersects_each(&bbox, &others, &mut hits);
+        for (idx, other) in others.iter().enumerate() {
+            assert_eq!(
+                hits[idx / 64] >> (idx % 64) & 1 == 1,
+                Closed.intersects(&bbox, other)
+            );
+        }
+    }
+}
+
+#[test]
+fn scans_flat() {
+    // boxes that all overlap in dimension 1, and mostly in dimension 3, so the scans check dimension 2 first
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(36);
+    let mut flat = |start: usize| {
+        let mut set = BBoxSet::new();
+        for id in start..start + 300 {
+            let lo = [
+                r.gen_range(0.0..100.0),
+                r.gen_range(0.0..1.0),
+                r.gen_range(0.0..100.0),
+                r.gen_range(0.0..10.0),
+            ];
+            let hi = [lo[0] + 10.0, lo[1] + 5.0, lo[2] + 10.0, lo[3] + 5.0];
+            set.push(id, Box4Df64::new(lo, hi));
+        }
+        set.sort();
+        set
+    };
+    let (a, b) = (flat(0), flat(300));
+    let mut expected = Vec::new();
+    intersect_brute_force(&a, &b, &mut expected);
+    let mut res = Vec::new();
+    crate::internals::two_way_scan(&a, &b, &mut res, HalfOpen);
+    assert!(same(&expected, &res));
+
+    let mut expected = Vec::new();
+    intersect_brute_force(&a, &a, &mut expected);
+    res.clear();
+    crate::internals::one_way_scan(&a, &a, 3, &mut res, HalfOpen);
+    assert!(same(&expected, &res));
+    res.clear();
+    crate::internals::simulated_one_way_scan(&a, &a, 3, &mut res, HalfOpen);
+    assert!(same(&expected, &res));
+
+    // the segment trees check the dimensions in the order computed once for the whole query
+    res.clear();
+    crate::intersect_ze_with_cutoff(&a, &a, &mut res, &mut r, 8);
+    assert!(same(&expected, &res));
+    let mut expected = Vec::new();
+    intersect_brute_force(&a, &b, &mut expected);
+    res.clear();
+    crate::intersect_ze_with_cutoff(&a, &b, &mut res, &mut r, 8);
+    assert!(same(&expected, &res));
+}
+
+#[test]
+fn overlapping_dims() {
+    // a thin layer of boxes that all overlap in dimension 2, and with `flat`, also in dimension 1
+    let layer = |start: usize, flat: bool| {
+        let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(37 + start as u64);
+        let mut set = BBoxSet::new();
+        for id in start..start + 300 {
+            let x = r.gen_range(0.0..1000.0);
+            let y = if flat { 0.0 } else { r.gen_range(0.0..1000.0) };
+            let z = r.gen_range(0.0..1.0);
+            set.push(id, Box3Df32::new([x, y, z], [x + 10.0, y + 10.0, z + 2.0]));
+        }
+        set.sort();
+        set
+    };
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(38);
+    for flat in [false, true] {
+        let (a, b) = (layer(0, flat), layer(300, flat));
+        for (x, y) in [(&a, &a), (&a, &b)] {
+            let mut expected = Vec::new();
+            intersect_brute_force(x, y, &mut expected);
+            let mut res = Vec::new();
+            crate::intersect_ze(x, y, &mut res, &mut r);
+            assert!(same(&expected, &res));
+            let mut res: Vec<_> = IntersectIter::new(x, y, r.clone()).collect();
+            assert!(same(&expected, &res));
+            res.clear();
+            crate::intersect_scan(x, y, &mut res);
+            assert!(same(&expected, &res));
+        }
+    }
+}
+
+#[test]
+fn overlapping_dims_degenerate() {
+    // box 1 is flat in dimension 1, so it intersects nothing, though every box starts below the end of `big`
+    // and `big` starts below the end of every box; the check of the order only samples other boxes
+    let mut a = BBoxSet::new();
+    for id in 0..100 {
+        let (x, hi) = (id as f32, if id == 1 { 5.0 } else { 6.0 });
+        a.push(id, Box2Df32::new([x, 5.0], [x + 1.0, hi]));
+    }
+    a.sort();
+    let mut b = BBoxSet::new();
+    b.push(1000, Box2Df32::new([-1.0, 0.0], [200.0, 10.0]));
+    b.sort();
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(39);
+    let mut expected = Vec::new();
+    intersect_brute_force(&a, &b, &mut expected);
+    assert_eq!(expected.len(), 99);
+    assert!(!expected.contains(&(1, 1000)));
+    let mut res = Vec::new();
+    crate::intersect_scan(&a, &b, &mut res);
+    assert!(same(&expected, &res));
+    res.clear();
+    crate::intersect_ze(&a, &b, &mut res, &mut r);
+    assert!(same(&expected, &res));
+}
+
+#[test]
+fn sweep_and_prune() {
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(41);
+    let mut sap = SweepAndPrune::new();
+    let mut boxes = BBoxSet::new();
+    let random_box = |r: &mut rand_chacha::ChaCha8Rng| {
+        let lo = [r.gen_range(0..100) as f32, r.gen_range(0..100) as f32, 0.0];
+        let hi = [lo[0] + r.gen_range(0..10) as f32, lo[1] + 10.0, 1.0];
+        Box3Df32::new(lo, hi)
+    };
+    for id in 0..200 {
+        let bbox = random_box(&mut r);
+        sap.insert(id, bbox);
+        boxes.push(id, bbox);
+    }
+    for frame in 0..20 {
+        for (id, bbox) in &mut boxes {
+            // most boxes move a little, some jump or become empty
+            let step = [r.gen_range(-2..3) as f32, r.gen_range(-2..3) as f32, 0.0];
+            *bbox = match r.gen_range(0..20) {
+                0 => random_box(&mut r),
+                1 => bbox.emptied(),
+                _ => Box3Df32::new(
+                    [0, 1, 2].map(|dim| bbox.min()[dim] + step[dim]),
+                    [0, 1, 2].map(|dim| bbox.max()[dim].max(bbox.min()[dim]) + step[dim]),
+                ),
+            };
+            sap.update(*id, *bbox);
+        }
+        if frame % 5 == 0 {
+            let (_, id) = boxes.remove_at(r.gen_range(0..boxes.len()));
+            assert!(sap.remove(id).is_some());
+            let bbox = random_box(&mut r);
+            sap.insert(1000 + frame, bbox);
+            boxes.push(1000 + frame, bbox);
+        }
+        boxes.sort();
+        let mut expected = Vec::new();
+        intersect_brute_force(&boxes, &boxes, &mut expected);
+        assert!(same(&expected, &sap.pairs().collect()));
+        assert_eq!(sap.len(), boxes.len());
+    }
+}
+
+#[test]
+fn broad_phase() {
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(42);
+    let mut broad_phase = BroadPhase::new(rand_chacha::ChaCha8Rng::seed_from_u64(43));
+    let random_box = |r: &mut rand_chacha::ChaCha8Rng| {
+        let lo = [r.gen_range(0.0..100.0), r.gen_range(0.0..100.0)];
+        Box2Df32::new(lo, [lo[0] + 8.0, lo[1] + 8.0])
+    };
+    let mut ids: Vec<usize> = (0..100).collect();
+    for &id in &ids {
+        broad_phase.insert(id, random_box(&mut r));
+    }
+    let mut previous: Vec<(usize, usize)> = Vec::new();
+    for frame in 0..10 {
+        for &id in &ids {
+            if r.gen_range(0..3) == 0 {
+                assert!(broad_phase.update(id, random_box(&mut r)).is_some());
+            }
+        }
+        let removed = ids.swap_remove(r.gen_range(0..ids.len()));
+        assert!(broad_phase.remove(removed).is_some());
+        assert!(broad_phase.remove(removed).is_none());
+        ids.push(100 + frame);
+        broad_phase.insert(100 + frame, random_box(&mut r));
+
+        let delta = broad_phase.step();
+        let mut boxes = BBoxSet::new();
+        for &id in &ids {
+            boxes.push(id, broad_phase.get(id).unwrap());
+        }
+        boxes.sort();
+        let mut current = Vec::new();
+        intersect_brute_force(&boxes, &boxes, &mut current);
+        let current: Vec<_> = current.iter().map(|&(a, b)| (a.min(b), a.max(b))).collect();
+        assert!(same(&current, &broad_phase.pairs().to_vec()));
+        let added = current.iter().filter(|p| !previous.contains(p));
+        let removed = previous.iter().filter(|p| !current.contains(p));
+        let persisted = previous.iter().filter(|p| current.contains(p));
+        assert!(same(&added.copied().collect(), &delta.added));
+        assert!(same(&removed.copied().collect(), &delta.removed));
+        assert!(same(&persisted.copied().collect(), &delta.persisted));
+        assert!(delta.added.windows(2).all(|pair| pair[0] < pair[1]));
+        previous = current;
+    }
+    assert_eq!(broad_phase.len(), ids.len());
+}
+
+#[test]
+fn pair_tracker() {
+    let mut tracker = PairTracker::new();
+    let delta = tracker.update(vec![(2, 1), (3, 4), (1, 2)]);
+    assert_eq!(delta.added, [(1, 2), (3, 4)]);
+    assert!(delta.removed.is_empty() && delta.persisted.is_empty());
+    let delta = tracker.update(vec![(4, 3), (5, 0)]);
+    assert_eq!(
+        (delta.added, delta.removed, delta.persisted),
+        (vec![(0, 5)], vec![(1, 2)], vec![(3, 4)])
+    );
+    assert_eq!(tracker.previous(), [(0, 5), (3, 4)]);
+}
+
+#[test]
+fn bvh() {
+    let (boxes1, boxes2) = (&TEST_DATA.boxes1, &TEST_DATA.boxes2);
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(7);
+    for split in [Split::Sah, Split::Median] {
+        let mut bvh = Bvh::build(boxes1, split);
+        assert_eq!(bvh.len(), boxes1.len());
+        let mut res = Vec::new();
+        bvh.pairs(&mut res);
+        assert!(same(&TEST_DATA.complete, &res));
+        let mut res = Vec::new();
+        bvh.pairs_with(&Bvh::build(boxes2, split), &mut res);
+        assert_eq!(res.len(), TEST_DATA.bipartite.len());
+        assert!(res.iter().all(|pair| TEST_DATA.bipartite.contains(pair)));
+
+        for (&id, query) in boxes2 {
+            let mut hits = Vec::new();
+            bvh.query(query, |hit, _bbox| hits.push(hit));
+            hits.sort_unstable();
+            let mut expected: Vec<_> = boxes1
+                .iter()
+                .filter(|(_, &bbox)| bbox.intersects(query))
+                .map(|(&id, _)| id)
+                .collect();
+            expected.sort_unstable();
+            assert_eq!(hits, expected, "query {}", id);
+        }
+
+        for _ in 0..50 {
+            let origin = [r.gen_range(-10.0..160.0), r.gen_range(-10.0..160.0), -5.0];
+            let ray = Ray::new(
+                origin,
+                [r.gen_range(-1.0..1.0), r.gen_range(-1.0..1.0), 1.0],
+            );
+            let mut hits = Vec::new();
+            bvh.ray(&ray, 100.0, |hit, t| hits.push((hit, t)));
+            hits.sort_unstable_by_key(|&(id, _)| id);
+            let mut expected: Vec<_> = boxes1
+                .iter()
+                .filter_map(|(&id, bbox)| Some((id, ray.entry(bbox, 100.0)?)))
+                .collect();
+            expected.sort_unstable_by_key(|&(id, _)| id);
+            assert_eq!(hits, expected);
+        }
+
+        // move every box a bit, and remove one by making it empty
+        let moved: BBoxSet<_, _> = boxes1
+            .iter()
+            .map(|(&id, bbox)| {
+                let shift = (id % 7) as f32 - 3.0;
+                let min = [bbox.lo(0) + shift, bbox.lo(1), bbox.lo(2) - shift];
+                let max = [bbox.hi(0) + shift, bbox.hi(1), bbox.hi(2) - shift];
+                let moved = Box3Df32::new(min, max);
+                (id, if id == 0 { moved.emptied() } else { moved })
+            })
+            .collect();
+        bvh.refit(|id| moved.find(id).unwrap());
+        let mut res = Vec::new();
+        bvh.pairs(&mut res);
+        let mut expected = Vec::new();
+        intersect_brute_force(&moved, &moved, &mut expected);
+        assert!(same(&expected, &res));
+    }
+    let empty: Bvh<f32, usize, 3> = Bvh::build(&BBoxSet::new(), Split::Sah);
+    let mut res = Vec::new();
+    empty.pairs(&mut res);
+    assert!(empty.is_empty() && res.is_empty());
+}
+
+#[test]
+fn rtree() {
+    let (boxes1, boxes2) = (&TEST_DATA.boxes1, &TEST_DATA.boxes2);
+    for node_size in [2, 5, 16] {
+        let rtree = RTree::bulk_load_with_node_size(boxes1, node_size);
+        assert_eq!(rtree.len(), boxes1.len());
+        let mut res = Vec::new();
+        rtree.pairs(&mut res);
+        assert!(same(&TEST_DATA.complete, &res));
+        let mut res = Vec::new();
+        rtree.pairs_with(&RTree::bulk_load_with_node_size(boxes2, 3), &mut res);
+        assert_eq!(res.len(), TEST_DATA.bipartite.len());
+        assert!(res.iter().all(|pair| TEST_DATA.bipartite.contains(pair)));
+
+        for (&id, query) in boxes2 {
+            let mut hits = Vec::new();
+            rtree.query(query, |hit, _bbox| hits.push(hit));
+            hits.sort_unstable();
+            let mut expected: Vec<_> = boxes1
+                .iter()
+                .filter(|(_, &bbox)| bbox.intersects(query))
+                .map(|(&id, _)| id)
+                .collect();
+            expected.sort_unstable();
+            assert_eq!(hits, expected, "query {}", id);
+
+            let point = query.min();
+            let mut hits = Vec::new();
+            rtree.query_point(point, |hit, _bbox| hits.push(hit));
+            hits.sort_unstable();
+            let mut expected: Vec<_> = boxes1
+                .iter()
+                .filter(|(_, bbox)| (0..3).all(|dim| bbox.contains_in(dim, point[dim])))
+                .map(|(&id, _)| id)
+                .collect();
+            expected.sort_unstable();
+            assert_eq!(hits, expected, "point query {}", id);
+        }
+    }
+    assert_eq!(RTree::bulk_load_with_node_size(boxes1, 16).height(), 2);
+    let empty: RTree<f32, usize, 3> = RTree::bulk_load(&BBoxSet::new());
+    let mut res = Vec::new();
+    empty.pairs(&mut res);
+    assert!(empty.is_empty() && res.is_empty() && empty.height() == 0);
+}
+
+#[test]
+fn grid_broad_phase() {
+    let mut boxes = TEST_DATA.boxes1.clone();
+    // a ground plane, overlapping far too many cells
+    boxes.push(
+        1000,
+        Box3Df32::new(
+            [f32::NEG_INFINITY, f32::NEG_INFINITY, 0.0],
+            [f32::INFINITY, f32::INFINITY, 20.0],
+        ),
+    );
+    boxes.push(1001, Box3Df32::new([0.0, 0.0, 0.0], [200.0, 200.0, 200.0]));
+    let mut complete = Vec::new();
+    intersect_brute_force(&boxes, &boxes, &mut complete);
+    for cell_size in [0.5, 5.0, 20.0, 1000.0] {
+        let grid = GridBroadPhase::from_source(&boxes, cell_size);
+        assert_eq!(grid.len(), boxes.len());
+        let mut res = Vec::new();
+        grid.pairs(&mut res);
+        assert!(same(&complete, &res), "cell size {}", cell_size);
+
+        for (&id, query) in &TEST_DATA.boxes2 {
+            let mut hits = Vec::new();
+            grid.query(query, |hit, _bbox| hits.push(hit));
+            hits.sort_unstable();
+            let mut expected: Vec<_> = boxes
+                .iter()
+                .filter(|(_, &bbox)| bbox.intersects(query))
+                .map(|(&id, _)| id)
+                .collect();
+            expected.sort_unstable();
+            assert_eq!(hits, expected, "query {}", id);
+        }
+    }
+}
+
+#[test]
+fn kdtree() {
+    let (boxes1, boxes2) = (&TEST_DATA.boxes1, &TEST_DATA.boxes2);
+    let kdtree = KdTree::build(boxes1);
+    assert_eq!(kdtree.len(), boxes1.len());
+    let mut res = Vec::new();
+    kdtree.query_set(boxes2, &mut res);
+    assert_eq!(res.len(), TEST_DATA.bipartite.len());
+    assert!(res
+        .iter()
+        .all(|&(query, id)| TEST_DATA.bipartite.contains(&(id, query))));
+
+    for (&id, query) in boxes2 {
+        let point = query.min();
+        let mut hits = Vec::new();
+        kdtree.query_point(point, |hit, _bbox| hits.push(hit));
+        hits.sort_unstable();
+        let mut expected: Vec<_> = boxes1
+            .iter()
+            .filter(|(_, bbox)| (0..3).all(|dim| bbox.contains_in(dim, point[dim])))
+            .map(|(&id, _)| id)
+            .collect();
+        expected.sort_unstable();
+        assert_eq!(hits, expected, "point query {}", id);
+    }
+    let empty: KdTree<f32, usize, 3> = KdTree::build(&BBoxSet::new());
+    let mut hits = 0;
+    empty.query_point([0.0; 3], |_id, _bbox| hits += 1);
+    assert!(empty.is_empty() && hits == 0);
+}
+
+#[test]
+fn interval_tree() {
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(1608);
+    let mut intervals = BBoxSet::new();
+    for id in 0..300 {
+        let lo = r.gen_range(0..1000);
+        intervals.push(id, Interval::new(lo, lo + r.gen_range(1..60)));
+    }
+    intervals.push(300, Interval::new(500, 400));
+    intervals.sort();
+    let mut complete = Vec::new();
+    intersect_brute_force(&intervals, &intervals, &mut complete);
+    let mut res = Vec::new();
+    crate::intersect_ze(
+        &intervals,
+        &intervals,
+        &mut res,
+        &mut rand_chacha::ChaCha8Rng::seed_from_u64(1),
+    );
+    assert!(same(&complete, &res));
+
+    let tree = IntervalTree::build(&intervals);
+    assert_eq!(tree.len(), 300);
+    for _ in 0..100 {
+        let lo = r.gen_range(-10..1010);
+        let query = Interval::new(lo, lo + r.gen_range(0..30));
+        let mut hits = Vec::new();
+        tree.query(&query, |id, interval| hits.push((interval.lo, id)));
+        assert!(hits.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+        hits.sort_unstable();
+        let mut expected: Vec<_> = intervals
+            .iter()
+            .filter(|(_, &interval)| interval.intersects(&query) && !interval.is_empty())
+            .map(|(&id, interval)| (interval.lo, id))
+            .collect();
+        expected.sort_unstable();
+        assert_eq!(hits, expected);
+
+        let mut hits = Vec::new();
+        tree.query_point(lo, |id, _interval| hits.push(id));
+        hits.sort_unstable();
+        let mut expected: Vec<_> = intervals
+            .iter()
+            .filter(|(_, interval)| interval.lo <= lo && lo < interval.hi)
+            .map(|(&id, _)| id)
+            .collect();
+        expected.sort_unstable();
+        assert_eq!(hits, expected);
+    }
+}
+
+#[test]
+fn curves() {
+    assert_eq!(morton([0b10, 0b01], 2), 0b1001);
+    assert_eq!(morton([5, 3, 1], 3), 0b100_010_111);
+    // consecutive cells along the Hilbert curve are neighbours, and every cell is visited once
+    fn check_hilbert<const D: usize>(bits: u32) {
+        let cells = 1usize << (bits as usize * D);
+        let mut by_key = vec![None; cells];
+        for idx in 0..cells {
+            let mut cell = [0; D];
+            for (dim, coord) in cell.iter_mut().enumerate() {
+                *coord = (idx >> (dim * bits as usize) & ((1 << bits) - 1)) as u32;
+            }
+            let key = hilbert(cell, bits) as usize;
+            assert!(by_key[key].replace(cell).is_none());
+        }
+        for pair in by_key.windows(2) {
+            let (a, b) = (pair[0].unwrap(), pair[1].unwrap());
+            let distance: u32 = (0..D).map(|dim| a[dim].abs_diff(b[dim])).sum();
+            assert_eq!(distance, 1, "{:?} {:?}", a, b);
+        }
+    }
+    check_hilbert::<2>(3);
+    check_hilbert::<3>(2);
+    check_hilbert::<4>(2);
+
+    let mut boxes = TEST_DATA.boxes1.clone();
+    boxes.push(
+        1000,
+        Box3Df32::new([f32::NEG_INFINITY, 0.0, 0.0], [1.0, 1.0, 1.0]),
+    );
+    for curve in [Curve::Morton, Curve::Hilbert] {
+        boxes.sort_by_curve(curve);
+        let keys = curve_keys(&boxes, curve);
+        assert!(keys.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(boxes.len(), TEST_DATA.boxes1.len() + 1);
+        assert!(boxes.find(1000).is_some());
+    }
+    boxes.sort();
+    let mut res = Vec::new();
+    crate::intersect_ze(
+        &boxes,
+        &boxes,
+        &mut res,
+        &mut rand_chacha::ChaCha8Rng::seed_from_u64(1),
+    );
+    let mut expected = Vec::new();
+    intersect_brute_force(&boxes, &boxes, &mut expected);
+    assert!(same(&expected, &res));
+}
+
+#[test]
+fn binned() {
+    let mut rand = rand_chacha::ChaCha8Rng::seed_from_u64(3);
+    let mut res = Vec::new();
+    intersect_binned(&TEST_DATA.boxes1, &TEST_DATA.boxes1, &mut res, &mut rand);
+    assert!(same(&TEST_DATA.complete, &res));
+
+    let mut boxes = TEST_DATA.boxes1.clone();
+    // a ground plane, overlapping every bin
+    boxes.push(
+        1000,
+        Box3Df32::new(
+            [f32::NEG_INFINITY, f32::NEG_INFINITY, 0.0],
+            [f32::INFINITY, f32::INFINITY, 20.0],
+        ),
+    );
+    boxes.sort();
+    let mut complete = Vec::new();
+    intersect_brute_force(&boxes, &boxes, &mut complete);
+    let mut bipartite = Vec::new();
+    intersect_brute_force(&boxes, &TEST_DATA.boxes2, &mut bipartite);
+    // small bins, so that many boxes overlap several bins, and some too many
+    for bin_size in [1, 8, 32, 1000] {
+        let mut res = Vec::new();
+        intersect_binned_with_bin_size(&boxes, &boxes, &mut res, &mut rand, bin_size);
+        assert!(same(&complete, &res), "bin size {}", bin_size);
+
+        let mut res = Vec::new();
+        intersect_binned_with_bin_size(&boxes, &TEST_DATA.boxes2, &mut res, &mut rand, bin_size);
+        assert!(same(&bipartite, &res), "bin size {}", bin_size);
+        let mut res = Vec::new();
+        intersect_binned_with_bin_size(&TEST_DATA.boxes2, &boxes, &mut res, &mut rand, bin_size);
+        assert_eq!(res.len(), bipartite.len(), "bin size {}", bin_size);
+    }
+}
+
+#[test]
+fn single_box_query() {
+    let mut boxes = TEST_DATA.boxes1.clone();
+    boxes.push(1000, Box3Df32::new([-1000.0; 3], [1000.0; 3]).emptied());
+    boxes.sort();
+    // so its order isn't checked on every query
+    assert!(crate::source::BoxSource::is_sorted(&boxes));
+    for (&id, query) in &TEST_DATA.boxes2 {
+        let mut hits = Vec::new();
+        query_box(&boxes, query, &mut hits);
+        let expected: Vec<_> = boxes
+            .iter()
+            .filter(|(_, &bbox)| bbox.intersects(query) && !bbox.is_empty())
+            .map(|(&id, _)| id)
+            .collect();
+        assert_eq!(hits, expected, "query {}", id);
+    }
+    let mut hits = Vec::new();
+    let everything = Box3Df32::new([f32::NEG_INFINITY; 3], [f32::INFINITY; 3]);
+    query_box(&boxes, &everything, &mut hits);
+    assert_eq!(hits.len(), TEST_DATA.boxes1.len());
+    query_box(&boxes, &everything.emptied(), &mut hits);
+    assert_eq!(hits.len(), TEST_DATA.boxes1.len());
+}
+
+#[test]
+fn static_index() {
+    let mut boxes = TEST_DATA.boxes1.clone();
+    boxes.push(1000, Box3Df32::new([-1000.0; 3], [1000.0; 3]).emptied());
+    boxes.sort();
+    let index = StaticIndex::build(&boxes);
+    assert_eq!(index.len(), TEST_DATA.boxes1.len());
+    for (&id, query) in &TEST_DATA.boxes2 {
+        let mut hits = Vec::new();
+        index.query_box(query, &mut hits);
+        let mut expected = Vec::new();
+        query_box(&boxes, query, &mut expected);
+        assert_eq!(hits, expected, "query {}", id);
+    }
+    let mut res = Vec::new();
+    index.query_set(&TEST_DATA.boxes2, &mut res);
+    assert!(same(&TEST_DATA.bipartite, &res));
+
+    let mut intervals = BBoxSet::new();
+    for (lo, hi) in [(5, 9), (0, 3), (2, 6), (8, 12)] {
+        intervals.push(lo, Interval::new(lo, hi));
+    }
+    let index = StaticIndex::build(&intervals);
+    let mut hits = Vec::new();
+    index.query_box(&Interval::new(3, 8), &mut hits);
+    assert_eq!(hits, [2, 5]);
+
+    // ties are broken by identifier, as in a sorted set
+    let mut ties = BBoxSet::new();
+    for id in [3, 1, 2] {
+        ties.push(id, Interval::new(0, 4));
+    }
+    let index = StaticIndex::build(&ties);
+    let mut hits = Vec::new();
+    index.query_box(&Interval::new(1, 2), &mut hits);
+    assert_eq!(hits, [1, 2, 3]);
+}
+
+#[test]
+fn ray_queries() {
+    let boxes = &TEST_DATA.boxes1;
+    let bvh = Bvh::build(boxes, Split::Sah);
+    let rtree = RTree::bulk_load_with_node_size(boxes, 4);
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(11);
+    for _ in 0..50 {
+        let origin = [r.gen_range(-10.0..160.0), r.gen_range(-10.0..160.0), -5.0];
+        let ray = Ray::new(
+            origin,
+            [r.gen_range(-1.0..1.0), r.gen_range(-1.0..1.0), 1.0],
+        );
+        let min = r.gen_range(0.0..50.0);
+        let mut hits = Vec::new();
+        query_ray(boxes, &ray, min, 100.0, &mut hits);
+        assert!(hits.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+        let mut expected: Vec<_> = boxes
+            .iter()
+            .filter_map(|(&id, bbox)| Some((id, ray.entry_between(bbox, min, 100.0)?)))
+            .collect();
+        expected.sort_by(|a, b| a.1.total_cmp(&b.1));
+        assert_eq!(hits, expected);
+        assert!(hits.iter().all(|&(_, t)| (min..=100.0).contains(&t)));
+
+        let mut bvh_hits = Vec::new();
+        bvh.query_ray(&ray, min, 100.0, &mut bvh_hits);
+        let mut rtree_hits = Vec::new();
+        rtree.query_ray(&ray, min, 100.0, &mut rtree_hits);
+        for other in [bvh_hits, rtree_hits] {
+            assert!(other.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+            let key = |hits: &[(usize, f64)]| {
+                let mut hits = hits.to_vec();
+                hits.sort_by_key(|&(id, _)| id);
+                hits
+            };
+            assert_eq!(key(&other), key(&hits));
+        }
+    }
+
+    // starting inside a box: the whole ray hits it at 0, the rest of the ray beyond it misses it
+    let mut level = BBoxSet::new();
+    level.push(0, Box2Df32::new([0.0, 0.0], [2.0, 2.0]));
+    level.push(1, Box2Df32::new([5.0, 0.0], [6.0, 2.0]));
+    level.push(2, Box2Df32::new([3.0, 0.0], [4.0, 2.0]));
+    let ray = Ray::new([1.0, 1.0], [1.0, 0.0]);
+    let mut hits = Vec::new();
+    query_ray(&level, &ray, 0.0, f64::INFINITY, &mut hits);
+    assert_eq!(hits, [(0, 0.0), (2, 2.0), (1, 4.0)]);
+    let mut hits = Vec::new();
+    query_ray(&level, &ray, 1.5, 3.0, &mut hits);
+    assert_eq!(hits, [(2, 2.0)]);
+}
+
+#[test]
+fn region_queries() {
+    let mut boxes = TEST_DATA.boxes1.clone();
+    // a ground plane, reaching out of every region in x and y
+    boxes.push(
+        1000,
+        Box3Df32::new(
+            [f32::NEG_INFINITY, f32::NEG_INFINITY, -1.0],
+            [f32::INFINITY, f32::INFINITY, 0.0],
+        ),
+    );
+    let bvh = Bvh::build(&boxes, Split::Median);
+    let rtree = RTree::bulk_load_with_node_size(&boxes, 4);
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(13);
+    for _ in 0..50 {
+        // a random convex region containing a point of the scene
+        let center: [f64; 3] = [
+            r.gen_range(0.0..150.0),
+            r.gen_range(0.0..150.0),
+            r.gen_range(0.0..150.0),
+        ];
+        let half_spaces: Vec<_> = (0..5)
+            .map(|_| {
+                let normal = [
+                    r.gen_range(-1.0..1.0),
+                    r.gen_range(-1.0..1.0),
+                    r.gen_range(-1.0..1.0),
+                ];
+                let dot: f64 = (0..3).map(|dim| normal[dim] * center[dim]).sum();
+                HalfSpace::new(normal, dot + r.gen_range(0.0..40.0))
+            })
+            .collect();
+        let mut hits = Vec::new();
+        query_region(&boxes, &half_spaces, &mut hits);
+        let expected: Vec<_> = boxes
+            .iter()
+            .filter(|(_, bbox)| {
+                half_spaces
+                    .iter()
+                    .all(|half_space| half_space.intersects(bbox))
+            })
+            .map(|(&id, _)| id)
+            .collect();
+        assert_eq!(hits, expected);
+        // every box containing the center is inside the region
+        for (&id, bbox) in &boxes {
+            if (0..3).all(|dim| bbox.contains_in(dim, center[dim] as f32)) {
+                assert!(hits.contains(&id), "box {}", id);
+            }
+        }
+
+        let mut bvh_hits = Vec::new();
+        bvh.query_region(&half_spaces, |id, _bbox| bvh_hits.push(id));
+        let mut rtree_hits = Vec::new();
+        rtree.query_region(&half_spaces, |id, _bbox| rtree_hits.push(id));
+        hits.sort_unstable();
+        for mut other in [bvh_hits, rtree_hits] {
+            other.sort_unstable();
+            assert_eq!(other, hits);
+        }
+    }
+
+    // the ground plane is below the half-space above z = 1, but grazes the one above z = 0
+    let above = |z: f64| [HalfSpace::new([0.0, 0.0, -1.0], -z)];
+    let mut hits = Vec::new();
+    query_region(&boxes, &above(1.0), &mut hits);
+    assert!(!hits.contains(&1000));
+    let mut hits = Vec::new();
+    query_region(&boxes, &above(0.0), &mut hits);
+    assert!(hits.contains(&1000));
+}
+
+#[test]
+fn nearest_box() {
+    let mut boxes = TEST_DATA.boxes1.clone();
+    boxes.push(1000, Box3Df32::new([-1000.0; 3], [1000.0; 3]).emptied());
+    let mut bvh = Bvh::build(&boxes, Split::Sah);
+    let rtree = RTree::bulk_load_with_node_size(&boxes, 4);
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(17);
+    for _ in 0..100 {
+        let point = [
+            r.gen_range(-50.0..200.0),
+            r.gen_range(-50.0..200.0),
+            r.gen_range(-50.0..200.0),
+        ];
+        let (id, distance) = nearest(&boxes, point).unwrap();
+        let expected = boxes
+            .iter()
+            .filter(|(_, bbox)| !bbox.is_empty())
+            .map(|(_, bbox)| bbox.distance_to(point))
+            .fold(f64::INFINITY, f64::min);
+        assert_eq!(distance, expected);
+        assert_eq!(boxes.find(id).unwrap().distance_to(point), distance);
+        for (_, other) in [bvh.nearest(point).unwrap(), rtree.nearest(point).unwrap()] {
+            assert_eq!(other, distance);
+        }
+    }
+
+    let mut level = BBoxSet::new();
+    level.push(0, Box2Df32::new([0.0, 0.0], [1.0, 1.0]));
+    level.push(1, Box2Df32::new([3.0, 4.0], [5.0, 5.0]));
+    assert_eq!(nearest(&level, [0.5, 0.5]), Some((0, 0.0)));
+    assert_eq!(nearest(&level, [1.0, 1.0]), Some((0, 0.0)));
+    assert_eq!(nearest(&level, [6.0, 8.0]), Some((1, 10.0f64.sqrt())));
+
+    // all boxes made empty
+    bvh.refit(|_| Box3Df32::new([0.0; 3], [1.0; 3]).emptied());
+    assert_eq!(bvh.nearest([0.0; 3]), None);
+    let empty: BBoxSet<Box2Df32, usize> = BBoxSet::new();
+    assert_eq!(nearest(&empty, [0.0, 0.0]), None);
+    assert_eq!(RTree::bulk_load(&empty).nearest([0.0, 0.0]), None);
+}
+
+#[test]
+fn max_stabbing_depth() {
+    // the deepest point can be moved to the low boundaries of boxes in every dimension
+    fn brute_force(boxes: &BBoxSet<Box3Df32, usize>) -> usize {
+        let corner = |dim: usize| boxes.iter().map(move |(_, bbox)| bbox.lo(dim));
+        let mut best = 0;
+        for x in corner(0) {
+            for y in corner(1) {
+                for z in corner(2) {
+                    let depth = boxes
+                        .iter()
+                        .filter(|(_, bbox)| {
+                            bbox.contains_in(0, x)
+                                && bbox.contains_in(1, y)
+                                && bbox.contains_in(2, z)
+                        })
+                        .count();
+                    best = best.max(depth);
+                }
+            }
+        }
+        best
+    }
+    for seed in 0..5 {
+        let boxes = random_boxes(40, 0, seed);
+        assert_eq!(max_depth(&boxes), brute_force(&boxes), "seed {}", seed);
+    }
+    let depth = max_depth(&TEST_DATA.boxes1);
+    assert!(depth > 1);
+
+    let mut pile = BBoxSet::new();
+    for id in 0..300 {
+        pile.push(id, Box3Df32::new([0.0, 0.0, 0.0], [1.0, 1.0, 1.0]));
+    }
+    // a chain of boxes touching each other, a flat box and an empty one
+    for id in 300..310 {
+        let x = id as f32;
+        pile.push(id, Box3Df32::new([x, 0.0, 0.0], [x + 1.0, 1.0, 1.0]));
+    }
+    pile.push(310, Box3Df32::new([0.0, 0.0, 0.5], [1.0, 1.0, 0.5]));
+    pile.push(311, Box3Df32::new([0.0; 3], [1.0; 3]).emptied());
+    assert_eq!(max_depth(&pile), 300);
+    pile.push(
+        312,
+        Box3Df32::new([f32::NEG_INFINITY; 3], [f32::INFINITY; 3]),
+    );
+    assert_eq!(max_depth(&pile), 301);
+
+    let mut intervals = BBoxSet::new();
+    for (id, &(lo, hi)) in [(0, 3), (3, 6), (1, 4), (2, 5), (5, 9)].iter().enumerate() {
+        intervals.push(id, Interval::new(lo, hi));
+    }
+    assert_eq!(max_depth(&intervals), 3);
+    assert_eq!(max_depth(&BBoxSet::<Box2Df32, usize>::new()), 0);
+}
+
+#[test]
+fn union_volume() {
+    // the boxes have integer bounds, so count the unit cells they cover
+    fn brute_force(boxes: &BBoxSet<Box3Df32, usize>) -> f64 {
+        let max = boxes
+            .iter()
+            .map(|(_, bbox)| bbox.hi(0).max(bbox.hi(1)).max(bbox.hi(2)));
+        let size = max.fold(0.0, f32::max) as usize;
+        let mut cells = 0;
+        for x in 0..size {
+            for y in 0..size {
+                for z in 0..size {
+                    let center = [x as f32 + 0.5, y as f32 + 0.5, z as f32 + 0.5];
+                    if boxes
+                        .iter()
+                        .any(|(_, bbox)| (0..3).all(|dim| bbox.contains_in(dim, center[dim])))
+                    {
+                        cells += 1;
+                    }
+                }
+            }
+        }
+        cells as f64
+    }
+    for seed in 0..5 {
+        let boxes = random_boxes(40, 0, seed);
+        assert_eq!(union_measure(&boxes), brute_force(&boxes), "seed {}", seed);
+    }
+    let mut flat: BBoxSet<Box2Df32, usize> = BBoxSet::new();
+    for (&id, bbox) in &random_boxes(100, 0, 5) {
+        flat.push(
+            id,
+            Box2Df32::new([bbox.lo(0), bbox.lo(1)], [bbox.hi(0), bbox.hi(1)]),
+        );
+    }
+    let union_area = union_measure(&flat);
+    let total: f32 = flat.iter().map(|(_, bbox)| bbox.measure()).sum();
+    assert!(union_area > 0.0 && union_area < total as f64);
+
+    // identical boxes, a box inside another, flat and empty boxes count once or not at all
+    let mut boxes = BBoxSet::new();
+    boxes.push(0, Box3Df32::new([0.0; 3], [2.0; 3]));
+    boxes.push(1, Box3Df32::new([0.0; 3], [2.0; 3]));
+    boxes.push(2, Box3Df32::new([0.5; 3], [1.0; 3]));
+    boxes.push(3, Box3Df32::new([5.0, 0.0, 0.0], [6.0, 1.0, 0.0]));
+    boxes.push(4, Box3Df32::new([5.0; 3], [6.0; 3]).emptied());
+    boxes.push(5, Box3Df32::new([2.0, 0.0, 0.0], [3.0, 1.0, 1.0]));
+    assert_eq!(union_measure(&boxes), 9.0);
+    boxes.push(
+        6,
+        Box3Df32::new([f32::NEG_INFINITY, 0.0, 0.0], [0.0, 1.0, 1.0]),
+    );
+    assert_eq!(union_measure(&boxes), f64::INFINITY);
+
+    let mut intervals = BBoxSet::new();
+    for (id, &(lo, hi)) in [(0, 3), (2, 5), (7, 9), (8, 8)].iter().enumerate() {
+        intervals.push(id, BoxND::new([lo], [hi]));
+    }
+    assert_eq!(union_measure(&intervals), 7.0);
+    assert_eq!(union_measure(&BBoxSet::<Box2Df32, usize>::new()), 0.0);
+}
+
+fn check_median_strategy(median: impl MedianStrategy<f32> + MedianStrategy<i64>) {
+    let mut r = rand_chacha::ChaCha8Rng::seed_from_u64(21);
+    let (a, b) = (&TEST_DATA.boxes1, &TEST_DATA.boxes2);
+    let mut res = Vec::new();
+    crate::intersect_ze_with_median::<_, _, _, 5>(a, a, &mut res, &mut r, median);
+    assert!(same(&TEST_DATA.complete, &res));
+    let mut res = Vec::new();
+    crate::intersect_ze_with_median::<_, _, _, 5>(a, b, &mut res, &mut r, median);
+    assert!(same(&TEST_DATA.bipartite, &res));
+
+    // most boxes piled up near the origin, a few far out, and some infinite ones
+    let mut skewed = BBoxSet::new();
+    for i in 0..400i64 {
+        let lo = if i % 10 == 0 { i * 1000 } else { i % 7 };
+        let (lo1, hi1) = (i % 13, i % 13 + 3);
+        skewed.push(i as usize, BoxND::new([lo, lo1], [lo + 5, hi1]));
+    }
+    skewed.push(400, BoxND::new([i64::MIN, 0], [0, 2]));
+    skewed.push(401, BoxND::new([1000, 0], [i64::MAX, 2]));
+    skewed.sort();
+    let (mut correct, mut res) = (Vec::new(), Vec::new());
+    intersect_brute_force(&skewed, &skewed, &mut correct);
+    crate::intersect_ze_with_median::<_, _, _, 5>(&skewed, &skewed, &mut res, &mut r, median);
+    assert_eq!(res.len(), correct.len());
+    assert!(same(&correct, &res));
+}
+
+#[test]
+fn median_strategies() {
+    check_median_strategy(ApproxMedian);
+    check_median_strategy(ExactMedian);
+    check_median_strategy(Halfway);
+}
+
+#[test]
+fn intersect_ze_deterministic() {
+    let (a, b) = (&TEST_DATA.boxes1, &TEST_DATA.boxes2);
+    let mut res = Vec::new();
+    crate::intersect_ze_deterministic(a, a, &mut res);
+    assert!(same(&TEST_DATA.complete, &res));
+    let mut res = Vec::new();
+    crate::intersect_ze_deterministic(a, b, &mut res);
+    assert!(same(&TEST_DATA.bipartite, &res));
+    let mut again = Vec::new();
+    crate::intersect_ze_deterministic(a, b, &mut again);
+    assert_eq!(res, again);
+
+    let mut boxes = random_boxes(5000, 0, 22);
+    boxes.sort();
+    let mut res = Vec::new();
+    crate::intersect_ze_deterministic(&boxes, &boxes, &mut res);
+    let mut correct = Vec::new();
+    crate::intersect_scan(&boxes, &boxes, &mut correct);
+    assert_eq!(res.len(), correct.len());
+
+    // random numbers drawn anyway are the same every time
+    let draw = |rng: &mut crate::FixedRng| {
+        (0..100)
+            .map(|_| crate::Rng::rand_usize(rng, 7))
+            .collect::<Vec<_>>()
+    };
+    let first = draw(&mut crate::FixedRng::new());
+    assert_eq!(first, draw(&mut crate::FixedRng::new()));
+    assert!(first.iter().all(|&x| x < 7) && first.iter().any(|&x| x != first[0]));
+}
@@ -0,0 +1,181 @@
+//! Self-checking wrappers around the algorithms, which compare their results to brute force
+//! on a random sample of boxes and panic with a minimal reproducer if they disagree.
+//! Requires the `verify` feature.
+//!
+//! Checking `samples` boxes of `a` costs *O*(`samples` * `b.len()`) on top of the algorithm, and sorting the boxes
+//! of distinct inputs by identifier *O*(*n* log *n*) for their *n* boxes.
+
+use std::cmp::Ordering;
+use std::fmt::Debug;
+
+use crate::boxes::BBox;
+use crate::policy::{HalfOpen, Policy};
+use crate::set::BBoxSet;
+use crate::sink::Sink;
+use crate::source::{self, BoxSource};
+use crate::{intersect_brute_force, intersect_scan, intersect_ze, HasInfinity, Rng};
+
+/// A [`Sink`] passing pairs on to another sink while keeping a copy of them
+struct Tee<'s, S, ID> {
+    inner: &'s mut S,
+    pairs: Vec<(ID, ID)>,
+}
+
+impl<B, ID, S> Sink<B, ID> for Tee<'_, S, ID>
+where
+    ID: Copy,
+    S: Sink<B, ID>,
+{
+    fn report(&mut self, a_id: ID, a: &B, b_id: ID, b: &B) {
+        self.pairs.push((a_id, b_id));
+        self.inner.report(a_id, a, b_id, b);
+    }
+}
+
+/// Like [`intersect_ze`], but verifies the result for `samples` randomly chosen boxes of `a`.
+/// # Panics
+/// If the result for one of the sampled boxes differs from brute force, or a pair was reported twice.
+pub fn intersect_ze_verified<B, ID, R>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    out: &mut impl Sink<B, ID>,
+    rand: &mut R,
+    samples: usize,
+) where
+    B: BBox + Debug,
+    B::Num: PartialOrd + HasInfinity,
+    ID: PartialOrd + Copy + Debug,
+    R: Rng,
+{
+    let mut tee = Tee {
+        inner: out,
+        pairs: Vec::new(),
+    };
+    intersect_ze(a, b, &mut tee, rand);
+    verify(a, b, tee.pairs, rand, samples, "intersect_ze");
+}
+
+/// Like [`intersect_scan`], but verifies the result for `samples` randomly chosen boxes of `a`.
+/// # Panics
+/// If the result for one of the sampled boxes differs from brute force, or a pair was reported twice.
+pub fn intersect_scan_verified<B, ID, R>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    out: &mut impl Sink<B, ID>,
+    rand: &mut R,
+    samples: usize,
+) where
+    B: BBox + Debug,
+    ID: PartialOrd + Copy + Debug,
+    R: Rng,
+{
+    let mut tee = Tee {
+        inner: out,
+        pairs: Vec::new(),
+    };
+    intersect_scan(a, b, &mut tee);
+    verify(a, b, tee.pairs, rand, samples, "intersect_scan");
+}
+
+fn verify<B, ID, R>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    mut pairs: Vec<(ID, ID)>,
+    rand: &mut R,
+    samples: usize,
+    algorithm: &str,
+) where
+    B: BBox + Debug,
+    ID: PartialOrd + Copy + Debug,
+    R: Rng,
+{
+    // The algorithms may swap the identifiers of a pair, so pairs are compared unordered. The same source
+    // reports every pair once and never pairs a box with itself. Distinct sources may share identifiers,
+    // so the pair of `x` and `y` stands for both the box `x` of `a` with the box `y` of `b` and the other way around.
+    let same = source::same(a, b);
+    let cmp = |x: &ID, y: &ID| x.partial_cmp(y).unwrap_or(Ordering::Equal);
+    let unordered = |x: ID, y: ID| {
+        if cmp(&x, &y) == Ordering::Greater {
+            (y, x)
+        } else {
+            (x, y)
+        }
+    };
+    let cmp_pairs = |p: &(ID, ID), q: &(ID, ID)| cmp(&p.0, &q.0).then_with(|| cmp(&p.1, &q.1));
+    for pair in &mut pairs {
+        *pair = unordered(pair.0, pair.1);
+    }
+    pairs.sort_by(cmp_pairs);
+    let most = if same { 1 } else { 2 };
+    if let Some(pair) = pairs
+        .windows(most + 1)
+        .find(|w| w.iter().all(|p| *p == w[0]))
+    {
+        panic!("{} reported the pair {:?} twice", algorithm, pair[0]);
+    }
+    let (a_by_id, b_by_id) = if same {
+        (Vec::new(), Vec::new())
+    } else {
+        (by_id(a), by_id(b))
+    };
+    let find = |by_id: &[(ID, B)], id: ID| {
+        let idx = by_id.binary_search_by(|(other, _)| cmp(other, &id)).ok()?;
+        Some(by_id[idx].1)
+    };
+
+    if a.is_empty() {
+        return;
+    }
+    for _ in 0..samples.min(a.len()) {
+        let (x, x_id) = a.get(rand.rand_usize(a.len()));
+        let mut single = BBoxSet::with_capacity(1);
+        single.push(x_id, x);
+        let mut expected = Vec::new();
+        intersect_brute_force(&single, b, &mut expected);
+
+        for (y, y_id) in (0..b.len()).map(|idx| b.get(idx)) {
+            if same && y_id == x_id {
+                continue;
+            }
+            let mut should = expected.iter().any(|&(_, id)| id == y_id) as usize;
+            if !same && y_id != x_id {
+                if let (Some(a_y), Some(b_x)) = (find(&a_by_id, y_id), find(&b_by_id, x_id)) {
+                    should += HalfOpen.intersects(&a_y, &b_x) as usize;
+                }
+            }
+            let pair = unordered(x_id, y_id);
+            let start = pairs.partition_point(|p| cmp_pairs(p, &pair) == Ordering::Less);
+            let did = pairs[start..].iter().take_while(|&&p| p == pair).count();
+            if should > 0 && did > should {
+                panic!("{} reported the pair {:?} twice", algorithm, pair);
+            }
+            if should != did {
+                panic!(
+                    "{} {} the pair of box {:?}: {:?} and box {:?}: {:?}",
+                    algorithm,
+                    if should > did {
+                        "missed"
+                    } else {
+                        "wrongly reported"
+                    },
+                    x_id,
+                    x,
+                    y_id,
+                    y
+                );
+            }
+        }
+    }
+}
+
+/// Returns the boxes of `source` with their identifiers, sorted by identifier.
+fn by_id<B: BBox, ID: PartialOrd + Copy>(source: &impl BoxSource<B, ID>) -> Vec<(ID, B)> {
+    let mut by_id: Vec<_> = (0..source.len())
+        .map(|idx| {
+            let (bbox, id) = source.get(idx);
+            (id, bbox)
+        })
+        .collect();
+    by_id.sort_by(|(x, _), (y, _)| x.partial_cmp(y).unwrap_or(Ordering::Equal));
+    by_id
+}
@@ -0,0 +1,170 @@
+//! Hand-written SSE and AVX kernels for boxes with `f32` boundaries, the most common case, e.g. [`Box3Df32`].
+//! Only available on `x86_64`. AVX is used if the processor supports it, detected at runtime,
+//! otherwise SSE, which every `x86_64` processor supports.
+//!
+//! The scans in this module report the same pairs in the same order as their scalar counterparts
+//! in [`internals`](crate::internals).
+
+use std::arch::x86_64::*;
+
+use crate::boxes::{BBox, Box3Df32};
+use crate::kernel::{self, Kernel};
+use crate::policy::Policy;
+use crate::sink::Sink;
+use crate::source::BoxSource;
+
+/// Returns `true` if `a` and `b` intersect, comparing all three dimensions at once.
+/// Like [`BBox::intersects`], the projections of the boxes are half-open intervals.
+#[inline]
+pub fn intersects(a: &Box3Df32, b: &Box3Df32) -> bool {
+    let (a_min, a_max, b_min, b_max) = (a.min(), a.max(), b.min(), b.max());
+    // SAFETY: SSE is supported by every x86_64 processor
+    let mask = unsafe {
+        let a_min = _mm_set_ps(0.0, a_min[2], a_min[1], a_min[0]);
+        let a_max = _mm_set_ps(0.0, a_max[2], a_max[1], a_max[0]);
+        let b_min = _mm_set_ps(0.0, b_min[2], b_min[1], b_min[0]);
+        let b_max = _mm_set_ps(0.0, b_max[2], b_max[1], b_max[0]);
+        _mm_movemask_ps(_mm_and_ps(
+            _mm_cmplt_ps(a_min, b_max),
+            _mm_cmplt_ps(b_min, a_max),
+        ))
+    };
+    mask & 0b111 == 0b111
+}
+
+/// Like [`one_way_scan`](crate::internals::one_way_scan), but testing 4 (SSE) or 8 (AVX) points per iteration.
+pub fn one_way_scan<B, ID>(
+    intervals: &impl BoxSource<B, ID>,
+    points: &impl BoxSource<B, ID>,
+    max_dim_check: usize,
+    out: &mut impl Sink<B, ID>,
+    policy: impl Policy<f32>,
+) where
+    B: BBox<Num = f32>,
+    ID: Copy + PartialOrd,
+{
+    if is_x86_feature_detected!("avx") {
+        // SAFETY: AVX is supported
+        unsafe { one_way_scan_avx(intervals, points, max_dim_check, out, policy) }
+    } else {
+        kernel::one_way_scan(intervals, points, max_dim_check, out, policy, Sse);
+    }
+}
+
+/// Like [`two_way_scan`](crate::internals::two_way_scan), but testing 4 (SSE) or 8 (AVX) boxes per iteration.
+pub fn two_way_scan<B, ID>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    out: &mut impl Sink<B, ID>,
+    policy: impl Policy<f32>,
+) where
+    B: BBox<Num = f32>,
+    ID: Copy + PartialOrd,
+{
+    if is_x86_feature_detected!("avx") {
+        // SAFETY: AVX is supported
+        unsafe { two_way_scan_avx(a, b, out, policy) }
+    } else {
+        kernel::two_way_scan(a, b, out, policy, Sse);
+    }
+}
+
+#[target_feature(enable = "avx")]
+unsafe fn one_way_scan_avx<B, ID>(
+    intervals: &impl BoxSource<B, ID>,
+    points: &impl BoxSource<B, ID>,
+    max_dim_check: usize,
+    out: &mut impl Sink<B, ID>,
+    policy: impl Policy<f32>,
+) where
+    B: BBox<Num = f32>,
+    ID: Copy + PartialOrd,
+{
+    kernel::one_way_scan(intervals, points, max_dim_check, out, policy, Avx(()));
+}
+
+#[target_feature(enable = "avx")]
+unsafe fn two_way_scan_avx<B, ID>(
+    a: &impl BoxSource<B, ID>,
+    b: &impl BoxSource<B, ID>,
+    out: &mut impl Sink<B, ID>,
+    policy: impl Policy<f32>,
+) where
+    B: BBox<Num = f32>,
+    ID: Copy + PartialOrd,
+{
+    kernel::two_way_scan(a, b, out, policy, Avx(()));
+}
+
+/// [`Kernel`] comparing 4 boundaries per instruction with SSE
+#[derive(Clone, Copy)]
+pub(crate) struct Sse;
+
+impl Kernel<f32> for Sse {
+    const LANES: usize = 4;
+
+    #[inline]
+    fn overlap_mask(
+        self,
+        los: &[Vec<f32>],
+        his: &[Vec<f32>],
+        start: usize,
+        lo: &[f32],
+        hi: &[f32],
+    ) -> u64 {
+        debug_assert!(
+            (0..los.len()).all(|dim| los[dim].len() >= start + 4 && his[dim].len() >= start + 4)
+        );
+        // SAFETY: SSE is supported by every x86_64 processor, `kernel` only asks for boxes that exist, so the loads are in bounds
+        unsafe {
+            let mut mask = _mm_castsi128_ps(_mm_set1_epi32(-1));
+            for dim in 0..los.len() {
+                let p_lo = _mm_loadu_ps(los[dim].as_ptr().add(start));
+                let p_hi = _mm_loadu_ps(his[dim].as_ptr().add(start));
+                let overlaps = _mm_and_ps(
+                    _mm_cmplt_ps(p_lo, _mm_set1_ps(hi[dim])),
+                    _mm_cmplt_ps(_mm_set1_ps(lo[dim]), p_hi),
+                );
+                mask = _mm_and_ps(mask, overlaps);
+            }
+            _mm_movemask_ps(mask) as u64
+        }
+    }
+}
+
+/// [`Kernel`] comparing 8 boundaries per instruction with AVX.
+/// Only created by functions that are compiled with AVX enabled and only called if it's supported.
+#[derive(Clone, Copy)]
+struct Avx(());
+
+impl Kernel<f32> for Avx {
+    const LANES: usize = 8;
+
+    #[inline(always)]
+    fn overlap_mask(
+        self,
+        los: &[Vec<f32>],
+        his: &[Vec<f32>],
+        start: usize,
+        lo: &[f32],
+        hi: &[f32],
+    ) -> u64 {
+        debug_assert!(
+            (0..los.len()).all(|dim| los[dim].len() >= start + 8 && his[dim].len() >= start + 8)
+        );
+        // SAFETY: an `Avx` only exists if AVX is supported, `kernel` only asks for boxes that exist, so the loads are in bounds
+        unsafe {
+            let mut mask = _mm256_castsi256_ps(_mm256_set1_epi32(-1));
+            for dim in 0..los.len() {
+                let p_lo = _mm256_loadu_ps(los[dim].as_ptr().add(start));
+                let p_hi = _mm256_loadu_ps(his[dim].as_ptr().add(start));
+                let overlaps = _mm256_and_ps(
+                    _mm256_cmp_ps::<_CMP_LT_OQ>(p_lo, _mm256_set1_ps(hi[dim])),
+                    _mm256_cmp_ps::<_CMP_LT_OQ>(_mm256_set1_ps(lo[dim]), p_hi),
+                );
+                mask = _mm256_and_ps(mask, overlaps);
+            }
+            _mm256_movemask_ps(mask) as u64
+        }
+    }
+}